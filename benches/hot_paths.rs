@@ -0,0 +1,177 @@
+//! Benchmarks for the code that runs once or more for every evaluation,
+//! at roughly the sizes seen in a typical ROPER or hello_world run. Run with
+//! `cargo bench`, and compare against a baseline saved from `master` with
+//! `cargo bench -- --save-baseline master` and `--baseline master`.
+//!
+//! The profile and disassembly benchmarks need a binary to load, and use
+//! `/bin/sh`, just like the profiler's tests.
+use std::sync::{Arc, Mutex};
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use rand::Rng;
+use unicorn::CpuX86;
+
+use berbalib::configure::RoperConfig;
+use berbalib::emulator::loader;
+use berbalib::emulator::profiler::{Block, Profile, Profiler, SparseDataHelper};
+use berbalib::util::count_min_sketch::{suggest_depth, suggest_width, CountMinSketch};
+use berbalib::util::distance::{levenshtein, levenshtein_bounded};
+use berbalib::util::random::hash_seed_rng;
+
+/// The number of runs in a profile, one per register pattern case.
+const NUM_RUNS: usize = 8;
+/// The number of blocks in each run's execution path.
+const PATH_LEN: usize = 512;
+/// The number of distinct addresses counted in a sketch, about as many as
+/// a population of 0x500 visits over a few generations.
+const SKETCH_COUNT: usize = 100_000;
+
+fn load_binary() -> &'static loader::MemoryImage {
+    let config = RoperConfig {
+        arch: unicorn::Arch::X86,
+        mode: unicorn::Mode::MODE_64,
+        binary_path: "/bin/sh".to_string(),
+        ..Default::default()
+    };
+    let _ = loader::load_from_path(&config, true);
+    loader::get_static_memory_image()
+}
+
+/// Random blocks from the executable segments of the binary, much like the
+/// ones visited in the course of a run.
+fn random_path<R: Rng>(memory: &loader::MemoryImage, rng: &mut R) -> Vec<Block> {
+    let segs = memory
+        .segments()
+        .iter()
+        .filter(|s| s.is_executable())
+        .collect::<Vec<_>>();
+    (0..PATH_LEN)
+        .map(|_| {
+            let seg = segs[rng.gen_range(0, segs.len())];
+            let size = rng.gen_range(1, 32);
+            let offset = rng.gen_range(0, seg.data.len().saturating_sub(size).max(1));
+            Block {
+                entry: seg.aligned_start() + offset as u64,
+                size,
+            }
+        })
+        .collect()
+}
+
+fn random_profiler<R: Rng>(memory: &loader::MemoryImage, rng: &mut R) -> Profiler<CpuX86<'static>> {
+    let mut writes = SparseDataHelper::new();
+    for _ in 0..64 {
+        writes.insert_u8(rng.gen_range(0x1000, 0x2000), rng.gen());
+    }
+    Profiler {
+        committed_trace_log: Arc::new(Mutex::new(random_path(memory, rng))),
+        committed_write_log: Arc::new(Mutex::new(writes)),
+        ..Default::default()
+    }
+}
+
+fn bench_addresses_visited(c: &mut Criterion) {
+    let memory = load_binary();
+    let mut rng = hash_seed_rng(&"addresses_visited");
+    let profile = Profile {
+        paths: (0..NUM_RUNS)
+            .map(|_| random_path(memory, &mut rng))
+            .collect(),
+        ..Default::default()
+    };
+    c.bench_function("code_coverage_ff address accumulation", |b| {
+        b.iter(|| black_box(&profile).addresses_visited())
+    });
+}
+
+fn bench_collate(c: &mut Criterion) {
+    let memory = load_binary();
+    let mut rng = hash_seed_rng(&"collate");
+    c.bench_function("Profile::collate", |b| {
+        b.iter_batched(
+            || {
+                (0..NUM_RUNS)
+                    .map(|_| random_profiler(memory, &mut rng))
+                    .collect::<Vec<_>>()
+            },
+            Profile::collate,
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn bench_disassemble(c: &mut Criterion) {
+    let memory = load_binary();
+    let mut rng = hash_seed_rng(&"disassemble");
+    let path = random_path(memory, &mut rng);
+    c.bench_function("Block::disassemble", |b| {
+        b.iter(|| {
+            path.iter()
+                .filter_map(|block| block.disassemble())
+                .map(|insts| insts.len())
+                .sum::<usize>()
+        })
+    });
+}
+
+fn bench_levenshtein(c: &mut Criterion) {
+    let mut rng = hash_seed_rng(&"levenshtein");
+    // the length of a typical hello_world target, and of a genome that's
+    // still some way from it
+    let target = (0..64)
+        .map(|_| rng.gen_range(b'a', b'z') as char)
+        .collect::<String>();
+    let genome = (0..80)
+        .map(|_| rng.gen_range(b'a', b'z') as char)
+        .collect::<String>();
+    c.bench_function("damerau_levenshtein", |b| {
+        b.iter(|| distance::damerau_levenshtein(black_box(&genome), black_box(&target)))
+    });
+    c.bench_function("levenshtein", |b| {
+        b.iter(|| levenshtein(black_box(genome.as_bytes()), black_box(target.as_bytes())))
+    });
+    c.bench_function("levenshtein_bounded", |b| {
+        b.iter(|| {
+            levenshtein_bounded(
+                black_box(genome.as_bytes()),
+                black_box(target.as_bytes()),
+                16,
+            )
+        })
+    });
+}
+
+fn bench_count_min_sketch(c: &mut Criterion) {
+    let mut rng = hash_seed_rng(&"count_min_sketch");
+    let mut sketch =
+        CountMinSketch::with_dimensions(suggest_depth(SKETCH_COUNT), suggest_width(SKETCH_COUNT));
+    for _ in 0..SKETCH_COUNT {
+        sketch.insert(rng.gen::<u64>());
+    }
+    let addresses = (0..1024).map(|_| rng.gen::<u64>()).collect::<Vec<u64>>();
+    c.bench_function("CountMinSketch::insert", |b| {
+        b.iter(|| {
+            for addr in addresses.iter() {
+                sketch.insert(*addr)
+            }
+        })
+    });
+    c.bench_function("CountMinSketch::query", |b| {
+        b.iter(|| {
+            addresses
+                .iter()
+                .map(|addr| sketch.query(*addr))
+                .sum::<f64>()
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_addresses_visited,
+    bench_collate,
+    bench_disassemble,
+    bench_levenshtein,
+    bench_count_min_sketch
+);
+criterion_main!(benches);
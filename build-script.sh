@@ -48,7 +48,7 @@ source "${HOME}/.cargo/env"
 echo "Building berbalang"
 git clone https://github.com/oblivia-simplex/berbalang
 pushd berbalang
-cargo build --release
+cargo build --release --features emulator
 find target/release -maxdepth 2 -type f -executable -exec strip -s {} +
 cp -v target/release/berbalang /root/berbalang
 
@@ -1,4 +1,4 @@
 #! /bin/sh
 
-nix-shell --command "rustup default stable && cargo build --release --bin berbalang"
+nix-shell --command "rustup default stable && cargo build --release --features emulator --bin berbalang"
 
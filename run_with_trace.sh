@@ -1,3 +1,3 @@
 #! /bin/sh
-BERBALANG_LOG=berbalib::emulator::hatchery=trace cargo run --features disassemble_trace -- $*
+BERBALANG_LOG=berbalang::emulator=trace cargo run --features disassemble_trace -- $*
 
@@ -1,6 +1,9 @@
 use berbalib::configure::{Config, Job};
 use berbalib::examples::{hello_world, linear_gp};
-use berbalib::{limit_threads, logger, roper, set_starting_timestamp, set_timeout};
+use berbalib::logger::RUN;
+#[cfg(feature = "emulator")]
+use berbalib::roper;
+use berbalib::{limit_threads, logger, set_starting_timestamp, set_timeout};
 
 
 fn main() {
@@ -36,11 +39,22 @@ fn main() {
         Job::Hello => {
             hello_world::run(config);
         }
+        #[cfg(feature = "emulator")]
         Job::Roper => {
             roper::run(config);
         }
+        #[cfg(not(feature = "emulator"))]
+        Job::Roper => {
+            log::error!(
+                target: RUN,
+                "The Roper job requires berbalang to be built with the `emulator` feature"
+            );
+        }
     }
 
-    log::info!("Waiting 3 seconds for file writes to complete...");
+    log::info!(
+        target: RUN,
+        "Waiting 3 seconds for file writes to complete..."
+    );
     std::thread::sleep(std::time::Duration::from_secs(3));
 }
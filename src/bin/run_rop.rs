@@ -9,38 +9,51 @@ use berbalib::emulator::hatchery::Hatchery;
 use berbalib::emulator::loader::falcon_loader::load_from_path;
 use berbalib::emulator::register_pattern::Register;
 use berbalib::error::Error;
-use berbalib::logger;
-use berbalib::util::architecture::{constant_register_state, random_register_state};
+use berbalib::logger::{self, RUN};
+use berbalib::util::architecture::{
+    constant_register_state, general_purpose_registers, random_register_state,
+};
+use berbalib::util::dump::dump;
 
 /// This purpose of this tool is to:
 /// - load a binary into unicorn emulator memory
 /// - parse a json document containing a ROP payload
 /// - execute that binary with the payload loaded
 /// - report the CPU status
+/// - optionally, write the execution trace of each run to a gzipped JSON
+///   file
 
 pub fn main() {
     let argv = std::env::args().collect::<Vec<String>>();
-    if argv.len() != 3 {
-        println!("Usage: {} <binary> <payload>", argv[0]);
+    if argv.len() != 3 && argv.len() != 4 {
+        println!("Usage: {} <binary> <payload> [trace output]", argv[0]);
         exit(1);
     }
     let binary = &argv[1];
     let payload = &argv[2];
+    let trace_path = argv.get(3).cloned();
     logger::init("no population");
     let mut config = generate_config(binary);
+    config.roper.record_write_order = trace_path.is_some();
+    config.roper.record_register_snapshots = trace_path.is_some();
     load_from_path(&mut config, true).expect("Failed to load binary");
-    log::info!("Binary {} loaded. Config: {:#?}", binary, config);
+    log::info!(
+        target: RUN,
+        "Binary {} loaded. Config: {:#?}",
+        binary,
+        config
+    );
     set_significant_registers(&mut config);
     let chain = parse_payload(payload).expect("Failed to parse payload");
-    log::info!("About to emulate payload: {:#x?}", chain);
+    log::info!(target: RUN, "About to emulate payload: {:#x?}", chain);
     use unicorn::Arch::*;
     match config.roper.arch {
-        X86 => emulate::<unicorn::CpuX86<'_>>(config, chain),
-        ARM => emulate::<unicorn::CpuARM<'_>>(config, chain),
-        ARM64 => emulate::<unicorn::CpuARM64<'_>>(config, chain),
-        MIPS => emulate::<unicorn::CpuMIPS<'_>>(config, chain),
-        SPARC => emulate::<unicorn::CpuSPARC<'_>>(config, chain),
-        M68K => emulate::<unicorn::CpuM68K<'_>>(config, chain),
+        X86 => emulate::<unicorn::CpuX86<'_>>(config, chain, trace_path),
+        ARM => emulate::<unicorn::CpuARM<'_>>(config, chain, trace_path),
+        ARM64 => emulate::<unicorn::CpuARM64<'_>>(config, chain, trace_path),
+        MIPS => emulate::<unicorn::CpuMIPS<'_>>(config, chain, trace_path),
+        SPARC => emulate::<unicorn::CpuSPARC<'_>>(config, chain, trace_path),
+        M68K => emulate::<unicorn::CpuM68K<'_>>(config, chain, trace_path),
         _ => unimplemented!("architecture unimplemented"),
     }
 }
@@ -66,30 +79,19 @@ fn parse_payload(payload: &str) -> Result<Vec<u64>, Error> {
     from_str::<Vec<u64>>(&data).map_err(Error::from)
 }
 
-macro_rules! strings {
-    ($($v:expr $(,)?)*) => {
-        vec![$( $v, )*].into_iter().map(|s| s.to_string()).collect::<Vec<String>>()
-    }
-}
-
 fn set_significant_registers(config: &mut Config) {
-    use unicorn::{Arch::*, Mode::*};
-    let arch = config.roper.arch;
-    let mode = config.roper.mode;
-    let regs = match (arch, mode) {
-        (X86, MODE_64) => {
-            strings!["RAX", "RBX", "RCX", "RDX", "RSI", "RDI", "R9", "RBP", "RSP", "RIP", "EFLAGS",]
-        }
-        (X86, MODE_32) => {
-            strings!["EAX", "EBX", "ECX", "EDX", "ESI", "EDI", "EBP", "ESP", "EIP", "EFLAGS",]
-        }
-        _ => unimplemented!("this mode/arch combination is not yet implemented"),
-    };
+    let regs = general_purpose_registers(config.roper.arch, config.roper.mode)
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<String>>();
+    if regs.is_empty() {
+        unimplemented!("this mode/arch combination is not yet implemented")
+    }
     config.roper.output_registers = regs.clone();
     config.roper.input_registers = regs;
 }
 
-fn emulate<C: 'static + Cpu<'static>>(config: Config, chain: Vec<u64>) {
+fn emulate<C: 'static + Cpu<'static>>(config: Config, chain: Vec<u64>, trace_path: Option<String>) {
     let output_registers: Vec<Register<C>> = {
         config
             .roper
@@ -100,16 +102,30 @@ fn emulate<C: 'static + Cpu<'static>>(config: Config, chain: Vec<u64>) {
             .collect::<Vec<_>>()
     };
     let initial_register_state = if config.roper.randomize_registers {
-        random_register_state::<u64, C>(&output_registers, config.random_seed)
+        random_register_state::<u64, C>(
+            &output_registers,
+            config.random_seed,
+            &config.roper.register_distribution,
+        )
     } else {
         constant_register_state::<C>(&output_registers, 1_u64)
     };
-    let hatchery: Hatchery<C> = Hatchery::new(
+    let hatchery: Hatchery<C> = match Hatchery::new(
         Arc::new(config.roper),
         Arc::new(initial_register_state),
         Arc::new(output_registers),
-    );
+    ) {
+        Ok(hatchery) => hatchery,
+        Err(e) => {
+            eprintln!("Failed to start the hatchery: {:?}", e);
+            exit(1);
+        }
+    };
     let profile = hatchery.execute(chain, None).expect("Emulation failed!");
-    log::info!("Execution complete.");
+    log::info!(target: RUN, "Execution complete.");
     println!("{:#x?}", profile);
+    if let Some(path) = trace_path {
+        dump(profile.to_trace(), &path).expect("Failed to dump trace");
+        log::info!(target: RUN, "Trace written to {}", path);
+    }
 }
@@ -4,10 +4,13 @@ use std::path::Path;
 
 use chrono::prelude::*;
 use hashbrown::{HashMap, HashSet};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
-use crate::emulator::register_pattern::{parse_register_pattern_file, RegisterPattern};
+#[cfg(feature = "emulator")]
+use crate::emulator::register_pattern::{parse_register_pattern_file, RegisterPattern, Tolerance};
 use crate::error::Error;
+use crate::logger::CONFIG;
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct DataConfig {
@@ -56,11 +59,21 @@ pub struct Config {
     pub crossover_period: f64,
     #[serde(default = "default_crossover_algorithm")]
     pub crossover_algorithm: String,
+    /// The chance that an offspring is bred by recombining its parents.
+    /// Otherwise, it's a clone of just one of them, left to mutation alone.
+    /// Crossover always takes place at the default rate of 1.
+    #[serde(default = "default_one")]
     pub crossover_rate: f64,
     #[serde(default)]
     pub data: DataConfig,
     pub max_init_len: usize,
     pub max_length: usize,
+    /// The number of alleles at the head of a linear chromosome that are
+    /// never altered by crossover or mutation, and are inherited from the
+    /// first parent. In a ROP chain, this can pin an entry point, such as
+    /// a stack pivot, in place. Zero locks nothing.
+    #[serde(default)]
+    pub locked_prefix: usize,
     pub min_init_len: usize,
     // See the comments in util::levy_flight for an explanation
     // There is a mutation_rate chance, per genome, that
@@ -69,6 +82,15 @@ pub struct Config {
     #[serde(default = "default_one")]
     pub mutation_rate: f64,
     pub mutation_exponent: f64,
+    /// The operators from which one is drawn for each mutation of a linear
+    /// chromosome. Left empty, every mutation is pointwise.
+    #[serde(default)]
+    pub mutation_operators: MutationOperatorSet,
+    /// If set, each linear chromosome carries its own pointwise mutation
+    /// rate, which is inherited and adapted along with its genes, in place
+    /// of the levy-flight decision governed by `mutation_exponent`.
+    #[serde(default)]
+    pub self_adaptation: Option<SelfAdaptationConfig>,
     pub observer: ObserverConfig,
     pub pop_size: usize,
     pub problems: Option<Vec<ClassificationProblem>>,
@@ -76,6 +98,7 @@ pub struct Config {
     pub roulette: RouletteConfig,
     #[serde(default)]
     pub tournament: TournamentConfig,
+    #[cfg(feature = "emulator")]
     #[serde(default)]
     pub roper: RoperConfig,
     #[serde(default)]
@@ -88,6 +111,332 @@ pub struct Config {
     pub random_seed: u64,
     #[serde(default)]
     pub push_vm: PushVm,
+    #[serde(default)]
+    pub restart: Option<RestartConfig>,
+    /// The number of the worst evaluated specimens to replace with randomly
+    /// generated newcomers, once per generation.
+    #[serde(default)]
+    pub random_immigrants: usize,
+    /// How to trim the population back to `pop_size` at the end of an
+    /// epoch, if elitism or immigration have left it larger.
+    #[serde(default)]
+    pub culling: CullingPolicy,
+    #[serde(default)]
+    pub annealing: Option<AnnealingConfig>,
+    #[serde(default)]
+    pub alps: AlpsConfig,
+    #[serde(default)]
+    pub map_elites: MapElitesConfig,
+    #[serde(default)]
+    pub coevolution: CoevolutionConfig,
+    /// A rough limit, in bytes, on the behavioural data (execution paths,
+    /// memory write logs, etc.) retained by each island's population. When
+    /// it's exceeded, the oldest data is pruned from the living creatures'
+    /// profiles, once per generation.
+    #[serde(default)]
+    pub memory_budget: Option<usize>,
+    /// A population file, in the format written by `export_population`,
+    /// from which to seed the initial population. If it holds fewer than
+    /// `pop_size` compatible creatures, the rest are generated at random.
+    #[serde(default)]
+    pub initial_population_path: Option<String>,
+}
+
+/// The mutation operators available to linear chromosomes, named in the
+/// config in snake case:
+///
+/// - `point`: levy-flight pointwise mutation, using the genome's own point
+///   mutation
+/// - `insert`: a copy of a random allele is inserted at a random position
+/// - `delete`: a random allele is removed
+/// - `rotate`: the chromosome is rotated by a random number of places
+/// - `levy_step`: a random allele is shifted by a step whose length follows
+///   a power law, with `mutation_exponent`, so most steps are short
+/// - `gadget_snap`: a random allele is moved to the nearest address in the
+///   ROPER gadget soup
+///
+/// The last two only apply to alleles that are addresses. Other alleles
+/// are mutated pointwise instead.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum MutationOperator {
+    Point,
+    Insert,
+    Delete,
+    Rotate,
+    LevyStep,
+    GadgetSnap,
+}
+
+impl MutationOperator {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Point => "point",
+            Self::Insert => "insert",
+            Self::Delete => "delete",
+            Self::Rotate => "rotate",
+            Self::LevyStep => "levy_step",
+            Self::GadgetSnap => "gadget_snap",
+        }
+    }
+}
+
+impl std::str::FromStr for MutationOperator {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use MutationOperator::*;
+        [Point, Insert, Delete, Rotate, LevyStep, GadgetSnap]
+            .iter()
+            .find(|op| op.name() == s)
+            .copied()
+            .ok_or_else(|| Error::Misc(format!("No such mutation operator as {}", s)))
+    }
+}
+
+/// The mutation operators available to linear chromosomes, with the
+/// relative weights with which they're chosen. Given in the config as a
+/// table from operator names to weights, and any name that isn't one of
+/// the `MutationOperator`s is rejected when the config is read.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(try_from = "HashMap<String, f64>", into = "HashMap<String, f64>")]
+pub struct MutationOperatorSet {
+    pub weights: HashMap<MutationOperator, f64>,
+}
+
+impl std::convert::TryFrom<HashMap<String, f64>> for MutationOperatorSet {
+    // serde needs an error it can display
+    type Error = String;
+
+    fn try_from(weights: HashMap<String, f64>) -> Result<Self, Self::Error> {
+        let weights = weights
+            .into_iter()
+            .map(|(name, weight)| {
+                name.parse()
+                    .map(|op| (op, weight))
+                    .map_err(|_| format!("No such mutation operator as {}", name))
+            })
+            .collect::<Result<HashMap<MutationOperator, f64>, String>>()?;
+        Ok(Self { weights })
+    }
+}
+
+impl From<MutationOperatorSet> for HashMap<String, f64> {
+    fn from(set: MutationOperatorSet) -> Self {
+        set.weights
+            .into_iter()
+            .map(|(op, weight)| (op.name().to_string(), weight))
+            .collect()
+    }
+}
+
+impl MutationOperatorSet {
+    /// Draw an operator at random, in proportion to its weight, or `None`
+    /// if there are no operators with positive weights.
+    pub fn choose<R: Rng>(&self, rng: &mut R) -> Option<MutationOperator> {
+        let mut operators = self
+            .weights
+            .iter()
+            .filter(|(_, w)| **w > 0.0)
+            .map(|(op, w)| (*op, *w))
+            .collect::<Vec<(MutationOperator, f64)>>();
+        // sorted, so that the same draw picks the same operator every time
+        operators.sort_by(|a, b| a.0.cmp(&b.0));
+        if operators.is_empty() {
+            return None;
+        }
+        let total = operators.iter().map(|(_, w)| *w).sum::<f64>();
+        let mut point = rng.gen_range(0.0, total);
+        for (op, weight) in operators.iter() {
+            if point < *weight {
+                return Some(*op);
+            }
+            point -= *weight;
+        }
+        operators.last().map(|(op, _)| *op)
+    }
+}
+
+/// Parameters for the self-adaptation of per-genome mutation rates, as in
+/// evolution strategies. Each time a genome is mutated, its rate is first
+/// multiplied by `exp(learning_rate * N(0, 1))`, and kept within
+/// `[min_rate, max_rate]`, and then each of its genes is mutated with that
+/// probability. Offspring inherit the mean of their parents' rates.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SelfAdaptationConfig {
+    /// The rate given to randomly generated genomes.
+    #[serde(default = "default_initial_mutation_rate")]
+    pub initial_rate: f64,
+    #[serde(default = "default_self_adaptation_learning_rate")]
+    pub learning_rate: f64,
+    #[serde(default = "default_min_mutation_rate")]
+    pub min_rate: f64,
+    #[serde(default = "default_max_mutation_rate")]
+    pub max_rate: f64,
+}
+
+impl Default for SelfAdaptationConfig {
+    fn default() -> Self {
+        Self {
+            initial_rate: default_initial_mutation_rate(),
+            learning_rate: default_self_adaptation_learning_rate(),
+            min_rate: default_min_mutation_rate(),
+            max_rate: default_max_mutation_rate(),
+        }
+    }
+}
+
+fn default_initial_mutation_rate() -> f64 {
+    0.05
+}
+
+fn default_self_adaptation_learning_rate() -> f64 {
+    0.2
+}
+
+fn default_min_mutation_rate() -> f64 {
+    0.001
+}
+
+fn default_max_mutation_rate() -> f64 {
+    0.5
+}
+
+/// Parameters for the age-layered population structure, used when
+/// `selection = "Alps"`. The population is divided evenly among the
+/// layers, and the bottom layer is reseeded every `age_gap` generations.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AlpsConfig {
+    pub num_layers: usize,
+    pub age_gap: usize,
+}
+
+impl Default for AlpsConfig {
+    fn default() -> Self {
+        Self {
+            num_layers: 5,
+            age_gap: 10,
+        }
+    }
+}
+
+impl AlpsConfig {
+    /// The oldest age permitted in the given layer, following a polynomial
+    /// scheme. The top layer has no age limit.
+    pub fn age_limit(&self, layer: usize) -> usize {
+        self.age_gap * (layer + 1).pow(2)
+    }
+}
+
+/// Parameters for the co-evolution of ROPER chains with the initial
+/// register states they're run from, used when `selection = "Coevolution"`.
+/// The chains are scored on how well they fare against a sample of the
+/// register states, and the register states on how badly the chains that
+/// meet them fare.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CoevolutionConfig {
+    /// The size of the population of register states.
+    pub num_parasites: usize,
+    /// The number of register states that each chain is run against, in
+    /// each generation.
+    pub encounters: usize,
+    /// The chance that each register of a newly bred register state is
+    /// drawn afresh from `roper.register_distribution`.
+    pub mutation_rate: f64,
+}
+
+impl Default for CoevolutionConfig {
+    fn default() -> Self {
+        Self {
+            num_parasites: 32,
+            encounters: 4,
+            mutation_rate: 0.1,
+        }
+    }
+}
+
+/// Parameters for MAP-Elites, used when `selection = "MapElites"`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapElitesConfig {
+    /// The axes spanning the behaviour space.
+    #[serde(default)]
+    pub axes: Vec<AxisConfig>,
+    /// The number of offspring bred from the elites in each iteration.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Dump the grid every `dump_period` iterations. 0 disables the dumps.
+    #[serde(default)]
+    pub dump_period: usize,
+}
+
+impl Default for MapElitesConfig {
+    fn default() -> Self {
+        Self {
+            axes: vec![],
+            batch_size: default_batch_size(),
+            dump_period: 0,
+        }
+    }
+}
+
+fn default_batch_size() -> usize {
+    64
+}
+
+/// An axis of the MAP-Elites behaviour space: the `element`th element of
+/// the given feature of the creature's behaviour descriptor (see
+/// `roper::behavior`). The range from `min` to `max` is divided into `bins`
+/// equal bins. Values outside of the range fall into the outermost bins.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AxisConfig {
+    pub feature: BehaviorFeature,
+    #[serde(default)]
+    pub element: usize,
+    /// Descriptor elements lie between 0 and 1, so that's the default range.
+    #[serde(default)]
+    pub min: f64,
+    #[serde(default = "default_axis_max")]
+    pub max: f64,
+    pub bins: usize,
+}
+
+fn default_axis_max() -> f64 {
+    1.0
+}
+
+impl AxisConfig {
+    pub fn bin(&self, value: f64) -> usize {
+        if self.bins == 0 || self.max <= self.min || value.is_nan() {
+            return 0;
+        }
+        let ratio = (value - self.min) / (self.max - self.min);
+        ((ratio * self.bins as f64).floor().max(0.0) as usize).min(self.bins - 1)
+    }
+}
+
+/// Lets offspring that are worse than the loser whose place they take
+/// still replace it, with a probability that falls as the temperature
+/// cools (the Metropolis criterion).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnnealingConfig {
+    pub initial_temperature: f64,
+    /// The temperature is multiplied by this factor every generation.
+    pub cooling_rate: f64,
+}
+
+impl AnnealingConfig {
+    pub fn temperature(&self, generation: usize) -> f64 {
+        self.initial_temperature * self.cooling_rate.powi(generation as i32)
+    }
+}
+
+/// When the best fitness in the population has failed to improve for
+/// `stagnation_generations`, the `keep` best specimens are preserved and
+/// the rest of the population is re-initialized at random.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RestartConfig {
+    pub stagnation_generations: usize,
+    pub keep: usize,
 }
 
 fn default_tournament_size() -> usize {
@@ -116,6 +465,151 @@ pub struct FitnessConfig {
     priority: String,
     pub function: String,
     pub weighting: String,
+    /// The weight of the parsimony penalty, which is proportional to genome
+    /// size. A weight of 0 disables parsimony pressure.
+    #[serde(default)]
+    pub parsimony: f64,
+    /// The value of the `non_executable` fitness component for a chain
+    /// that executed no gadgets at all, in ROPER. It's 0 for chains that
+    /// did. With a penalty of 0, the component is always 0.
+    #[serde(default)]
+    pub non_executable_penalty: f64,
+    /// If set, each creature is scored for the novelty of its behaviour,
+    /// and the score is recorded as the `novelty` fitness component, which
+    /// can then be used in the weighting expression.
+    #[serde(default)]
+    pub novelty: Option<NoveltyConfig>,
+    /// If set, each fitness component is standardized against the values
+    /// recently observed for it before the weighting expression is applied,
+    /// so that the weights express the components' relative importance
+    /// rather than compensating for their scales. Exported and reported
+    /// scores remain raw, as do expressions other than the weighting, such
+    /// as a separately configured `priority`.
+    #[serde(default)]
+    pub standardization: Option<StandardizationConfig>,
+    /// Bounds into which the raw values of fitness components are clamped
+    /// before weighting, by the name of the component, to cap the influence
+    /// of outlying evaluations. Unlisted components are left as they are.
+    #[serde(default)]
+    pub clamp: HashMap<String, Bounds>,
+    /// The fitness functions run by the `composite` fitness function.
+    #[serde(default)]
+    pub components: Vec<String>,
+    /// The factor by which each of the composite fitness function's
+    /// components scales its scores, by the name of the component. Unlisted
+    /// components have a weight of 1.
+    #[serde(default)]
+    pub weights: HashMap<String, f64>,
+}
+
+/// Parameters for novelty search. For a description, see Joel Lehman and
+/// Kenneth Stanley, "Abandoning Objectives: Evolution Through the Search
+/// for Novelty Alone", Evolutionary Computation 19(2), 2011.
+///
+/// A creature's novelty is the mean distance of its behaviour descriptor
+/// (see `roper::behavior`) from the `k` nearest descriptors in an archive
+/// of those seen before. Since the weighting expression is minimized,
+/// select for novelty by including a term like `(1 - novelty)`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NoveltyConfig {
+    #[serde(default = "default_novelty_k")]
+    pub k: usize,
+    /// The features that make up the behaviour descriptor.
+    #[serde(default = "default_behavior_features")]
+    pub features: Vec<BehaviorFeature>,
+    /// The oldest behaviours are dropped once the archive is this large.
+    #[serde(default = "default_archive_size")]
+    pub archive_size: usize,
+    /// Behaviours are added to the archive when their novelty is at least
+    /// this great.
+    #[serde(default = "default_archive_threshold")]
+    pub archive_threshold: f64,
+}
+
+/// Parameters for the online standardization of fitness components.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StandardizationConfig {
+    #[serde(default)]
+    pub method: Standardization,
+    /// The number of the most recent values of each component from which
+    /// its statistics are drawn.
+    #[serde(default = "default_standardization_window")]
+    pub window: usize,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Standardization {
+    /// The number of standard deviations from the mean.
+    ZScore,
+    /// The position between the least and greatest value, from 0 to 1.
+    MinMax,
+}
+
+impl Default for Standardization {
+    fn default() -> Self {
+        Self::ZScore
+    }
+}
+
+fn default_standardization_window() -> usize {
+    1000
+}
+
+/// An interval, either end of which may be left open.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Bounds {
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+impl Bounds {
+    /// Clamps `value` into the interval. NaN is left as it is.
+    pub fn clamp(&self, value: f64) -> f64 {
+        let mut value = value;
+        if let Some(min) = self.min {
+            if value < min {
+                value = min
+            }
+        }
+        if let Some(max) = self.max {
+            if value > max {
+                value = max
+            }
+        }
+        value
+    }
+}
+
+/// The aspects of a creature's behaviour that can make up its behaviour
+/// descriptor.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BehaviorFeature {
+    /// The basic blocks visited, hashed into a fixed number of buckets.
+    Blocks,
+    /// The final state of the output registers.
+    Registers,
+    /// The span of memory addresses written to.
+    WriteSpan,
+    /// The number of gadgets returned through.
+    ChainLength,
+}
+
+fn default_behavior_features() -> Vec<BehaviorFeature> {
+    vec![BehaviorFeature::Blocks]
+}
+
+fn default_novelty_k() -> usize {
+    15
+}
+
+fn default_archive_size() -> usize {
+    1000
+}
+
+fn default_archive_threshold() -> f64 {
+    0.1
 }
 
 impl FitnessConfig {
@@ -126,6 +620,23 @@ impl FitnessConfig {
             &self.priority
         }
     }
+
+    /// Whether a scalar fitness has reached the `target`. Fitness is
+    /// minimized, so any score at or below the target will do, which is
+    /// what makes the target reachable for weighted or noisy fitnesses that
+    /// are unlikely ever to hit it exactly.
+    pub fn is_target_reached(&self, score: f64) -> bool {
+        score - self.target <= std::f64::EPSILON
+    }
+
+    /// The number of recent values of each fitness component from which
+    /// its running statistics are drawn.
+    pub fn statistics_window(&self) -> usize {
+        self.standardization
+            .as_ref()
+            .map(|s| s.window)
+            .unwrap_or_else(default_standardization_window)
+    }
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -135,7 +646,62 @@ pub struct TournamentConfig {
     pub geographic_radius: usize,
     pub migration_rate: f64,
     pub num_offspring: usize,
+    /// The number of parents recombined to produce each offspring.
+    #[serde(default = "default_num_parents")]
     pub num_parents: usize,
+    #[serde(default)]
+    pub sampling: Sampling,
+    /// If set, combatants are ranked by fitness shared with the others in
+    /// their niche, so that a crowd of similar creatures fares worse than an
+    /// equally fit loner. Each scalar fitness is multiplied by its niche
+    /// count: the sum, over the combatants, of `max(0, 1 - d / niche_radius)`,
+    /// where `d` is the distance between the two. This assumes that
+    /// fitnesses are non-negative.
+    #[serde(default)]
+    pub niche_radius: Option<f64>,
+}
+
+/// Which creatures are culled when the population has grown larger than
+/// `pop_size`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CullingPolicy {
+    /// The least fit are culled first.
+    WorstFitness,
+    /// The oldest are culled first, breaking ties by fitness.
+    OldestAge,
+    /// Those with the lowest `novelty` fitness component are culled first,
+    /// starting with any that have no novelty score at all.
+    LeastNovel,
+}
+
+impl Default for CullingPolicy {
+    fn default() -> Self {
+        Self::WorstFitness
+    }
+}
+
+/// How combatants are drawn from the geographic range of a tournament.
+/// When sampling with replacement, the same specimen may be drawn more
+/// than once, and will then fight alongside copies of itself.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Sampling {
+    WithoutReplacement,
+    WithReplacement,
+    /// The fittest specimen in the whole population, wherever it lives,
+    /// fights in every tournament, and the rest are drawn from the range
+    /// without replacement. A light form of elitism, since the champion
+    /// can never be among the losers.
+    WithChampion,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self::WithoutReplacement
+    }
+}
+
+fn default_num_parents() -> usize {
+    2
 }
 
 fn default_weight_decay() -> f64 {
@@ -164,6 +730,40 @@ pub struct ObserverConfig {
     data_directory: String,
     #[serde(default = "random_population_name")]
     pub population_name: String,
+    /// Dump the sorted scalar fitnesses of the observation window every
+    /// `fitness_distribution_period` epochs. 0 disables the snapshots.
+    #[serde(default)]
+    pub fitness_distribution_period: usize,
+    /// The format in which champions, populations and grids are dumped.
+    #[serde(default)]
+    pub dump_format: DumpFormat,
+    /// Redraw a summary of each island on the terminal at the end of every
+    /// epoch. Only available with the `dashboard` feature.
+    #[serde(default)]
+    pub dashboard: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DumpFormat {
+    /// Gzipped JSON
+    Json,
+    /// Uncompressed bincode. Only available with the `binary_dump` feature.
+    Bincode,
+}
+
+impl Default for DumpFormat {
+    fn default() -> Self {
+        Self::Json
+    }
+}
+
+impl DumpFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Json => "json.gz",
+            Self::Bincode => "bin",
+        }
+    }
 }
 
 impl Config {
@@ -172,10 +772,36 @@ impl Config {
     }
 
     pub fn assert_invariants(&self) {
-        assert!(self.tournament.tournament_size >= self.tournament.num_offspring + 2);
+        assert!(self.tournament.num_parents >= 1);
+        assert!(
+            self.tournament.tournament_size
+                >= self.tournament.num_offspring + self.tournament.num_parents
+        );
         //assert_eq!(self.num_offspring, 2); // all that's supported for now
     }
 
+    /// Check for settings that are each well formed, but that can't be
+    /// honoured in combination.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.restart.is_some() && self.selection != Selection::Tournament {
+            return Err(Error::Misc(format!(
+                "restart on stagnation is only supported with Tournament selection, not {:?}",
+                self.selection
+            )));
+        }
+        #[cfg(feature = "emulator")]
+        {
+            if self.selection == Selection::Coevolution && self.roper.use_push {
+                return Err(Error::Misc(
+                    "Coevolution selection is only supported for bare chains, not with roper.use_push"
+                        .to_string(),
+                ));
+            }
+            self.roper.validate()?;
+        }
+        Ok(())
+    }
+
     pub fn from_path<P: AsRef<Path>>(
         path: P,
         population_name: Option<String>,
@@ -193,6 +819,7 @@ impl Config {
             config.observer.population_name
         );
         config.assert_invariants();
+        config.validate()?;
         config.set_data_directory();
         // copy the config file to the data directory for posterity
         // bit ugly, here: copying it to the parent of the directory, just above the island subdirs
@@ -227,11 +854,20 @@ impl Config {
             island = self.island_id,
         );
 
-        for sub in ["", "soup", "population", "champions"].iter() {
+        for sub in [
+            "",
+            "soup",
+            "population",
+            "champions",
+            "fitness_distribution",
+            "map_elites",
+        ]
+        .iter()
+        {
             let d = format!("{}/{}", path, sub);
             std::fs::create_dir_all(&d)
                 .map_err(|e| {
-                    log::error!("Error creating {}: {:?}", path, e);
+                    log::error!(target: CONFIG, "Error creating {}: {:?}", path, e);
                     e
                 })
                 .expect("Failed to create data directory");
@@ -248,6 +884,12 @@ impl Config {
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct HelloConfig {
     pub target: String,
+    /// Score each batch of genomes in parallel on rayon's thread pool,
+    /// rather than one at a time through a single worker. The genetic
+    /// frequencies then depend on the order in which the threads finish,
+    /// so runs are no longer reproducible.
+    #[serde(default)]
+    pub parallel: bool,
 }
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
@@ -259,14 +901,17 @@ pub struct LinearGpConfig {
     pub return_registers: Option<usize>,
 }
 
+#[cfg(feature = "emulator")]
 fn default_arch() -> unicorn::Arch {
     unicorn::Arch::X86
 }
 
+#[cfg(feature = "emulator")]
 fn default_mode() -> unicorn::Mode {
     unicorn::Mode::MODE_32
 }
 
+#[cfg(feature = "emulator")]
 #[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
 pub struct RoperConfig {
     #[serde(default)]
@@ -276,8 +921,14 @@ pub struct RoperConfig {
     pub output_registers: Vec<String>,
     #[serde(default)]
     pub input_registers: Vec<String>,
+    /// Read every general-purpose register at the end of each run, as well
+    /// as those listed in `output_registers` and the register patterns.
+    #[serde(default)]
+    pub read_all_registers: bool,
     #[serde(default)]
     pub randomize_registers: bool,
+    #[serde(default)]
+    pub register_distribution: RegisterDistribution,
     pub register_pattern_file: Option<String>,
     #[serde(skip)]
     pub parsed_register_patterns: Vec<RegisterPattern>,
@@ -289,6 +940,9 @@ pub struct RoperConfig {
     pub arch: unicorn::Arch,
     #[serde(default = "default_mode")]
     pub mode: unicorn::Mode,
+    /// The number of threads in the hatchery's own thread pool, which runs
+    /// the emulations apart from rayon's global pool. Defaults to the
+    /// number of CPUs.
     #[serde(default = "default_num_workers")]
     pub num_workers: usize,
     #[serde(default = "default_num_emu")]
@@ -296,35 +950,444 @@ pub struct RoperConfig {
     #[serde(default = "default_wait_limit")]
     pub wait_limit: u64,
     pub max_emu_steps: Option<usize>,
+    /// The most entries that each run may capture in its block and gadget
+    /// logs. Further entries are dropped, and the run is flagged in the
+    /// profile's `logs_truncated`. Unlimited if unset.
+    #[serde(default)]
+    pub max_log_entries: Option<usize>,
+    /// How many pointers are followed from each register's value when the
+    /// registers are read at the end of a run, to build the dereference
+    /// chains that register patterns are matched against. Cyclic pointers
+    /// are followed no further than this. Defaults to 10.
+    #[serde(default)]
+    pub max_deref_depth: Option<usize>,
     pub millisecond_timeout: Option<u64>,
     #[serde(default = "Default::default")]
     pub record_basic_blocks: bool,
+    #[serde(default)]
+    pub block_log_mode: BlockLogMode,
+    /// Read the output registers on entering each gadget, for the
+    /// execution traces built by `Profile::to_trace`. Only takes effect
+    /// when `record_basic_blocks` is set, and, like `record_write_order`,
+    /// is meant for re-running a single creature.
+    #[serde(default)]
+    pub record_register_snapshots: bool,
+    /// Count the instructions executed with these mnemonics, in the
+    /// profile's `instruction_counts`. This installs a hook on every
+    /// instruction, but is still much cheaper than disassembling the whole
+    /// path afterwards.
+    #[serde(default)]
+    pub count_mnemonics: Vec<String>,
+    /// The address to which a finished chain should return. It's planted on
+    /// the stack just past the payload, as a sentinel, so that a chain that
+    /// runs out of gadgets returns there. Emulation stops when the sentinel
+    /// is reached, and the run is counted as a clean exit. Only detected
+    /// when `record_basic_blocks` is set. May be given as a symbol.
+    #[serde(default)]
+    pub return_address: Address,
+    /// Start emulation here, rather than at the address in the first word
+    /// of the payload. The payload is still written to the stack, but none
+    /// of it is popped: the stack pointer is left pointing at its first
+    /// word, which is where the code at the entry point will return to,
+    /// if it returns. Useful for evaluating a single gadget or function
+    /// against the chain as its stack. May be given as a symbol.
+    #[serde(default)]
+    pub entry_point: Option<Address>,
     #[serde(default = "Default::default")]
     pub record_memory_writes: bool,
+    /// Keep every memory write of each run, in order, for the execution
+    /// traces built by `Profile::to_trace`. Only takes effect when
+    /// `record_memory_writes` is set. This is expensive, and meant for
+    /// re-running a single creature rather than a whole population.
+    #[serde(default)]
+    pub record_write_order: bool,
+    /// The regions of memory whose reads are logged, in the profile's
+    /// `read_logs`. Logging every read would be far too expensive, so only
+    /// the reads that touch one of these are recorded.
+    #[serde(default)]
+    pub read_watchpoints: Vec<Watchpoint>,
     #[serde(default = "default_stack_size")]
     pub emulator_stack_size: usize,
     pub binary_path: String,
     #[serde(default)]
     pub ld_paths: Option<Vec<String>>,
+    /// A substitution table, mapping hex-encoded bytes to their replacements,
+    /// which is applied to the payload when it is packed.
     #[serde(default)]
     pub bad_bytes: Option<HashMap<String, u8>>,
+    /// Bytes that must not appear in the packed payload. Every word that
+    /// contains one of these is counted in the `bad_bytes` fitness component.
+    #[serde(default)]
+    pub forbidden_bytes: Vec<u8>,
     pub memory_pattern: Option<Vec<u8>>,
+    /// The system call that the `syscall` fitness function should reward
+    /// reaching. If unset, any system call will do.
+    #[serde(default)]
+    pub syscall_number: Option<u64>,
+    /// The address that the `reach_target` fitness function rewards
+    /// executing, as a number or a symbol.
+    #[serde(default)]
+    pub target_address: Option<Address>,
+    /// Regions of memory whose code the chain should avoid executing,
+    /// such as `abort` or the stack protector's failure handler. Every
+    /// instruction executed within them is counted, and penalized by the
+    /// `reach_target` fitness function.
+    #[serde(default)]
+    pub bad_regions: Vec<Watchpoint>,
+    /// How closely register states have to match the register patterns.
+    #[serde(default)]
+    pub register_tolerance: Tolerance,
     #[serde(default)]
     pub break_on_calls: bool,
     #[serde(default)]
     pub monitor_stack_writes: bool,
+    #[serde(default)]
+    pub stack: StackConfig,
+    /// Overrides of the permissions with which the loaded segments are
+    /// mapped, applied in order at load time.
+    #[serde(default)]
+    pub segment_permissions: Vec<PermissionOverride>,
+    /// Which gadgets are admitted to the soup, when it's built from a
+    /// gadget file or sampled from executable memory. If unset, every
+    /// address is admitted. Only supported on x86.
+    #[serde(default)]
+    pub gadget_filter: Option<GadgetFilter>,
+    /// Build the soup from the gadgets found by searching the executable
+    /// segments, rather than from addresses sampled at random, when no
+    /// gadget file is given. Every distinct gadget found is included,
+    /// whatever the `soup_size`. Only supported on x86.
+    #[serde(default)]
+    pub discover_gadgets: bool,
+    /// How many bytes before each return, indirect jump or indirect call
+    /// the gadget search begins decoding from. It decodes from every byte
+    /// offset in that window, and not just from instruction boundaries.
+    #[serde(default = "default_gadget_search_window")]
+    pub gadget_search_window: usize,
+    /// Export the discovered gadgets to this path, as JSON if it ends in
+    /// `.json`, or otherwise as text, in the style of ROPgadget. Requires
+    /// `discover_gadgets`, and no `gadget_file`.
+    #[serde(default)]
+    pub gadget_catalog_path: Option<String>,
+    /// Chains longer than this are truncated before they're packed and executed.
+    #[serde(default)]
+    pub max_chain_length: Option<usize>,
+    /// If set, the `chain_length` fitness component is the length of the
+    /// executed chain, multiplied by this factor.
+    #[serde(default)]
+    pub chain_length_penalty: Option<f64>,
+    /// Disassemble each gadget in the soup to find out how many data words
+    /// it pops, and lay out each chain accordingly before packing it.
+    #[serde(default)]
+    pub model_stack_deltas: bool,
+    /// Skip emulating chains that don't contain a single word pointing into
+    /// executable memory, since they're sure to crash on their first
+    /// return. They're given the worst possible fitness instead.
+    #[serde(default)]
+    pub prefilter: bool,
+    /// Execute each chain this many times, scoring each execution
+    /// separately and aggregating the scores, for a steadier fitness when
+    /// emulation is noisy. Only used in bare mode. 0 means the same as 1.
+    #[serde(default)]
+    pub resample: usize,
+    /// How the fitnesses of the samples are combined.
+    #[serde(default)]
+    pub resample_aggregation: SampleAggregation,
+    /// Draw the initial register values of each sample afresh from the
+    /// `register_distribution`. The draws are the same for every creature.
+    /// Otherwise, every sample begins from the same register state.
+    #[serde(default)]
+    pub resample_registers: bool,
+    /// The stack deltas of the gadgets in the soup, computed when the soup
+    /// is initialized if `model_stack_deltas` is set.
+    #[serde(skip)]
+    pub stack_deltas: Option<HashMap<u64, usize>>,
+    /// When a champion reaches the fitness target, its packed payload is
+    /// written to this path, with the island's id inserted before the
+    /// extension, as raw bytes, with a report alongside it, in the same
+    /// path with `.txt` appended.
+    #[serde(default)]
+    pub output_payload_path: Option<String>,
+    /// The assembly syntax used when disassembling x86 code for reports
+    /// and dumps.
+    #[serde(default)]
+    pub disassembly_syntax: DisassemblySyntax,
+}
+
+/// How the fitnesses scored for several executions of the same chain
+/// are combined.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum SampleAggregation {
+    /// The mean of each component.
+    Mean,
+    /// The fitness of the worst sample, as judged by the weighting.
+    Worst,
+}
+
+impl Default for SampleAggregation {
+    fn default() -> Self {
+        Self::Mean
+    }
 }
 
+/// An address given in the config, either as a number or as the name of a
+/// symbol in the binary, optionally with an offset, as in `"system+0x10"`.
+/// Symbols are resolved once the binary has been loaded.
+#[cfg(feature = "emulator")]
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[serde(untagged)]
+pub enum Address {
+    Literal(u64),
+    Symbol(String),
+}
+
+#[cfg(feature = "emulator")]
+impl Default for Address {
+    fn default() -> Self {
+        Self::Literal(0)
+    }
+}
+
+#[cfg(feature = "emulator")]
+impl Address {
+    pub fn resolve(&self) -> Result<u64, Error> {
+        match self {
+            Self::Literal(addr) => Ok(*addr),
+            Self::Symbol(symbol) => crate::emulator::loader::resolve_symbol(symbol),
+        }
+    }
+}
+
+/// The `size` bytes of memory beginning at `address`, which may be given
+/// as a symbol.
+#[cfg(feature = "emulator")]
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+pub struct Watchpoint {
+    pub address: Address,
+    #[serde(default = "default_watchpoint_size")]
+    pub size: usize,
+}
+
+#[cfg(feature = "emulator")]
+const fn default_watchpoint_size() -> usize {
+    1
+}
+
+#[cfg(feature = "emulator")]
+impl Watchpoint {
+    /// The first address watched, and one past the last. A watchpoint
+    /// that reaches the top of the address space, leaving no address past
+    /// its last, is an error.
+    pub fn resolve(&self) -> Result<(u64, u64), Error> {
+        let start = self.address.resolve()?;
+        let end = start.checked_add(self.size.max(1) as u64).ok_or_else(|| {
+            Error::Misc(format!(
+                "The watchpoint of {} bytes at 0x{:x} runs past the top of the address space",
+                self.size, start
+            ))
+        })?;
+        Ok((start, end))
+    }
+}
+
+/// What the block log records of each run.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+pub enum BlockLogMode {
+    /// Every block executed, in order.
+    Full,
+    /// Each block only the first time it's entered from a given
+    /// predecessor, so that loops are recorded once rather than once per
+    /// iteration. The set of blocks visited is preserved, but consecutive
+    /// blocks in the path are no longer necessarily consecutive in
+    /// execution.
+    Edges,
+}
+
+impl Default for BlockLogMode {
+    fn default() -> Self {
+        Self::Full
+    }
+}
+
+/// How the initial values of the registers are drawn, when
+/// `randomize_registers` is set.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum RegisterDistribution {
+    /// Uniformly, over every possible word.
+    FullRange,
+    /// Uniformly, from `0` to `max` inclusive.
+    SmallIntegers { max: u64 },
+    /// Uniformly, from the addresses in the loaded segments.
+    MappedAddresses,
+    /// From a distribution given for each register, by name. Registers
+    /// that aren't listed are drawn from the full range.
+    PerRegister(HashMap<String, RegisterDistribution>),
+}
+
+impl Default for RegisterDistribution {
+    fn default() -> Self {
+        Self::FullRange
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+pub enum DisassemblySyntax {
+    Intel,
+    Att,
+}
+
+impl Default for DisassemblySyntax {
+    fn default() -> Self {
+        Self::Intel
+    }
+}
+
+/// Controls where the emulator's stack is mapped, and how the packed
+/// payload is laid onto it.
+///
+/// The chain is packed into bytes according to the architecture's word
+/// size and endianness (see `emulator::pack::Pack`), and written to the
+/// stack beginning at `address + payload_offset`. The first word is then
+/// "popped" into the program counter, and the stack pointer is left
+/// pointing at the second word, as if a `ret` had just been executed.
+/// Any part of the packed chain that would run past the end of the stack
+/// is truncated.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct StackConfig {
+    /// The address at which to map the stack. If `None`, the stack is
+    /// placed just above the highest loaded segment.
+    #[serde(default)]
+    pub address: Option<u64>,
+    /// The size of the stack region. If `None`, `emulator_stack_size` is used.
+    #[serde(default)]
+    pub size: Option<usize>,
+    /// The offset from the base of the stack at which the payload is written.
+    #[serde(default = "default_payload_offset")]
+    pub payload_offset: u64,
+}
+
+const fn default_payload_offset() -> u64 {
+    0x100
+}
+
+impl Default for StackConfig {
+    fn default() -> Self {
+        Self {
+            address: None,
+            size: None,
+            payload_offset: default_payload_offset(),
+        }
+    }
+}
+
+/// Changes the permissions of every loaded segment that overlaps the
+/// range from `start` up to `end`. A segment is mapped with a single set
+/// of permissions, so the change applies to the whole of it, and not just
+/// to the part in range. The permissions in `add` are granted before those
+/// in `remove` are taken away. Each is written as in the segment log, e.g.
+/// `"RW"`.
+///
+/// This can be used to model mitigations, like a non-executable stack, or
+/// to make a read-only region writeable when testing write objectives.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct PermissionOverride {
+    /// If `None`, the range has no lower bound.
+    #[serde(default)]
+    pub start: Option<u64>,
+    /// If `None`, the range has no upper bound.
+    #[serde(default)]
+    pub end: Option<u64>,
+    #[serde(default)]
+    pub add: String,
+    #[serde(default)]
+    pub remove: String,
+}
+
+/// The constraints a gadget must satisfy to be admitted to the soup. A
+/// gadget runs from its address up to the first instruction that transfers
+/// control. It's admitted only if that instruction is a return, or a call,
+/// when `allow_calls` is set, and only if it's within both the length
+/// limits. Gadgets that end in jumps or interrupts are never admitted.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct GadgetFilter {
+    /// The most instructions a gadget may have, counting the one that ends
+    /// it. Defaults to 16.
+    #[serde(default)]
+    pub max_insts: Option<usize>,
+    /// The most bytes a gadget may span. Defaults to 64.
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+    #[serde(default)]
+    pub allow_calls: bool,
+    /// Mnemonics, like `"hlt"` or `"int3"`, that may not appear anywhere
+    /// in the gadget.
+    #[serde(default)]
+    pub forbidden_mnemonics: Vec<String>,
+}
+
+#[cfg(feature = "emulator")]
 impl RoperConfig {
+    /// Check for settings that are only honoured by some of the ROPER
+    /// modes.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.use_push && self.num_samples() > 1 {
+            return Err(Error::Misc(
+                "roper.resample is only supported for bare chains, not with roper.use_push"
+                    .to_string(),
+            ));
+        }
+        if self.gadget_catalog_path.is_some()
+            && (!self.discover_gadgets || self.gadget_file.is_some())
+        {
+            return Err(Error::Misc(
+                "roper.gadget_catalog_path is only written when the gadgets are discovered, with roper.discover_gadgets set and no roper.gadget_file"
+                    .to_string(),
+            ));
+        }
+        if self.gadget_filter.is_some() && self.arch != unicorn::Arch::X86 {
+            return Err(Error::Misc(format!(
+                "roper.gadget_filter only recognizes the ends of x86 gadgets, not {:?} ones",
+                self.arch
+            )));
+        }
+        Ok(())
+    }
+
+    /// The number of times each chain is executed.
+    pub fn num_samples(&self) -> usize {
+        self.resample.max(1)
+    }
+
     pub fn parse_register_patterns(&mut self) {
         if let Some(ref pat_file) = self.register_pattern_file {
             let ps = parse_register_pattern_file(pat_file)
                 .expect("Failed to parse register pattern file");
-            log::info!("Parsed and reduced register patterns: {:#x?}", ps);
+            log::info!(
+                target: CONFIG,
+                "Parsed and reduced register patterns: {:#x?}",
+                ps
+            );
             self.parsed_register_patterns = ps;
         }
     }
 
+    /// Parse the `bad_bytes` substitution table into the form expected by `Pack`.
+    pub fn byte_filter(&self) -> Option<HashMap<u8, u8>> {
+        self.bad_bytes.as_ref().map(|table| {
+            table
+                .iter()
+                .map(|(k, v)| {
+                    let k = u8::from_str_radix(k.trim_start_matches("0x"), 16)
+                        .expect("Failed to parse bad_bytes key as a hex byte");
+                    (k, *v)
+                })
+                .collect::<HashMap<u8, u8>>()
+        })
+    }
+
+    pub fn stack_size(&self) -> usize {
+        self.stack.size.unwrap_or(self.emulator_stack_size)
+    }
+
     pub fn register_patterns(&self) -> &[RegisterPattern] {
         &self.parsed_register_patterns
     }
@@ -343,26 +1406,52 @@ impl RoperConfig {
                 set.insert(r.clone());
             }
         }
-        set.into_iter().collect::<Vec<String>>()
+        if self.read_all_registers {
+            let all = crate::util::architecture::general_purpose_registers(self.arch, self.mode);
+            if all.is_empty() {
+                log::warn!(
+                    target: CONFIG,
+                    "The general-purpose registers of {:?} {:?} aren't known, so only the listed registers will be read",
+                    self.arch,
+                    self.mode
+                );
+            }
+            set.extend(all.into_iter().map(String::from));
+        }
+        // sorted, so that the registers are listed in the same order on
+        // every run
+        let mut registers = set.into_iter().collect::<Vec<String>>();
+        registers.sort();
+        registers
     }
 }
 
+#[cfg(feature = "emulator")]
 fn default_num_workers() -> usize {
     num_cpus::get()
 }
 
+#[cfg(feature = "emulator")]
 fn default_num_emu() -> usize {
     default_num_workers() + 1
 }
 
+#[cfg(feature = "emulator")]
 const fn default_wait_limit() -> u64 {
     200
 }
 
+#[cfg(feature = "emulator")]
 const fn default_stack_size() -> usize {
     0x1000
 }
 
+#[cfg(feature = "emulator")]
+const fn default_gadget_search_window() -> usize {
+    16
+}
+
+#[cfg(feature = "emulator")]
 impl Default for RoperConfig {
     fn default() -> Self {
         Self {
@@ -370,7 +1459,9 @@ impl Default for RoperConfig {
             gadget_file: None,
             output_registers: vec![],
             input_registers: vec![],
+            read_all_registers: false,
             randomize_registers: false,
+            register_distribution: RegisterDistribution::default(),
             register_pattern_file: None,
             parsed_register_patterns: vec![],
             soup: None,
@@ -378,19 +1469,49 @@ impl Default for RoperConfig {
             arch: unicorn::Arch::X86,
             mode: unicorn::Mode::MODE_64,
             memory_pattern: None,
+            syscall_number: None,
+            target_address: None,
+            bad_regions: vec![],
+            register_tolerance: Tolerance::default(),
             num_workers: 8,
             num_emulators: 8,
             wait_limit: 500,
             max_emu_steps: Some(0x10_000),
+            max_log_entries: None,
+            max_deref_depth: None,
             millisecond_timeout: Some(500),
             record_basic_blocks: false,
+            block_log_mode: BlockLogMode::default(),
+            record_register_snapshots: false,
+            count_mnemonics: vec![],
+            return_address: Address::default(),
+            entry_point: None,
             record_memory_writes: false,
+            record_write_order: false,
+            read_watchpoints: vec![],
             emulator_stack_size: 0x1000,
             binary_path: "/bin/sh".to_string(),
             ld_paths: None,
             bad_bytes: None,
+            forbidden_bytes: vec![],
             break_on_calls: false,
             monitor_stack_writes: false,
+            stack: StackConfig::default(),
+            segment_permissions: vec![],
+            gadget_filter: None,
+            discover_gadgets: false,
+            gadget_search_window: default_gadget_search_window(),
+            gadget_catalog_path: None,
+            max_chain_length: None,
+            chain_length_penalty: None,
+            model_stack_deltas: false,
+            prefilter: false,
+            resample: 0,
+            resample_aggregation: SampleAggregation::default(),
+            resample_registers: false,
+            stack_deltas: None,
+            output_payload_path: None,
+            disassembly_syntax: DisassemblySyntax::default(),
         }
     }
 }
@@ -416,12 +1537,15 @@ impl Ord for ClassificationProblem {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Selection {
     Tournament,
     Roulette,
     Metropolis,
     Lexicase,
+    Alps,
+    MapElites,
+    Coevolution,
 }
 
 impl Default for Selection {
@@ -433,6 +1557,63 @@ impl Default for Selection {
 #[derive(Clone, Debug, Serialize)]
 pub enum Problem {
     Classification(ClassificationProblem),
+    #[cfg(feature = "emulator")]
     RegisterSpecification(RegisterPattern),
     MemoryPattern(Vec<u8>),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+        config.restart = Some(RestartConfig {
+            stagnation_generations: 10,
+            keep: 2,
+        });
+        assert!(config.validate().is_ok());
+        config.selection = Selection::Alps;
+        assert!(config.validate().is_err());
+    }
+
+    #[cfg(feature = "emulator")]
+    #[test]
+    fn test_resolve_watchpoint() {
+        let watchpoint = |address: u64, size: usize| Watchpoint {
+            address: Address::Literal(address),
+            size,
+        };
+        assert_eq!(watchpoint(0x1000, 0).resolve().unwrap(), (0x1000, 0x1001));
+        assert_eq!(watchpoint(0x1000, 8).resolve().unwrap(), (0x1000, 0x1008));
+        assert!(watchpoint(std::u64::MAX - 7, 8).resolve().is_err());
+        assert!(watchpoint(std::u64::MAX, 1).resolve().is_err());
+    }
+
+    #[cfg(feature = "emulator")]
+    #[test]
+    fn test_validate_roper() {
+        let mut config = Config::default();
+        config.roper.resample = 4;
+        assert!(config.validate().is_ok());
+        config.roper.use_push = true;
+        assert!(config.validate().is_err());
+        config.roper.resample = 1;
+        assert!(config.validate().is_ok());
+        config.selection = Selection::Coevolution;
+        assert!(config.validate().is_err());
+        config.roper.use_push = false;
+        assert!(config.validate().is_ok());
+        config.roper.gadget_filter = Some(GadgetFilter::default());
+        assert!(config.validate().is_ok());
+        config.roper.arch = unicorn::Arch::ARM;
+        assert!(config.validate().is_err());
+        config.roper.arch = unicorn::Arch::X86;
+        config.roper.gadget_catalog_path = Some("catalog.txt".to_string());
+        assert!(config.validate().is_err());
+        config.roper.discover_gadgets = true;
+        assert!(config.validate().is_ok());
+    }
+}
@@ -0,0 +1,195 @@
+//! A live summary of the run, redrawn in place on the terminal at the end
+//! of each epoch, for interactive use in place of a scrolling log. It's
+//! enabled by building with the `dashboard` feature and setting
+//! `observer.dashboard = true`, and it wraps the observer's report
+//! function, so the usual statistics are still written to the data
+//! directory.
+//!
+//! Every island's observer shares the one table, with a row per island.
+//! Log messages are still written to stderr, so it's best to leave
+//! `BERBALANG_LOG` at `warn` or lower while the dashboard is up.
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::configure::Config;
+use crate::evolution::{Genome, Phenome};
+use crate::logger::OBSERVER;
+use crate::observer::{ReportFn, Window};
+
+/// The latest summary of an island's observation window.
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub epoch: usize,
+    /// The number of specimens observed so far.
+    pub observed: usize,
+    pub best: Option<f64>,
+    pub mean: Option<f64>,
+    /// The fraction of alleles in the window that are distinct.
+    pub diversity: f64,
+    pub evals_per_sec: Option<f64>,
+    reported_at: Instant,
+}
+
+static ROWS: Mutex<BTreeMap<usize, Row>> = Mutex::new(BTreeMap::new());
+
+impl Row {
+    fn from_window<P: Phenome + Genome + 'static>(
+        window: &Window<P>,
+        counter: usize,
+        previous: Option<&Row>,
+    ) -> Self {
+        let weighting = &window.config.fitness.weighting;
+        let best = window
+            .best
+            .as_ref()
+            .and_then(|b| b.scalar_fitness(weighting));
+        let fitnesses = window.fitness_distribution();
+        let mean = if fitnesses.is_empty() {
+            None
+        } else {
+            Some(fitnesses.iter().sum::<f64>() / fitnesses.len() as f64)
+        };
+        let soup = window.soup();
+        let num_alleles = soup.values().sum::<usize>();
+        let diversity = if num_alleles == 0 {
+            0.0
+        } else {
+            soup.len() as f64 / num_alleles as f64
+        };
+        let reported_at = Instant::now();
+        let evals_per_sec = previous.and_then(|p| {
+            let secs = reported_at.duration_since(p.reported_at).as_secs_f64();
+            if secs > 0.0 {
+                Some(counter.saturating_sub(p.observed) as f64 / secs)
+            } else {
+                None
+            }
+        });
+        Self {
+            epoch: window.get_local_epoch(),
+            observed: counter,
+            best,
+            mean,
+            diversity,
+            evals_per_sec,
+            reported_at,
+        }
+    }
+}
+
+fn cell(value: Option<f64>) -> String {
+    value.map_or_else(|| "-".to_string(), |v| format!("{:.4}", v))
+}
+
+/// The hit rate of the emulator's block disassembly cache, when there is
+/// one.
+fn cache_hit_rate() -> Option<f64> {
+    #[cfg(feature = "emulator")]
+    {
+        crate::emulator::profiler::disassembly_cache_hit_rate()
+    }
+    #[cfg(not(feature = "emulator"))]
+    {
+        None
+    }
+}
+
+/// Render the table, with a header naming the population and the global
+/// epoch.
+pub fn render(
+    rows: &BTreeMap<usize, Row>,
+    population_name: &str,
+    cache_hit_rate: Option<f64>,
+) -> String {
+    let mut s = String::new();
+    writeln!(
+        s,
+        "{} | epoch {} | up {:?} | cache hit rate {}",
+        population_name,
+        crate::get_epoch_counter(),
+        crate::uptime(),
+        cell(cache_hit_rate),
+    )
+    .unwrap();
+    writeln!(
+        s,
+        "{:>6} {:>8} {:>10} {:>12} {:>12} {:>10} {:>10}",
+        "island", "epoch", "observed", "best", "mean", "diversity", "evals/s"
+    )
+    .unwrap();
+    for (island, row) in rows.iter() {
+        writeln!(
+            s,
+            "{:>6} {:>8} {:>10} {:>12} {:>12} {:>10.4} {:>10}",
+            island,
+            row.epoch,
+            row.observed,
+            cell(row.best),
+            cell(row.mean),
+            row.diversity,
+            row.evals_per_sec
+                .map_or_else(|| "-".to_string(), |v| format!("{:.1}", v)),
+        )
+        .unwrap();
+    }
+    s
+}
+
+/// Wrap a report function so that it also updates the island's row of the
+/// dashboard, and redraws it.
+pub fn wrap<P: Phenome + Genome + 'static>(report_fn: ReportFn<P>) -> ReportFn<P> {
+    Box::new(move |window: &Window<P>, counter: usize, config: &Config| {
+        report_fn(window, counter, config);
+        let table = match ROWS.lock() {
+            Ok(mut rows) => {
+                let row = Row::from_window(window, counter, rows.get(&config.island_id));
+                rows.insert(config.island_id, row);
+                render(&rows, &config.observer.population_name, cache_hit_rate())
+            }
+            Err(e) => {
+                log::error!(target: OBSERVER, "Failed to update the dashboard: {:?}", e);
+                return;
+            }
+        };
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        // clear the screen and move the cursor home before redrawing
+        let _ = write!(out, "\x1b[2J\x1b[H{}", table);
+        let _ = out.flush();
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut rows = BTreeMap::new();
+        for island in 0..3 {
+            rows.insert(
+                island,
+                Row {
+                    epoch: 7,
+                    observed: 700,
+                    best: Some(0.5),
+                    mean: None,
+                    diversity: 0.25,
+                    evals_per_sec: Some(120.0),
+                    reported_at: Instant::now(),
+                },
+            );
+        }
+        let table = render(&rows, "tapir", Some(0.75));
+        let lines = table.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("tapir"));
+        assert!(lines[0].ends_with("0.7500"));
+        assert!(lines[4].trim_start().starts_with('2'));
+        assert!(lines[4].contains("0.5000"));
+        assert!(lines[4].contains("120.0"));
+    }
+}
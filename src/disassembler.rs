@@ -1,7 +1,9 @@
 use std::fmt;
 
-use capstone::{Capstone, Insn, InsnDetail, Instructions, NO_EXTRA_MODE};
+use capstone::{Capstone, Insn, InsnDetail, Instructions, Syntax, NO_EXTRA_MODE};
+use serde::{Deserialize, Serialize};
 
+use crate::configure::DisassemblySyntax;
 use crate::emulator::loader;
 
 pub struct Disassembler(pub Capstone);
@@ -29,16 +31,23 @@ impl From<capstone::Error> for Error {
 }
 
 impl Disassembler {
-    pub fn new(arch: unicorn::Arch, mode: unicorn::Mode) -> Result<Self, Error> {
+    /// The syntax only affects x86 disassembly, and is ignored for other
+    /// architectures.
+    pub fn new(
+        arch: unicorn::Arch,
+        mode: unicorn::Mode,
+        syntax: DisassemblySyntax,
+    ) -> Result<Self, Error> {
+        let is_x86 = arch == unicorn::Arch::X86;
         let arch = convert_arch(arch);
         let mode = convert_mode(mode);
-        Capstone::new_raw(arch, mode, NO_EXTRA_MODE, None)
-            .map(|mut c| {
-                c.set_detail(true)
-                    .expect("Failed to set detail option on disassembler");
-                Self(c)
-            })
-            .map_err(Error::from)
+        let mut c = Capstone::new_raw(arch, mode, NO_EXTRA_MODE, None)?;
+        c.set_detail(true)
+            .expect("Failed to set detail option on disassembler");
+        if is_x86 {
+            c.set_syntax(convert_syntax(syntax))?;
+        }
+        Ok(Self(c))
     }
 
     pub fn disas(
@@ -59,6 +68,25 @@ impl Disassembler {
         self.0.insn_detail(insn).map_err(Error::from)
     }
 
+    /// Classify an instruction, using capstone's generic instruction groups
+    /// to pick out control flow, interrupts and privileged instructions,
+    /// and its mnemonic for everything else.
+    pub fn categorize(&self, insn: &Insn) -> InstructionCategory {
+        if let Ok(detail) = self.insn_detail(insn) {
+            for group in detail.groups() {
+                match self.0.group_name(group).as_deref() {
+                    Some("jump") | Some("call") | Some("ret") | Some("branch_relative") => {
+                        return InstructionCategory::ControlFlow
+                    }
+                    Some("int") | Some("iret") => return InstructionCategory::Interrupt,
+                    Some("privilege") => return InstructionCategory::Privileged,
+                    _ => {}
+                }
+            }
+        }
+        categorize_by_mnemonic(insn.mnemonic().unwrap_or(""), insn.op_str().unwrap_or(""))
+    }
+
     pub fn disas_from_mem_image(
         &self,
         start: u64,
@@ -84,6 +112,78 @@ impl Disassembler {
     }
 }
 
+/// A coarse classification of what an instruction does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum InstructionCategory {
+    ControlFlow,
+    Interrupt,
+    Privileged,
+    Stack,
+    Memory,
+    Arithmetic,
+    Logic,
+    DataMovement,
+    Other,
+}
+
+/// Mnemonic stems for each category, matched as prefixes so that AT&T
+/// size suffixes (`addl`, `movq`, ...) are covered too.
+const STACK_STEMS: &[&str] = &["push", "pop", "leave", "enter"];
+const ARITHMETIC_STEMS: &[&str] = &[
+    "add", "adc", "sub", "sbb", "inc", "dec", "neg", "mul", "imul", "div", "idiv", "cmp", "lea",
+];
+const LOGIC_STEMS: &[&str] = &[
+    "and", "or", "xor", "not", "test", "shl", "shr", "sal", "sar", "rol", "ror", "rcl", "rcr", "bt",
+];
+const DATA_MOVEMENT_STEMS: &[&str] = &[
+    "mov", "cmov", "xchg", "set", "bswap", "cbw", "cwd", "cdq", "cqo",
+];
+
+/// Classify an instruction by its mnemonic and operands. Instructions that
+/// touch memory, other than through the stack or `lea`, count as memory
+/// instructions, whatever else they do.
+pub fn categorize_by_mnemonic(mnemonic: &str, op_str: &str) -> InstructionCategory {
+    let stem_of = |stems: &[&str]| stems.iter().any(|s| mnemonic.starts_with(s));
+    // Intel syntax brackets memory operands, AT&T parenthesizes them
+    let touches_memory = op_str.contains('[') || op_str.contains('(');
+    if stem_of(STACK_STEMS) {
+        InstructionCategory::Stack
+    } else if touches_memory && !mnemonic.starts_with("lea") {
+        InstructionCategory::Memory
+    } else if stem_of(ARITHMETIC_STEMS) {
+        InstructionCategory::Arithmetic
+    } else if stem_of(LOGIC_STEMS) {
+        InstructionCategory::Logic
+    } else if stem_of(DATA_MOVEMENT_STEMS) {
+        InstructionCategory::DataMovement
+    } else {
+        InstructionCategory::Other
+    }
+}
+
+/// Whether an executed instruction's mnemonic is the one given, ignoring
+/// case, and any AT&T size suffix on the executed mnemonic, so that
+/// `call` matches `callq` and `mov` matches `movl`. Only the suffix is
+/// ignored, so that `mov` doesn't match `movsb`.
+pub fn mnemonic_matches(executed: &str, mnemonic: &str) -> bool {
+    if executed.eq_ignore_ascii_case(mnemonic) {
+        return true;
+    }
+    match executed.char_indices().last() {
+        Some((i, suffix)) if "bwlq".contains(suffix.to_ascii_lowercase()) => {
+            executed[..i].eq_ignore_ascii_case(mnemonic)
+        }
+        _ => false,
+    }
+}
+
+fn convert_syntax(syntax: DisassemblySyntax) -> Syntax {
+    match syntax {
+        DisassemblySyntax::Intel => Syntax::Intel,
+        DisassemblySyntax::Att => Syntax::Att,
+    }
+}
+
 fn convert_arch(arch: unicorn::Arch) -> capstone::Arch {
     use capstone::Arch as C;
     use unicorn::Arch as U;
@@ -112,3 +212,88 @@ fn convert_mode(mode: unicorn::Mode) -> capstone::Mode {
         _ => unimplemented!("i'll do it later"),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_disassembly_syntax() {
+        // mov eax, 1; ret
+        let code = [0xb8, 0x01, 0x00, 0x00, 0x00, 0xc3];
+        let intel = Disassembler::new(
+            unicorn::Arch::X86,
+            unicorn::Mode::MODE_32,
+            DisassemblySyntax::Intel,
+        )
+        .unwrap();
+        let att = Disassembler::new(
+            unicorn::Arch::X86,
+            unicorn::Mode::MODE_32,
+            DisassemblySyntax::Att,
+        )
+        .unwrap();
+
+        let insts = intel.disas(&code, 0x1000, None).unwrap();
+        let mov = insts.iter().next().unwrap();
+        assert_eq!(mov.mnemonic(), Some("mov"));
+        assert_eq!(mov.op_str(), Some("eax, 1"));
+
+        let insts = att.disas(&code, 0x1000, None).unwrap();
+        let mov = insts.iter().next().unwrap();
+        assert_eq!(mov.mnemonic(), Some("movl"));
+        assert_eq!(mov.op_str(), Some("$1, %eax"));
+    }
+
+    #[test]
+    fn test_mnemonic_matches() {
+        assert!(mnemonic_matches("call", "call"));
+        assert!(mnemonic_matches("callq", "call"));
+        assert!(mnemonic_matches("movl", "MOV"));
+        assert!(mnemonic_matches("SYSCALL", "syscall"));
+        assert!(!mnemonic_matches("movsb", "mov"));
+        assert!(!mnemonic_matches("call", "callq"));
+        assert!(!mnemonic_matches("ret", "call"));
+    }
+
+    #[test]
+    fn test_categorize() {
+        use InstructionCategory::*;
+
+        // push rbx; add rax, rbx; xor eax, eax; mov rax, [rsp+8];
+        // lea rax, [rsp+8]; mov rdi, rax; int 0x80; call rax; ret
+        let code = [
+            0x53, 0x48, 0x01, 0xd8, 0x31, 0xc0, 0x48, 0x8b, 0x44, 0x24, 0x08, 0x48, 0x8d, 0x44,
+            0x24, 0x08, 0x48, 0x89, 0xc7, 0xcd, 0x80, 0xff, 0xd0, 0xc3,
+        ];
+        let disassembler = Disassembler::new(
+            unicorn::Arch::X86,
+            unicorn::Mode::MODE_64,
+            DisassemblySyntax::Intel,
+        )
+        .unwrap();
+        let insts = disassembler.disas(&code, 0x1000, None).unwrap();
+        let categories = insts
+            .iter()
+            .map(|i| disassembler.categorize(&i))
+            .collect::<Vec<InstructionCategory>>();
+        assert_eq!(
+            categories,
+            vec![
+                Stack,
+                Arithmetic,
+                Logic,
+                Memory,
+                Arithmetic,
+                DataMovement,
+                Interrupt,
+                ControlFlow,
+                ControlFlow
+            ]
+        );
+
+        assert_eq!(categorize_by_mnemonic("addl", "$8, %esp"), Arithmetic);
+        assert_eq!(categorize_by_mnemonic("movl", "4(%esp), %eax"), Memory);
+        assert_eq!(categorize_by_mnemonic("nop", ""), Other);
+    }
+}
@@ -4,23 +4,25 @@ use std::sync::{Arc, Mutex};
 use std::thread::{spawn, JoinHandle};
 use std::time::{Duration, Instant};
 
-//use rayon::{ThreadPoolBuilder, };
 //use indexmap::map::IndexMap;
+use crossbeam::queue::SegQueue;
 use hashbrown::HashMap;
 use object_pool::{Pool, Reusable};
 use rayon::prelude::*;
-use threadpool::ThreadPool;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use unicorn::{Context, Cpu, Mode};
 
 pub use crate::configure::RoperConfig;
+use crate::configure::{Address, Watchpoint};
 use crate::disassembler::Disassembler;
 use crate::emulator::hatchery::hooking::emu_prep_fn;
 use crate::emulator::loader;
 use crate::emulator::loader::Seg;
 use crate::emulator::pack::Pack;
 use crate::emulator::profiler::{Profile, Profiler};
-use crate::emulator::register_pattern::Register;
+use crate::emulator::register_pattern::{Register, DEFAULT_DEREF_DEPTH};
 use crate::error::Error;
+use crate::logger::EMULATOR;
 
 //use std::sync::atomic::{AtomicUsize, Ordering};
 
@@ -49,27 +51,36 @@ struct EmuPool<C: Cpu<'static>> {
 }
 
 impl<C: Cpu<'static>> EmuPool<C> {
-    pub fn new(config: &RoperConfig) -> Self {
+    pub fn new(config: &RoperConfig) -> Result<Self, Error> {
         let static_memory = loader::get_static_memory_image();
 
         let memory = Some(Pin::new(static_memory.segments().clone()));
 
-        let pool: Pool<C> = Pool::new(config.num_workers, || {
-            Self::init_emu(&config, &memory).expect("failed to initialize emulator")
+        // Build the emulators up front, so that a failure is returned to the
+        // caller, rather than panicking inside the pool's initializer.
+        let emus = (0..config.num_workers.max(1))
+            .map(|_| Self::init_emu(&config, &memory))
+            .collect::<Result<Vec<C>, Error>>()?;
+        let emus = Mutex::new(emus);
+        let pool: Pool<C> = Pool::new(config.num_workers.max(1), || {
+            emus.lock()
+                .unwrap()
+                .pop()
+                .expect("Ran out of prebuilt emulators")
         });
         let init_context = {
             let emu = Self::wait_for_emu(&pool, config.wait_limit, config.mode);
-            let ctx = (*emu).context_save().expect("Failed to save context");
+            let ctx = (*emu).context_save()?;
             ctx
         };
 
-        Self {
+        Ok(Self {
             pool,
             init_context,
             mode: config.mode,
             wait_limit: config.wait_limit,
             memory: Arc::new(memory),
-        }
+        })
     }
 
     /// Returns a reusable pointer to an emulator, which will be returned to the pool when it's
@@ -89,6 +100,7 @@ impl<C: Cpu<'static>> EmuPool<C> {
             // First, map the non-writeable segments to memory. These can be shared.
             segments.iter().for_each(|s| {
                 log::info!(
+                    target: EMULATOR,
                     "Mapping segment 0x{:x} - 0x{:x} {:?} [{:?}]",
                     s.aligned_start(),
                     s.aligned_end(),
@@ -120,6 +132,7 @@ impl<C: Cpu<'static>> EmuPool<C> {
         };
         emu.mem_regions()?.iter().for_each(|rgn| {
             log::info!(
+                target: EMULATOR,
                 "Mapped region: 0x{:x} - 0x{:x} [{:?}]",
                 rgn.begin,
                 rgn.end,
@@ -135,11 +148,16 @@ impl<C: Cpu<'static>> EmuPool<C> {
         loop {
             if let Some(c) = pool.try_pull() {
                 if wait_time > 0 {
-                    log::warn!("Waited {} milliseconds for CPU", wait_time);
+                    log::warn!(
+                        target: EMULATOR,
+                        "Waited {} milliseconds for CPU",
+                        wait_time
+                    );
                 }
                 return c;
             } else if wait_time > wait_limit {
                 log::warn!(
+                    target: EMULATOR,
                     "Waited {} milliseconds for CPU, creating new one",
                     wait_time
                 );
@@ -153,20 +171,56 @@ impl<C: Cpu<'static>> EmuPool<C> {
     }
 }
 
-type InboundTx<T, C> = SyncSender<(T, Option<HashMap<Register<C>, u64>>)>;
-type InboundRx<T, C> = Receiver<(T, Option<HashMap<Register<C>, u64>>)>;
-type OutboundTx = SyncSender<Profile>;
-type OutboundRx = Receiver<Profile>;
+// Each run is numbered, so that its profile can be matched up with it when
+// several are in flight at once, and carries the sender on which its profile
+// is to be returned, so that every caller receives only its own runs. A run
+// whose emulation panicked is returned without a profile.
+type Run<T, C> = (usize, T, Option<HashMap<Register<C>, u64>>, OutboundTx);
+type InboundTx<T, C> = SyncSender<Run<T, C>>;
+type InboundRx<T, C> = Receiver<Run<T, C>>;
+type OutboundTx = SyncSender<(usize, Option<Profile>)>;
+type OutboundRx = Receiver<(usize, Option<Profile>)>;
 type InboundChannel<T, C> = (InboundTx<T, C>, InboundRx<T, C>);
 type OutboundChannel = (OutboundTx, OutboundRx);
 
+/// Returns a run's profile to its caller when dropped, so that the caller
+/// hears back even if the emulation panics before finishing.
+struct Reply {
+    run: usize,
+    profile: Option<Profile>,
+    tx: OutboundTx,
+}
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        if self.profile.is_none() {
+            log::error!(target: EMULATOR, "Run {} ended without a profile", self.run);
+        }
+        // the caller may have stopped listening, as when a pipeline is
+        // dropped before it's drained, and that's fine
+        let _ = self.tx.send((self.run, self.profile.take()));
+    }
+}
+
+fn receive(rx: &OutboundRx) -> Result<(usize, Profile), Error> {
+    match rx.recv()? {
+        (run, Some(profile)) => Ok((run, profile)),
+        (run, None) => Err(Error::Misc(format!(
+            "The emulation of run {} panicked",
+            run
+        ))),
+    }
+}
+
 pub struct Hatchery<C: Cpu<'static> + Send> {
     emu_pool: Arc<EmuPool<C>>,
-    thread_pool: Arc<Mutex<ThreadPool>>,
+    /// The hatchery's own thread pool, in which the emulations run, along
+    /// with any parallel work they do, so that they neither compete for
+    /// rayon's global pool nor are held up by its other users.
+    thread_pool: Arc<ThreadPool>,
     config: Arc<RoperConfig>,
     memory: Arc<Option<Pin<Vec<Seg>>>>,
     tx: InboundTx<Vec<u64>, C>,
-    rx: OutboundRx,
     handle: JoinHandle<()>,
     disassembler: Arc<Disassembler>,
 }
@@ -174,14 +228,13 @@ pub struct Hatchery<C: Cpu<'static> + Send> {
 impl<C: Cpu<'static> + Send> Drop for Hatchery<C> {
     fn drop(&mut self) {
         // unmap the unwriteable memory in the emu pool's emus
-        log::debug!("Dropping Hatchery");
+        log::debug!(target: EMULATOR, "Dropping Hatchery");
         let Self {
             emu_pool,
             thread_pool: _thread_pool,
             config: _config,
             memory,
             tx: _tx,
-            rx: _rx,
             handle: _handle,
             disassembler: _disassembler,
         } = self;
@@ -197,6 +250,7 @@ impl<C: Cpu<'static> + Send> Drop for Hatchery<C> {
                     .filter(|&s| !s.is_writeable())
                     .for_each(|s| {
                         log::debug!(
+                            target: EMULATOR,
                             "Unmapping region 0x{:x} - 0x{:x} [{:?}]",
                             s.aligned_start(),
                             s.aligned_end(),
@@ -204,31 +258,61 @@ impl<C: Cpu<'static> + Send> Drop for Hatchery<C> {
                         );
                         //log::debug!("Unmapping segment at 0x{:x}", s.aligned_start());
                         emu.mem_unmap(s.aligned_start(), s.aligned_size())
-                            .unwrap_or_else(|e| log::error!("Failed to unmap segment: {:?}", e));
+                            .unwrap_or_else(|e| {
+                                log::error!(target: EMULATOR, "Failed to unmap segment: {:?}", e)
+                            });
                     });
             }
         }
     }
 }
 
+/// Check that a CPU of type `C` can be constructed in the configured mode,
+/// so that a bad architecture configuration can be reported at startup,
+/// rather than causing a panic in the middle of a run.
+pub fn check_cpu<C: Cpu<'static>>(config: &RoperConfig) -> Result<(), Error> {
+    C::new(config.mode).map(|_| ()).map_err(|e| {
+        Error::Misc(format!(
+            "Failed to construct a {:?} CPU in mode {:?}: {:?}",
+            config.arch, config.mode, e
+        ))
+    })
+}
+
 impl<C: 'static + Cpu<'static> + Send> Hatchery<C> {
+    /// Returns an error if the emulators can't be constructed, which is
+    /// usually a sign of a mismatch between `roper.arch`, `roper.mode`,
+    /// and the CPU type `C`.
     pub fn new(
         config: Arc<RoperConfig>,
         initial_register_state: Arc<HashMap<Register<C>, u64>>,
         output_registers: Arc<Vec<Register<C>>>,
-    ) -> Self {
-        let disassembler = Arc::new(
-            Disassembler::new(config.arch, config.mode).expect("Failed to build disassembler"),
-        );
+    ) -> Result<Self, Error> {
+        check_cpu::<C>(&config)?;
+        let disassembler = Arc::new(Disassembler::new(
+            config.arch,
+            config.mode,
+            config.disassembly_syntax,
+        )?);
         let (tx, our_rx): InboundChannel<Vec<u64>, C> = sync_channel(config.num_workers);
-        let (our_tx, rx): OutboundChannel = sync_channel(config.num_workers);
 
         let static_memory = loader::get_static_memory_image();
 
         let memory = Some(Pin::new(static_memory.segments().clone()));
 
-        let emu_pool = Arc::new(EmuPool::new(&config));
-        let thread_pool = Arc::new(Mutex::new(ThreadPool::new(config.num_workers)));
+        let emu_pool = Arc::new(EmuPool::new(&config)?);
+        let thread_pool = Arc::new(
+            ThreadPoolBuilder::new()
+                .num_threads(config.num_workers.max(1))
+                .thread_name(|i| format!("hatchery-{}", i))
+                .panic_handler(
+                    |_| log::error!(target: EMULATOR, "An emulation panicked in the hatchery"),
+                )
+                .build()
+                .map_err(|e| {
+                    Error::Misc(format!("Failed to build the hatchery's thread pool: {}", e))
+                })?,
+        );
 
         let millisecond_timeout = config.millisecond_timeout.unwrap_or(0);
         let max_emu_steps = config.max_emu_steps.unwrap_or(0);
@@ -240,21 +324,48 @@ impl<C: 'static + Cpu<'static> + Send> Hatchery<C> {
         let parameters = config.clone();
         let mem = memory.clone();
         let disas = disassembler.clone();
-        let bad_bytes: Arc<Option<HashMap<u8, u8>>> =
-            Arc::new(config.bad_bytes.as_ref().map(|table| {
-                table
-                    .iter()
-                    // FIXME do this in a less dirty, shotgunny way
-                    .map(|(k, v)| (u8::from_str_radix(k, 16).unwrap(), *v))
-                    .collect::<HashMap<u8, u8>>()
-            }));
+        let bad_bytes: Arc<Option<HashMap<u8, u8>>> = Arc::new(config.byte_filter());
+        let return_address = config
+            .return_address
+            .resolve()
+            .map_err(|e| Error::Misc(format!("Failed to resolve roper.return_address: {:?}", e)))?;
+        let entry_point = config
+            .entry_point
+            .as_ref()
+            .map(Address::resolve)
+            .transpose()
+            .map_err(|e| Error::Misc(format!("Failed to resolve roper.entry_point: {:?}", e)))?;
+        let target_address = config
+            .target_address
+            .as_ref()
+            .map(Address::resolve)
+            .transpose()
+            .map_err(|e| Error::Misc(format!("Failed to resolve roper.target_address: {:?}", e)))?;
+        let read_watchpoints = Arc::new(
+            config
+                .read_watchpoints
+                .iter()
+                .map(Watchpoint::resolve)
+                .collect::<Result<Vec<(u64, u64)>, Error>>()?,
+        );
+        let bad_regions = Arc::new(
+            config
+                .bad_regions
+                .iter()
+                .map(Watchpoint::resolve)
+                .collect::<Result<Vec<(u64, u64)>, Error>>()
+                .map_err(|e| {
+                    Error::Misc(format!("Failed to resolve roper.bad_regions: {:?}", e))
+                })?,
+        );
         let handle = spawn(move || {
-            for (payload, args) in our_rx.iter() {
+            for (run, payload, args, reply_tx) in our_rx.iter() {
                 let config = parameters.clone();
                 let bad_bytes = bad_bytes.clone();
-                let our_tx = our_tx.clone();
+                let read_watchpoints = read_watchpoints.clone();
+                let bad_regions = bad_regions.clone();
                 let output_registers = output_registers.clone();
-                let thread_pool = t_pool.lock().expect("Failed to unlock thread_pool mutex");
+                let thread_pool = t_pool.clone();
                 let emulator_pool = e_pool.clone();
                 let memory = mem.clone();
                 let initial_register_state = if let Some(args) = args {
@@ -264,11 +375,24 @@ impl<C: 'static + Cpu<'static> + Send> Hatchery<C> {
                 };
                 let disas = disas.clone();
                 // let's get a clean context to use here.
-                thread_pool.execute(move || {
+                thread_pool.spawn(move || {
+                    let mut reply = Reply {
+                        run,
+                        profile: None,
+                        tx: reply_tx,
+                    };
                     // Acquire an emulator from the pool.
                     let mut emu: Reusable<'_, C> = emulator_pool.pull();
                     // Initialize the profiler
                     let mut profiler = Profiler::new(&output_registers, &initial_register_state);
+                    profiler.log_limit = config.max_log_entries;
+                    profiler.deref_depth = config.max_deref_depth.unwrap_or(DEFAULT_DEREF_DEPTH);
+                    if config.record_write_order {
+                        profiler.ordered_write_log = Some(Arc::new(SegQueue::new()));
+                    }
+                    if config.record_register_snapshots {
+                        profiler.register_log = Some(Arc::new(SegQueue::new()));
+                    }
                     // load the inputs
                     for (reg, val) in initial_register_state.iter() {
                         emu.reg_write(*reg, *val).expect("Failed to load registers");
@@ -286,10 +410,10 @@ impl<C: 'static + Cpu<'static> + Send> Hatchery<C> {
                     }
 
                     let code = payload.pack(word_size, endian, (*bad_bytes).as_ref());
-                    let initial_pc = emu_prep_fn(&mut (*emu), &config, &code, &profiler).expect("Failure in the emulator preparation function.");
+                    let initial_pc = emu_prep_fn(&mut (*emu), &config, &code, return_address, entry_point, &profiler).expect("Failure in the emulator preparation function.");
 
                     if config.record_basic_blocks {
-                        let _hook = hooking::install_code_logging_hook(&mut (*emu), &profiler, &payload.as_code_addrs(word_size, endian), config.break_on_calls).expect("Failed to install code_logging_hook");
+                        let _hook = hooking::install_code_logging_hook(&mut (*emu), &profiler, &payload.as_code_addrs(word_size, endian), config.break_on_calls, config.block_log_mode, return_address).expect("Failed to install code_logging_hook");
                     }
 
                     // WONTFIX: It turns out that Unicorn never implemented a fetch hook. It's an unused enum in the C code. Balls.
@@ -305,6 +429,18 @@ impl<C: 'static + Cpu<'static> + Send> Hatchery<C> {
                         let _hooks = hooking::install_mem_write_hook(&mut (*emu), &profiler, config.monitor_stack_writes).expect("Failed to install mem_write_hook");
                     }
 
+                    if !config.count_mnemonics.is_empty() {
+                        let _hook = hooking::install_instruction_counting_hook(&mut (*emu), &profiler, &config.count_mnemonics).expect("Failed to install instruction counting hook");
+                    }
+
+                    if !read_watchpoints.is_empty() {
+                        let _hooks = hooking::install_read_watchpoint_hooks(&mut (*emu), &profiler, &read_watchpoints, endian).expect("Failed to install read watchpoint hooks");
+                    }
+
+                    if target_address.is_some() || !bad_regions.is_empty() {
+                        let _hooks = hooking::install_target_hooks(&mut (*emu), &profiler, target_address, &bad_regions).expect("Failed to install target hooks");
+                    }
+
                     ;
                     // If the preparation was successful, launch the emulator and execute
                     // the payload. We want to hang onto the exit code of this task.
@@ -341,29 +477,26 @@ impl<C: 'static + Cpu<'static> + Send> Hatchery<C> {
                             emu.mem_write(seg.aligned_start(),
                                           &seg.data,
                             ).unwrap_or_else(|e| {
-                                log::error!("Failed to refresh writeable memory at 0x{:x} - 0x{:x}: {:?}",
+                                log::error!(target: EMULATOR, "Failed to refresh writeable memory at 0x{:x} - 0x{:x}: {:?}",
                                 seg.aligned_start(), seg.aligned_end(), e
                             )
                             });
                         });
                     }
-                    let profile = profiler.into();
-                    // Now send the code back, along with its profile information.
-                    // (The genotype, along with its phenotype.)
-                    our_tx.send(profile).map_err(Error::from).expect("TX Failure in pipeline");
+                    // The profile is sent back when the reply is dropped.
+                    reply.profile = Some(profiler.into());
                 });
             }
         });
-        Self {
+        Ok(Self {
             emu_pool,
             thread_pool,
             config,
             memory: Arc::new(memory),
             tx,
-            rx,
             handle,
             disassembler,
-        }
+        })
     }
 
     pub fn execute(
@@ -371,8 +504,74 @@ impl<C: 'static + Cpu<'static> + Send> Hatchery<C> {
         payload: Vec<u64>,
         args: Option<HashMap<Register<C>, u64>>,
     ) -> Result<Profile, Error> {
-        self.tx.send((payload, args))?;
-        self.rx.recv().map_err(Error::from)
+        const RUN: usize = 0;
+        let (tx, rx): OutboundChannel = sync_channel(1);
+        self.tx.send((RUN, payload, args, tx))?;
+        match receive(&rx)? {
+            (RUN, profile) => Ok(profile),
+            (run, _) => Err(Error::Misc(format!(
+                "Expected the profile of run {}, but received that of run {}",
+                RUN, run
+            ))),
+        }
+    }
+
+    /// Execute a batch of runs concurrently, keeping up to
+    /// `roper.num_workers` of them in flight at once. The profile of each
+    /// run is yielded as soon as it completes, along with the run's index
+    /// in the batch, so the profiles may arrive out of order. The pipeline
+    /// has a channel of its own, so any runs still in flight when it's
+    /// dropped are discarded, rather than received by a later caller.
+    pub fn eval_pipeline<I>(&self, runs: I) -> Pipeline<'_, C, I::IntoIter>
+    where
+        I: IntoIterator<Item = (Vec<u64>, Option<HashMap<Register<C>, u64>>)>,
+    {
+        let (tx, rx): OutboundChannel = sync_channel(self.config.num_workers.max(1));
+        Pipeline {
+            hatchery: self,
+            runs: runs.into_iter().enumerate(),
+            in_flight: 0,
+            tx,
+            rx,
+        }
+    }
+}
+
+/// The profiles of a batch of runs, as they complete. See
+/// `Hatchery::eval_pipeline`.
+pub struct Pipeline<'a, C: Cpu<'static> + Send, I> {
+    hatchery: &'a Hatchery<C>,
+    runs: std::iter::Enumerate<I>,
+    in_flight: usize,
+    tx: OutboundTx,
+    rx: OutboundRx,
+}
+
+impl<'a, C, I> Iterator for Pipeline<'a, C, I>
+where
+    C: 'static + Cpu<'static> + Send,
+    I: Iterator<Item = (Vec<u64>, Option<HashMap<Register<C>, u64>>)>,
+{
+    type Item = Result<(usize, Profile), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let capacity = self.hatchery.config.num_workers.max(1);
+        while self.in_flight < capacity {
+            match self.runs.next() {
+                Some((run, (payload, args))) => {
+                    if let Err(e) = self.hatchery.tx.send((run, payload, args, self.tx.clone())) {
+                        return Some(Err(e.into()));
+                    }
+                    self.in_flight += 1;
+                }
+                None => break,
+            }
+        }
+        if self.in_flight == 0 {
+            return None;
+        }
+        self.in_flight -= 1;
+        Some(receive(&self.rx))
     }
 }
 // TODO: try to reduce the number of mutexes needed in this setup. it seems like a code smell.
@@ -397,10 +596,22 @@ pub mod tools {
             .map_err(Error::from)
     }
 
-    /// Returns the uppermost readable/writeable memory region, in the emulator's
-    /// memory map.
+    /// Returns the memory region holding the stack segment of the static
+    /// memory image, if there is one, or else the uppermost readable/writeable
+    /// memory region, in the emulator's memory map.
     pub fn find_stack<C: 'static + Cpu<'static>>(emu: &C) -> Option<MemRegion> {
         if let Ok(regions) = emu.mem_regions() {
+            if let Some(stack_seg) = loader::try_to_get_static_memory_image().and_then(|m| {
+                m.segments()
+                    .iter()
+                    .find(|s| s.segtype == loader::SegType::Stack)
+            }) {
+                if let Some(region) = regions.iter().find(|r| {
+                    r.begin <= stack_seg.aligned_start() && stack_seg.aligned_start() < r.end
+                }) {
+                    return Some(region.clone());
+                }
+            }
             let mut bottom = 0;
             let mut stack = None;
             for region in regions.iter() {
@@ -423,10 +634,17 @@ pub mod hooking {
     use hashbrown::HashSet;
     use unicorn::{CodeHookType, MemHookType, MemType, Protection};
 
+    use crate::configure::BlockLogMode;
+    use crate::disassembler::mnemonic_matches;
     use crate::emulator::hatchery::tools::find_stack;
     use crate::emulator::loader::get_static_memory_image;
-    use crate::emulator::profiler::{read_registers_in_hook, Block, MemLogEntry};
-    use crate::util::architecture::{endian, read_integer, word_size_in_bytes, Perms};
+    use crate::emulator::profiler::{
+        read_registers_in_hook, within_log_limit, Block, EdgeLog, MemLogEntry, ReadLogEntry,
+        Syscall,
+    };
+    use crate::util::architecture::{
+        endian, read_integer, word_size_in_bytes, write_integer, Endian, Perms,
+    };
 
     use super::*;
 
@@ -451,6 +669,26 @@ pub mod hooking {
         }
     }
 
+    /// The registers holding the syscall number and its first three
+    /// arguments, for the syscall instruction `inst_bytes`. Legacy `int 0x80`
+    /// syscalls use the 32-bit convention, even in 64-bit mode.
+    fn syscall_registers(
+        arch: unicorn::Arch,
+        mode: unicorn::Mode,
+        inst_bytes: &[u8],
+    ) -> Option<(i32, [i32; 3])> {
+        use unicorn::RegisterX86::*;
+
+        let legacy = inst_bytes.first() == Some(&0xCD);
+        match (arch, mode) {
+            (unicorn::Arch::X86, unicorn::Mode::MODE_64) if !legacy => {
+                Some((RAX as i32, [RDI as i32, RSI as i32, RDX as i32]))
+            }
+            (unicorn::Arch::X86, _) => Some((EAX as i32, [EBX as i32, ECX as i32, EDX as i32])),
+            _ => None,
+        }
+    }
+
     fn is_call(arch: unicorn::Arch, mode: unicorn::Mode, inst: &Insn<'_>) -> bool {
         // use unicorn::Arch::*;
         // use unicorn::Mode::*;
@@ -522,9 +760,10 @@ pub mod hooking {
                 .join(", ");
 
             if let Ok(disas) = disassembler.disas_from_mem_image(address, block_length as usize) {
-                log::trace!("\n{}\n{}", registers, disas);
+                log::trace!(target: EMULATOR, "\n{}\n{}", registers, disas);
             } else {
                 log::trace!(
+                    target: EMULATOR,
                     "\n{}\nUnable to disassemble 0x{:x} bytes at address 0x{:x}",
                     registers,
                     block_length,
@@ -542,41 +781,68 @@ pub mod hooking {
         profiler: &Profiler<C>,
     ) -> Result<Vec<unicorn::uc_hook>, unicorn::Error> {
         let gadget_log = profiler.gadget_log.clone();
+        let sequence = profiler.sequence.clone();
 
         let callback = move |_engine, _mem_type, address, _size, value| {
             log::warn!(
+                target: EMULATOR,
                 "mem: {:?}, address = 0x{:x}, size = 0x{:x}, value = 0x{:x}",
                 _mem_type,
                 address,
                 _size,
                 value
             );
-            gadget_log.push(address);
+            gadget_log.push((sequence.fetch_add(1, atomic::Ordering::Relaxed), address));
             false
         };
 
         mem_hook_by_prot(emu, MemHookType::MEM_FETCH, Protection::ALL, callback, true)
     }
 
+    /// Writes the packed payload onto the stack, at `config.stack.payload_offset`
+    /// bytes from its base, and then pops the first word into the program counter.
+    /// If an `entry_point` is given, nothing is popped, and emulation starts
+    /// there instead, with the stack pointer at the start of the payload.
     pub fn emu_prep_fn<C: 'static + Cpu<'static>>(
         emu: &mut C,
-        _config: &RoperConfig,
+        config: &RoperConfig,
         code: &[u8],
+        return_address: u64,
+        entry_point: Option<u64>,
         _profiler: &Profiler<C>,
     ) -> Result<u64, Error> {
         // now write the payload
         let stack = tools::find_stack(emu).expect("Can't find stack");
-        let pad = 0x100;
+        let pad = config.stack.payload_offset;
+        if stack.begin + pad >= stack.end {
+            return Err(Error::Misc(format!(
+                "Stack payload offset 0x{:x} exceeds stack size 0x{:x}",
+                pad,
+                stack.end - stack.begin
+            )));
+        }
         let sp = stack.begin + pad;
         let room = (stack.end - (stack.begin + pad)) as usize;
         let end = room.min(code.len());
         let payload = &code[0..end];
         emu.mem_write(sp, payload)?;
+        let word_size = word_size_in_bytes(emu.arch(), emu.mode());
+        let endian = endian(emu.arch(), emu.mode());
+        // plant the return address just past the payload, as a sentinel, so
+        // that a chain that runs out of gadgets returns there, rather than
+        // to whatever an earlier payload left on the stack
+        if end + word_size <= room {
+            let mut sentinel = vec![0_u8; word_size];
+            write_integer(endian, word_size, return_address, &mut sentinel);
+            emu.mem_write(sp + end as u64, &sentinel)?;
+        }
+        if let Some(entry_point) = entry_point {
+            emu.write_stack_pointer(sp)?;
+            return Ok(entry_point);
+        }
         // set the stack pointer to the middle of the stack
         // now "pop" the stack into the program counter
-        let word_size = word_size_in_bytes(emu.arch(), emu.mode());
         let a_bytes = emu.mem_read_as_vec(sp, word_size)?;
-        let endian = endian(emu.arch(), emu.mode());
         if let Some(address) = read_integer(&a_bytes, endian, word_size) {
             emu.write_stack_pointer(sp + word_size as u64)?;
 
@@ -591,6 +857,8 @@ pub mod hooking {
         profiler: &Profiler<C>,
         gadget_addrs: &[u64],
         break_on_calls: bool,
+        block_log_mode: BlockLogMode,
+        return_address: u64,
     ) -> Result<unicorn::uc_hook, unicorn::Error> {
         let memory = get_static_memory_image();
         // let stack_region: MemRegion = find_stack(emu).expect("Could not find stack");
@@ -603,17 +871,27 @@ pub mod hooking {
         let gadget_addrs: Arc<HashSet<u64>> = Arc::new(gadget_addrs.iter().cloned().collect());
         let block_log = profiler.trace_log.clone();
         let gadget_log = profiler.gadget_log.clone();
+        let register_log = profiler.register_log.clone();
+        let log_limit = profiler.log_limit;
+        let block_log_len = profiler.block_log_len.clone();
+        let gadget_log_len = profiler.gadget_log_len.clone();
+        let log_truncated = profiler.log_truncated.clone();
+        let edge_log = Mutex::new(EdgeLog::default());
+        let clean_exit = profiler.clean_exit.clone();
         let ret_count = profiler.ret_count.clone();
         let call_stack_depth = profiler.call_stack_depth.clone();
         let register_state = profiler.registers_at_last_ret.clone();
         let registers_to_read = Arc::new(profiler.registers_to_read.clone());
         let committed_write_log = profiler.committed_write_log.clone();
         let committed_trace_log = profiler.committed_trace_log.clone();
+        let committed_block_sequence = profiler.committed_block_sequence.clone();
+        let sequence = profiler.sequence.clone();
         let write_log = profiler.write_log.clone();
+        let syscall = profiler.syscall.clone();
         let sp: i32 = emu.stack_pointer().into();
 
         macro_rules! commit_logs {
-            ($engine: expr, $registers_to_read: expr => $register_state: expr, $write_log: expr => $committed_write_log: expr, $trace_log: expr => $committed_trace_log: expr) => {
+            ($engine: expr, $registers_to_read: expr => $register_state: expr, $write_log: expr => $committed_write_log: expr, $trace_log: expr => $committed_trace_log: expr, $committed_block_sequence: expr) => {
                 read_registers_in_hook::<C>(
                     ($register_state).clone(),
                     &($registers_to_read),
@@ -623,9 +901,13 @@ pub mod hooking {
                     .lock()
                     .unwrap()
                     .absorb_segqueue(&($write_log));
-                if let Ok(mut log) = $committed_trace_log.lock() {
-                    while let Ok(b) = $trace_log.pop() {
-                        log.push(b)
+                if let (Ok(mut log), Ok(mut seqs)) = (
+                    $committed_trace_log.lock(),
+                    $committed_block_sequence.lock(),
+                ) {
+                    while let Ok((seq, b)) = $trace_log.pop() {
+                        log.push(b);
+                        seqs.push(seq);
                     }
                 }
             };
@@ -638,10 +920,37 @@ pub mod hooking {
             //     .unwrap_or_default();
             let memory = get_static_memory_image();
 
+            // the sentinel can also be reached by a jump or call, if it's
+            // mapped
+            if entry == return_address {
+                clean_exit.store(true, atomic::Ordering::Relaxed);
+                engine.emu_stop().expect("Failed to stop emulator");
+                return;
+            }
+
+            let seq = sequence.fetch_add(1, atomic::Ordering::Relaxed);
             let block = Block { entry, size };
-            block_log.push(block);
-            if gadget_addrs.contains(&entry) {
-                gadget_log.push(entry);
+            let new_edge = match block_log_mode {
+                BlockLogMode::Full => true,
+                BlockLogMode::Edges => edge_log.lock().unwrap().first_visit(entry),
+            };
+            if new_edge && within_log_limit(log_limit, &block_log_len, &log_truncated) {
+                block_log.push((seq, block));
+            }
+            if gadget_addrs.contains(&entry)
+                && within_log_limit(log_limit, &gadget_log_len, &log_truncated)
+            {
+                gadget_log.push((seq, entry));
+                if let Some(log) = register_log.as_ref() {
+                    let registers = registers_to_read
+                        .iter()
+                        .map(|r| {
+                            let reg: i32 = (*r).into();
+                            (*r, engine.reg_read(reg).expect("Failed to read register!"))
+                        })
+                        .collect();
+                    log.push((seq, entry, registers));
+                }
             }
 
             if let Ok(inst) = engine.mem_read_as_vec(entry, size) {
@@ -661,13 +970,12 @@ pub mod hooking {
                         .ok()
                         .and_then(|v| read_integer(&v, endian, word_size))
                     {
-                        // We check to see if the return address is 0, too, because this is what we expect at
-                        // the end of a healthy rop chain execution.
-                        // TODO: use a magic word instead of 0? like 0xBAAD_F00D?
+                        // We check to see if this is the configured return address, too, because this is what
+                        // we expect at the end of a healthy rop chain execution.
                         // we could use a weaker restriction here, and just ensure that the addr is executable
                         // but no, then we wouldn't really be safeguarding composability
                         // more loosely, the ret marks a composable joint if the stack pointer points to writeable memory
-                        if addr == 0
+                        if addr == return_address
                             || (Some(true)
                                 == memory
                                     .perm_of_addr(stack_pointer)
@@ -682,10 +990,12 @@ pub mod hooking {
                                 call_stack_depth.fetch_sub(1, atomic::Ordering::Relaxed);
                             } else {
                                 ret_count.fetch_add(1, atomic::Ordering::Relaxed);
-                                commit_logs!(engine, registers_to_read => register_state, write_log => committed_write_log, block_log => committed_trace_log);
+                                commit_logs!(engine, registers_to_read => register_state, write_log => committed_write_log, block_log => committed_trace_log, committed_block_sequence);
                             }
-                            // Quietly stop the emulator if there's an attempt to return to 0
-                            if addr == 0 {
+                            // Quietly stop the emulator if there's an attempt to return to the
+                            // return address
+                            if addr == return_address {
+                                clean_exit.store(true, atomic::Ordering::Relaxed);
                                 engine.emu_stop().expect("Failed to stop emulator");
                             }
                         }
@@ -693,8 +1003,21 @@ pub mod hooking {
                 // it would be cool if we could save the context at each ret, so that we can rewind
                 // bad gadgets.
                 } else if is_syscall(arch, mode, &inst) {
+                    if let Some((number, args)) = syscall_registers(arch, mode, &inst) {
+                        let mut syscall = syscall.lock().unwrap();
+                        if syscall.is_none() {
+                            *syscall = Some(Syscall {
+                                address: entry,
+                                number: engine.reg_read(number).unwrap_or(0),
+                                args: args
+                                    .iter()
+                                    .map(|r| engine.reg_read(*r).unwrap_or(0))
+                                    .collect(),
+                            });
+                        }
+                    }
                     // Committing the logs at a syscall is one way to get trapped in a non-composable local optima.
-                    // commit_logs!(engine, registers_to_read => register_state, write_log => committed_write_log, block_log => committed_trace_log);
+                    // commit_logs!(engine, registers_to_read => register_state, write_log => committed_write_log, block_log => committed_trace_log, committed_block_sequence);
                     engine.emu_stop().expect("Failed to stop emulator");
                 } else {
                     // if not a RETURN
@@ -724,6 +1047,8 @@ pub mod hooking {
     ) -> Result<Vec<unicorn::uc_hook>, unicorn::Error> {
         let pc: i32 = emu.program_counter().into();
         let write_log = profiler.write_log.clone();
+        let ordered_write_log = profiler.ordered_write_log.clone();
+        let sequence = profiler.sequence.clone();
         let mem_write_callback =
             // TODO: we might want to track the # of unique addresses written to instead.
             move |engine: &unicorn::Unicorn<'_>, mem_type, address, num_bytes_written, value| {
@@ -735,8 +1060,12 @@ pub mod hooking {
                         address,
                         num_bytes_written,
                         value: value as u64,
+                        sequence: sequence.fetch_add(1, atomic::Ordering::Relaxed),
                     };
                     write_log.push(entry);
+                    if let Some(log) = ordered_write_log.as_ref() {
+                        log.push(entry);
+                    }
                     false // means "handled"
                 } else {
                     false
@@ -754,6 +1083,134 @@ pub mod hooking {
         Ok(hooks)
     }
 
+    /// Count the executed instructions whose mnemonics are among those
+    /// given, ignoring case and AT&T size suffixes. The counts are keyed by
+    /// the given mnemonics, in lower case. Each address is only
+    /// disassembled the first time it's executed, so an instruction that's
+    /// overwritten afterwards is still counted as what it was.
+    pub fn install_instruction_counting_hook<C: 'static + Cpu<'static>>(
+        emu: &mut C,
+        profiler: &Profiler<C>,
+        mnemonics: &[String],
+    ) -> Result<unicorn::uc_hook, unicorn::Error> {
+        let counts = profiler.instruction_counts.clone();
+        let mnemonics = mnemonics
+            .iter()
+            .map(|m| m.to_ascii_lowercase())
+            .collect::<Vec<String>>();
+        let mut known: HashMap<u64, Option<usize>> = HashMap::new();
+        let callback = move |engine: &unicorn::Unicorn<'_>, address: u64, size: u32| {
+            let index = *known.entry(address).or_insert_with(|| {
+                let memory = get_static_memory_image();
+                let mnemonic = engine
+                    .mem_read_as_vec(address, size as usize)
+                    .ok()
+                    .and_then(|bytes| {
+                        memory.disassemble_bytes(&bytes).and_then(|insts| {
+                            insts
+                                .iter()
+                                .next()
+                                .and_then(|i| i.mnemonic().map(String::from))
+                        })
+                    })?;
+                mnemonics
+                    .iter()
+                    .position(|m| mnemonic_matches(&mnemonic, m))
+            });
+            if let Some(i) = index {
+                *counts
+                    .lock()
+                    .unwrap()
+                    .entry(mnemonics[i].clone())
+                    .or_insert(0) += 1;
+            }
+        };
+        emu.add_code_hook(CodeHookType::CODE, 1, 0, callback)
+    }
+
+    /// Log every read that touches any of the watchpoints, given as ranges
+    /// from their first address to one past their last. Unicorn doesn't
+    /// pass the value read to the hook, so it's read from memory instead.
+    pub fn install_read_watchpoint_hooks<C: 'static + Cpu<'static>>(
+        emu: &mut C,
+        profiler: &Profiler<C>,
+        watchpoints: &[(u64, u64)],
+        endian: Endian,
+    ) -> Result<Vec<unicorn::uc_hook>, unicorn::Error> {
+        let pc: i32 = emu.program_counter().into();
+        let mut hooks = Vec::new();
+        for (start, end) in watchpoints.iter().copied() {
+            let read_log = profiler.read_log.clone();
+            let sequence = profiler.sequence.clone();
+            let mem_read_callback =
+                move |engine: &unicorn::Unicorn<'_>, mem_type, address, num_bytes_read, _value| {
+                    // compared by distance, so that a read near the top of
+                    // the address space doesn't overflow
+                    let touches = address < end
+                        && (start <= address || start - address < num_bytes_read as u64);
+                    if let (MemType::READ, true) = (mem_type, touches) {
+                        let program_counter =
+                            engine.reg_read(pc).expect("Failed to read PC register");
+                        let bytes = engine
+                            .mem_read_as_vec(address, num_bytes_read.min(8))
+                            .unwrap_or_default();
+                        let shift = |v: u64, b: &u8| v << 8 | *b as u64;
+                        let value = match endian {
+                            Endian::Little => bytes.iter().rev().fold(0, shift),
+                            Endian::Big => bytes.iter().fold(0, shift),
+                        };
+                        read_log.push(ReadLogEntry {
+                            program_counter,
+                            address,
+                            num_bytes_read,
+                            value,
+                            sequence: sequence.fetch_add(1, atomic::Ordering::Relaxed),
+                        });
+                    }
+                    false
+                };
+            // The hook fires on reads that begin within its range, which is
+            // inclusive, so it's widened to catch the reads of up to a word
+            // that begin before the watchpoint, but run into it.
+            let hook = emu.add_mem_hook(
+                MemHookType::MEM_READ,
+                start.saturating_sub(7),
+                end - 1,
+                mem_read_callback,
+            )?;
+            hooks.push(hook);
+        }
+        Ok(hooks)
+    }
+
+    /// Flag the run as having reached the target once the instruction at
+    /// `target` is executed, and count every instruction executed within
+    /// the bad regions, given as ranges from their first address to one
+    /// past their last.
+    pub fn install_target_hooks<C: 'static + Cpu<'static>>(
+        emu: &mut C,
+        profiler: &Profiler<C>,
+        target: Option<u64>,
+        bad_regions: &[(u64, u64)],
+    ) -> Result<Vec<unicorn::uc_hook>, unicorn::Error> {
+        let mut hooks = Vec::new();
+        if let Some(target) = target {
+            let reached = profiler.target_reached.clone();
+            let callback = move |_engine: &unicorn::Unicorn<'_>, _address: u64, _size: u32| {
+                reached.store(true, atomic::Ordering::Relaxed);
+            };
+            hooks.push(emu.add_code_hook(CodeHookType::CODE, target, target, callback)?);
+        }
+        for (start, end) in bad_regions.iter().copied() {
+            let hits = profiler.bad_region_hits.clone();
+            let callback = move |_engine: &unicorn::Unicorn<'_>, _address: u64, _size: u32| {
+                hits.fetch_add(1, atomic::Ordering::Relaxed);
+            };
+            hooks.push(emu.add_code_hook(CodeHookType::CODE, start, end - 1, callback)?);
+        }
+        Ok(hooks)
+    }
+
     /// Add a memory hook wherever the specified protections are satisfied.
     ///
     /// The callback takes four arguments:
@@ -891,6 +1348,23 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_invalid_arch_mode_pairing() {
+        // ARM has no 64-bit mode in unicorn; that's the job of ARM64
+        let config = RoperConfig {
+            arch: unicorn::Arch::ARM,
+            mode: unicorn::Mode::MODE_64,
+            ..Default::default()
+        };
+        assert!(check_cpu::<unicorn::CpuARM<'static>>(&config).is_err());
+        let hatchery = Hatchery::<unicorn::CpuARM<'static>>::new(
+            Arc::new(config),
+            Arc::new(HashMap::new()),
+            Arc::new(vec![]),
+        );
+        assert!(hatchery.is_err());
+    }
+
     // FIXME - currently broken for want for full Pack impl for Vec<u8> #[test]
     // fn test_hatchery() {
     //     env_logger::init();
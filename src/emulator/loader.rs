@@ -12,9 +12,10 @@ use rand::distributions::{Distribution, WeightedIndex};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
-use crate::configure::RoperConfig;
+use crate::configure::{DisassemblySyntax, PermissionOverride, RoperConfig};
 use crate::disassembler::Disassembler;
 use crate::error::Error;
+use crate::logger::EMULATOR;
 use crate::util::architecture::{endian, read_integer, word_size_in_bytes, Endian, Perms};
 use crate::util::random::hash_seed_rng;
 
@@ -30,6 +31,7 @@ pub static mut MEM_IMAGE: MemoryImage = MemoryImage {
     word_size: 8,
     disasm: None,
     il_program: None,
+    symbols: Vec::new(),
 };
 static INIT_MEM_IMAGE: Once = Once::new();
 
@@ -42,6 +44,8 @@ pub struct MemoryImage {
     pub word_size: usize,
     pub disasm: Option<Disassembler>,
     pub il_program: Option<falcon::il::Program>,
+    /// The binary's symbols, as (address, name) pairs, sorted by address.
+    pub symbols: Vec<(u64, String)>,
 }
 
 impl MemoryImage {
@@ -52,7 +56,7 @@ impl MemoryImage {
         count: Option<usize>,
     ) -> Option<Instructions<'_>> {
         self.try_dereference(addr, None)
-            .map(|b| &b[..size])
+            .and_then(|b| b.get(..size))
             .and_then(|b| {
                 self.disasm
                     .as_ref()
@@ -135,7 +139,7 @@ impl MemoryImage {
         self.containing_seg(addr, extra_segs).and_then(|s| {
             let offset = (addr - s.aligned_start()) as usize;
 
-            if offset > s.data.len() {
+            if offset >= s.data.len() {
                 None
             } else {
                 Some(&s.data[offset..])
@@ -143,6 +147,18 @@ impl MemoryImage {
         })
     }
 
+    /// Like `try_dereference`, but returns no more than `max_bytes` bytes.
+    /// Fewer are returned if the segment ends sooner.
+    pub fn read<'a>(
+        &'a self,
+        addr: u64,
+        max_bytes: usize,
+        extra_segs: Option<&'a [Seg]>,
+    ) -> Option<&'a [u8]> {
+        self.try_dereference(addr, extra_segs)
+            .map(|bytes| &bytes[..bytes.len().min(max_bytes)])
+    }
+
     pub fn random_address<H: Hash>(&self, permissions: Option<Perms>, seed: H) -> u64 {
         let mut rng = hash_seed_rng(&seed);
         let segments = self
@@ -168,6 +184,7 @@ impl MemoryImage {
 
     pub fn seek(&self, offset: u64, sequence: &[u8], extra_segs: Option<&[Seg]>) -> Option<u64> {
         log::debug!(
+            target: EMULATOR,
             "seeking sequence {:x?} from offset 0x{:x}",
             sequence,
             offset
@@ -177,7 +194,7 @@ impl MemoryImage {
             let mut ptr = offset;
             for window in s.data[start..].windows(sequence.len()) {
                 if window == sequence {
-                    log::debug!("Found sequence at address 0x{:x}", ptr);
+                    log::debug!(target: EMULATOR, "Found sequence at address 0x{:x}", ptr);
                     return Some(ptr as u64);
                 } else {
                     ptr += 1
@@ -227,30 +244,59 @@ impl MemoryImage {
         &self.segs
     }
 
+    /// The name of a symbol at exactly `addr`, if there is one.
+    pub fn symbol_for_address(&self, addr: u64) -> Option<&str> {
+        let i = self.symbols.partition_point(|(a, _)| *a < addr);
+        self.symbols
+            .get(i)
+            .filter(|(a, _)| *a == addr)
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// The address of the symbol called `name`, if there is one.
+    pub fn address_for_symbol(&self, name: &str) -> Option<u64> {
+        self.symbols
+            .iter()
+            .find(|(_, n)| n == name)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// The closest symbol at or below `addr`, and the offset of `addr` from
+    /// it, provided they lie in the same segment.
+    pub fn nearest_symbol(&self, addr: u64) -> Option<(&str, u64)> {
+        let i = self.symbols.partition_point(|(a, _)| *a <= addr);
+        let (sym_addr, name) = self.symbols.get(i.checked_sub(1)?)?;
+        let seg = self.containing_seg(addr, None)?;
+        if *sym_addr >= seg.aligned_start() {
+            Some((name.as_str(), addr - sym_addr))
+        } else {
+            None
+        }
+    }
+
     /// Returns a chain of dereferences beginning with the address `start`.
     /// If `start` fails to dereference to any value, the chain will just
     /// be `vec![start]`, so the caller can always assume that the chain
-    /// is non-empty.
+    /// is non-empty. No more than `steps` pointers are followed, so the
+    /// chain of a cyclic or self-referential pointer ends after `steps + 1`
+    /// entries.
     pub fn deref_chain(&self, start: u64, steps: usize, extra_segs: Option<&[Seg]>) -> Vec<u64> {
         let word_size = word_size_in_bytes(self.arch, self.mode);
         let endian = endian(self.arch, self.mode);
         let mut chain = vec![start];
-        struct Crawl<'s> {
-            f: &'s dyn Fn(&Crawl<'_>, u64, usize, &mut Vec<u64>) -> (),
-        };
-        let crawl = Crawl {
-            f: &|crawl, addr, steps, mut chain| {
-                if steps > 0 {
-                    if let Some(bytes) = self.try_dereference(addr, extra_segs) {
-                        if let Some(addr) = read_integer(bytes, endian, word_size) {
-                            chain.push(addr);
-                            (crawl.f)(crawl, addr, steps - 1, &mut chain);
-                        }
-                    }
+        let mut addr = start;
+        for _ in 0..steps {
+            match self
+                .read(addr, word_size, extra_segs)
+                .and_then(|bytes| read_integer(bytes, endian, word_size))
+            {
+                Some(next) => {
+                    chain.push(next);
+                    addr = next;
                 }
-            },
-        };
-        (crawl.f)(&crawl, start, steps, &mut chain);
+                None => break,
+            }
+        }
         chain
     }
 }
@@ -299,6 +345,7 @@ impl Seg {
         let mut windows = self.data.windows(seq.len());
         while let Some(_occurrence) = windows.position(|window| window == seq) {
             log::debug!(
+                target: EMULATOR,
                 "Found occurrence of pattern {:x?} at address 0x{:x}",
                 seq,
                 _occurrence as u64 + self.aligned_start()
@@ -443,7 +490,33 @@ impl SegType {
     }
 }
 
-fn load_elf(elf: Elf<'_>, code_buffer: &[u8], stack_size: usize) -> Vec<Seg> {
+/// The named function and object symbols in the ELF's symbol tables, static
+/// and dynamic.
+fn elf_symbols(elf: &Elf<'_>) -> Vec<(u64, String)> {
+    let static_syms = elf.syms.iter().map(|sym| (sym, &elf.strtab));
+    let dynamic_syms = elf.dynsyms.iter().map(|sym| (sym, &elf.dynstrtab));
+    static_syms
+        .chain(dynamic_syms)
+        .filter(|(sym, _)| {
+            sym.st_value != 0
+                && (sym.st_type() == elf::sym::STT_FUNC || sym.st_type() == elf::sym::STT_OBJECT)
+        })
+        .filter_map(|(sym, strtab)| {
+            strtab
+                .get(sym.st_name)
+                .and_then(Result::ok)
+                .filter(|name| !name.is_empty())
+                .map(|name| (sym.st_value, name.to_string()))
+        })
+        .collect()
+}
+
+fn load_elf(
+    elf: Elf<'_>,
+    code_buffer: &[u8],
+    stack_size: usize,
+    stack_address: Option<u64>,
+) -> Vec<Seg> {
     //let mut page_one = false;
     let shdrs = &elf.section_headers;
     let phdrs = &elf.program_headers;
@@ -481,6 +554,7 @@ fn load_elf(elf: Elf<'_>, code_buffer: &[u8], stack_size: usize) -> Vec<Seg> {
                 for byte in sdata {
                     if v_off >= seg.data.len() {
                         log::warn!(
+                            target: EMULATOR,
                             "[x] v_off 0x{:x} > seg.data.len() 0x{:x}",
                             v_off,
                             seg.data.len()
@@ -495,13 +569,16 @@ fn load_elf(elf: Elf<'_>, code_buffer: &[u8], stack_size: usize) -> Vec<Seg> {
         }
     }
     /* now allocate the stack */
-    let mut bottom = 0;
-    for seg in &segs {
-        let b = seg.aligned_end();
-        if b > bottom {
-            bottom = b
-        };
-    }
+    let bottom = stack_address.unwrap_or_else(|| {
+        let mut bottom = 0;
+        for seg in &segs {
+            let b = seg.aligned_end();
+            if b > bottom {
+                bottom = b
+            };
+        }
+        bottom
+    });
     segs.push(Seg {
         addr: bottom,
         perm: Perms::READ | Perms::WRITE,
@@ -513,14 +590,76 @@ fn load_elf(elf: Elf<'_>, code_buffer: &[u8], stack_size: usize) -> Vec<Seg> {
     segs
 }
 
+/// Apply the permission overrides to the segments, in order. An override
+/// that overlaps no segment, or that leaves the permissions of the ones it
+/// overlaps as they were, is almost certainly a mistake in the config, and
+/// is warned about.
+pub fn apply_permission_overrides(
+    segs: &mut [Seg],
+    overrides: &[PermissionOverride],
+) -> Result<(), Error> {
+    for over in overrides {
+        let add = over.add.parse::<Perms>()?;
+        let remove = over.remove.parse::<Perms>()?;
+        let start = over.start.unwrap_or(0);
+        let end = over.end.unwrap_or(std::u64::MAX);
+        if start >= end {
+            return Err(Error::Misc(format!(
+                "Empty range in segment permission override {:?}",
+                over
+            )));
+        }
+        let mut overlapping = 0;
+        let mut changed = 0;
+        for seg in segs
+            .iter_mut()
+            .filter(|s| s.aligned_start() < end && start < s.aligned_end())
+        {
+            overlapping += 1;
+            let mut perm = seg.perm;
+            perm.insert(add);
+            perm.remove(remove);
+            if perm != seg.perm {
+                log::info!(
+                    target: EMULATOR,
+                    "Overriding permissions of segment {} with {}",
+                    seg,
+                    perm
+                );
+                seg.perm = perm;
+                changed += 1;
+            }
+        }
+        if overlapping == 0 {
+            log::warn!(
+                target: EMULATOR,
+                "Segment permission override {:?} overlaps no segment",
+                over
+            );
+        } else if changed == 0 {
+            log::warn!(
+                target: EMULATOR,
+                "Segment permission override {:?} leaves every segment as it was",
+                over
+            );
+        }
+    }
+    Ok(())
+}
+
 fn initialize_memory_image(
     segments: &[Seg],
     arch: unicorn::Arch,
     mode: unicorn::Mode,
+    syntax: DisassemblySyntax,
     il_program: Option<il::Program>,
+    mut symbols: Vec<(u64, String)>,
 ) {
     let endian = endian(arch, mode);
     let word_size = word_size_in_bytes(arch, mode);
+    symbols.sort();
+    symbols.dedup();
+    log::info!(target: EMULATOR, "Loaded {} symbols", symbols.len());
     unsafe {
         MEM_IMAGE = MemoryImage {
             segs: segments.to_owned(),
@@ -528,8 +667,11 @@ fn initialize_memory_image(
             mode,
             endian,
             word_size,
-            disasm: Some(Disassembler::new(arch, mode).expect("Failed to initialize disassembler")),
+            disasm: Some(
+                Disassembler::new(arch, mode, syntax).expect("Failed to initialize disassembler"),
+            ),
             il_program,
+            symbols,
         }
     }
 }
@@ -542,6 +684,34 @@ pub fn try_to_get_static_memory_image() -> Option<&'static MemoryImage> {
     }
 }
 
+/// The address named by `symbol`, which may be followed by an offset, as
+/// in `system+0x10`. Fails if the symbol isn't found, or if the binary
+/// hasn't been loaded yet.
+pub fn resolve_symbol(symbol: &str) -> Result<u64, Error> {
+    let (name, offset) = match symbol.find('+') {
+        Some(i) => {
+            let offset = symbol[i + 1..].trim();
+            let offset = if offset.starts_with("0x") {
+                u64::from_str_radix(offset.trim_start_matches("0x"), 16)?
+            } else {
+                offset.parse::<u64>()?
+            };
+            (symbol[..i].trim(), offset)
+        }
+        None => (symbol.trim(), 0),
+    };
+    let memory = try_to_get_static_memory_image().ok_or_else(|| {
+        Error::Misc(format!(
+            "Can't resolve the symbol {} before the binary has been loaded",
+            name
+        ))
+    })?;
+    memory
+        .address_for_symbol(name)
+        .map(|addr| addr + offset)
+        .ok_or_else(|| Error::Misc(format!("No symbol named {} in the binary", name)))
+}
+
 pub fn get_static_memory_image() -> &'static MemoryImage {
     if INIT_MEM_IMAGE.is_completed() {
         unsafe { &MEM_IMAGE }
@@ -553,26 +723,37 @@ pub fn get_static_memory_image() -> &'static MemoryImage {
 pub fn load(
     code_buffer: &[u8],
     stack_size: usize,
+    stack_address: Option<u64>,
     arch: unicorn::Arch,
     mode: unicorn::Mode,
+    syntax: DisassemblySyntax,
+    overrides: &[PermissionOverride],
     init: bool,
 ) -> Result<Vec<Seg>, Error> {
     if INIT_MEM_IMAGE.is_completed() {
         unsafe { Ok(MEM_IMAGE.segments().clone()) }
     } else {
         let obj = Object::parse(code_buffer)?;
-        let mut segs = match obj {
-            Object::Elf(elf) => load_elf(elf, code_buffer, stack_size),
+        let (mut segs, symbols) = match obj {
+            Object::Elf(elf) => {
+                let symbols = elf_symbols(&elf);
+                (
+                    load_elf(elf, code_buffer, stack_size, stack_address),
+                    symbols,
+                )
+            }
             _ => unimplemented!("Only ELF binaries are supported at this time."),
         };
         segs.sort_by_key(|s| s.aligned_start());
+        apply_permission_overrides(&mut segs, overrides)?;
         for seg in &segs {
-            log::info!("{}, data len: {:x}", seg, seg.data.len());
+            log::info!(target: EMULATOR, "{}, data len: {:x}", seg, seg.data.len());
         }
 
         // Cache the memory image as a globally accessible static
         if init {
-            INIT_MEM_IMAGE.call_once(|| initialize_memory_image(&segs, arch, mode, None));
+            INIT_MEM_IMAGE
+                .call_once(|| initialize_memory_image(&segs, arch, mode, syntax, None, symbols));
         }
 
         Ok(segs)
@@ -581,10 +762,20 @@ pub fn load(
 
 pub fn load_from_path(config: &RoperConfig, init: bool) -> Result<Vec<Seg>, Error> {
     let path = &config.binary_path;
-    let stack_size = config.emulator_stack_size;
+    let stack_size = config.stack_size();
+    let stack_address = config.stack.address;
     let arch = config.arch;
     let mode = config.mode;
-    load(&std::fs::read(path)?, stack_size, arch, mode, init)
+    load(
+        &std::fs::read(path)?,
+        stack_size,
+        stack_address,
+        arch,
+        mode,
+        config.disassembly_syntax,
+        &config.segment_permissions,
+        init,
+    )
 }
 
 pub mod falcon_loader {
@@ -625,10 +816,11 @@ pub mod falcon_loader {
         if INIT_MEM_IMAGE.is_completed() {
             unsafe { Ok(MEM_IMAGE.segments().clone()) }
         } else {
-            log::info!("Using falcon loader");
+            log::info!(target: EMULATOR, "Using falcon loader");
             let path = &config.roper.binary_path;
             if config.roper.ld_paths.is_none() {
                 log::warn!(
+                    target: EMULATOR,
                     "No ld_paths supplied. Attempting to complete using `ldd {}`.",
                     path
                 );
@@ -644,14 +836,16 @@ pub mod falcon_loader {
             //let program = elf.program()?;
             let mut memory = linker.memory()?;
             // figure out where the stack should go
-            let stack_position = memory
-                .sections()
-                .iter()
-                .map(|(&addr, sec)| addr + sec.data().len() as u64)
-                .max()
-                .expect("Could not find maximum address");
+            let stack_position = config.roper.stack.address.unwrap_or_else(|| {
+                memory
+                    .sections()
+                    .iter()
+                    .map(|(&addr, sec)| addr + sec.data().len() as u64)
+                    .max()
+                    .expect("Could not find maximum address")
+            });
             // initialize empty memory for the stack
-            let stack_data = vec![0; config.roper.emulator_stack_size];
+            let stack_data = vec![0; config.roper.stack_size()];
             // insert the stack into memory
             memory.set_memory(
                 stack_position,
@@ -682,6 +876,7 @@ pub mod falcon_loader {
             for seg in segs.iter_mut() {
                 seg.ensure_data_alignment()
             }
+            apply_permission_overrides(&mut segs, &config.roper.segment_permissions)?;
 
             let (arch, mode) = arch_mode_from_linker(&linker);
             config.roper.arch = arch;
@@ -711,11 +906,14 @@ pub mod falcon_loader {
                         }),
                     )
                 } else {
-                    log::info!("Lifting the intermediate representation of the program...");
+                    log::info!(
+                        target: EMULATOR,
+                        "Lifting the intermediate representation of the program..."
+                    );
                     let program = linker
                         .program()
                         .expect("Failed to lift il::Program from ElfLinker");
-                    log::info!("Finished lifting program.");
+                    log::info!(target: EMULATOR, "Finished lifting program.");
                     // ron_dump(&program, cached_path).expect("Failed to dump il::Program");
                     Some(program)
                 }
@@ -725,7 +923,15 @@ pub mod falcon_loader {
 
             if init {
                 // TODO: let lift_program be optional, and only activated when using Push
-                INIT_MEM_IMAGE.call_once(|| initialize_memory_image(&segs, arch, mode, program));
+                let syntax = config.roper.disassembly_syntax;
+                let symbols = linker
+                    .symbols()
+                    .into_iter()
+                    .map(|s| (s.address(), s.name().to_string()))
+                    .collect::<Vec<(u64, String)>>();
+                INIT_MEM_IMAGE.call_once(|| {
+                    initialize_memory_image(&segs, arch, mode, syntax, program, symbols)
+                });
             }
             Ok(segs)
         }
@@ -782,6 +988,126 @@ mod test {
         //     assert_eq!(s1, s2);
         // }
     }
+
+    #[test]
+    fn test_permission_overrides() {
+        let seg = |addr, perm| Seg {
+            addr,
+            memsz: 0x1000,
+            perm,
+            segtype: SegType::Load,
+            data: vec![0; 0x1000],
+        };
+        let mut segs = vec![
+            seg(0x1000, Perms::READ | Perms::EXEC),
+            seg(0x2000, Perms::READ | Perms::WRITE | Perms::EXEC),
+            seg(0x3000, Perms::READ),
+        ];
+        let overrides = vec![
+            // a non-executable stack, say
+            PermissionOverride {
+                start: Some(0x2000),
+                end: Some(0x3000),
+                remove: "x".into(),
+                ..Default::default()
+            },
+            PermissionOverride {
+                start: Some(0x3800),
+                add: "W".into(),
+                ..Default::default()
+            },
+            // a no-op, which is only warned about
+            PermissionOverride {
+                end: Some(0x2000),
+                add: "RX".into(),
+                ..Default::default()
+            },
+        ];
+        apply_permission_overrides(&mut segs, &overrides).unwrap();
+        assert_eq!(
+            segs.iter().map(|s| s.perm).collect::<Vec<Perms>>(),
+            vec![
+                Perms::READ | Perms::EXEC,
+                Perms::READ | Perms::WRITE,
+                Perms::READ | Perms::WRITE,
+            ]
+        );
+
+        let bad = PermissionOverride {
+            add: "RWZ".into(),
+            ..Default::default()
+        };
+        assert!(apply_permission_overrides(&mut segs, &[bad]).is_err());
+        let empty = PermissionOverride {
+            start: Some(0x2000),
+            end: Some(0x2000),
+            add: "W".into(),
+            ..Default::default()
+        };
+        assert!(apply_permission_overrides(&mut segs, &[empty]).is_err());
+    }
+
+    #[test]
+    fn test_symbol_lookup() {
+        let memory = MemoryImage {
+            segs: vec![Seg {
+                addr: 0x1000,
+                memsz: 0x1000,
+                perm: Perms::READ | Perms::EXEC,
+                segtype: SegType::Load,
+                data: vec![0; 0x1000],
+            }],
+            arch: Arch::X86,
+            mode: Mode::MODE_64,
+            endian: Endian::Little,
+            word_size: 8,
+            disasm: None,
+            il_program: None,
+            symbols: vec![(0x800, "before".into()), (0x1100, "system".into())],
+        };
+        assert_eq!(memory.symbol_for_address(0x1100), Some("system"));
+        assert_eq!(memory.symbol_for_address(0x1101), None);
+        assert_eq!(memory.address_for_symbol("system"), Some(0x1100));
+        assert_eq!(memory.address_for_symbol("execve"), None);
+        assert_eq!(memory.nearest_symbol(0x1180), Some(("system", 0x80)));
+        // the closest symbol below is in another segment
+        assert_eq!(memory.nearest_symbol(0x1010), None);
+        assert_eq!(memory.nearest_symbol(0x100), None);
+    }
+
+    #[test]
+    fn test_bounded_dereference() {
+        let mut data = vec![0; 0x1000];
+        // a word that points to itself, and a chain that runs off the
+        // end of the segment
+        data[..8].copy_from_slice(&0x1000_u64.to_le_bytes());
+        data[8..16].copy_from_slice(&0x1ffc_u64.to_le_bytes());
+        let memory = MemoryImage {
+            segs: vec![Seg {
+                addr: 0x1000,
+                memsz: 0x1000,
+                perm: Perms::READ | Perms::WRITE,
+                segtype: SegType::Load,
+                data,
+            }],
+            arch: Arch::X86,
+            mode: Mode::MODE_64,
+            endian: Endian::Little,
+            word_size: 8,
+            disasm: None,
+            il_program: None,
+            symbols: vec![],
+        };
+        assert_eq!(memory.deref_chain(0x1000, 5, None), vec![0x1000; 6]);
+        assert_eq!(memory.deref_chain(0x1000, 0, None), vec![0x1000]);
+        // only four bytes remain at 0x1ffc, too few for a word
+        assert_eq!(memory.deref_chain(0x1008, 5, None), vec![0x1008, 0x1ffc]);
+        assert_eq!(memory.deref_chain(0x3000, 5, None), vec![0x3000]);
+
+        assert_eq!(memory.read(0x1000, 16, None).map(<[u8]>::len), Some(16));
+        assert_eq!(memory.read(0x1ffc, 16, None).map(<[u8]>::len), Some(4));
+        assert_eq!(memory.read(0x2000, 16, None), None);
+    }
 }
 
 // TODO:
@@ -14,6 +14,15 @@ pub trait Pack {
     fn as_code_addrs(&self, word_size: usize, endian: Endian) -> Vec<u64>;
 }
 
+/// Count the words in a packed payload that contain at least one of the
+/// `forbidden` bytes.
+pub fn count_words_with_bytes(packed: &[u8], word_size: usize, forbidden: &[u8]) -> usize {
+    packed
+        .chunks(word_size)
+        .filter(|word| word.iter().any(|b| forbidden.contains(b)))
+        .count()
+}
+
 impl Pack for Vec<u8> {
     fn pack(&self, _w: usize, _e: Endian, _byte_filter: Option<&HashMap<u8, u8>>) -> Vec<u8> {
         self.clone()
@@ -75,3 +84,18 @@ impl Pack for Vec<u64> {
             .collect::<Vec<_>>()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_count_words_with_bytes() {
+        let payload: Vec<u64> = vec![0x4141_4141_4141_4141, 0x0000_7fff_dead_beef, 0x0a];
+        let packed = payload.pack(8, Endian::Little, None);
+        assert_eq!(count_words_with_bytes(&packed, 8, &[0x00]), 2);
+        assert_eq!(count_words_with_bytes(&packed, 8, &[0x0a]), 1);
+        assert_eq!(count_words_with_bytes(&packed, 8, &[0x41, 0xef]), 2);
+        assert_eq!(count_words_with_bytes(&packed, 8, &[]), 0);
+    }
+}
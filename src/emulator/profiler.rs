@@ -1,10 +1,11 @@
 use std::cmp::{Ord, PartialOrd};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use cached::{cached_key, Cached, SizedCache};
 use capstone::Instructions;
 use crossbeam::queue::SegQueue;
 use hashbrown::{HashMap, HashSet};
@@ -14,9 +15,11 @@ use subslice::SubsliceExt;
 pub use unicorn::unicorn_const::Error as UCError;
 use unicorn::Cpu;
 
+use crate::disassembler::InstructionCategory;
 use crate::emulator::loader;
 use crate::emulator::loader::{get_static_memory_image, try_to_get_static_memory_image, Seg};
-use crate::emulator::register_pattern::{Register, RegisterState};
+use crate::emulator::register_pattern::{Register, RegisterState, DEFAULT_DEREF_DEPTH};
+use crate::logger::EMULATOR;
 use crate::util::architecture::{write_integer, Endian};
 
 #[derive(Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize, Hash)]
@@ -27,19 +30,69 @@ pub struct Block {
 }
 
 impl Block {
-    pub fn get_code(&self) -> &'static [u8] {
-        let memory = loader::get_static_memory_image();
+    /// Returns `None` if the block isn't wholly contained in the static
+    /// memory image.
+    pub fn get_code(&self) -> Option<&'static [u8]> {
+        let memory = loader::try_to_get_static_memory_image()?;
         memory
             .try_dereference(self.entry, None)
-            .map(|b| &b[..self.size])
-            .unwrap()
+            .and_then(|b| b.get(..self.size))
     }
 
-    pub fn disassemble(&self) -> Instructions<'_> {
-        let memory = loader::get_static_memory_image();
-        memory
-            .disassemble(self.entry, self.size, None)
-            .expect("Failed to disassemble basic block")
+    /// Returns `None` if the block's code can't be found or disassembled.
+    pub fn disassemble(&self) -> Option<Instructions<'static>> {
+        let memory = loader::try_to_get_static_memory_image()?;
+        memory.disassemble(self.entry, self.size, None)
+    }
+
+    /// The formatted disassembly of the block. Since the static memory
+    /// image doesn't change over the course of a run, this is memoized,
+    /// keyed on the block's entry and size.
+    pub fn disassembly(&self) -> Option<String> {
+        disassemble_block(self.entry, self.size)
+    }
+
+    /// The categories of the block's instructions, in order. Memoized like
+    /// `disassembly`.
+    pub fn categories(&self) -> Option<Vec<InstructionCategory>> {
+        categorize_block(self.entry, self.size)
+    }
+}
+
+cached_key! {
+    BLOCK_DISASSEMBLY: SizedCache<(u64, usize), Option<String>> = SizedCache::with_size(0x1_0000);
+
+    Key = { (entry, size) };
+
+    fn disassemble_block(entry: u64, size: usize) -> Option<String> = {
+        Block { entry, size }.disassemble().map(|insts| insts.to_string())
+    }
+}
+
+/// The fraction of lookups in the block disassembly cache that were hits,
+/// or `None` if there have been none yet.
+pub fn disassembly_cache_hit_rate() -> Option<f64> {
+    let cache = BLOCK_DISASSEMBLY.lock().ok()?;
+    let hits = cache.cache_hits()? as f64;
+    let misses = cache.cache_misses()? as f64;
+    if hits + misses == 0.0 {
+        None
+    } else {
+        Some(hits / (hits + misses))
+    }
+}
+
+cached_key! {
+    BLOCK_CATEGORIES: SizedCache<(u64, usize), Option<Vec<InstructionCategory>>> = SizedCache::with_size(0x1_0000);
+
+    Key = { (entry, size) };
+
+    fn categorize_block(entry: u64, size: usize) -> Option<Vec<InstructionCategory>> = {
+        let memory = loader::try_to_get_static_memory_image()?;
+        let disasm = memory.disasm.as_ref()?;
+        Block { entry, size }
+            .disassemble()
+            .map(|insts| insts.iter().map(|i| disasm.categorize(&i)).collect())
     }
 }
 
@@ -56,23 +109,92 @@ impl fmt::Debug for Block {
 
 pub struct Profiler<C: Cpu<'static>> {
     /// The Arc<RwLock<_>> fields need to be writeable for the unicorn callbacks.
-    pub trace_log: Arc<SegQueue<Block>>,
+    /// Blocks are logged along with their sequence numbers.
+    pub trace_log: Arc<SegQueue<(usize, Block)>>,
     pub committed_trace_log: Arc<Mutex<Vec<Block>>>,
+    /// The sequence numbers of the blocks in the committed trace log.
+    pub committed_block_sequence: Arc<Mutex<Vec<usize>>>,
+    /// Counts the blocks executed, the memory writes made, and the
+    /// watched reads, so that each can be given a sequence number recording
+    /// the order in which they happened, relative to each other.
+    pub sequence: Arc<AtomicUsize>,
 
     pub ret_count: Arc<AtomicUsize>,
     pub call_stack_depth: Arc<AtomicUsize>,
-    pub gadget_log: Arc<SegQueue<u64>>,
+    /// Gadgets are logged along with the sequence numbers of their blocks.
+    pub gadget_log: Arc<SegQueue<(usize, u64)>>,
     //Arc<RwLock<Vec<u64>>>,
     /// These fields are written to after the emulation has finished.
     pub written_memory: Vec<Seg>,
     pub write_log: Arc<SegQueue<MemLogEntry>>,
     pub committed_write_log: Arc<Mutex<SparseDataHelper>>,
+    /// Every memory write, in the order it was made, if
+    /// `roper.record_write_order` is set.
+    pub ordered_write_log: Option<Arc<SegQueue<MemLogEntry>>>,
     //Arc<RwLock<Vec<MemLogEntry>>>,
+    /// The reads that touched any of `roper.read_watchpoints`.
+    pub read_log: Arc<SegQueue<ReadLogEntry>>,
+    /// The registers read on entering each gadget, with the sequence number
+    /// of the gadget's block, if `roper.record_register_snapshots` is set.
+    #[allow(clippy::type_complexity)]
+    pub register_log: Option<Arc<SegQueue<(usize, u64, HashMap<Register<C>, u64>)>>>,
+    /// The number of times each of `roper.count_mnemonics` was executed.
+    pub instruction_counts: Arc<Mutex<BTreeMap<String, usize>>>,
     pub cpu_error: Option<unicorn::Error>,
     pub emulation_time: Duration,
     pub registers_at_last_ret: Arc<Mutex<HashMap<Register<C>, u64>>>,
     pub registers_to_read: Vec<Register<C>>,
     pub input: HashMap<Register<C>, u64>,
+    /// The system call at which the emulation stopped, if any.
+    pub syscall: Arc<Mutex<Option<Syscall>>>,
+    /// The most entries that each of the block and gadget logs may capture.
+    /// Any further entries are dropped.
+    pub log_limit: Option<usize>,
+    /// How many pointers to follow from each register's value when the
+    /// register state is recorded.
+    pub deref_depth: usize,
+    pub block_log_len: Arc<AtomicUsize>,
+    pub gadget_log_len: Arc<AtomicUsize>,
+    /// Set once an entry has been dropped from either log.
+    pub log_truncated: Arc<AtomicBool>,
+    /// Set if the chain reached the sentinel, `roper.return_address`.
+    pub clean_exit: Arc<AtomicBool>,
+    /// Set if the chain executed the instruction at `roper.target_address`.
+    pub target_reached: Arc<AtomicBool>,
+    /// The number of instructions executed within `roper.bad_regions`.
+    pub bad_region_hits: Arc<AtomicUsize>,
+}
+
+/// Count an entry against a log's limit, returning false, and flagging the
+/// log as truncated, once the limit has been reached.
+pub fn within_log_limit(limit: Option<usize>, len: &AtomicUsize, truncated: &AtomicBool) -> bool {
+    match limit {
+        None => true,
+        Some(max) => {
+            let within = len.fetch_add(1, std::sync::atomic::Ordering::Relaxed) < max;
+            if !within {
+                truncated.store(true, std::sync::atomic::Ordering::Relaxed);
+            }
+            within
+        }
+    }
+}
+
+/// The control flow edges already recorded in a block log, for logging
+/// each edge only once.
+#[derive(Debug, Default)]
+pub struct EdgeLog {
+    previous: Option<u64>,
+    seen: HashSet<(Option<u64>, u64)>,
+}
+
+impl EdgeLog {
+    /// Note that the block at `entry` was entered, returning true if this
+    /// is the first time it was entered from the previous block.
+    pub fn first_visit(&mut self, entry: u64) -> bool {
+        let edge = (self.previous.replace(entry), entry);
+        self.seen.insert(edge)
+    }
 }
 
 impl<C: Cpu<'static>> Default for Profiler<C> {
@@ -90,7 +212,22 @@ impl<C: Cpu<'static>> Default for Profiler<C> {
             gadget_log: Arc::new(SegQueue::new()), //Arc::new(RwLock::new(Vec::new())),
             written_memory: vec![],
             committed_write_log: Default::default(),
+            ordered_write_log: None,
+            read_log: Arc::new(SegQueue::new()),
+            register_log: None,
+            instruction_counts: Default::default(),
             committed_trace_log: Default::default(),
+            committed_block_sequence: Default::default(),
+            sequence: Arc::new(AtomicUsize::new(0)),
+            syscall: Default::default(),
+            log_limit: None,
+            deref_depth: DEFAULT_DEREF_DEPTH,
+            block_log_len: Arc::new(AtomicUsize::new(0)),
+            gadget_log_len: Arc::new(AtomicUsize::new(0)),
+            log_truncated: Arc::new(AtomicBool::new(false)),
+            clean_exit: Arc::new(AtomicBool::new(false)),
+            target_reached: Arc::new(AtomicBool::new(false)),
+            bad_region_hits: Arc::new(AtomicUsize::new(0)),
         }
     }
 }
@@ -127,11 +264,113 @@ pub struct Profile {
     pub emulation_times: Vec<Duration>,
     pub registers: Vec<RegisterState>,
     pub gadgets_executed: Vec<HashMap<u64, usize>>,
-    #[cfg(not(feature = "full_dump"))]
-    #[serde(skip)]
+    /// The gadgets executed in each run, in order, along with their
+    /// sequence numbers, which place them in order with the blocks and
+    /// memory writes.
+    #[serde(default)]
+    pub gadget_sequences: Vec<Vec<(usize, u64)>>,
+    /// Memory writes are always captured, since fitness functions rely
+    /// on them. Only their serialization depends on the `full_dump` feature.
+    #[cfg_attr(not(feature = "full_dump"), serde(skip))]
     pub memory_writes: Vec<SparseData>,
+    /// The writeable segments that differed from the static memory image
+    /// at the end of each run.
+    #[cfg_attr(not(feature = "full_dump"), serde(skip))]
+    pub writeable_memory: Vec<Vec<Seg>>,
     pub executable: bool,
     pub ret_counts: Vec<usize>,
+    /// The system call reached in each run, if any.
+    #[serde(default)]
+    pub syscalls: Vec<Option<Syscall>>,
+    /// Whether entries were dropped from each run's block or gadget log,
+    /// after reaching `roper.max_log_entries`.
+    #[serde(default)]
+    pub logs_truncated: Vec<bool>,
+    /// Whether each run reached the sentinel, `roper.return_address`, which
+    /// is planted on the stack just past the payload.
+    #[serde(default)]
+    pub clean_exits: Vec<bool>,
+    /// The memory writes of each run, in the order they were made. Empty
+    /// unless `roper.record_write_order` is set.
+    #[serde(default)]
+    pub write_logs: Vec<Vec<MemLogEntry>>,
+    /// The sequence numbers of the blocks in each run's path, which place
+    /// them in order with the memory writes.
+    #[serde(default)]
+    pub block_sequences: Vec<Vec<usize>>,
+    /// The reads of each run that touched any of `roper.read_watchpoints`,
+    /// in the order they were made.
+    #[serde(default)]
+    pub read_logs: Vec<Vec<ReadLogEntry>>,
+    /// The number of times each of `roper.count_mnemonics` was executed,
+    /// in each run, keyed by the mnemonic in lower case. Mnemonics that
+    /// weren't executed are absent.
+    #[serde(default)]
+    pub instruction_counts: Vec<BTreeMap<String, usize>>,
+    /// Whether each run executed the instruction at `roper.target_address`.
+    #[serde(default)]
+    pub targets_reached: Vec<bool>,
+    /// The registers read on entering each gadget executed, in each run.
+    /// Empty unless `roper.record_register_snapshots` is set.
+    #[serde(default)]
+    pub register_snapshots: Vec<Vec<RegisterSnapshot>>,
+    /// The number of instructions executed within `roper.bad_regions`, in
+    /// each run.
+    #[serde(default)]
+    pub bad_region_hits: Vec<usize>,
+}
+
+/// An event in the execution trace of a single run, as reconstructed by
+/// `Profile::to_trace`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TraceEvent {
+    Block(Block),
+    Write(MemLogEntry),
+    /// The registers as they were on entering a gadget, preceding its
+    /// block.
+    Snapshot(RegisterSnapshot),
+    /// The registers as they were read at the last composable return,
+    /// along with the chains of values dereferenced from them.
+    Registers(RegisterState),
+    Exit {
+        cpu_error: Option<UCError>,
+        clean_exit: bool,
+        syscall: Option<Syscall>,
+    },
+}
+
+/// The registers read on entering a gadget.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisterSnapshot {
+    /// The sequence number of the gadget's block.
+    pub sequence: usize,
+    pub address: u64,
+    /// The registers' values, without any dereferences, since the memory
+    /// they point to is only read at the end of the run.
+    pub registers: RegisterState,
+}
+
+/// A loop found in the executed paths, identified by the block at which
+/// control re-entered it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoopInfo {
+    pub header: u64,
+    /// The entry points of the blocks executed between leaving the header
+    /// and returning to it, including those of any nested loops, sorted.
+    pub body_blocks: Vec<u64>,
+    /// The number of times the back-edge to the header was taken, summed
+    /// over all runs.
+    pub iterations: usize,
+}
+
+/// The state of the machine when a system call instruction was reached.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Syscall {
+    pub address: u64,
+    /// The syscall number, as found in the accumulator.
+    pub number: u64,
+    /// The first three syscall arguments, in calling convention order.
+    pub args: Vec<u64>,
 }
 
 fn fetch_code_executed(path: &Vec<Block>, extra_segs: Option<&[Seg]>) -> Vec<u8> {
@@ -148,21 +387,50 @@ fn fetch_code_executed(path: &Vec<Block>, extra_segs: Option<&[Seg]>) -> Vec<u8>
     code
 }
 
-// FIXME: refactor so that we don't have any code duplication between
-// this method and collate. Or just get rid of collate entirely, I guess.
-
 impl<C: 'static + Cpu<'static>> From<Profiler<C>> for Profile {
-    #[allow(unused_variables)]
     fn from(p: Profiler<C>) -> Self {
-        let mut paths = Vec::new(); // PrefixSet::new();
-        let mut cpu_errors = Vec::new();
-        let mut computation_times = Vec::new();
-        let mut register_maps = Vec::new();
-        let mut gadgets_executed = Vec::new();
-        let mut memory_writes = Vec::new();
-        let mut ret_counts = Vec::new();
-        let mut code_paths_executed = Vec::new();
+        Self::collate(vec![p])
+    }
+}
+
+impl<C: 'static + Cpu<'static>> From<Vec<Profiler<C>>> for Profile {
+    fn from(v: Vec<Profiler<C>>) -> Self {
+        Self::collate(v)
+    }
+}
+
+impl Profile {
+    /// Combine the results of several emulations into a single profile,
+    /// with one entry per profiler in each of the per-run fields.
+    pub fn collate<C: 'static + Cpu<'static>>(profilers: Vec<Profiler<C>>) -> Self {
+        Self::collate_iter(profilers.into_iter())
+    }
 
+    /// Like `collate`, but folds the profilers into the profile one at a
+    /// time, as they arrive, so that they never all have to be held in
+    /// memory at once.
+    pub fn collate_iter<C, I>(profilers: I) -> Self
+    where
+        C: 'static + Cpu<'static>,
+        I: Iterator<Item = Profiler<C>>,
+    {
+        profilers.fold(
+            Self {
+                executable: true,
+                ..Default::default()
+            },
+            |mut profile, p| {
+                profile.push_run(p);
+                profile
+            },
+        )
+    }
+
+    /// Append the results of a single emulation to each of the per-run
+    /// fields. This is the only place where a `Profiler` is taken apart, so
+    /// that every field stays aligned with the others.
+    #[allow(unused_variables)]
+    fn push_run<C: 'static + Cpu<'static>>(&mut self, p: Profiler<C>) {
         let Profiler {
             trace_log,
             call_stack_depth,
@@ -174,9 +442,24 @@ impl<C: 'static + Cpu<'static>> From<Profiler<C>> for Profile {
             written_memory,
             ret_count,
             committed_write_log,
+            ordered_write_log,
+            read_log,
+            register_log,
+            instruction_counts,
             committed_trace_log,
+            committed_block_sequence,
+            sequence,
             registers_to_read,
             input,
+            syscall,
+            log_limit,
+            deref_depth,
+            block_log_len,
+            gadget_log_len,
+            log_truncated,
+            clean_exit,
+            target_reached,
+            bad_region_hits,
         } = p;
         let path = Arc::try_unwrap(committed_trace_log)
             .ok()
@@ -184,19 +467,33 @@ impl<C: 'static + Cpu<'static>> From<Profiler<C>> for Profile {
             .into_inner()
             .unwrap();
         let code_executed = fetch_code_executed(&path, Some(&written_memory));
-        paths.push(path);
-        code_paths_executed.push(code_executed);
+        self.paths.push(path);
+        self.code_executed.push(code_executed);
+        self.block_sequences.push(
+            Arc::try_unwrap(committed_block_sequence)
+                .ok()
+                .unwrap()
+                .into_inner()
+                .unwrap(),
+        );
 
         let mut executed = HashMap::new();
-        while let Ok(g) = gadget_log.pop() {
+        let mut gadget_sequence = Vec::new();
+        while let Ok((seq, g)) = gadget_log.pop() {
             (*executed.entry(g).or_insert(0)) += 1;
+            gadget_sequence.push((seq, g));
         }
-        gadgets_executed.push(executed);
-        cpu_errors.push(cpu_error);
-        computation_times.push(emulation_time);
-        register_maps.push(RegisterState::new::<C>(
+        // the log is filled from a single thread, but sort it anyway, in
+        // case that ever changes
+        gadget_sequence.sort_unstable();
+        self.gadgets_executed.push(executed);
+        self.gadget_sequences.push(gadget_sequence);
+        self.cpu_errors.push(cpu_error);
+        self.emulation_times.push(emulation_time);
+        self.registers.push(RegisterState::new::<C>(
             &registers.lock().unwrap(),
             Some(&written_memory),
+            deref_depth,
         ));
 
         let log = Arc::try_unwrap(committed_write_log)
@@ -204,54 +501,73 @@ impl<C: 'static + Cpu<'static>> From<Profiler<C>> for Profile {
             .unwrap()
             .into_inner()
             .unwrap();
-        memory_writes.push(log.into());
-
-        ret_counts.push(ret_count.load(std::sync::atomic::Ordering::Relaxed));
+        self.memory_writes.push(log.into());
+        self.writeable_memory.push(written_memory);
+
+        self.ret_counts
+            .push(ret_count.load(std::sync::atomic::Ordering::Relaxed));
+        self.syscalls.push(syscall.lock().unwrap().take());
+        self.logs_truncated
+            .push(log_truncated.load(std::sync::atomic::Ordering::Relaxed));
+        self.clean_exits
+            .push(clean_exit.load(std::sync::atomic::Ordering::Relaxed));
+        let mut writes = Vec::new();
+        if let Some(log) = ordered_write_log {
+            while let Ok(w) = log.pop() {
+                writes.push(w);
+            }
+        }
+        self.write_logs.push(writes);
+        let mut reads = Vec::new();
+        while let Ok(r) = read_log.pop() {
+            reads.push(r);
+        }
+        self.read_logs.push(reads);
+        let mut snapshots = Vec::new();
+        if let Some(log) = register_log {
+            while let Ok((sequence, address, registers)) = log.pop() {
+                snapshots.push(RegisterSnapshot {
+                    sequence,
+                    address,
+                    registers: RegisterState::new::<C>(&registers, None, 0),
+                });
+            }
+        }
+        self.register_snapshots.push(snapshots);
+        self.instruction_counts
+            .push(std::mem::take(&mut *instruction_counts.lock().unwrap()));
+        self.targets_reached
+            .push(target_reached.load(std::sync::atomic::Ordering::Relaxed));
+        self.bad_region_hits
+            .push(bad_region_hits.load(std::sync::atomic::Ordering::Relaxed));
 
         if cfg!(debug_assertions) {
             log::debug!(
+                target: EMULATOR,
                 "registers: {} strong, {} weak",
                 Arc::strong_count(&registers),
                 Arc::weak_count(&registers)
             );
             log::debug!(
+                target: EMULATOR,
                 "gadget_log: {} strong, {} weak",
                 Arc::strong_count(&gadget_log),
                 Arc::weak_count(&gadget_log)
             );
             log::debug!(
+                target: EMULATOR,
                 "trace_log: {} strong, {} weak",
                 Arc::strong_count(&trace_log),
                 Arc::weak_count(&trace_log),
             );
             log::debug!(
+                target: EMULATOR,
                 "call_stack_depth: {} strong, {} weak",
                 Arc::strong_count(&call_stack_depth),
                 Arc::weak_count(&call_stack_depth),
             )
         }
-
-        Self {
-            paths,
-            code_executed: code_paths_executed,
-            cpu_errors,
-            emulation_times: computation_times,
-            gadgets_executed,
-            registers: register_maps,
-            memory_writes,
-            executable: true,
-            ret_counts,
-        }
-    }
-}
-
-fn segqueue_to_vec<T>(sq: Arc<SegQueue<T>>) -> Vec<T> {
-    let mut v = vec![];
-    while let Ok(x) = sq.pop() {
-        v.push(x)
     }
-    //log::debug!("vec of {} blocks", v.len());
-    v
 }
 
 impl Profile {
@@ -265,9 +581,21 @@ impl Profile {
             emulation_times,
             registers,
             gadgets_executed,
+            gadget_sequences,
             memory_writes,
+            writeable_memory,
             executable,
             ret_counts,
+            syscalls,
+            logs_truncated,
+            clean_exits,
+            write_logs,
+            block_sequences,
+            read_logs,
+            instruction_counts,
+            targets_reached,
+            register_snapshots,
+            bad_region_hits,
         } = other;
 
         self.paths.extend(paths.into_iter());
@@ -276,11 +604,296 @@ impl Profile {
         self.emulation_times.extend(emulation_times.into_iter());
         self.registers.extend(registers.into_iter());
         self.gadgets_executed.extend(gadgets_executed.into_iter());
+        self.gadget_sequences.extend(gadget_sequences.into_iter());
         self.memory_writes.extend(memory_writes.into_iter());
+        self.writeable_memory.extend(writeable_memory.into_iter());
         self.ret_counts.extend(ret_counts.into_iter());
+        self.syscalls.extend(syscalls.into_iter());
+        self.logs_truncated.extend(logs_truncated.into_iter());
+        self.clean_exits.extend(clean_exits.into_iter());
+        self.write_logs.extend(write_logs.into_iter());
+        self.block_sequences.extend(block_sequences.into_iter());
+        self.read_logs.extend(read_logs.into_iter());
+        self.instruction_counts
+            .extend(instruction_counts.into_iter());
+        self.targets_reached.extend(targets_reached.into_iter());
+        self.register_snapshots
+            .extend(register_snapshots.into_iter());
+        self.bad_region_hits.extend(bad_region_hits.into_iter());
         self.executable &= executable;
     }
 
+    /// Like `absorb`, but afterwards retains only the `max_paths` most
+    /// recent execution paths, and the `max_write_logs` most recent memory
+    /// write logs, dropping the oldest.
+    pub fn absorb_capped(&mut self, other: Self, max_paths: usize, max_write_logs: usize) {
+        self.absorb(other);
+        self.prune(max_paths, max_write_logs);
+    }
+
+    /// Split the profile into `n` profiles of consecutive runs, undoing the
+    /// absorption of several profiles with the same number of runs. Runs
+    /// whose paths or write logs have been pruned have none in the parts.
+    pub fn split_runs(&self, n: usize) -> Vec<Profile> {
+        let total = self.cpu_errors.len();
+        let size = (total + n.max(1) - 1) / n.max(1);
+        // pruning drops the oldest entries, so shorter logs are aligned
+        // with the most recent runs
+        fn runs<T: Clone>(v: &[T], total: usize, start: usize, end: usize) -> Vec<T> {
+            let offset = total.saturating_sub(v.len());
+            let start = start.saturating_sub(offset).min(v.len());
+            let end = end.saturating_sub(offset).min(v.len());
+            v[start..end].to_vec()
+        }
+        (0..n)
+            .map(|i| {
+                let start = (i * size).min(total);
+                let end = ((i + 1) * size).min(total);
+                Profile {
+                    paths: runs(&self.paths, total, start, end),
+                    code_executed: runs(&self.code_executed, total, start, end),
+                    cpu_errors: runs(&self.cpu_errors, total, start, end),
+                    emulation_times: runs(&self.emulation_times, total, start, end),
+                    registers: runs(&self.registers, total, start, end),
+                    gadgets_executed: runs(&self.gadgets_executed, total, start, end),
+                    gadget_sequences: runs(&self.gadget_sequences, total, start, end),
+                    memory_writes: runs(&self.memory_writes, total, start, end),
+                    writeable_memory: runs(&self.writeable_memory, total, start, end),
+                    executable: self.executable,
+                    ret_counts: runs(&self.ret_counts, total, start, end),
+                    syscalls: runs(&self.syscalls, total, start, end),
+                    logs_truncated: runs(&self.logs_truncated, total, start, end),
+                    clean_exits: runs(&self.clean_exits, total, start, end),
+                    write_logs: runs(&self.write_logs, total, start, end),
+                    block_sequences: runs(&self.block_sequences, total, start, end),
+                    read_logs: runs(&self.read_logs, total, start, end),
+                    instruction_counts: runs(&self.instruction_counts, total, start, end),
+                    targets_reached: runs(&self.targets_reached, total, start, end),
+                    register_snapshots: runs(&self.register_snapshots, total, start, end),
+                    bad_region_hits: runs(&self.bad_region_hits, total, start, end),
+                }
+            })
+            .collect()
+    }
+
+    /// Drop all but the `max_paths` most recent execution paths (along with
+    /// the code they executed), and all but the `max_write_logs` most recent
+    /// memory write logs (along with the written segments).
+    pub fn prune(&mut self, max_paths: usize, max_write_logs: usize) {
+        fn drop_oldest<T>(v: &mut Vec<T>, max: usize) {
+            if v.len() > max {
+                v.drain(..v.len() - max);
+            }
+        }
+        drop_oldest(&mut self.paths, max_paths);
+        drop_oldest(&mut self.code_executed, max_paths);
+        drop_oldest(&mut self.block_sequences, max_paths);
+        drop_oldest(&mut self.gadget_sequences, max_paths);
+        drop_oldest(&mut self.register_snapshots, max_paths);
+        drop_oldest(&mut self.memory_writes, max_write_logs);
+        drop_oldest(&mut self.writeable_memory, max_write_logs);
+        drop_oldest(&mut self.write_logs, max_write_logs);
+        drop_oldest(&mut self.read_logs, max_write_logs);
+    }
+
+    /// Drop the oldest execution paths and memory write logs, one run at
+    /// a time, until the approximate size of the profile is within
+    /// `max_bytes`, or only the most recent run is left.
+    pub fn prune_to_size(&mut self, max_bytes: usize) {
+        let mut runs = self.paths.len().max(self.memory_writes.len());
+        while runs > 1 && self.approx_heap_size() > max_bytes {
+            runs -= 1;
+            self.prune(runs, runs);
+        }
+    }
+
+    /// A coarse estimate, in bytes, of the heap memory held by the profile.
+    pub fn approx_heap_size(&self) -> usize {
+        use std::mem::size_of;
+
+        self.paths
+            .iter()
+            .map(|p| p.len() * size_of::<Block>())
+            .sum::<usize>()
+            + self.code_executed.iter().map(Vec::len).sum::<usize>()
+            + self.cpu_errors.len() * size_of::<Option<UCError>>()
+            + self.emulation_times.len() * size_of::<Duration>()
+            + self
+                .registers
+                .iter()
+                .flat_map(|r| r.0.values())
+                .map(|vals| vals.len() * size_of::<u64>())
+                .sum::<usize>()
+            + self
+                .gadgets_executed
+                .iter()
+                .map(|g| g.len() * size_of::<(u64, usize)>())
+                .sum::<usize>()
+            + self
+                .gadget_sequences
+                .iter()
+                .map(|g| g.len() * size_of::<(usize, u64)>())
+                .sum::<usize>()
+            + self
+                .memory_writes
+                .iter()
+                .map(SparseData::len)
+                .sum::<usize>()
+            + self
+                .writeable_memory
+                .iter()
+                .flatten()
+                .map(|seg| seg.data.len())
+                .sum::<usize>()
+            + self.ret_counts.len() * size_of::<usize>()
+            + self
+                .syscalls
+                .iter()
+                .flatten()
+                .map(|s| size_of::<Syscall>() + s.args.len() * size_of::<u64>())
+                .sum::<usize>()
+            + self.logs_truncated.len() * size_of::<bool>()
+            + self.clean_exits.len() * size_of::<bool>()
+            + self
+                .write_logs
+                .iter()
+                .map(|w| w.len() * size_of::<MemLogEntry>())
+                .sum::<usize>()
+            + self
+                .block_sequences
+                .iter()
+                .map(|s| s.len() * size_of::<usize>())
+                .sum::<usize>()
+            + self
+                .read_logs
+                .iter()
+                .map(|r| r.len() * size_of::<ReadLogEntry>())
+                .sum::<usize>()
+            + self
+                .instruction_counts
+                .iter()
+                .flatten()
+                .map(|(m, _)| m.len() + size_of::<usize>())
+                .sum::<usize>()
+            + self.targets_reached.len() * size_of::<bool>()
+            + self
+                .register_snapshots
+                .iter()
+                .flatten()
+                .flat_map(|s| (s.registers).0.values())
+                .map(|vals| vals.len() * size_of::<u64>())
+                .sum::<usize>()
+            + self.bad_region_hits.len() * size_of::<usize>()
+    }
+
+    /// Reconstruct the execution trace of each run, as a list of events in
+    /// the order in which they happened: the blocks executed, each gadget's
+    /// block preceded by a snapshot of the registers on entering it, with
+    /// each memory write following the block whose code made it, then the
+    /// registers read at the last composable return, and finally how the
+    /// run ended. Blocks and writes are put in order by their sequence
+    /// numbers, where the run's blocks have them, and otherwise each write
+    /// is placed after the first block from there on that contains its
+    /// program counter.
+    ///
+    /// Only what was recorded can be traced. Blocks are only logged when
+    /// `roper.record_basic_blocks` is set, and every visit to a block only
+    /// in the `Full` block log mode. The order of writes is only kept when
+    /// `roper.record_write_order` is set, and register snapshots are only
+    /// taken when `roper.record_register_snapshots` is set. Writes made after the last block
+    /// in the path, such as those made after the last return, come after
+    /// it. This is meant for the close inspection of a single creature,
+    /// re-run with all of these settings, rather than for a whole
+    /// population.
+    pub fn to_trace(&self) -> Vec<Vec<TraceEvent>> {
+        let total = self.cpu_errors.len();
+        // pruning drops the oldest entries, so shorter logs are aligned
+        // with the most recent runs
+        fn run<T>(v: &[T], total: usize, i: usize) -> Option<&T> {
+            (i + v.len()).checked_sub(total).and_then(|i| v.get(i))
+        }
+        (0..total)
+            .map(|i| {
+                let mut events = Vec::new();
+                let mut writes = run(&self.write_logs, total, i)
+                    .map(Vec::as_slice)
+                    .unwrap_or(&[])
+                    .iter()
+                    .peekable();
+                let path = run(&self.paths, total, i).map(Vec::as_slice).unwrap_or(&[]);
+                let sequence = run(&self.block_sequences, total, i)
+                    .filter(|s| s.len() == path.len() && !s.is_empty());
+                let mut snapshots = run(&self.register_snapshots, total, i)
+                    .map(Vec::as_slice)
+                    .unwrap_or(&[])
+                    .iter()
+                    .peekable();
+                for (j, block) in path.iter().enumerate() {
+                    while let Some(s) = snapshots.peek() {
+                        let taken_here = match sequence {
+                            Some(seq) => s.sequence <= seq[j],
+                            None => s.address == block.entry,
+                        };
+                        if !taken_here {
+                            break;
+                        }
+                        events.push(TraceEvent::Snapshot((*s).clone()));
+                        snapshots.next();
+                        if sequence.is_none() {
+                            break;
+                        }
+                    }
+                    events.push(TraceEvent::Block(block.clone()));
+                    let end = block.entry + block.size as u64;
+                    while let Some(w) = writes.peek() {
+                        let made_here = match sequence {
+                            Some(seq) => seq.get(j + 1).map_or(true, |next| w.sequence < *next),
+                            None => block.entry <= w.program_counter && w.program_counter < end,
+                        };
+                        if !made_here {
+                            break;
+                        }
+                        events.push(TraceEvent::Write(**w));
+                        writes.next();
+                    }
+                }
+                events.extend(writes.map(|w| TraceEvent::Write(*w)));
+                events.extend(snapshots.map(|s| TraceEvent::Snapshot(s.clone())));
+                if let Some(registers) = run(&self.registers, total, i) {
+                    events.push(TraceEvent::Registers(registers.clone()));
+                }
+                events.push(TraceEvent::Exit {
+                    cpu_error: self.cpu_errors[i],
+                    clean_exit: run(&self.clean_exits, total, i).copied().unwrap_or(false),
+                    syscall: run(&self.syscalls, total, i).cloned().flatten(),
+                });
+                events
+            })
+            .collect()
+    }
+
+    /// Whether any gadgets were executed in any of the runs. Gadgets are
+    /// only logged when `roper.record_basic_blocks` is set, so this is
+    /// false for every profile recorded without it.
+    pub fn executed_any_gadgets(&self) -> bool {
+        self.executable && self.gadgets_executed.iter().any(|g| !g.is_empty())
+    }
+
+    /// The number of runs that ended by returning to `roper.return_address`,
+    /// without a CPU error.
+    pub fn clean_exits(&self) -> usize {
+        self.clean_exits
+            .iter()
+            .zip(self.cpu_errors.iter())
+            .filter(|(clean, error)| **clean && error.is_none())
+            .count()
+    }
+
+    /// The number of runs in which a system call was reached.
+    pub fn syscalls_reached(&self) -> usize {
+        self.syscalls.iter().filter(|s| s.is_some()).count()
+    }
+
     pub fn avg_emulation_micros(&self) -> f64 {
         self.emulation_times.iter().sum::<Duration>().as_micros() as f64
             / self.emulation_times.len() as f64
@@ -299,12 +912,49 @@ impl Profile {
                     } else {
                         ""
                     };
-                    format!("{}{}", prefix, b.disassemble())
+                    let disassembly = b.disassembly().unwrap_or_else(|| {
+                        log::warn!(target: EMULATOR, "Failed to disassemble {:?}", b);
+                        format!("{:?} <unavailable>\n", b)
+                    });
+                    format!("{}{}", prefix, disassembly)
                 })
                 .collect::<String>()
         })
     }
 
+    /// Counts the instructions executed in every run of the profile by
+    /// category. Blocks that can't be disassembled are left out.
+    pub fn instruction_category_histogram(&self) -> BTreeMap<InstructionCategory, usize> {
+        let mut histogram = BTreeMap::new();
+        for block in self.paths.iter().flatten() {
+            for category in block.categories().unwrap_or_default() {
+                *histogram.entry(category).or_insert(0) += 1;
+            }
+        }
+        histogram
+    }
+
+    /// The number of logged reads, over all runs, that touched `addr`.
+    /// Only reads that touch one of `roper.read_watchpoints` are logged.
+    pub fn times_read(&self, addr: u64) -> usize {
+        self.read_logs
+            .iter()
+            .flatten()
+            .filter(|r| r.address <= addr && addr - r.address < r.num_bytes_read as u64)
+            .count()
+    }
+
+    /// The number of times an instruction with the given mnemonic was
+    /// executed, over all runs, ignoring case. Only the mnemonics in
+    /// `roper.count_mnemonics` are counted.
+    pub fn instruction_count(&self, mnemonic: &str) -> usize {
+        let mnemonic = mnemonic.to_ascii_lowercase();
+        self.instruction_counts
+            .iter()
+            .filter_map(|counts| counts.get(&mnemonic))
+            .sum()
+    }
+
     pub fn times_executed(&self, w: u64) -> usize {
         let mut count = 0;
         for gads in self.gadgets_executed.iter() {
@@ -324,7 +974,7 @@ impl Profile {
     /// instructions in sequence, each of which belonging to the
     /// chromosome. But it's a good enough place to start.
     pub fn gadgets_executed(&self, index: usize) -> usize {
-        if index < self.gadgets_executed.len() {
+        if index >= self.gadgets_executed.len() || index >= self.ret_counts.len() {
             0
         } else {
             self.gadgets_executed[index]
@@ -333,6 +983,108 @@ impl Profile {
         }
     }
 
+    /// Resolve `len` bytes at `addr` as they stood at the end of the most
+    /// recent run, consulting the written memory captured in the profile
+    /// before falling back on the static memory image.
+    pub fn dereference(&self, addr: u64, len: usize) -> Option<Vec<u8>> {
+        if self.writeable_memory.is_empty() && self.memory_writes.is_empty() {
+            return get_static_memory_image()
+                .try_dereference(addr, None)
+                .filter(|b| b.len() >= len)
+                .map(|b| b[..len].to_vec());
+        }
+        let last = self.writeable_memory.len().max(self.memory_writes.len()) - 1;
+        self.dereference_in_run(last, addr, len)
+    }
+
+    /// Like `dereference`, but for the run at `index`. The written segments
+    /// shadow the static memory image, and the committed write log (which
+    /// reflects the state at the last composable return, like the register
+    /// state) is overlaid on top of that.
+    pub fn dereference_in_run(&self, index: usize, addr: u64, len: usize) -> Option<Vec<u8>> {
+        let memory = get_static_memory_image();
+        let extra_segs = self.writeable_memory.get(index).map(Vec::as_slice);
+        let mut buf = memory
+            .try_dereference(addr, extra_segs)
+            .filter(|b| b.len() >= len)
+            .map(|b| b[..len].to_vec())?;
+        if let Some(writes) = self.memory_writes.get(index) {
+            writes.overlay(addr, &mut buf);
+        }
+        Some(buf)
+    }
+
+    /// A compact summary of the profile, small enough to export alongside
+    /// every member of a population.
+    pub fn summary(&self) -> ProfileSummary {
+        ProfileSummary {
+            num_runs: self.paths.len(),
+            executable: self.executable,
+            blocks_visited: self
+                .paths
+                .iter()
+                .flatten()
+                .map(|b| b.entry)
+                .collect::<HashSet<u64>>()
+                .len(),
+            addresses_visited: self.addresses_visited().len(),
+            gadgets_executed: self
+                .gadgets_executed
+                .iter()
+                .flat_map(|g| g.keys())
+                .collect::<HashSet<&u64>>()
+                .len(),
+            ret_counts: self.ret_counts.clone(),
+            cpu_errors: self.cpu_errors.iter().filter(|e| e.is_some()).count(),
+            bytes_written: self.memory_writes.iter().map(SparseData::len).sum(),
+            avg_emulation_micros: self.avg_emulation_micros(),
+            syscalls_reached: self.syscalls_reached(),
+            clean_exits: self.clean_exits(),
+            executable_writes: self.executable_addresses_written_to().len(),
+        }
+    }
+
+    /// The loops in the executed paths. A block that is re-entered while
+    /// it's still on the current (loop-free) path is taken to be the header
+    /// of a loop, whose body is everything executed since, and the path is
+    /// then cut back to the header, so each turn through a loop counts as
+    /// one iteration of that loop alone. Paths recorded with
+    /// `BlockLogMode::Edges` don't repeat, and so show at most one
+    /// iteration of each loop.
+    pub fn loops(&self) -> Vec<LoopInfo> {
+        let mut loops: BTreeMap<u64, (BTreeSet<u64>, usize)> = BTreeMap::new();
+        for path in self.paths.iter() {
+            let mut stack: Vec<u64> = Vec::new();
+            for block in path.iter() {
+                match stack.iter().position(|e| *e == block.entry) {
+                    Some(start) => {
+                        let body = stack[start..]
+                            .iter()
+                            .flat_map(|e| {
+                                let nested = loops.get(e).map(|(b, _)| b.clone());
+                                std::iter::once(*e).chain(nested.into_iter().flatten())
+                            })
+                            .collect::<Vec<u64>>();
+                        let info = loops.entry(block.entry).or_default();
+                        info.0.extend(body);
+                        info.1 += 1;
+                        stack.truncate(start + 1);
+                    }
+                    None => stack.push(block.entry),
+                }
+            }
+        }
+        loops
+            .into_iter()
+            .map(|(header, (body, iterations))| LoopInfo {
+                header,
+                body_blocks: body.into_iter().collect(),
+                iterations,
+            })
+            .collect()
+    }
+
+    /// Every address covered by a block executed in any of the runs.
     pub fn addresses_visited(&self) -> HashSet<u64> {
         let mut set = HashSet::new();
         for path in self.paths.iter() {
@@ -344,13 +1096,102 @@ impl Profile {
         }
         set
     }
+
+    /// The control flow edges traversed in any of the runs, as pairs of the
+    /// entry points of consecutive blocks in a path. In the `Edges` block
+    /// log mode, consecutive blocks weren't necessarily executed one after
+    /// the other, but every edge that was taken is still among these.
+    pub fn edge_coverage(&self) -> HashSet<(u64, u64)> {
+        self.paths
+            .iter()
+            .flat_map(|path| path.windows(2).map(|w| (w[0].entry, w[1].entry)))
+            .collect()
+    }
+
+    /// Every address written to in any of the runs whose memory writes
+    /// haven't been pruned.
+    pub fn addresses_written_to(&self) -> HashSet<u64> {
+        self.memory_writes
+            .iter()
+            .flat_map(SparseData::addresses)
+            .collect()
+    }
+
+    /// The addresses written to that lie within any of the given segments,
+    /// sorted.
+    pub fn addresses_written_to_in(&self, segs: &[&Seg]) -> Vec<u64> {
+        let mut addrs = self
+            .addresses_written_to()
+            .into_iter()
+            .filter(|a| {
+                segs.iter()
+                    .any(|s| s.aligned_start() <= *a && *a < s.aligned_end())
+            })
+            .collect::<Vec<u64>>();
+        addrs.sort_unstable();
+        addrs
+    }
+
+    /// The addresses written to that lie in the executable segments of the
+    /// static memory image, sorted. Such a write may have modified the code
+    /// of a gadget, so that what was executed there afterwards is not what
+    /// the image's disassembly, or the coverage accounting, takes it to be.
+    pub fn executable_addresses_written_to(&self) -> Vec<u64> {
+        match try_to_get_static_memory_image() {
+            Some(memory) => {
+                let segs = memory
+                    .segments()
+                    .iter()
+                    .filter(|s| s.is_executable())
+                    .collect::<Vec<&Seg>>();
+                self.addresses_written_to_in(&segs)
+            }
+            None => vec![],
+        }
+    }
+
+    /// Whether any of the runs wrote into executable memory, possibly
+    /// modifying its own gadgets.
+    pub fn wrote_to_executable(&self) -> bool {
+        !self.executable_addresses_written_to().is_empty()
+    }
+}
+
+/// The total number of times each gadget was executed, over every run of
+/// every profile given. Counts are taken from `gadgets_executed`, and so
+/// only cover the addresses in each chain that were actually reached.
+pub fn gadget_execution_counts<'a, I>(profiles: I) -> HashMap<u64, usize>
+where
+    I: IntoIterator<Item = &'a Profile>,
+{
+    let mut counts = HashMap::new();
+    for run in profiles.into_iter().flat_map(|p| p.gadgets_executed.iter()) {
+        for (addr, n) in run.iter() {
+            *counts.entry(*addr).or_insert(0) += n;
+        }
+    }
+    counts
 }
 
-// impl<C: 'static + Cpu<'static>> From<Vec<Profiler<C>>> for Profile {
-//     fn from(v: Vec<Profiler<C>>) -> Self {
-//         Self::collate(v)
-//     }
-// }
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ProfileSummary {
+    pub num_runs: usize,
+    pub executable: bool,
+    pub blocks_visited: usize,
+    pub addresses_visited: usize,
+    pub gadgets_executed: usize,
+    pub ret_counts: Vec<usize>,
+    pub cpu_errors: usize,
+    pub bytes_written: usize,
+    pub avg_emulation_micros: f64,
+    pub syscalls_reached: usize,
+    /// The number of runs that reached the sentinel return address.
+    #[serde(default)]
+    pub clean_exits: usize,
+    /// The number of distinct addresses in executable memory written to.
+    #[serde(default)]
+    pub executable_writes: usize,
+}
 
 impl<C: Cpu<'static>> fmt::Debug for Profiler<C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -383,6 +1224,22 @@ pub struct MemLogEntry {
     pub address: u64,
     pub num_bytes_written: usize,
     pub value: u64,
+    /// The place of the write in the run's sequence of blocks and writes.
+    #[serde(default)]
+    pub sequence: usize,
+}
+
+/// A read from memory, logged because it touched a read watchpoint.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize, Hash)]
+pub struct ReadLogEntry {
+    pub program_counter: u64,
+    pub address: u64,
+    pub num_bytes_read: usize,
+    /// The value read, as an integer of the architecture's endianness.
+    pub value: u64,
+    /// The place of the read in the run's sequence of blocks and writes.
+    #[serde(default)]
+    pub sequence: usize,
 }
 
 #[derive(Clone, Hash, Default)]
@@ -468,7 +1325,7 @@ impl SparseDataHelper {
                 let mut new_buf = vec![];
                 std::mem::swap(&mut buf, &mut new_buf);
                 if !new_buf.is_empty() {
-                    let start = 1 + last - new_buf.len() as u64;
+                    let start = last - (new_buf.len() as u64 - 1);
                     scoped.insert(start, new_buf);
                 }
             }
@@ -478,7 +1335,7 @@ impl SparseDataHelper {
         }
 
         if !buf.is_empty() {
-            let start = 1 + last - buf.len() as u64;
+            let start = last - (buf.len() as u64 - 1);
             scoped.insert(start, buf);
         }
 
@@ -486,7 +1343,7 @@ impl SparseDataHelper {
     }
 }
 
-#[derive(Clone, Hash)]
+#[derive(Clone, Hash, Default, Serialize, Deserialize)]
 pub struct SparseData(BTreeMap<u64, Vec<u8>>);
 
 impl From<SparseDataHelper> for SparseData {
@@ -513,6 +1370,52 @@ impl SparseData {
     pub fn len(&self) -> usize {
         self.0.values().map(|buf| buf.len()).sum()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Every address written to, in ascending order. Bytes that would lie
+    /// past the top of the address space are skipped.
+    pub fn addresses(&self) -> impl Iterator<Item = u64> + '_ {
+        self.0
+            .iter()
+            .flat_map(|(addr, buf)| (0..buf.len() as u64).filter_map(move |i| addr.checked_add(i)))
+    }
+
+    /// The lowest address written to, and one past the highest, saturating
+    /// at the top of the address space.
+    pub fn span(&self) -> Option<(u64, u64)> {
+        let lo = *self.0.keys().next()?;
+        let hi = self
+            .0
+            .iter()
+            .map(|(addr, buf)| addr.saturating_add(buf.len() as u64))
+            .max()?;
+        Some((lo, hi))
+    }
+
+    /// Copy any written bytes falling within `addr..addr + buf.len()`
+    /// into `buf`.
+    pub fn overlay(&self, addr: u64, buf: &mut [u8]) {
+        if buf.is_empty() {
+            return;
+        }
+        // the last address is used, rather than one past it, so that a
+        // buffer reaching the top of the address space doesn't overflow
+        let last = addr.saturating_add(buf.len() as u64 - 1);
+        for (start, data) in self.0.range(..=last) {
+            for (i, byte) in data.iter().enumerate() {
+                let a = match start.checked_add(i as u64) {
+                    Some(a) => a,
+                    None => break,
+                };
+                if a >= addr && a <= last {
+                    buf[(a - addr) as usize] = *byte;
+                }
+            }
+        }
+    }
 }
 
 impl fmt::Debug for SparseData {
@@ -523,7 +1426,7 @@ impl fmt::Debug for SparseData {
                 f,
                 "    0x{:x}..0x{:x} => {}",
                 addr,
-                addr + buf.len() as u64,
+                addr.saturating_add(buf.len() as u64),
                 hex::encode(&buf)
             )?;
         }
@@ -576,4 +1479,605 @@ mod test {
 
         println!("res = {:#x?}", res);
     }
+
+    #[test]
+    fn test_log_limit() {
+        let len = AtomicUsize::new(0);
+        let truncated = AtomicBool::new(false);
+        assert!((0..3).all(|_| within_log_limit(Some(3), &len, &truncated)));
+        assert!(!truncated.load(std::sync::atomic::Ordering::Relaxed));
+        assert!(!within_log_limit(Some(3), &len, &truncated));
+        assert!(truncated.load(std::sync::atomic::Ordering::Relaxed));
+
+        let len = AtomicUsize::new(0);
+        let truncated = AtomicBool::new(false);
+        assert!((0..100).all(|_| within_log_limit(None, &len, &truncated)));
+        assert!(!truncated.load(std::sync::atomic::Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_loops() {
+        let path = |entries: &[u64]| {
+            entries
+                .iter()
+                .map(|entry| Block {
+                    entry: *entry,
+                    size: 1,
+                })
+                .collect::<Vec<Block>>()
+        };
+        let profile = Profile {
+            paths: vec![
+                // an inner loop 3-4, run three times, inside an outer loop
+                // 2-3-4-5, run twice
+                path(&[1, 2, 3, 4, 3, 4, 3, 4, 5, 2, 3, 4, 5, 6]),
+                path(&[7, 8, 9]),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            profile.loops(),
+            vec![
+                LoopInfo {
+                    header: 2,
+                    body_blocks: vec![2, 3, 4, 5],
+                    iterations: 1,
+                },
+                LoopInfo {
+                    header: 3,
+                    body_blocks: vec![3, 4],
+                    iterations: 2,
+                },
+            ]
+        );
+        assert!(Profile::default().loops().is_empty());
+    }
+
+    #[test]
+    fn test_edge_coverage() {
+        let path = |entries: &[u64]| {
+            entries
+                .iter()
+                .map(|entry| Block {
+                    entry: *entry,
+                    size: 1,
+                })
+                .collect::<Vec<Block>>()
+        };
+        let profile = Profile {
+            paths: vec![path(&[1, 2, 1, 2, 3]), path(&[3, 1]), path(&[4])],
+            ..Default::default()
+        };
+        let mut edges = profile.edge_coverage().into_iter().collect::<Vec<_>>();
+        edges.sort();
+        assert_eq!(edges, vec![(1, 2), (2, 1), (2, 3), (3, 1)]);
+    }
+
+    #[test]
+    fn test_clean_exits() {
+        let mut profile = Profile {
+            cpu_errors: vec![None, None, None],
+            clean_exits: vec![true, false, true],
+            ..Default::default()
+        };
+        assert_eq!(profile.clean_exits(), 2);
+        profile.absorb(Profile {
+            cpu_errors: vec![None],
+            clean_exits: vec![true],
+            ..Default::default()
+        });
+        assert_eq!(profile.clean_exits(), 3);
+    }
+
+    #[test]
+    fn test_edge_log() {
+        let mut edges = EdgeLog::default();
+        // a loop of two blocks, entered from 1 and run three times
+        let visits = [1, 2, 3, 2, 3, 2, 3, 4]
+            .iter()
+            .map(|entry| edges.first_visit(*entry))
+            .collect::<Vec<bool>>();
+        assert_eq!(
+            visits,
+            vec![true, true, true, true, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn test_sparse_data_overlay() {
+        let mut sparse = SparseDataHelper::new();
+        sparse.insert_u8(0x10, 0xaa);
+        sparse.insert_u8(0x11, 0xbb);
+        sparse.insert_u8(0x20, 0xcc);
+        let sparse: SparseData = sparse.into();
+
+        let mut buf = vec![0_u8; 4];
+        sparse.overlay(0x0f, &mut buf);
+        assert_eq!(buf, vec![0, 0xaa, 0xbb, 0]);
+
+        // writes at the top of the address space don't overflow
+        let mut sparse = SparseDataHelper::new();
+        sparse.insert_u8(std::u64::MAX - 1, 0xdd);
+        sparse.insert_u8(std::u64::MAX, 0xee);
+        let sparse: SparseData = sparse.into();
+        let mut buf = vec![0_u8; 4];
+        sparse.overlay(std::u64::MAX - 3, &mut buf);
+        assert_eq!(buf, vec![0, 0, 0xdd, 0xee]);
+        assert_eq!(sparse.span(), Some((std::u64::MAX - 1, std::u64::MAX)));
+        assert_eq!(
+            sparse.addresses().collect::<Vec<u64>>(),
+            vec![std::u64::MAX - 1, std::u64::MAX]
+        );
+        // even a buffer running past the top of the address space
+        let sparse = SparseData(
+            vec![(std::u64::MAX, vec![0xff, 0xff])]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(
+            sparse.addresses().collect::<Vec<u64>>(),
+            vec![std::u64::MAX]
+        );
+    }
+
+    #[test]
+    fn test_writeable_memory_captured() {
+        use crate::configure::RoperConfig;
+        use crate::util::architecture::Perms;
+
+        let config = RoperConfig {
+            arch: unicorn::Arch::X86,
+            mode: unicorn::Mode::MODE_64,
+            emulator_stack_size: 0x1000,
+            binary_path: "/bin/sh".to_string(),
+            ..Default::default()
+        };
+        let _ = loader::load_from_path(&config, true);
+
+        let mut profiler: Profiler<CpuX86> = Profiler::default();
+        profiler.written_memory = vec![Seg {
+            addr: 0x1000,
+            memsz: 0x1000,
+            perm: Perms::READ | Perms::WRITE,
+            segtype: loader::SegType::Stack,
+            data: vec![0xff; 0x1000],
+        }];
+
+        let profile: Profile = profiler.into();
+        assert_eq!(profile.writeable_memory.len(), 1);
+        assert!(!profile.writeable_memory[0].is_empty());
+        assert_eq!(profile.writeable_memory[0][0].data[0], 0xff);
+    }
+
+    #[test]
+    fn test_writes_to_executable() {
+        use crate::configure::RoperConfig;
+
+        let config = RoperConfig {
+            arch: unicorn::Arch::X86,
+            mode: unicorn::Mode::MODE_64,
+            binary_path: "/bin/sh".to_string(),
+            ..Default::default()
+        };
+        let _ = loader::load_from_path(&config, true);
+        let memory = loader::get_static_memory_image();
+        let code = memory
+            .segments()
+            .iter()
+            .find(|s| s.is_executable())
+            .expect("no executable segment");
+        let data = memory
+            .segments()
+            .iter()
+            .find(|s| !s.is_executable())
+            .expect("no unexecutable segment");
+
+        let mut writes = SparseDataHelper::new();
+        writes.insert_word(data.aligned_start(), 0xdead_beef, 4);
+        let mut profile = Profile {
+            memory_writes: vec![writes.into()],
+            ..Default::default()
+        };
+        assert_eq!(profile.addresses_written_to().len(), 4);
+        assert!(!profile.wrote_to_executable());
+
+        let mut writes = SparseDataHelper::new();
+        writes.insert_u8(code.aligned_start() + 1, 0x90);
+        writes.insert_u8(code.aligned_start() + 2, 0x90);
+        profile.memory_writes.push(writes.into());
+        assert!(profile.wrote_to_executable());
+        assert_eq!(
+            profile.executable_addresses_written_to(),
+            vec![code.aligned_start() + 1, code.aligned_start() + 2]
+        );
+        assert_eq!(profile.summary().executable_writes, 2);
+    }
+
+    #[test]
+    fn test_times_read() {
+        let read = |address, num_bytes_read| ReadLogEntry {
+            program_counter: 0x400000,
+            address,
+            num_bytes_read,
+            value: 0,
+            sequence: 0,
+        };
+        let mut profile = Profile {
+            read_logs: vec![vec![read(0x1000, 8), read(0x1004, 1)], vec![]],
+            cpu_errors: vec![None, None],
+            ..Default::default()
+        };
+        profile.absorb(Profile {
+            read_logs: vec![vec![read(0x0ffc, 4)]],
+            cpu_errors: vec![None],
+            ..Default::default()
+        });
+        assert_eq!(profile.times_read(0x1000), 1);
+        assert_eq!(profile.times_read(0x1004), 2);
+        assert_eq!(profile.times_read(0x0fff), 1);
+        assert_eq!(profile.times_read(0x1008), 0);
+        let parts = profile.split_runs(3);
+        assert_eq!(parts[2].read_logs, vec![vec![read(0x0ffc, 4)]]);
+        // a read at the top of the address space doesn't overflow
+        let top = Profile {
+            read_logs: vec![vec![read(std::u64::MAX - 3, 4)]],
+            cpu_errors: vec![None],
+            ..Default::default()
+        };
+        assert_eq!(top.times_read(std::u64::MAX), 1);
+        assert_eq!(top.times_read(0), 0);
+    }
+
+    #[test]
+    fn test_instruction_counts() {
+        let profilers = (0..3).map(|i| {
+            let profiler: Profiler<CpuX86> = Profiler::default();
+            {
+                let mut counts = profiler.instruction_counts.lock().unwrap();
+                counts.insert("call".to_string(), i);
+                if i == 2 {
+                    counts.insert("syscall".to_string(), 1);
+                }
+            }
+            profiler
+        });
+        let profile = Profile::collate_iter(profilers);
+        assert_eq!(profile.instruction_counts.len(), 3);
+        assert_eq!(profile.instruction_count("call"), 3);
+        assert_eq!(profile.instruction_count("CALL"), 3);
+        assert_eq!(profile.instruction_count("syscall"), 1);
+        assert_eq!(profile.instruction_count("ret"), 0);
+    }
+
+    #[test]
+    fn test_collate_iter() {
+        use crate::configure::RoperConfig;
+
+        let config = RoperConfig {
+            arch: unicorn::Arch::X86,
+            mode: unicorn::Mode::MODE_64,
+            binary_path: "/bin/sh".to_string(),
+            ..Default::default()
+        };
+        let _ = loader::load_from_path(&config, true);
+
+        let profilers = (0..5).map(|i| {
+            let profiler: Profiler<CpuX86> = Profiler::default();
+            profiler
+                .ret_count
+                .store(i, std::sync::atomic::Ordering::Relaxed);
+            profiler
+        });
+        let profile = Profile::collate_iter(profilers);
+        assert!(profile.executable);
+        assert_eq!(profile.ret_counts, vec![0, 1, 2, 3, 4]);
+        assert_eq!(profile.paths.len(), 5);
+        assert_eq!(profile.registers.len(), 5);
+        assert_eq!(profile.memory_writes.len(), 5);
+        assert_eq!(profile.writeable_memory.len(), 5);
+    }
+
+    #[test]
+    fn test_absorb_capped() {
+        let run = |i: u64| Profile {
+            paths: vec![vec![Block { entry: i, size: 1 }]],
+            code_executed: vec![vec![i as u8]],
+            memory_writes: vec![SparseData::default()],
+            writeable_memory: vec![vec![]],
+            ret_counts: vec![i as usize],
+            executable: true,
+            ..Default::default()
+        };
+        let mut profile = run(0);
+        for i in 1..10 {
+            profile.absorb_capped(run(i), 3, 2);
+        }
+        assert_eq!(
+            profile.paths.iter().map(|p| p[0].entry).collect::<Vec<_>>(),
+            vec![7, 8, 9]
+        );
+        assert_eq!(profile.code_executed, vec![vec![7], vec![8], vec![9]]);
+        assert_eq!(profile.memory_writes.len(), 2);
+        assert_eq!(profile.writeable_memory.len(), 2);
+        // everything else is retained
+        assert_eq!(profile.ret_counts.len(), 10);
+    }
+
+    #[test]
+    fn test_syscalls_absorbed_and_summarized() {
+        let run = |syscall: Option<u64>| Profile {
+            syscalls: vec![syscall.map(|number| Syscall {
+                address: 0x1000,
+                number,
+                args: vec![0, 0, 0],
+            })],
+            ..Default::default()
+        };
+        let mut profile = run(Some(59));
+        profile.absorb(run(None));
+        profile.absorb(run(Some(1)));
+        assert_eq!(profile.syscalls.len(), 3);
+        assert_eq!(profile.syscalls_reached(), 2);
+        assert_eq!(profile.summary().syscalls_reached, 2);
+        assert_eq!(profile.syscalls[2].as_ref().map(|s| s.number), Some(1));
+    }
+
+    #[test]
+    fn test_split_runs() {
+        let run = |i: u64| Profile {
+            paths: vec![vec![Block { entry: i, size: 1 }]],
+            code_executed: vec![vec![0]],
+            cpu_errors: vec![None],
+            memory_writes: vec![SparseData::default()],
+            writeable_memory: vec![vec![]],
+            ret_counts: vec![i as usize],
+            executable: true,
+            ..Default::default()
+        };
+        let mut profile = run(0);
+        for i in 1..6 {
+            profile.absorb(run(i));
+        }
+        let parts = profile.split_runs(3);
+        assert_eq!(parts.len(), 3);
+        for (i, part) in parts.iter().enumerate() {
+            assert_eq!(part.ret_counts, vec![2 * i, 2 * i + 1]);
+            assert_eq!(part.paths.len(), 2);
+            assert_eq!(part.paths[0][0].entry, 2 * i as u64);
+            assert!(part.executable);
+        }
+
+        // the paths of the oldest runs are gone
+        profile.prune(3, 6);
+        let parts = profile.split_runs(3);
+        assert!(parts[0].paths.is_empty());
+        assert_eq!(parts[1].paths.len(), 1);
+        assert_eq!(parts[1].paths[0][0].entry, 3);
+        assert_eq!(parts[2].paths.len(), 2);
+        assert_eq!(parts[2].memory_writes.len(), 2);
+    }
+
+    #[test]
+    fn test_to_trace() {
+        let write = |program_counter: u64, address: u64| MemLogEntry {
+            program_counter,
+            address,
+            num_bytes_written: 8,
+            value: 0,
+            sequence: 0,
+        };
+        let run = |writes: Vec<MemLogEntry>| Profile {
+            paths: vec![vec![
+                Block {
+                    entry: 0x10,
+                    size: 4,
+                },
+                Block {
+                    entry: 0x20,
+                    size: 4,
+                },
+                Block {
+                    entry: 0x10,
+                    size: 4,
+                },
+            ]],
+            cpu_errors: vec![None],
+            registers: vec![RegisterState(HashMap::new())],
+            clean_exits: vec![true],
+            write_logs: vec![writes],
+            executable: true,
+            ..Default::default()
+        };
+        let mut profile = run(vec![
+            write(0x12, 1),
+            write(0x21, 2),
+            write(0x11, 3),
+            write(0x40, 4),
+        ]);
+        profile.absorb(run(vec![]));
+        let traces = profile.to_trace();
+        assert_eq!(traces.len(), 2);
+        let summary = traces[0]
+            .iter()
+            .map(|e| match e {
+                TraceEvent::Block(b) => format!("b{:x}", b.entry),
+                TraceEvent::Write(w) => format!("w{}", w.address),
+                TraceEvent::Snapshot(s) => format!("s{:x}", s.address),
+                TraceEvent::Registers(_) => "r".to_string(),
+                TraceEvent::Exit { clean_exit, .. } => format!("x{}", clean_exit),
+            })
+            .collect::<Vec<String>>();
+        // the write made after the last block follows it
+        assert_eq!(
+            summary,
+            vec!["b10", "w1", "b20", "w2", "b10", "w3", "w4", "r", "xtrue"]
+        );
+        assert_eq!(traces[1].len(), 5);
+
+        // the paths of the oldest run are gone
+        profile.prune(1, 2);
+        let traces = profile.to_trace();
+        assert!(!traces[0].iter().any(|e| matches!(e, TraceEvent::Block(_))));
+        assert_eq!(traces[1].len(), 5);
+
+        // with sequence numbers, writes follow the blocks executed before
+        // them, wherever their code is
+        let mut profile = run(vec![
+            MemLogEntry {
+                sequence: 1,
+                ..write(0x40, 1)
+            },
+            MemLogEntry {
+                sequence: 4,
+                ..write(0x40, 2)
+            },
+        ]);
+        profile.block_sequences = vec![vec![0, 2, 3]];
+        let write_positions = profile.to_trace()[0]
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| matches!(e, TraceEvent::Write(_)))
+            .map(|(i, _)| i)
+            .collect::<Vec<usize>>();
+        assert_eq!(write_positions, vec![1, 4]);
+
+        // register snapshots precede the blocks of the gadgets in which
+        // they were taken
+        let snapshot = |sequence: usize, address: u64| RegisterSnapshot {
+            sequence,
+            address,
+            registers: RegisterState(HashMap::new()),
+        };
+        let mut profile = run(vec![]);
+        profile.register_snapshots = vec![vec![snapshot(0, 0x10), snapshot(3, 0x10)]];
+        let kinds = |profile: &Profile| {
+            profile.to_trace()[0]
+                .iter()
+                .map(|e| match e {
+                    TraceEvent::Snapshot(_) => 's',
+                    TraceEvent::Block(_) => 'b',
+                    _ => '.',
+                })
+                .collect::<String>()
+        };
+        assert_eq!(kinds(&profile), "sbbsb..");
+        profile.block_sequences = vec![vec![0, 2, 3]];
+        assert_eq!(kinds(&profile), "sbbsb..");
+    }
+
+    #[test]
+    fn test_gadget_execution_counts() {
+        let run = |gadgets: &[(u64, usize)]| Profile {
+            gadgets_executed: vec![gadgets.iter().cloned().collect()],
+            ret_counts: vec![gadgets.iter().map(|(_, n)| n).sum()],
+            executable: true,
+            ..Default::default()
+        };
+        let mut a = run(&[(0x1000, 2), (0x2000, 1)]);
+        a.absorb(run(&[(0x1000, 1)]));
+        let b = run(&[(0x2000, 4), (0x3000, 1)]);
+        assert_eq!(a.gadgets_executed(0), 2);
+        assert_eq!(a.gadgets_executed(2), 0);
+
+        let counts = gadget_execution_counts(vec![a, b].iter());
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[&0x1000], 3);
+        assert_eq!(counts[&0x2000], 5);
+        assert_eq!(counts[&0x3000], 1);
+        assert!(gadget_execution_counts(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_approx_heap_size_counts_contents() {
+        let empty = Profile::default();
+        assert_eq!(empty.approx_heap_size(), 0);
+
+        let mut gadgets = HashMap::new();
+        gadgets.insert(0x1000, 3);
+        let profile = Profile {
+            paths: vec![vec![Block { entry: 0, size: 1 }; 100]],
+            code_executed: vec![vec![0; 100]],
+            gadgets_executed: vec![gadgets],
+            writeable_memory: vec![vec![Seg {
+                addr: 0x1000,
+                memsz: 0x1000,
+                perm: crate::util::architecture::Perms::READ,
+                segtype: loader::SegType::Stack,
+                data: vec![0; 0x1000],
+            }]],
+            ..Default::default()
+        };
+        // the contents of the vectors, and not just their headers, are
+        // counted
+        assert!(profile.approx_heap_size() > 0x1000 + 100 + 100 * std::mem::size_of::<Block>());
+    }
+
+    #[test]
+    fn test_prune_to_size() {
+        let run = |i: u64| Profile {
+            paths: vec![vec![Block { entry: i, size: 1 }; 64]],
+            code_executed: vec![vec![0; 64]],
+            memory_writes: vec![SparseData::default()],
+            writeable_memory: vec![vec![]],
+            ret_counts: vec![i as usize],
+            executable: true,
+            ..Default::default()
+        };
+        let mut profile = run(0);
+        for i in 1..10 {
+            profile.absorb(run(i));
+        }
+        let one_run = run(0).approx_heap_size();
+        assert!(profile.approx_heap_size() > 9 * one_run);
+
+        profile.prune_to_size(4 * one_run);
+        assert!(profile.approx_heap_size() <= 4 * one_run);
+        assert!(profile.paths.len() < 4);
+        // the most recent run is the one that's kept
+        assert_eq!(profile.paths.last().unwrap()[0].entry, 9);
+
+        // we never prune away the last run
+        profile.prune_to_size(0);
+        assert_eq!(profile.paths.len(), 1);
+        assert_eq!(profile.memory_writes.len(), 1);
+    }
+
+    #[test]
+    fn test_unmapped_block_is_not_fatal() {
+        use crate::configure::RoperConfig;
+
+        let config = RoperConfig {
+            arch: unicorn::Arch::X86,
+            mode: unicorn::Mode::MODE_64,
+            binary_path: "/bin/sh".to_string(),
+            ..Default::default()
+        };
+        let _ = loader::load_from_path(&config, true);
+
+        let block = Block {
+            entry: 0xffff_ffff_ffff_0000,
+            size: 0x10,
+        };
+        assert!(block.get_code().is_none());
+        assert!(block.disassemble().is_none());
+        assert!(block.disassembly().is_none());
+
+        let memory = loader::get_static_memory_image();
+        let seg = memory
+            .segments()
+            .iter()
+            .find(|s| s.is_executable())
+            .expect("no executable segment");
+        let block = Block {
+            entry: seg.aligned_start(),
+            size: 4,
+        };
+        assert_eq!(block.get_code().map(<[u8]>::len), Some(4));
+        // a block that runs off the end of its segment
+        let block = Block {
+            entry: seg.aligned_start(),
+            size: seg.data.len() + 0x1000,
+        };
+        assert!(block.get_code().is_none());
+    }
 }
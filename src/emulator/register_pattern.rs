@@ -1,8 +1,9 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use unicorn::Cpu;
@@ -10,6 +11,7 @@ use unicorn::Cpu;
 use crate::emulator::loader;
 use crate::emulator::loader::{get_static_memory_image, Seg};
 use crate::error::Error;
+use crate::logger::EMULATOR;
 use crate::util;
 use crate::util::architecture::{write_integer, Endian};
 use crate::util::bitwise;
@@ -20,7 +22,7 @@ use std::ops::Sub;
 pub fn parse_register_pattern_file(path: &str) -> Result<Vec<RegisterPattern>, Error> {
     let data = std::fs::read_to_string(path)?;
     let res = parse_register_patterns(&data);
-    log::info!("Parsed register patterns: {:#?}", res);
+    log::info!(target: EMULATOR, "Parsed register patterns: {:#?}", res);
     res
 }
 
@@ -29,7 +31,7 @@ pub fn parse_register_patterns(data: &str) -> Result<Vec<RegisterPattern>, Error
         .into_iter()
         .map(|chunk| {
             let rp_conf = RegisterPatternConfig(toml::from_str(chunk)?);
-            log::info!("Register pattern config: {:#x?}", rp_conf);
+            log::info!(target: EMULATOR, "Register pattern config: {:#x?}", rp_conf);
             // the reduction happens here, in the into()
             Ok(rp_conf.into())
         })
@@ -82,8 +84,10 @@ impl From<u64> for RegisterValue {
 
 /// Grammar:
 /// ```
-/// RegisterValue -> numeric_literal | & RegisterValue
+/// RegisterValue -> numeric_literal | symbol | symbol + offset | & RegisterValue
 /// ```
+/// Symbols are resolved against the loaded binary, so patterns that use
+/// them have to be parsed after it's been loaded.
 impl FromStr for RegisterValue {
     type Err = Error;
 
@@ -114,6 +118,13 @@ impl FromStr for RegisterValue {
                         ))
                     }
                 }
+                Some(x) if x.is_ascii_alphabetic() || x == '_' => {
+                    let symbol = format!("{}{}", x, chars.collect::<String>());
+                    Ok(RegisterValue {
+                        vals: vec![loader::resolve_symbol(&symbol)?],
+                        deref,
+                    })
+                }
                 Some(x) => {
                     let rest = chars.collect::<String>();
                     let numeral = format!("{}{}", x, rest);
@@ -174,9 +185,9 @@ impl From<&RegisterPatternConfig> for RegisterPattern {
             let mut value = v
                 .parse::<RegisterValue>()
                 .expect("Failed to parse RegisterValue");
-            log::debug!("Before reduction: {:#x?}", value);
+            log::debug!(target: EMULATOR, "Before reduction: {:#x?}", value);
             value.reduce_references();
-            log::debug!("After reduction: {:#x?}", value);
+            log::debug!(target: EMULATOR, "After reduction: {:#x?}", value);
             map.insert(k.to_string(), value);
         }
         Self(map)
@@ -268,26 +279,80 @@ fn least_word_distance(w1: u64, w2s: &[u64]) -> f64 {
         .unwrap() as f64
 }
 
+/// Like `least_word_distance`, but any word within `slack` of one of the
+/// targets counts as an exact match.
+fn least_word_distance_within(w1: u64, w2s: &[u64], slack: u64) -> f64 {
+    if w2s.iter().any(|w2| within(w1, *w2, slack)) {
+        0.0
+    } else {
+        least_word_distance(w1, w2s)
+    }
+}
+
+fn within(w1: u64, w2: u64, slack: u64) -> bool {
+    w1.max(w2) - w1.min(w2) <= slack
+}
+
+/// Loosens comparisons between register states and patterns: registers
+/// can be allowed to miss their targets by some absolute amount, or be
+/// left out of the comparison entirely.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tolerance {
+    /// The amount by which each register's values may differ from their
+    /// targets and still count as matching. Registers not listed must
+    /// match exactly.
+    #[serde(default)]
+    pub slack: HashMap<String, u64>,
+    /// Registers that are left out of comparisons altogether.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+impl Tolerance {
+    pub fn ignores(&self, reg: &str) -> bool {
+        self.ignore.iter().any(|r| r == reg)
+    }
+
+    pub fn slack(&self, reg: &str) -> u64 {
+        self.slack.get(reg).cloned().unwrap_or(0)
+    }
+}
+
 fn max_word_distance() -> f64 {
     get_static_memory_image().word_size as f64 * 8.0
 }
 
 // TODO: write some integration tests for this. there's a LOT of room for error!
 impl RegisterPattern {
-    pub fn distance_from_register_state(&self, register_state: &RegisterState) -> f64 {
+    /// Registers ignored by the tolerance are left out, both as targets and
+    /// as candidates for matching them.
+    pub fn distance_from_register_state(
+        &self,
+        register_state: &RegisterState,
+        tolerance: &Tolerance,
+    ) -> f64 {
         const WRONG_REG_PENALTY: f64 = 5.0;
 
         let summed_dist = self
             .0
             .iter()
+            .filter(|(reg, _)| !tolerance.ignores(reg))
             .map(|(reg, r_val): (&String, &RegisterValue)| {
                 self.0
                     .keys()
+                    .filter(|r| !tolerance.ignores(r))
                     .map(|r| {
                         let mut d = register_state
-                            .distance_from_register_val(r, r_val)
+                            .distance_from_register_val(r, r_val, tolerance.slack(reg))
                             .expect("Failed to get distance from register val");
-                        log::debug!("[{}] summed_dist_for_reg({}, {:x?}) = {}", reg, r, r_val, d);
+                        log::debug!(
+                            target: EMULATOR,
+                            "[{}] summed_dist_for_reg({}, {:x?}) = {}",
+                            reg,
+                            r,
+                            r_val,
+                            d
+                        );
                         if r != reg {
                             d += WRONG_REG_PENALTY
                         };
@@ -296,7 +361,7 @@ impl RegisterPattern {
                     .fold(std::f64::MAX, |a, b| a.min(b))
             })
             .sum();
-        log::debug!("summed_dist = {}", summed_dist);
+        log::debug!(target: EMULATOR, "summed_dist = {}", summed_dist);
 
         summed_dist
     }
@@ -308,12 +373,14 @@ impl RegisterPattern {
     pub fn incorrect_register_states<'a>(
         &'a self,
         register_state: &'a RegisterState,
+        tolerance: &Tolerance,
     ) -> Vec<(&'a String, &'a Vec<u64>)> {
         self.0
             .iter()
+            .filter(|(reg, _)| !tolerance.ignores(reg))
             .filter_map(|(reg, r_val)| {
                 let d = register_state
-                    .distance_from_register_val(reg, r_val)
+                    .distance_from_register_val(reg, r_val, tolerance.slack(reg))
                     .expect("Failed to get distance from register val");
                 if d > 0.0 {
                     register_state.0.get(reg).map(|v| (reg, v))
@@ -426,23 +493,30 @@ impl Hash for RegisterState {
     }
 }
 
+/// The number of pointers followed from each register's value, unless
+/// `roper.max_deref_depth` says otherwise.
+pub const DEFAULT_DEREF_DEPTH: usize = 10;
+
 impl RegisterState {
+    /// Records each register's value, followed by the chain of values
+    /// reached by dereferencing it, at most `max_depth` times.
     pub fn new<C: 'static + Cpu<'static>>(
         registers: &HashMap<Register<C>, u64>,
         extra_segs: Option<&[Seg]>,
+        max_depth: usize,
     ) -> Self {
-        Self(Self::spider::<C>(registers, extra_segs))
+        Self(Self::spider::<C>(registers, extra_segs, max_depth))
     }
 
     fn spider<C: 'static + Cpu<'static>>(
         registers: &HashMap<Register<C>, u64>,
         extra_segs: Option<&[Seg]>,
+        max_depth: usize,
     ) -> HashMap<String, Vec<u64>> {
-        const MAX_SPIDER_STEPS: usize = 10;
         let mut map = HashMap::new();
         if let Some(memory) = loader::try_to_get_static_memory_image() {
             for (k, v) in registers.iter() {
-                let path = memory.deref_chain(*v, MAX_SPIDER_STEPS, extra_segs);
+                let path = memory.deref_chain(*v, max_depth, extra_segs);
                 let reg_name = format!("{:?}", k);
                 map.insert(reg_name, path);
             }
@@ -455,7 +529,52 @@ impl RegisterState {
         map
     }
 
-    fn distance_from_register_val(&self, reg: &str, r_val: &RegisterValue) -> Result<f64, Error> {
+    /// The registers whose spidered values differ between the two states,
+    /// mapped to their values in `self` and in `other`. A register that's
+    /// missing from one of the states is `None` on that side.
+    pub fn diff<'a>(
+        &'a self,
+        other: &'a Self,
+    ) -> BTreeMap<&'a str, (Option<&'a Vec<u64>>, Option<&'a Vec<u64>>)> {
+        self.0
+            .keys()
+            .chain(other.0.keys())
+            .filter_map(|reg| {
+                let mine = self.0.get(reg);
+                let theirs = other.0.get(reg);
+                if mine == theirs {
+                    None
+                } else {
+                    Some((reg.as_str(), (mine, theirs)))
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the two states agree on every register that the tolerance
+    /// doesn't ignore, up to each register's slack.
+    pub fn matches(&self, other: &Self, tolerance: &Tolerance) -> bool {
+        self.0
+            .keys()
+            .chain(other.0.keys())
+            .filter(|reg| !tolerance.ignores(reg))
+            .all(|reg| match (self.0.get(reg), other.0.get(reg)) {
+                (Some(a), Some(b)) => {
+                    let slack = tolerance.slack(reg);
+                    a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| within(*x, *y, slack))
+                }
+                _ => false,
+            })
+    }
+
+    /// The distance of the register's value from its target, counting
+    /// values within `slack` of the target as matching it.
+    fn distance_from_register_val(
+        &self,
+        reg: &str,
+        r_val: &RegisterValue,
+        slack: u64,
+    ) -> Result<f64, Error> {
         fn pos_distance(pos: usize, target: usize) -> f64 {
             let pos_dist_scale: f64 = 4.0 * get_static_memory_image().word_size as f64;
             let dist = pos as i32 - target as i32;
@@ -476,19 +595,19 @@ impl RegisterState {
             }
         }
 
-        log::debug!("want {:x?}", r_val);
+        log::debug!(target: EMULATOR, "want {:x?}", r_val);
         if let Some(vals) = self.0.get(reg) {
             let distance = if r_val.deref == 0 {
                 // Immediate values
-                least_word_distance(vals[0], &r_val.vals)
+                least_word_distance_within(vals[0], &r_val.vals, slack)
             } else {
                 // dereferenced values
                 vals.iter()
                     .enumerate()
                     .map(|(i, val)| {
                         if is_mutable(i, &vals) {
-                            least_word_distance(*val, &r_val.vals)
-                        } else if r_val.vals.iter().any(|v| v == val) {
+                            least_word_distance_within(*val, &r_val.vals, slack)
+                        } else if r_val.vals.iter().any(|v| within(*v, *val, slack)) {
                             0.0 + pos_distance(i, r_val.deref)
                         } else {
                             2.0 * least_word_distance(*val, &r_val.vals)
@@ -505,6 +624,86 @@ impl RegisterState {
     }
 }
 
+/// A note on what a word points to: the segment it falls in, if any, the
+/// nearest symbol, and whether it's the address of one of `gadgets`,
+/// followed by the word read as a string, if it looks like one. Empty if
+/// there's nothing to say.
+pub fn annotate_word(word: u64, gadgets: &HashSet<u64>) -> String {
+    let mut notes = Vec::new();
+    let memory = loader::try_to_get_static_memory_image();
+    if let Some(seg) = memory.and_then(|m| m.containing_seg(word, None)) {
+        notes.push(format!(
+            "{:?} segment 0x{:x}+0x{:x} {}",
+            seg.segtype,
+            seg.aligned_start(),
+            word - seg.aligned_start(),
+            seg.perm
+        ));
+    }
+    match memory.and_then(|m| m.nearest_symbol(word)) {
+        Some((name, 0)) => notes.push(format!("<{}>", name)),
+        Some((name, offset)) => notes.push(format!("<{}+0x{:x}>", name, offset)),
+        None => (),
+    }
+    if gadgets.contains(&word) {
+        notes.push("gadget".to_string());
+    }
+    let string = memory
+        .and_then(|m| util::bitwise::try_word_as_string(word, m.endian, m.word_size))
+        .map(|s| format!(" \"{}\"", s))
+        .unwrap_or_else(String::new);
+    if notes.is_empty() {
+        string
+    } else {
+        format!(" ({}){}", notes.join(", "), string)
+    }
+}
+
+impl RegisterState {
+    /// A listing of the state, one register per line, in which every value
+    /// in each register's dereference chain is annotated by `annotate_word`.
+    /// Pass the addresses of the chain's gadgets as `gadgets`, to have them
+    /// picked out.
+    pub fn annotated(&self, gadgets: &HashSet<u64>) -> String {
+        self.0
+            .iter()
+            .sorted_by_key(|p| p.0)
+            .map(|(reg, vals)| {
+                let chain = vals
+                    .iter()
+                    .map(|v| format!("0x{:x}{}", v, annotate_word(*v, gadgets)))
+                    .collect::<Vec<String>>()
+                    .join(" -> ");
+                format!("{}: {}\n", reg, chain)
+            })
+            .collect()
+    }
+}
+
+/// A side-by-side table of the registers whose values vary across the
+/// given states (one per run, typically), with a column for each state, or
+/// `None` if the states all agree.
+pub fn register_diff_table(states: &[RegisterState]) -> Option<String> {
+    let first = states.first()?;
+    let varying = states[1..]
+        .iter()
+        .flat_map(|state| first.diff(state).into_iter().map(|(reg, _)| reg))
+        .collect::<BTreeSet<&str>>();
+    if varying.is_empty() {
+        return None;
+    }
+    let column = |vals: Option<&Vec<u64>>| {
+        vals.map(|v| v.iter().map(|x| format!("0x{:x}", x)).join(" -> "))
+            .unwrap_or_else(|| "-".to_string())
+    };
+    let mut table = String::new();
+    for reg in varying {
+        let row = states.iter().map(|s| column(s.0.get(reg))).join(" | ");
+        table.push_str(&format!("{}: {}\n", reg, row));
+    }
+    Some(table)
+}
+
 impl fmt::Debug for RegisterState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let memory = get_static_memory_image();
@@ -585,6 +784,24 @@ mod test {
         let _ = loader::load_from_path(&config, true);
     }
 
+    #[test]
+    fn test_annotated_register_state() {
+        let state = RegisterState(hashmap! {
+            "RBX".to_string() => vec![0x2000],
+            "RAX".to_string() => vec![0x1000, 0x3000],
+        });
+        let mut gadgets = HashSet::new();
+        gadgets.insert(0x2000);
+        let annotated = state.annotated(&gadgets);
+        let lines = annotated.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("RAX: 0x1000"));
+        assert!(lines[0].contains(" -> 0x3000"));
+        assert!(!lines[0].contains("gadget"));
+        assert!(lines[1].starts_with("RBX: 0x2000"));
+        assert!(lines[1].contains("gadget)"));
+    }
+
     #[test]
     fn test_register_value_parser() {
         let rvs = vec![
@@ -620,9 +837,13 @@ mod test {
 
         for (s, reg_val) in rvs.into_iter() {
             let rv: RegisterValue = s.parse().expect("Failed to parse");
-            log::debug!("{} --> {:?}", s, rv);
+            log::debug!(target: EMULATOR, "{} --> {:?}", s, rv);
             assert_eq!(rv, reg_val);
         }
+
+        // names are taken to be symbols, and must be found in the binary
+        assert!("no_such_symbol".parse::<RegisterValue>().is_err());
+        assert!("&no_such_symbol+0x10".parse::<RegisterValue>().is_err());
     }
 
     #[test]
@@ -645,7 +866,8 @@ mod test {
             },
         });
 
-        let res = register_pattern.distance_from_register_state(&register_state);
+        let res =
+            register_pattern.distance_from_register_state(&register_state, &Tolerance::default());
 
         assert!(res < std::f64::EPSILON, "nonzero score on match");
     }
@@ -707,6 +929,7 @@ mod test {
                     vals: vec![0xbeef],
                     deref: 1,
                 },
+                0,
             )
             .unwrap();
         assert!(res < std::f64::EPSILON, "Match failed");
@@ -718,6 +941,7 @@ mod test {
                     vals: vec![3],
                     deref: 2,
                 },
+                0,
             )
             .unwrap();
         assert!(res < std::f64::EPSILON, "Match failed");
@@ -729,6 +953,7 @@ mod test {
                     vals: vec![0x1000_beef],
                     deref: 1,
                 },
+                0,
             )
             .unwrap();
         assert!(res - 1.0 < std::f64::EPSILON, "Match failed");
@@ -740,6 +965,7 @@ mod test {
                     vals: vec![0x1000_beef],
                     deref: 0,
                 },
+                0,
             )
             .unwrap();
         assert!(res - 2.0 < std::f64::EPSILON, "Match failed");
@@ -756,9 +982,85 @@ mod test {
                     vals: vec![9],
                     deref: 3,
                 },
+                0,
             )
             .unwrap();
         println!("res = {}", res);
         assert!(res - (1.0 + 3.0) < std::f64::EPSILON);
     }
+
+    #[test]
+    fn test_register_state_diff() {
+        let a = RegisterState(hashmap! {
+            "RAX".to_string() => vec![1],
+            "RBX".to_string() => vec![2, 3],
+            "RCX".to_string() => vec![4],
+        });
+        let b = RegisterState(hashmap! {
+            "RAX".to_string() => vec![1],
+            "RBX".to_string() => vec![2, 5],
+            "RDX".to_string() => vec![6],
+        });
+        let diff = a.diff(&b);
+        assert_eq!(
+            diff.keys().cloned().collect::<Vec<&str>>(),
+            vec!["RBX", "RCX", "RDX"]
+        );
+        assert_eq!(diff["RBX"], (Some(&vec![2, 3]), Some(&vec![2, 5])));
+        assert_eq!(diff["RCX"], (Some(&vec![4]), None));
+        assert_eq!(diff["RDX"], (None, Some(&vec![6])));
+        assert!(a.diff(&a).is_empty());
+
+        let table = register_diff_table(&[a.clone(), b, a.clone()]).unwrap();
+        assert_eq!(
+            table,
+            "RBX: 0x2 -> 0x3 | 0x2 -> 0x5 | 0x2 -> 0x3\n\
+             RCX: 0x4 | - | 0x4\n\
+             RDX: - | 0x6 | -\n"
+        );
+        assert_eq!(register_diff_table(&[a.clone(), a]), None);
+    }
+
+    #[test]
+    fn test_tolerance() {
+        let register_state = RegisterState(hashmap! {
+            "RDI".to_string() => vec![0x1005],
+            "RSI".to_string() => vec![0xdead],
+        });
+        let register_pattern = RegisterPattern(hashmap! {
+            "RDI".to_string() => RegisterValue {
+                vals: vec![0x1000],
+                deref: 0,
+            },
+            "RSI".to_string() => RegisterValue {
+                vals: vec![0],
+                deref: 0,
+            },
+        });
+        let exact = Tolerance::default();
+        let loose = Tolerance {
+            slack: hashmap! { "RDI".to_string() => 8 },
+            ignore: vec!["RSI".to_string()],
+        };
+        assert!(register_pattern.distance_from_register_state(&register_state, &exact) > 0.0);
+        assert_eq!(
+            register_pattern.distance_from_register_state(&register_state, &loose),
+            0.0
+        );
+        assert!(register_pattern
+            .incorrect_register_states(&register_state, &loose)
+            .is_empty());
+
+        let other = RegisterState(hashmap! {
+            "RDI".to_string() => vec![0x1000],
+            "RSI".to_string() => vec![0],
+        });
+        assert!(!register_state.matches(&other, &exact));
+        assert!(register_state.matches(&other, &loose));
+        let too_far = RegisterState(hashmap! {
+            "RDI".to_string() => vec![0x1010],
+            "RSI".to_string() => vec![0],
+        });
+        assert!(!too_far.matches(&other, &loose));
+    }
 }
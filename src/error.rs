@@ -11,8 +11,11 @@ pub enum Error {
     MissingKey(String),
     NoVacancy,
     Misc(String),
+    #[cfg(feature = "emulator")]
     Unicorn(unicorn::Error),
+    #[cfg(feature = "emulator")]
     Falcon(falcon::error::Error),
+    #[cfg(feature = "emulator")]
     Goblin(goblin::error::Error),
 }
 
@@ -26,7 +29,9 @@ macro_rules! impl_error_from {
     };
 }
 
+#[cfg(feature = "emulator")]
 impl_error_from!(goblin::error::Error, Goblin);
+#[cfg(feature = "emulator")]
 impl_error_from!(falcon::error::Error, Falcon);
 impl_error_from!(std::io::Error, IO);
 impl_error_from!(fasteval::error::Error, Eval);
@@ -50,6 +55,7 @@ impl From<serde_json::error::Error> for Error {
     }
 }
 
+#[cfg(feature = "emulator")]
 impl From<unicorn::Error> for Error {
     fn from(e: unicorn::Error) -> Error {
         Error::Unicorn(e)
@@ -68,6 +74,13 @@ impl From<std::str::Utf8Error> for Error {
     }
 }
 
+#[cfg(feature = "binary_dump")]
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Self::Parsing(e.to_string())
+    }
+}
+
 impl From<ron::error::Error> for Error {
     fn from(e: ron::error::Error) -> Self {
         Self::Parsing(e.to_string())
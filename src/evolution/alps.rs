@@ -0,0 +1,217 @@
+use std::fmt::Debug;
+use std::iter;
+use std::path::Path;
+
+use rand::Rng;
+
+use crate::configure::Config;
+use crate::error::Error;
+use crate::evolution::{compare_fitness, export, Genome, Phenome};
+use crate::increment_epoch_counter;
+use crate::logger::EVOLUTION;
+use crate::observer::Observer;
+use crate::ontogenesis::Develop;
+use crate::util::random::hash_seed_rng;
+
+/// For a description of the Age-Layered Population Structure, see Gregory
+/// Hornby, "ALPS: The Age-Layered Population Structure for Reducing the
+/// Problem of Premature Convergence", GECCO 2006.
+///
+/// Each member of a layer is stored alongside the generation in which the
+/// oldest of its genetic material entered the population. Its age is the
+/// number of generations since then. Offspring inherit the birth generation
+/// of their oldest parent, and the bottom layer is periodically reseeded
+/// with newcomers of age zero.
+pub struct Alps<E: Develop<P>, P: Phenome + Genome + 'static> {
+    pub layers: Vec<Vec<(usize, P)>>,
+    pub config: Config,
+    pub iteration: usize,
+    pub observer: Observer<P>,
+    pub evaluator: E,
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Alps<E, P> {
+    pub fn new(config: &Config, observer: Observer<P>, evaluator: E) -> Self {
+        let layers = iter::repeat(())
+            .take(config.alps.num_layers)
+            .map(|()| Vec::new())
+            .collect::<Vec<Vec<(usize, P)>>>();
+        let mut alps = Self {
+            layers,
+            config: config.clone(),
+            iteration: 0,
+            observer,
+            evaluator,
+        };
+        alps.reseed();
+        alps
+    }
+
+    fn layer_size(&self) -> usize {
+        (self.config.pop_size / self.config.alps.num_layers).max(1)
+    }
+
+    fn age(&self, birth: usize) -> usize {
+        self.iteration - birth
+    }
+
+    /// Replace the bottom layer with freshly generated, evaluated
+    /// newcomers.
+    fn reseed(&mut self) {
+        log::debug!(
+            target: EVOLUTION,
+            "Reseeding the bottom layer of island {} at generation {}",
+            self.config.island_id,
+            self.iteration
+        );
+        let iteration = self.iteration;
+        let config = &self.config;
+        let newcomers = (0..self.layer_size())
+            .map(|i| P::random(config, (iteration, i)))
+            .collect::<Vec<P>>();
+        let newcomers = self.evaluate(newcomers);
+        self.layers[0] = newcomers.into_iter().map(|p| (iteration, p)).collect();
+    }
+
+    fn evaluate(&mut self, creatures: Vec<P>) -> Vec<P> {
+        let evaluator = &mut self.evaluator;
+        evaluator
+            .development_pipeline(creatures.into_iter())
+            .into_iter()
+            .map(|p| evaluator.apply_fitness_function(p))
+            .collect::<Vec<P>>()
+    }
+
+    /// Select a parent by tournament from the given layer and the layer
+    /// beneath it.
+    fn select<'a, R: Rng>(&'a self, layer: usize, rng: &mut R) -> &'a (usize, P) {
+        let below: &[(usize, P)] = if layer > 0 {
+            &self.layers[layer - 1]
+        } else {
+            &[]
+        };
+        let pool = self.layers[layer]
+            .iter()
+            .chain(below.iter())
+            .collect::<Vec<&(usize, P)>>();
+        iter::repeat(())
+            .take(self.config.tournament.tournament_size.max(1))
+            .map(|()| pool[rng.gen_range(0, pool.len())])
+            .min_by(|a, b| compare_fitness(&a.1, &b.1))
+            .expect("empty tournament")
+    }
+
+    /// Export the members of every layer to a single JSON file at `path`.
+    pub fn export_population<Q: AsRef<Path> + Debug>(&self, path: Q) -> Result<(), Error> {
+        let members = self.layers.iter().flatten().map(|(_, p)| p);
+        export::export_population(members, &self.config, path)
+    }
+
+    pub fn evolve(mut self) -> Self {
+        if self.iteration > 0 && self.iteration % self.config.alps.age_gap == 0 {
+            self.reseed();
+        }
+
+        let mut rng = hash_seed_rng(&self.layers);
+        let layer_size = self.layer_size();
+
+        // breed a new generation for each layer, before any of the layers
+        // are changed
+        let mut births = Vec::new();
+        let mut offspring = Vec::new();
+        for layer in 0..self.layers.len() {
+            if self.layers[layer].is_empty() {
+                continue;
+            }
+            for _ in 0..layer_size {
+                let parents = iter::repeat(())
+                    .take(self.config.tournament.num_parents)
+                    .map(|()| self.select(layer, &mut rng))
+                    .collect::<Vec<&(usize, P)>>();
+                let birth = parents.iter().map(|p| p.0).min().unwrap_or(self.iteration);
+                let parents = parents.into_iter().map(|p| &p.1).collect::<Vec<&P>>();
+                births.push((layer, birth));
+                offspring.push(Genome::mate(&parents, &self.config));
+            }
+        }
+        let offspring = self.evaluate(offspring);
+        for child in offspring.iter() {
+            self.observer.observe(child.clone());
+        }
+        let mut offspring = births
+            .into_iter()
+            .zip(offspring.into_iter())
+            .map(|((layer, birth), child)| (layer, (birth, child)))
+            .collect::<Vec<(usize, (usize, P))>>();
+
+        // Each layer keeps its best members, old and new, that have not
+        // outgrown it. Those that have are promoted to compete in the layer
+        // above, and any that outgrow the top layer remain there.
+        let num_layers = self.layers.len();
+        let mut promoted: Vec<(usize, P)> = Vec::new();
+        for layer in 0..num_layers {
+            let mut candidates = std::mem::take(&mut self.layers[layer]);
+            candidates.append(&mut promoted);
+            let (mine, others): (Vec<_>, Vec<_>) =
+                offspring.into_iter().partition(|(l, _)| *l == layer);
+            offspring = others;
+            candidates.extend(mine.into_iter().map(|(_, c)| c));
+
+            let limit = self.config.alps.age_limit(layer);
+            let (mut members, too_old): (Vec<_>, Vec<_>) = candidates
+                .into_iter()
+                .partition(|(birth, _)| layer == num_layers - 1 || self.age(*birth) <= limit);
+            promoted = too_old;
+            members.sort_by(|a, b| compare_fitness(&a.1, &b.1));
+            members.truncate(layer_size);
+            self.layers[layer] = members;
+        }
+
+        for (_, member) in self.layers.iter_mut().flatten() {
+            member.incr_age();
+        }
+
+        increment_epoch_counter();
+        self.iteration += 1;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::AlpsConfig;
+    use crate::ontogenesis::fixtures::{length_evaluator, quiet_observer};
+
+    use super::*;
+
+    #[test]
+    fn test_layers_respect_age_limits() {
+        let mut config = Config::default();
+        config.pop_size = 40;
+        config.max_init_len = 16;
+        config.tournament.tournament_size = 3;
+        config.tournament.num_parents = 2;
+        config.tournament.num_offspring = 2;
+        config.alps = AlpsConfig {
+            num_layers: 4,
+            age_gap: 3,
+        };
+        let observer = quiet_observer(&mut config, "alps");
+        let mut alps = Alps::new(&config, observer, length_evaluator());
+        for _ in 0..30 {
+            alps = alps.evolve();
+            for (layer, members) in alps.layers.iter().enumerate() {
+                assert!(members.len() <= 10);
+                if layer < 3 {
+                    let limit = config.alps.age_limit(layer);
+                    for (birth, _) in members.iter() {
+                        // ages are measured before the generation counter
+                        // is incremented
+                        assert!(alps.iteration - 1 - birth <= limit);
+                    }
+                }
+            }
+        }
+        assert!(alps.layers.iter().all(|l| !l.is_empty()));
+    }
+}
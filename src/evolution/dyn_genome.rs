@@ -0,0 +1,240 @@
+use std::any::Any;
+use std::fmt::Debug;
+
+use rand::Rng;
+
+use crate::configure::Config;
+use crate::evolution::{Genome, Phenome};
+use crate::fitness::total_cmp_f64;
+use crate::ontogenesis::Develop;
+
+/// An object-safe facade over the core operations of `Genome` and
+/// `Phenome`, so that a driver can work with a `Vec<Box<dyn DynGenome>>`
+/// holding genomes of different types, chosen at runtime. Every type that
+/// implements both traits implements this one, and the statically typed
+/// drivers are unaffected.
+///
+/// The methods are prefixed with `dyn_` so that they can't be confused
+/// with those of the underlying traits.
+pub trait DynGenome: Debug + Send {
+    fn dyn_name(&self) -> &str;
+
+    fn dyn_len(&self) -> usize;
+
+    /// The scalar fitness under the configured weighting, if the genome has
+    /// been scored.
+    fn dyn_score(&self, config: &Config) -> Option<f64>;
+
+    fn dyn_mutate(&mut self, config: &Config);
+
+    /// An offspring of this genome and `other`, by crossover and mutation.
+    /// Genomes of different types can't be crossed, so if `other` is of
+    /// another type, the offspring is bred from this genome alone. Either
+    /// way, the offspring is a new individual of the next generation, and
+    /// has yet to be scored.
+    fn dyn_mate(&self, other: &dyn DynGenome, config: &Config) -> Box<dyn DynGenome>;
+
+    fn dyn_clone(&self) -> Box<dyn DynGenome>;
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<P: Phenome + Genome + 'static> DynGenome for P {
+    fn dyn_name(&self) -> &str {
+        Phenome::name(self)
+    }
+
+    fn dyn_len(&self) -> usize {
+        Genome::len(self)
+    }
+
+    fn dyn_score(&self, config: &Config) -> Option<f64> {
+        self.scalar_fitness(&config.fitness.weighting)
+    }
+
+    fn dyn_mutate(&mut self, config: &Config) {
+        Genome::mutate(self, config)
+    }
+
+    fn dyn_mate(&self, other: &dyn DynGenome, config: &Config) -> Box<dyn DynGenome> {
+        match other.as_any().downcast_ref::<P>() {
+            Some(other) => Box::new(Genome::mate(&[self, other], config)),
+            // mated with itself, since not every crossover operator can
+            // take a lone parent
+            None => Box::new(Genome::mate(&[self, self], config)),
+        }
+    }
+
+    fn dyn_clone(&self) -> Box<dyn DynGenome> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Clone for Box<dyn DynGenome> {
+    fn clone(&self) -> Self {
+        self.dyn_clone()
+    }
+}
+
+/// Develop and score the genome in place, if it's a `P`, returning false
+/// and leaving it untouched otherwise. A driver over a mixed population
+/// can try each type's evaluator in turn.
+pub fn evaluate_dyn<P, E>(genome: &mut dyn DynGenome, evaluator: &mut E) -> bool
+where
+    P: Phenome + Genome + 'static,
+    E: Develop<P>,
+{
+    match genome.as_any_mut().downcast_mut::<P>() {
+        Some(phenome) => {
+            let developed = evaluator.develop(phenome.clone());
+            *phenome = evaluator.apply_fitness_function(developed);
+            true
+        }
+        None => false,
+    }
+}
+
+/// One round of a steady-state tournament over a mixed population. The
+/// two fittest of `config.tournament.tournament_size` combatants are
+/// mated, and their offspring, once scored by `evaluate`, replaces the
+/// least fit. Unscored genomes count as the least fit of all.
+pub fn dyn_tournament<R, F>(
+    population: &mut Vec<Box<dyn DynGenome>>,
+    mut evaluate: F,
+    config: &Config,
+    rng: &mut R,
+) where
+    R: Rng,
+    F: FnMut(&mut dyn DynGenome),
+{
+    if population.is_empty() {
+        return;
+    }
+    let score = |g: &dyn DynGenome| g.dyn_score(config).unwrap_or(std::f64::MAX);
+    let mut combatants = (0..config.tournament.tournament_size.max(2))
+        .map(|_| rng.gen_range(0, population.len()))
+        .collect::<Vec<usize>>();
+    combatants.sort_by(|a, b| total_cmp_f64(score(&*population[*a]), score(&*population[*b])));
+    let mother = &population[combatants[0]];
+    let father = &population[combatants[1]];
+    let mut child = mother.dyn_mate(&**father, config);
+    evaluate(&mut *child);
+    let worst = combatants[combatants.len() - 1];
+    population[worst] = child;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::examples::{hello_world, linear_gp};
+    use crate::fitness::Weighted;
+    use crate::ontogenesis::IdentityEvaluator;
+    use crate::util::random::hash_seed_rng;
+
+    use super::*;
+
+    #[test]
+    fn test_mixed_population() {
+        let mut config = Config::default();
+        config.pop_size = 20;
+        config.min_init_len = 1;
+        config.max_init_len = 16;
+        config.max_length = 64;
+        config.mutation_rate = 0.5;
+        config.crossover_rate = 0.5;
+        config.crossover_period = 3.0;
+        config.crossover_algorithm = "uniform".to_string();
+        config.tournament.tournament_size = 4;
+        config.linear_gp.num_registers = Some(4);
+        config.fitness.weighting = "length".to_string();
+
+        let mut strings =
+            IdentityEvaluator::new(|g: &hello_world::Genotype| vec![g.genes.len() as f64]);
+        let mut programs = IdentityEvaluator::new(|c: &linear_gp::Creature| {
+            let mut fitness = Weighted::new("length");
+            fitness.insert("length", c.len() as f64);
+            fitness
+        });
+        let mut evaluate = |g: &mut dyn DynGenome| {
+            assert!(
+                evaluate_dyn::<hello_world::Genotype, _>(g, &mut strings)
+                    || evaluate_dyn::<linear_gp::Creature, _>(g, &mut programs)
+            );
+        };
+
+        let mut population: Vec<Box<dyn DynGenome>> =
+            (0..10)
+                .map(|i| Box::new(hello_world::Genotype::random(&config, i)) as Box<dyn DynGenome>)
+                .chain((0..10).map(|i| {
+                    Box::new(linear_gp::Creature::random(&config, i)) as Box<dyn DynGenome>
+                }))
+                .collect();
+        for genome in population.iter_mut() {
+            evaluate(&mut **genome);
+        }
+        let best = |population: &[Box<dyn DynGenome>]| {
+            population
+                .iter()
+                .filter_map(|g| g.dyn_score(&config))
+                .fold(std::f64::MAX, f64::min)
+        };
+        let initial_best = best(&population);
+
+        let mut rng = hash_seed_rng(&"mixed population");
+        for _ in 0..200 {
+            dyn_tournament(&mut population, &mut evaluate, &config, &mut rng);
+        }
+        assert_eq!(population.len(), 20);
+        assert!(population.iter().all(|g| g.dyn_score(&config).is_some()));
+        assert!(best(&population) <= initial_best);
+
+        // a genome can only be crossed with one of its own type
+        let child = population[0].dyn_mate(&*population[0].clone(), &config);
+        let is_string = |g: &dyn DynGenome| g.as_any().is::<hello_world::Genotype>();
+        assert_eq!(is_string(&*child), is_string(&*population[0]));
+    }
+
+    #[test]
+    fn test_mate_across_types() {
+        let mut config = Config::default();
+        config.min_init_len = 1;
+        config.max_init_len = 16;
+        config.max_length = 64;
+        config.linear_gp.num_registers = Some(4);
+
+        let mut parent = hello_world::Genotype::random(&config, 1);
+        parent.set_fitness(vec![-1.0]);
+        let other = linear_gp::Creature::random(&config, 2);
+        let mut child = parent.dyn_mate(&other, &config);
+        assert!(child.dyn_score(&config).is_none());
+        {
+            let child = child
+                .as_any()
+                .downcast_ref::<hello_world::Genotype>()
+                .unwrap();
+            assert_ne!(child.tag(), parent.tag());
+            assert_eq!(child.generation(), parent.generation() + 1);
+        }
+
+        // an evaluator that leaves scored genomes alone scores the child
+        // afresh, rather than keeping its parent's fitness
+        let mut strings =
+            IdentityEvaluator::new(|g: &hello_world::Genotype| vec![g.genes.len() as f64]);
+        if child.dyn_score(&config).is_none() {
+            assert!(evaluate_dyn::<hello_world::Genotype, _>(
+                &mut *child,
+                &mut strings
+            ));
+        }
+        assert_eq!(child.dyn_score(&config), Some(child.dyn_len() as f64));
+    }
+}
@@ -0,0 +1,427 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use non_dominated_sort::DominanceOrd;
+
+use crate::configure::{Config, Selection};
+use crate::error::Error;
+use crate::evolution::alps::Alps;
+use crate::evolution::map_elites::MapElites;
+use crate::evolution::metropolis::Metropolis;
+use crate::evolution::pareto_roulette::Roulette;
+use crate::evolution::population::pier::Pier;
+use crate::evolution::tournament::Tournament;
+use crate::evolution::{compare_fitness, Genome, Phenome};
+use crate::fitness::total_cmp;
+use crate::observer::Observer;
+use crate::ontogenesis::Develop;
+
+/// The interface shared by the selection drivers, each of which holds a
+/// population, and evolves it one generation per call to `evolve`.
+pub trait Epoch<P> {
+    fn evolve(self) -> Self;
+
+    /// The number of generations evolved so far.
+    fn iteration(&self) -> usize;
+
+    /// The current members of the population.
+    fn population(&self) -> Vec<&P>;
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Epoch<P> for Tournament<E, P> {
+    fn evolve(self) -> Self {
+        Tournament::evolve(self)
+    }
+
+    fn iteration(&self) -> usize {
+        self.iteration
+    }
+
+    fn population(&self) -> Vec<&P> {
+        self.population.iter().collect()
+    }
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Epoch<P> for Metropolis<E, P> {
+    fn evolve(self) -> Self {
+        Metropolis::evolve(self)
+    }
+
+    fn iteration(&self) -> usize {
+        self.iteration
+    }
+
+    fn population(&self) -> Vec<&P> {
+        vec![&self.specimen]
+    }
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Epoch<P>
+    for Roulette<E, P, D>
+{
+    fn evolve(self) -> Self {
+        Roulette::evolve(self)
+    }
+
+    fn iteration(&self) -> usize {
+        self.iteration
+    }
+
+    fn population(&self) -> Vec<&P> {
+        self.population.iter().collect()
+    }
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Epoch<P> for Alps<E, P> {
+    fn evolve(self) -> Self {
+        Alps::evolve(self)
+    }
+
+    fn iteration(&self) -> usize {
+        self.iteration
+    }
+
+    fn population(&self) -> Vec<&P> {
+        self.layers.iter().flatten().map(|(_, p)| p).collect()
+    }
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Epoch<P> for MapElites<E, P> {
+    fn evolve(self) -> Self {
+        MapElites::evolve(self)
+    }
+
+    fn iteration(&self) -> usize {
+        self.iteration
+    }
+
+    fn population(&self) -> Vec<&P> {
+        self.grid.values().collect()
+    }
+}
+
+/// One of the selection drivers that can be built from a `Config`, an
+/// observer and an evaluator alone, chosen by `config.selection`.
+pub enum Driver<E: Develop<P>, P: Phenome + Genome + 'static> {
+    Tournament(Tournament<E, P>),
+    Metropolis(Metropolis<E, P>),
+    Alps(Alps<E, P>),
+    MapElites(MapElites<E, P>),
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Driver<E, P> {
+    /// Roulette selection needs a dominance order, and lexicase selection
+    /// a set of problems, so neither can be built here. Construct those
+    /// drivers directly and wrap them with `Engine::new` instead.
+    pub fn new(config: &Config, observer: Observer<P>, evaluator: E) -> Result<Self, Error> {
+        match config.selection {
+            Selection::Tournament => {
+                let pier = Arc::new(Pier::new(config.num_islands.max(1)));
+                Ok(Driver::Tournament(Tournament::new(
+                    config, observer, evaluator, pier,
+                )))
+            }
+            Selection::Metropolis => Ok(Driver::Metropolis(Metropolis::new(
+                config, observer, evaluator,
+            ))),
+            Selection::Alps => Ok(Driver::Alps(Alps::new(config, observer, evaluator))),
+            Selection::MapElites => Ok(Driver::MapElites(MapElites::new(
+                config, observer, evaluator,
+            ))),
+            selection => Err(Error::Misc(format!(
+                "{:?} selection can't be built from the config alone",
+                selection
+            ))),
+        }
+    }
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Epoch<P> for Driver<E, P> {
+    fn evolve(self) -> Self {
+        match self {
+            Driver::Tournament(d) => Driver::Tournament(d.evolve()),
+            Driver::Metropolis(d) => Driver::Metropolis(d.evolve()),
+            Driver::Alps(d) => Driver::Alps(d.evolve()),
+            Driver::MapElites(d) => Driver::MapElites(d.evolve()),
+        }
+    }
+
+    fn iteration(&self) -> usize {
+        match self {
+            Driver::Tournament(d) => d.iteration,
+            Driver::Metropolis(d) => d.iteration,
+            Driver::Alps(d) => d.iteration,
+            Driver::MapElites(d) => d.iteration,
+        }
+    }
+
+    fn population(&self) -> Vec<&P> {
+        match self {
+            Driver::Tournament(d) => Epoch::population(d),
+            Driver::Metropolis(d) => Epoch::population(d),
+            Driver::Alps(d) => Epoch::population(d),
+            Driver::MapElites(d) => Epoch::population(d),
+        }
+    }
+}
+
+/// What the hooks are told about the state of evolution.
+#[derive(Clone, Debug)]
+pub struct GenerationStats {
+    pub iteration: usize,
+    pub population_size: usize,
+    /// The number of generations since the best fitness last improved.
+    pub stagnant_for: usize,
+}
+
+/// A callback, given the latest generation's stats and the fittest member
+/// of the population.
+pub type Hook<P> = Box<dyn FnMut(&GenerationStats, Option<&P>) + Send + 'static>;
+
+/// The callbacks registered on an `Engine`, by the point at which they're
+/// invoked.
+pub struct Hooks<P> {
+    after_generation: Vec<Hook<P>>,
+    on_new_best: Vec<Hook<P>>,
+    // each hook is invoked once the best fitness has failed to improve
+    // for the given number of generations
+    on_stagnation: Vec<(usize, Hook<P>)>,
+    on_termination: Vec<Hook<P>>,
+}
+
+impl<P> Default for Hooks<P> {
+    fn default() -> Self {
+        Self {
+            after_generation: vec![],
+            on_new_best: vec![],
+            on_stagnation: vec![],
+            on_termination: vec![],
+        }
+    }
+}
+
+fn fittest<'a, P: Phenome + Genome>(population: Vec<&'a P>) -> Option<&'a P> {
+    population
+        .into_iter()
+        .filter(|p| p.fitness().is_some())
+        .min_by(|a, b| compare_fitness(*a, *b))
+}
+
+/// A handle on an evolving population, for library users who want to run
+/// the evolutionary loop themselves, rather than calling one of the
+/// crate's `run` functions.
+pub struct Engine<D: Epoch<P>, P: Phenome> {
+    // only ever `None` in the middle of a `step`
+    driver: Option<D>,
+    hooks: Hooks<P>,
+    best_fitness: Option<P::Fitness>,
+    last_improvement: usize,
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> Engine<Driver<E, P>, P> {
+    /// Build an engine around the driver selected in the config.
+    pub fn from_config(
+        config: &Config,
+        observer: Observer<P>,
+        evaluator: E,
+    ) -> Result<Self, Error> {
+        Driver::new(config, observer, evaluator).map(Self::new)
+    }
+}
+
+impl<D: Epoch<P>, P: Phenome + Genome> Engine<D, P> {
+    pub fn new(driver: D) -> Self {
+        Self {
+            driver: Some(driver),
+            hooks: Hooks::default(),
+            best_fitness: None,
+            last_improvement: 0,
+        }
+    }
+
+    /// Register a hook to be called after every generation.
+    pub fn after_generation<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: FnMut(&GenerationStats, Option<&P>) + Send + 'static,
+    {
+        self.hooks.after_generation.push(Box::new(hook));
+        self
+    }
+
+    /// Register a hook to be called whenever a generation improves on the
+    /// best fitness seen so far.
+    pub fn on_new_best<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: FnMut(&GenerationStats, Option<&P>) + Send + 'static,
+    {
+        self.hooks.on_new_best.push(Box::new(hook));
+        self
+    }
+
+    /// Register a hook to be called when the best fitness has gone
+    /// `generations` generations without improving. It's called again
+    /// after every later improvement that's followed by as long a spell of
+    /// stagnation.
+    pub fn on_stagnation<F>(&mut self, generations: usize, hook: F) -> &mut Self
+    where
+        F: FnMut(&GenerationStats, Option<&P>) + Send + 'static,
+    {
+        self.hooks.on_stagnation.push((generations, Box::new(hook)));
+        self
+    }
+
+    /// Register a hook to be called when `run_until` returns.
+    pub fn on_termination<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: FnMut(&GenerationStats, Option<&P>) + Send + 'static,
+    {
+        self.hooks.on_termination.push(Box::new(hook));
+        self
+    }
+
+    pub fn stats(&self) -> GenerationStats {
+        let iteration = self.iteration();
+        GenerationStats {
+            iteration,
+            population_size: self.population().len(),
+            stagnant_for: iteration - self.last_improvement,
+        }
+    }
+
+    pub fn driver(&self) -> &D {
+        self.driver.as_ref().expect("driver missing")
+    }
+
+    pub fn into_driver(self) -> D {
+        self.driver.expect("driver missing")
+    }
+
+    /// Evolve the population by one generation.
+    pub fn step(&mut self) {
+        let driver = self.driver.take().expect("driver missing");
+        self.driver = Some(driver.evolve());
+
+        let driver = self.driver.as_ref().expect("driver missing");
+        let best = fittest(driver.population());
+        let improved = match (best.and_then(|b| b.fitness()), self.best_fitness.as_ref()) {
+            (Some(f), prior) => total_cmp(Some(f), prior) == Ordering::Less,
+            (None, _) => false,
+        };
+        if improved {
+            self.best_fitness = best.and_then(|b| b.fitness()).cloned();
+            self.last_improvement = driver.iteration();
+        }
+        let stats = GenerationStats {
+            iteration: driver.iteration(),
+            population_size: driver.population().len(),
+            stagnant_for: driver.iteration() - self.last_improvement,
+        };
+
+        for hook in self.hooks.after_generation.iter_mut() {
+            hook(&stats, best);
+        }
+        if improved {
+            for hook in self.hooks.on_new_best.iter_mut() {
+                hook(&stats, best);
+            }
+        }
+        for (generations, hook) in self.hooks.on_stagnation.iter_mut() {
+            if stats.stagnant_for == *generations {
+                hook(&stats, best);
+            }
+        }
+    }
+
+    pub fn iteration(&self) -> usize {
+        self.driver().iteration()
+    }
+
+    pub fn population(&self) -> Vec<&P> {
+        self.driver().population()
+    }
+
+    /// The fittest member of the current population, among those whose
+    /// fitness has been assessed.
+    pub fn best(&self) -> Option<&P> {
+        fittest(self.population())
+    }
+
+    /// Step until the predicate holds, or until evolution is halted by a
+    /// champion or a timeout. The predicate is checked before each step.
+    pub fn run_until<F: FnMut(&Self) -> bool>(&mut self, mut predicate: F) {
+        while crate::keep_going() && !predicate(self) {
+            self.step();
+        }
+        let stats = self.stats();
+        let driver = self.driver.as_ref().expect("driver missing");
+        let best = fittest(driver.population());
+        for hook in self.hooks.on_termination.iter_mut() {
+            hook(&stats, best);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{self, AtomicUsize};
+
+    use crate::ontogenesis::fixtures::{length_evaluator, quiet_observer};
+
+    use super::*;
+
+    #[test]
+    fn test_engine() {
+        let mut config = Config::default();
+        config.selection = Selection::Alps;
+        config.pop_size = 20;
+        config.min_init_len = 1;
+        config.max_init_len = 16;
+        config.max_length = 32;
+        config.tournament.tournament_size = 3;
+        config.tournament.num_parents = 2;
+        let observer = quiet_observer(&mut config, "engine");
+        let mut engine = Engine::from_config(&config, observer, length_evaluator()).unwrap();
+        let generations = Arc::new(AtomicUsize::new(0));
+        let new_bests = Arc::new(AtomicUsize::new(0));
+        let terminations = Arc::new(AtomicUsize::new(0));
+        {
+            let generations = generations.clone();
+            let new_bests = new_bests.clone();
+            let terminations = terminations.clone();
+            engine
+                .after_generation(move |stats, best| {
+                    assert!(best.is_some());
+                    assert!(stats.population_size > 0);
+                    generations.fetch_add(1, atomic::Ordering::Relaxed);
+                })
+                .on_new_best(move |stats, _| {
+                    assert_eq!(stats.stagnant_for, 0);
+                    new_bests.fetch_add(1, atomic::Ordering::Relaxed);
+                })
+                .on_termination(move |_, _| {
+                    terminations.fetch_add(1, atomic::Ordering::Relaxed);
+                });
+        }
+        assert_eq!(engine.iteration(), 0);
+        engine.step();
+        assert_eq!(engine.iteration(), 1);
+        // the first assessed generation is always an improvement
+        assert_eq!(new_bests.load(atomic::Ordering::Relaxed), 1);
+        // evolution halts early if anything else in the process has called
+        // `stop_everything`
+        engine.run_until(|e| e.iteration() >= 5);
+        assert!(engine.iteration() <= 5);
+        assert_eq!(
+            generations.load(atomic::Ordering::Relaxed),
+            engine.iteration()
+        );
+        assert_eq!(terminations.load(atomic::Ordering::Relaxed), 1);
+
+        let population = engine.population();
+        assert!(!population.is_empty());
+        let best = engine.best().expect("no best");
+        assert!(population
+            .iter()
+            .all(|p| p.fitness().unwrap()[0] >= best.fitness().unwrap()[0]));
+    }
+}
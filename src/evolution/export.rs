@@ -0,0 +1,242 @@
+use std::fmt::Debug;
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::configure::Config;
+#[cfg(feature = "emulator")]
+use crate::emulator::profiler::ProfileSummary;
+use crate::error::Error;
+use crate::evolution::{Genome, Phenome};
+use crate::logger::EVOLUTION;
+
+/// Everything we export about a single member of the population. The
+/// genome and fitness are serialized as they are, but the profile is
+/// replaced by its summary, since raw profiles can run to megabytes.
+#[derive(Serialize)]
+pub struct IndividualRecord<'a, P: Phenome + Genome> {
+    pub name: &'a str,
+    pub tag: u64,
+    pub generation: usize,
+    pub age: usize,
+    pub num_offspring: usize,
+    pub native_island: usize,
+    pub parents: Vec<String>,
+    pub chromosome: &'a [P::Allele],
+    pub fitness: Option<&'a P::Fitness>,
+    pub scalar_fitness: Option<f64>,
+    #[cfg(feature = "emulator")]
+    pub profile: Option<ProfileSummary>,
+    pub behavior: Option<Vec<f64>>,
+}
+
+impl<'a, P: Phenome + Genome> IndividualRecord<'a, P> {
+    pub fn new(creature: &'a P, config: &Config) -> Self {
+        Self {
+            name: creature.name(),
+            tag: creature.tag(),
+            generation: creature.generation(),
+            age: creature.age(),
+            num_offspring: creature.num_offspring(),
+            native_island: creature.native_island(),
+            parents: creature.parent_names(),
+            chromosome: creature.chromosome(),
+            fitness: creature.fitness(),
+            scalar_fitness: creature.scalar_fitness(&config.fitness.weighting),
+            #[cfg(feature = "emulator")]
+            profile: creature.profile_summary(),
+            behavior: creature.behavior_descriptor(),
+        }
+    }
+}
+
+/// Write the whole population to `path` as a single, uncompressed JSON
+/// array of `IndividualRecord`s, for loading into external analysis tools.
+pub fn export_population<'a, P, I, Q>(population: I, config: &Config, path: Q) -> Result<(), Error>
+where
+    P: Phenome + Genome + 'a,
+    I: Iterator<Item = &'a P>,
+    Q: AsRef<Path> + Debug,
+{
+    let records = population
+        .map(|p| IndividualRecord::new(p, config))
+        .collect::<Vec<IndividualRecord<'_, P>>>();
+    let mut writer = BufWriter::new(fs::File::create(&path)?);
+    serde_json::to_writer(&mut writer, &records)?;
+    writer.flush()?;
+    log::info!(
+        target: EVOLUTION,
+        "Exported {} individuals to {:?}",
+        records.len(),
+        path
+    );
+    Ok(())
+}
+
+/// The parts of an exported individual that are needed to bring it back
+/// to life. Everything else in the record is ignored, so hand-written
+/// population files need only list chromosomes.
+#[derive(Deserialize)]
+struct ImportedRecord<A> {
+    #[serde(default)]
+    name: Option<String>,
+    chromosome: Vec<A>,
+}
+
+/// Read a population from a JSON file in the format written by
+/// `export_population`. Chromosomes that are empty, too long, or otherwise
+/// incompatible with the current configuration are skipped with a warning,
+/// and no more than `pop_size` creatures are returned.
+pub fn import_population<P, Q>(config: &Config, path: Q) -> Result<Vec<P>, Error>
+where
+    P: Genome,
+    Q: AsRef<Path> + Debug,
+{
+    let reader = BufReader::new(fs::File::open(&path)?);
+    let records: Vec<ImportedRecord<P::Allele>> = serde_json::from_reader(reader)?;
+    let num_records = records.len();
+    let population = records
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, record)| {
+            let len = record.chromosome.len();
+            let imported = if len == 0 || len > config.max_length {
+                Err(Error::Misc(format!(
+                    "chromosome length {} is outside of 1..={}",
+                    len, config.max_length
+                )))
+            } else {
+                P::from_chromosome(record.name, record.chromosome, config)
+            };
+            match imported {
+                Ok(creature) => Some(creature),
+                Err(e) => {
+                    log::warn!(
+                        target: EVOLUTION,
+                        "Skipping individual {} in {:?}: {:?}",
+                        i,
+                        path,
+                        e
+                    );
+                    None
+                }
+            }
+        })
+        .take(config.pop_size)
+        .collect::<Vec<P>>();
+    log::info!(
+        target: EVOLUTION,
+        "Imported {} of the {} individuals in {:?}",
+        population.len(),
+        num_records,
+        path
+    );
+    Ok(population)
+}
+
+/// The creatures with which to seed the initial population, if
+/// `initial_population_path` is set. It's up to the caller to fill the
+/// remainder of the population at random.
+pub fn initial_population<P: Genome>(config: &Config) -> Vec<P> {
+    match config.initial_population_path {
+        Some(ref path) => import_population(config, path).unwrap_or_else(|e| {
+            log::error!(
+                target: EVOLUTION,
+                "Failed to import a population from {}: {:?}",
+                path,
+                e
+            );
+            vec![]
+        }),
+        None => vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::examples::linear_gp::Creature;
+    use crate::fitness::Weighted;
+
+    use super::*;
+
+    #[test]
+    fn test_export_population() {
+        let mut config = Config::default();
+        config.min_init_len = 1;
+        config.max_init_len = 16;
+        config.linear_gp.num_registers = Some(4);
+        let mut population = (0..5)
+            .map(|i| Creature::random(&config, i))
+            .collect::<Vec<Creature>>();
+        for (i, p) in population.iter_mut().enumerate() {
+            let mut fitness = Weighted::new(&config.fitness.weighting);
+            fitness.insert("error_rate", i as f64);
+            p.set_fitness(fitness);
+        }
+        // unique to this process, so that concurrent test runs don't
+        // clobber each other's files
+        let dir = std::env::temp_dir().join(format!(
+            "berbalang_test_export_population_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("population.json");
+        export_population(population.iter(), &config, &path).unwrap();
+
+        let exported: serde_json::Value =
+            serde_json::from_reader(fs::File::open(&path).unwrap()).unwrap();
+        let records = exported.as_array().unwrap();
+        assert_eq!(records.len(), 5);
+        for (record, p) in records.iter().zip(population.iter()) {
+            assert_eq!(record["name"], p.name());
+            assert_eq!(record["tag"], p.tag());
+            assert_eq!(record["chromosome"].as_array().unwrap().len(), p.len());
+            // creatures without a profile have nothing to summarize
+            assert!(record["profile"].is_null());
+        }
+        assert_eq!(records[3]["fitness"]["scores"]["error_rate"], 3.0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_population() {
+        let mut config = Config::default();
+        config.min_init_len = 1;
+        config.max_init_len = 16;
+        config.max_length = 32;
+        config.pop_size = 3;
+        config.linear_gp.num_registers = Some(4);
+        let population = (0..3)
+            .map(|i| Creature::random(&config, i))
+            .collect::<Vec<Creature>>();
+        // unique to this process, so that concurrent test runs don't
+        // clobber each other's files
+        let dir = std::env::temp_dir().join(format!(
+            "berbalang_test_import_population_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("population.json");
+        export_population(population.iter(), &config, &path).unwrap();
+
+        let imported: Vec<Creature> = import_population(&config, &path).unwrap();
+        assert_eq!(imported.len(), 3);
+        for (a, b) in imported.iter().zip(population.iter()) {
+            assert_eq!(a.chromosome(), b.chromosome());
+        }
+
+        // with fewer registers, some of the instructions no longer make sense
+        config.linear_gp.num_registers = Some(1);
+        let imported: Vec<Creature> = import_population(&config, &path).unwrap();
+        assert!(imported.len() < 3);
+
+        // and the rest of the population is left to the caller
+        config.initial_population_path = Some(path.to_string_lossy().to_string());
+        config.linear_gp.num_registers = Some(4);
+        config.pop_size = 2;
+        assert_eq!(initial_population::<Creature>(&config).len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -15,6 +15,7 @@ use crate::configure::Config;
 use crate::evolution::population::pier::Pier;
 use crate::evolution::population::shuffling_heap::ShufflingHeap;
 use crate::evolution::{Genome, Phenome};
+use crate::logger::EVOLUTION;
 use crate::observer::Observer;
 use crate::ontogenesis::Develop;
 use crate::util::random::hash_seed_rng;
@@ -41,13 +42,13 @@ impl<Q: Hash + Debug, E: Develop<P>, P: Phenome<Problem = Q> + Genome + 'static>
     where
         Self: Sized,
     {
-        log::debug!("Initializing population");
+        log::debug!(target: EVOLUTION, "Initializing population");
         let config = Arc::new(config.clone());
         let conf = config.clone();
         let pop_size = config.pop_size;
         let population: ShufflingHeap<P> = womb
             .development_pipeline((0..pop_size).map(move |i| {
-                log::debug!("creating phenome {}/{}", i, pop_size);
+                log::debug!(target: EVOLUTION, "creating phenome {}/{}", i, pop_size);
                 P::random(&conf, i)
             }))
             .into_iter()
@@ -56,7 +57,7 @@ impl<Q: Hash + Debug, E: Develop<P>, P: Phenome<Problem = Q> + Genome + 'static>
                 phenome
             })
             .collect();
-        log::debug!("population initialized");
+        log::debug!(target: EVOLUTION, "population initialized");
 
         let problems: ShufflingHeap<Q> = problems.into_iter().collect();
 
@@ -114,9 +115,9 @@ impl<Q: Hash + Debug, E: Develop<P>, P: Phenome<Problem = Q> + Genome + 'static>
                 // if we just popped the last problem
                 // and we have some creatures who have passed,
                 // then the evolutionary process is complete!
-                log::info!("Solution(s) found!");
+                log::info!(target: EVOLUTION, "Solution(s) found!");
                 while let Some(champion) = pass.pop() {
-                    log::info!("{:?}", champion);
+                    log::info!(target: EVOLUTION, "{:?}", champion);
 
                     // TODO: Log this! have the observer handle it.
                 }
@@ -137,6 +138,7 @@ impl<Q: Hash + Debug, E: Develop<P>, P: Phenome<Problem = Q> + Genome + 'static>
         // If any problems are remaining, dump them into the next_problems heap,
         // shuffling them in the process.
         log::debug!(
+            target: EVOLUTION,
             "iteration {}: solved {} of {} problems",
             iteration,
             problems_solved,
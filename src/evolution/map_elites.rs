@@ -0,0 +1,210 @@
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::iter;
+use std::path::Path;
+
+use hashbrown::HashMap;
+use rand::Rng;
+
+use crate::configure::Config;
+use crate::error::Error;
+use crate::evolution::{export, Genome, Phenome};
+use crate::fitness::total_cmp_f64;
+use crate::increment_epoch_counter;
+use crate::logger::EVOLUTION;
+use crate::observer::Observer;
+use crate::ontogenesis::Develop;
+use crate::util::dump::dump_as;
+use crate::util::random::hash_seed_rng;
+
+/// For a description of MAP-Elites, see Jean-Baptiste Mouret and Jeff
+/// Clune, "Illuminating search spaces by mapping elites", 2015.
+///
+/// The behaviour space is spanned by the axes given in the `map_elites`
+/// config, each of which is an element of the creatures' behaviour
+/// descriptors. Each axis is divided into bins, and the grid keeps the
+/// fittest creature found in each cell.
+pub struct MapElites<E: Develop<P>, P: Phenome + Genome + 'static> {
+    pub grid: HashMap<Vec<usize>, P>,
+    pub config: Config,
+    pub iteration: usize,
+    pub observer: Observer<P>,
+    pub evaluator: E,
+}
+
+impl<E: Develop<P>, P: Phenome + Genome + 'static> MapElites<E, P> {
+    pub fn new(config: &Config, observer: Observer<P>, evaluator: E) -> Self {
+        let mut map = Self {
+            grid: HashMap::new(),
+            config: config.clone(),
+            iteration: 0,
+            observer,
+            evaluator,
+        };
+        let mut initial = export::initial_population::<P>(config);
+        initial.extend((initial.len()..config.pop_size).map(|i| P::random(config, i)));
+        for creature in map.evaluate(initial) {
+            map.place(creature);
+        }
+        map
+    }
+
+    fn evaluate(&mut self, creatures: Vec<P>) -> Vec<P> {
+        let evaluator = &mut self.evaluator;
+        evaluator
+            .development_pipeline(creatures.into_iter())
+            .into_iter()
+            .map(|p| evaluator.apply_fitness_function(p))
+            .collect::<Vec<P>>()
+    }
+
+    /// The coordinates of the cell in which the creature belongs, or `None`
+    /// if it has no behaviour descriptor, or the descriptor lacks an
+    /// element that an axis needs.
+    pub fn cell(&self, creature: &P) -> Option<Vec<usize>> {
+        self.config
+            .map_elites
+            .axes
+            .iter()
+            .map(|axis| {
+                creature
+                    .describe_behavior(&[axis.feature])?
+                    .get(axis.element)
+                    .map(|v| axis.bin(*v))
+            })
+            .collect()
+    }
+
+    fn quality(&self, creature: &P) -> f64 {
+        creature
+            .scalar_fitness(&self.config.fitness.weighting)
+            .unwrap_or(std::f64::MAX)
+    }
+
+    /// Put the creature in its cell, if the cell is empty or occupied by a
+    /// less fit creature. Returns true if the creature was placed.
+    pub fn place(&mut self, creature: P) -> bool {
+        let cell = match self.cell(&creature) {
+            Some(cell) => cell,
+            None => return false,
+        };
+        let quality = self.quality(&creature);
+        let better = match self.grid.get(&cell) {
+            Some(occupant) => total_cmp_f64(quality, self.quality(occupant)) == Ordering::Less,
+            None => true,
+        };
+        if better {
+            self.grid.insert(cell, creature);
+        }
+        better
+    }
+
+    /// Write the grid to the `map_elites` subdirectory of the data
+    /// directory, as a list of cells and their elites.
+    pub fn dump_grid(&self) {
+        let path = format!(
+            "{}/map_elites/grid_{}.{}",
+            self.config.data_directory(),
+            self.iteration,
+            self.config.observer.dump_format.extension(),
+        );
+        let grid = self.grid.iter().collect::<Vec<(&Vec<usize>, &P)>>();
+        if let Err(e) = dump_as(&grid, &path, self.config.observer.dump_format) {
+            log::error!(
+                target: EVOLUTION,
+                "Failed to dump MAP-Elites grid to {}: {:?}",
+                path,
+                e
+            );
+        }
+    }
+
+    /// Export the elites of every cell to a single JSON file at `path`.
+    pub fn export_population<Q: AsRef<Path> + Debug>(&self, path: Q) -> Result<(), Error> {
+        export::export_population(self.grid.values(), &self.config, path)
+    }
+
+    pub fn evolve(mut self) -> Self {
+        let mut rng = hash_seed_rng(&(self.config.random_seed, self.iteration));
+        let elites = self.grid.values().collect::<Vec<&P>>();
+        let offspring = if elites.is_empty() {
+            // nothing has been placed yet, so keep sampling at random
+            (0..self.config.map_elites.batch_size)
+                .map(|i| P::random(&self.config, (self.iteration, i)))
+                .collect::<Vec<P>>()
+        } else {
+            (0..self.config.map_elites.batch_size)
+                .map(|_| {
+                    let parents = iter::repeat(())
+                        .take(self.config.tournament.num_parents)
+                        .map(|()| elites[rng.gen_range(0, elites.len())])
+                        .collect::<Vec<&P>>();
+                    Genome::mate(&parents, &self.config)
+                })
+                .collect::<Vec<P>>()
+        };
+        let offspring = self.evaluate(offspring);
+        let mut placed = 0;
+        for child in offspring {
+            self.observer.observe(child.clone());
+            if self.place(child) {
+                placed += 1;
+            }
+        }
+        log::debug!(
+            target: EVOLUTION,
+            "Island {} iteration {}: placed {} offspring, {} cells filled",
+            self.config.island_id,
+            self.iteration,
+            placed,
+            self.grid.len()
+        );
+
+        let period = self.config.map_elites.dump_period;
+        if period > 0 && self.iteration % period == 0 {
+            self.dump_grid();
+        }
+
+        increment_epoch_counter();
+        self.iteration += 1;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::{AxisConfig, BehaviorFeature};
+    use crate::ontogenesis::fixtures::{length_evaluator, quiet_observer};
+
+    use super::*;
+
+    #[test]
+    fn test_grid_keeps_one_elite_per_cell() {
+        let mut config = Config::default();
+        config.pop_size = 20;
+        config.min_init_len = 1;
+        config.max_init_len = 32;
+        config.max_length = 64;
+        config.tournament.num_parents = 2;
+        config.map_elites.batch_size = 8;
+        // lengths of 1 to 64 squash into [0.5, 1)
+        config.map_elites.axes = vec![AxisConfig {
+            feature: BehaviorFeature::ChainLength,
+            element: 0,
+            min: 0.5,
+            max: 1.0,
+            bins: 8,
+        }];
+        let observer = quiet_observer(&mut config, "map_elites");
+        let mut map = MapElites::new(&config, observer, length_evaluator());
+        for _ in 0..20 {
+            map = map.evolve();
+        }
+        assert!(!map.grid.is_empty());
+        for (cell, elite) in map.grid.iter() {
+            assert_eq!(cell.len(), 1);
+            assert!(cell[0] < 8);
+            assert_eq!(map.cell(elite).as_ref(), Some(cell));
+        }
+    }
+}
@@ -4,6 +4,7 @@ use rand::Rng;
 
 use crate::configure::Config;
 use crate::evolution::{Genome, Phenome};
+use crate::logger::EVOLUTION;
 use crate::observer::Observer;
 use crate::ontogenesis::Develop;
 use crate::util::random::hash_seed_rng;
@@ -68,6 +69,7 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Metropolis<E, P> {
             //if delta < 0.0 { // pure hillclimbing
             specimen = variation;
             log::info!(
+                target: EVOLUTION,
                 "[{}] best: {:?}. specimen: {}, variation: {} (delta {}), switching",
                 iteration,
                 best.as_ref()
@@ -95,7 +97,7 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Metropolis<E, P> {
             _ => best,
         };
         if updated_best {
-            log::info!("new best: {:?}", best.as_ref().unwrap());
+            log::info!(target: EVOLUTION, "new best: {:?}", best.as_ref().unwrap());
         }
         Self {
             specimen,
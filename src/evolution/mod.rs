@@ -1,18 +1,31 @@
+use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::Debug;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
+use std::iter;
 
 use rand::{thread_rng, Rng};
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::configure::Config;
-use crate::fitness::FitnessScore;
+use crate::configure::{
+    BehaviorFeature, Config, CullingPolicy, MutationOperator, SelfAdaptationConfig,
+};
+#[cfg(feature = "emulator")]
+use crate::emulator::profiler::ProfileSummary;
+use crate::error::Error;
+use crate::fitness::{total_cmp, total_cmp_f64, FitnessScore};
+use crate::logger::EVOLUTION;
 use crate::util;
 use crate::util::count_min_sketch::Sketch;
 use crate::util::levy_flight::levy_decision;
-use crate::util::random::{hash_seed_rng, Prng};
+use crate::util::random::{hash_seed_rng, Prng, StableHasher};
 
+pub mod alps;
+pub mod dyn_genome;
+pub mod engine;
+pub mod export;
+pub mod map_elites;
 //pub mod lexicase;
 pub mod metropolis;
 pub mod pareto_roulette;
@@ -24,6 +37,26 @@ pub trait Mutation {
 
     fn mutate_point(allele: &mut Self::Allele, config: &Config) -> Self;
 
+    /// Shift the allele by a heavy-tailed random step, for the `levy_step`
+    /// mutation operator, or return `None`, leaving it untouched, if the
+    /// allele has no notion of distance.
+    fn levy_step(_allele: &mut Self::Allele, _config: &Config) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Move the allele to the nearest gadget, for the `gadget_snap`
+    /// mutation operator, or return `None`, leaving it untouched, if the
+    /// allele isn't an address, or there are no gadgets to snap to.
+    fn snap_to_gadget(_allele: &mut Self::Allele, _config: &Config) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
     fn mutate(chromosome: &mut [Self::Allele], config: &Config) -> Vec<Option<Self>>
     where
         Self: Sized,
@@ -40,6 +73,77 @@ pub trait Mutation {
             })
             .collect::<Vec<Option<Self>>>()
     }
+
+    /// Mutate each allele independently with probability `rate`, as when
+    /// the genome carries its own `StrategyParams`.
+    fn mutate_at_rate(
+        chromosome: &mut [Self::Allele],
+        rate: f64,
+        config: &Config,
+    ) -> Vec<Option<Self>>
+    where
+        Self: Sized,
+    {
+        let mut rng = rand::thread_rng();
+        let rate = rate.max(0.0).min(1.0);
+        chromosome
+            .iter_mut()
+            .map(|allele| {
+                if rng.gen_bool(rate) {
+                    Some(Self::mutate_point(allele, &config))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<Option<Self>>>()
+    }
+}
+
+/// Parameters of a genome's own mutation strategy, which are inherited and
+/// mutated along with it, when `config.self_adaptation` is set.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct StrategyParams {
+    pub mutation_rate: f64,
+}
+
+impl StrategyParams {
+    /// The parameters of a randomly generated genome, if self-adaptation is
+    /// enabled.
+    pub fn initial(config: &Config) -> Option<Self> {
+        config.self_adaptation.as_ref().map(|s| Self {
+            mutation_rate: s.initial_rate,
+        })
+    }
+
+    /// The mean of the parents' parameters, or `None` if none of them have
+    /// any.
+    pub fn inherit<'a, I: Iterator<Item = Option<&'a Self>>>(parents: I) -> Option<Self> {
+        let rates = parents
+            .filter_map(|p| p.map(|p| p.mutation_rate))
+            .collect::<Vec<f64>>();
+        if rates.is_empty() {
+            None
+        } else {
+            Some(Self {
+                mutation_rate: rates.iter().sum::<f64>() / rates.len() as f64,
+            })
+        }
+    }
+
+    /// Perturb the mutation rate log-normally, keeping it within the
+    /// configured bounds.
+    pub fn adapt<R: Rng>(&mut self, config: &SelfAdaptationConfig, rng: &mut R) {
+        let step = rng.sample::<f64, _>(rand_distr::StandardNormal) * config.learning_rate;
+        self.mutation_rate = (self.mutation_rate * step.exp())
+            .max(config.min_rate)
+            .min(config.max_rate);
+    }
+}
+
+impl Hash for StrategyParams {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.mutation_rate.to_bits().hash(state)
+    }
 }
 
 //@formatter:off
@@ -55,6 +159,7 @@ pub struct LinearChromosome<
     pub parent_names: Vec<String>,
     pub name: String,
     pub generation: usize,
+    pub strategy_params: Option<StrategyParams>,
 }
 
 // TODO: Define a mutation method on the mutation enum type
@@ -69,12 +174,24 @@ impl<
     }
 
     pub fn crossover(parents: &[&Self], config: &Config) -> Self {
+        Self::with_locked_prefix(parents, config, |parents| {
+            Self::unlocked_crossover(parents, config)
+        })
+    }
+
+    fn unlocked_crossover(parents: &[&Self], config: &Config) -> Self {
         let min_mate_len = parents.iter().map(|p| p.len()).min().unwrap();
         let lambda = min_mate_len as f64 / config.crossover_period;
         let mut rng = thread_rng();
         if rng.gen_bool(config.crossover_rate) {
             match config.crossover_algorithm.as_ref() {
+                // one point crossover only makes sense for a pair of parents
+                "one_point" if parents.len() > 2 => Self::uniform_crossover(&parents, config),
                 "one_point" => Self::one_point_crossover(&parents, config),
+                "uniform" => Self::uniform_crossover(&parents, config),
+                // boundaries can only be identified by callers that know what
+                // the alleles mean, and which use `boundary_crossover` directly
+                "gadget_boundary" => Self::one_point_crossover(&parents, config),
                 "alternating" => {
                     let distribution =
                         rand_distr::Exp::new(lambda).expect("Failed to create random distribution");
@@ -87,6 +204,68 @@ impl<
         }
     }
 
+    /// Breed an offspring by `op` from the parents stripped of their first
+    /// `config.locked_prefix` alleles, and then restore the first parent's
+    /// prefix to it, so that no cut point can fall within the prefix. If any
+    /// parent has nothing beyond the prefix, the offspring is a clone of the
+    /// first parent.
+    fn with_locked_prefix<F: FnOnce(&[&Self]) -> Self>(
+        parents: &[&Self],
+        config: &Config,
+        op: F,
+    ) -> Self {
+        let locked = config.locked_prefix;
+        if locked == 0 {
+            return op(parents);
+        }
+        if parents.iter().any(|p| p.len() <= locked) {
+            return parents[0].cloned_offspring();
+        }
+        let tails = parents
+            .iter()
+            .map(|p| p.tail(locked))
+            .collect::<Vec<Self>>();
+        let tails = tails.iter().collect::<Vec<&Self>>();
+        let prefix_parentage = vec![0; locked];
+        op(&tails).with_head(parents[0], locked, &prefix_parentage, config)
+    }
+
+    /// A copy of the chromosome without its first `n` alleles.
+    fn tail(&self, n: usize) -> Self {
+        let len = self.len();
+        let mut tail = self.clone();
+        tail.chromosome.drain(..n);
+        if tail.parentage.len() == len {
+            tail.parentage.drain(..n);
+        }
+        if tail.mutations.len() == len {
+            tail.mutations.drain(..n);
+        }
+        tail
+    }
+
+    /// Prepend the first `n` alleles of `head`, unmutated, and with the
+    /// given parentage, then trim the chromosome to the maximum length,
+    /// though never so far as to cut into the prefix.
+    fn with_head(mut self, head: &Self, n: usize, parentage: &[usize], config: &Config) -> Self {
+        let len = self.len();
+        self.chromosome
+            .splice(0..0, head.chromosome[..n].iter().cloned());
+        if self.parentage.len() == len && parentage.len() == n {
+            self.parentage.splice(0..0, parentage.iter().cloned());
+        }
+        if self.mutations.len() == len {
+            self.mutations.splice(0..0, iter::repeat(None).take(n));
+        }
+        if config.max_length > 0 {
+            let max_length = config.max_length.max(n);
+            self.chromosome.truncate(max_length);
+            self.parentage.truncate(max_length);
+            self.mutations.truncate(max_length);
+        }
+        self
+    }
+
     pub fn cloned_offspring(&self) -> Self {
         let mut offspring = self.clone();
         offspring.generation += 1;
@@ -100,13 +279,8 @@ impl<
         let mut rng = thread_rng();
         let mother_idx = rng.gen::<usize>() % parents.len();
         let father_idx = (mother_idx + 1) % parents.len();
-        let mother = parents[mother_idx];
-        let father = parents[father_idx];
-        let splice_f = rng.gen_range(0, father.len());
-        let splice_m = rng.gen_range(0, mother.len());
-        let mut chromosome = Vec::new();
-        let mut parentage = Vec::new();
-        let mut counter = 0;
+        let splice_f = rng.gen_range(0, parents[father_idx].len());
+        let splice_m = rng.gen_range(0, parents[mother_idx].len());
         // let there be some chance of the first allele being dropped.
         // because it's unlikely this will happen otherwise. the first allele
         // decides a lot.
@@ -115,6 +289,79 @@ impl<
         } else {
             0
         };
+        Self::splice(
+            parents,
+            (mother_idx, splice_m),
+            (father_idx, start, splice_f),
+            config,
+        )
+    }
+
+    /// One point crossover that only cuts the parents at positions holding
+    /// an allele for which `is_boundary` holds, such as the address of a
+    /// gadget in a ROP chain. This keeps whole frames intact across
+    /// recombination. Only two of the parents are recombined. Falls back to
+    /// ordinary one point crossover when either parent has no boundaries.
+    pub fn boundary_crossover<F: Fn(&A) -> bool>(
+        parents: &[&Self],
+        config: &Config,
+        is_boundary: F,
+    ) -> Self {
+        Self::with_locked_prefix(parents, config, |parents| {
+            Self::unlocked_boundary_crossover(parents, config, is_boundary)
+        })
+    }
+
+    fn unlocked_boundary_crossover<F: Fn(&A) -> bool>(
+        parents: &[&Self],
+        config: &Config,
+        is_boundary: F,
+    ) -> Self {
+        let mut rng = thread_rng();
+        if !rng.gen_bool(config.crossover_rate) {
+            return parents[rng.gen::<usize>() % parents.len()].cloned_offspring();
+        }
+        let mother_idx = rng.gen::<usize>() % parents.len();
+        let father_idx = (mother_idx + 1) % parents.len();
+        let boundaries = |p: &Self| {
+            p.chromosome
+                .iter()
+                .enumerate()
+                .filter(|(_, a)| is_boundary(a))
+                .map(|(i, _)| i)
+                .collect::<Vec<usize>>()
+        };
+        let boundaries_m = boundaries(parents[mother_idx]);
+        let boundaries_f = boundaries(parents[father_idx]);
+        if boundaries_m.is_empty() || boundaries_f.is_empty() {
+            log::debug!(
+                target: EVOLUTION,
+                "no boundaries to cut at, falling back to one point crossover"
+            );
+            return Self::one_point_crossover(&[parents[mother_idx], parents[father_idx]], config);
+        }
+        let splice_f = boundaries_f[rng.gen_range(0, boundaries_f.len())];
+        let splice_m = boundaries_m[rng.gen_range(0, boundaries_m.len())];
+        Self::splice(
+            parents,
+            (mother_idx, splice_m),
+            (father_idx, 0, splice_f),
+            config,
+        )
+    }
+
+    /// Joins `father[start..splice_f]` to `mother[splice_m..]`.
+    fn splice(
+        parents: &[&Self],
+        (mother_idx, splice_m): (usize, usize),
+        (father_idx, start, splice_f): (usize, usize, usize),
+        config: &Config,
+    ) -> Self {
+        let mother = parents[mother_idx];
+        let father = parents[father_idx];
+        let mut chromosome = Vec::new();
+        let mut parentage = Vec::new();
+        let mut counter = 0;
         for i in start..splice_f {
             chromosome.push(father.chromosome[i].clone());
             parentage.push(father_idx);
@@ -145,6 +392,49 @@ impl<
                 .collect::<Vec<String>>(),
             name,
             generation,
+            strategy_params: Self::inherited_strategy(parents),
+        }
+    }
+
+    fn inherited_strategy(parents: &[&Self]) -> Option<StrategyParams> {
+        StrategyParams::inherit(parents.iter().map(|p| p.strategy_params.as_ref()))
+    }
+
+    /// Builds the offspring one locus at a time, taking each allele from a
+    /// randomly chosen parent long enough to have one at that locus. Unlike
+    /// one point crossover, this works with any number of parents.
+    fn uniform_crossover(parents: &[&Self], config: &Config) -> Self {
+        let mut rng = thread_rng();
+        let mut len = parents[rng.gen_range(0, parents.len())].len();
+        if config.max_length > 0 {
+            len = len.min(config.max_length);
+        }
+        let mut chromosome = Vec::with_capacity(len);
+        let mut parentage = Vec::with_capacity(len);
+        for i in 0..len {
+            let donors = parents
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| p.len() > i)
+                .map(|(j, _)| j)
+                .collect::<Vec<usize>>();
+            let src = donors[rng.gen_range(0, donors.len())];
+            chromosome.push(parents[src].chromosome[i].clone());
+            parentage.push(src);
+        }
+
+        let name = util::name::random(4, &chromosome);
+        Self {
+            chromosome,
+            mutations: vec![None; len],
+            parentage,
+            parent_names: parents
+                .iter()
+                .map(|p| p.name.clone())
+                .collect::<Vec<String>>(),
+            name,
+            generation: parents.iter().map(|p| p.generation).max().unwrap_or(0) + 1,
+            strategy_params: Self::inherited_strategy(parents),
         }
     }
 
@@ -205,13 +495,127 @@ impl<
                 .collect::<Vec<String>>(),
             name,
             generation: parents.iter().map(|p| p.generation).max().unwrap_or(0) + 1,
+            strategy_params: Self::inherited_strategy(parents),
         }
     }
 
+    /// Mutate the chromosome by an operator drawn from
+    /// `config.mutation_operators`, or pointwise if none are configured.
+    /// A chromosome with its own `StrategyParams` adapts them first, and
+    /// uses its own rate for pointwise mutation. The first
+    /// `config.locked_prefix` alleles are left untouched.
     pub fn mutate(&mut self, config: &Config) {
-        // maybe check a uniform mutation rate to see if any pointwise mutations happen at all.
-        let mutations = M::mutate(&mut self.chromosome, config);
-        self.mutations = mutations;
+        let locked = config.locked_prefix.min(self.len());
+        if locked == 0 {
+            self.unlocked_mutate(config);
+            return;
+        }
+        if locked == self.len() {
+            return;
+        }
+        let parentage = if self.parentage.len() == self.len() {
+            self.parentage[..locked].to_vec()
+        } else {
+            vec![]
+        };
+        let mut tail = self.tail(locked);
+        tail.unlocked_mutate(config);
+        let mutated = tail.with_head(self, locked, &parentage, config);
+        *self = mutated;
+    }
+
+    fn unlocked_mutate(&mut self, config: &Config) {
+        let mut rng = thread_rng();
+        if let Some(adaptation) = config.self_adaptation.as_ref() {
+            if let Some(params) = self.strategy_params.as_mut() {
+                params.adapt(adaptation, &mut rng);
+            }
+        }
+        match config.mutation_operators.choose(&mut rng) {
+            Some(MutationOperator::Insert) => self.insert_mutation(config, &mut rng),
+            Some(MutationOperator::Delete) => self.delete_mutation(&mut rng),
+            Some(MutationOperator::Rotate) => self.rotate_mutation(&mut rng),
+            // alleles that these don't apply to are mutated pointwise instead
+            Some(MutationOperator::LevyStep)
+                if self.locus_mutation(M::levy_step, config, &mut rng) => {}
+            Some(MutationOperator::GadgetSnap)
+                if self.locus_mutation(M::snap_to_gadget, config, &mut rng) => {}
+            _ => {
+                let mutations = match self.strategy_params {
+                    Some(params) if config.self_adaptation.is_some() => {
+                        M::mutate_at_rate(&mut self.chromosome, params.mutation_rate, config)
+                    }
+                    // maybe check a uniform mutation rate to see if any pointwise mutations happen at all.
+                    _ => M::mutate(&mut self.chromosome, config),
+                };
+                self.mutations = mutations;
+            }
+        }
+    }
+
+    /// Apply the operator to a single, randomly chosen allele, returning
+    /// false if it didn't apply.
+    fn locus_mutation<R: Rng>(
+        &mut self,
+        operator: fn(&mut A, &Config) -> Option<M>,
+        config: &Config,
+        rng: &mut R,
+    ) -> bool {
+        let len = self.len();
+        if len == 0 {
+            return false;
+        }
+        let i = rng.gen_range(0, len);
+        match operator(&mut self.chromosome[i], config) {
+            Some(mutation) => {
+                self.mutations = vec![None; len];
+                self.mutations[i] = Some(mutation);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert_mutation<R: Rng>(&mut self, config: &Config, rng: &mut R) {
+        let len = self.len();
+        if len == 0 || (config.max_length > 0 && len >= config.max_length) {
+            return;
+        }
+        let src = rng.gen_range(0, len);
+        let dst = rng.gen_range(0, len + 1);
+        let allele = self.chromosome[src].clone();
+        self.chromosome.insert(dst, allele);
+        if self.parentage.len() == len {
+            let parent = self.parentage[src];
+            self.parentage.insert(dst, parent);
+        }
+        self.mutations = vec![None; len + 1];
+    }
+
+    fn delete_mutation<R: Rng>(&mut self, rng: &mut R) {
+        let len = self.len();
+        if len <= 1 {
+            return;
+        }
+        let i = rng.gen_range(0, len);
+        self.chromosome.remove(i);
+        if self.parentage.len() == len {
+            self.parentage.remove(i);
+        }
+        self.mutations = vec![None; len - 1];
+    }
+
+    fn rotate_mutation<R: Rng>(&mut self, rng: &mut R) {
+        let len = self.len();
+        if len <= 1 {
+            return;
+        }
+        let k = rng.gen_range(1, len);
+        self.chromosome.rotate_left(k);
+        if self.parentage.len() == len {
+            self.parentage.rotate_left(k);
+        }
+        self.mutations = vec![None; len];
     }
 }
 
@@ -222,6 +626,9 @@ where
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Name: {}\nGeneration: {}", self.name, self.generation)?;
+        if let Some(params) = self.strategy_params.as_ref() {
+            writeln!(f, "Mutation rate: {}", params.mutation_rate)?;
+        }
         for i in 0..self.chromosome.len() {
             let parent = if self.parent_names.is_empty() {
                 "seed"
@@ -249,8 +656,17 @@ where
     }
 }
 
+/// Why a genome fails to satisfy the constraints of the search space, as
+/// reported by `Genome::validate`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RepairNeeded(pub String);
+
+/// The number of times `Genome::mate` will try to produce a valid offspring
+/// before giving up and returning an invalid one.
+pub const MAX_MATING_ATTEMPTS: usize = 10;
+
 pub trait Genome: Hash {
-    type Allele: Clone + Debug + PartialEq + Eq + Hash + Serialize + Sized;
+    type Allele: Clone + Debug + PartialEq + Eq + Hash + Serialize + DeserializeOwned + Sized;
 
     fn chromosome(&self) -> &[Self::Allele];
 
@@ -260,12 +676,48 @@ pub trait Genome: Hash {
         self.chromosome().len()
     }
 
+    /// The size of the genome, for the purposes of parsimony pressure
+    /// and reporting. By default, this is the length of the chromosome.
+    fn size(&self) -> usize {
+        self.len()
+    }
+
     fn native_island(&self) -> usize;
 
+    /// A hash of the chromosome that's the same from run to run and from
+    /// platform to platform, unlike the one given by `Hash`, for caching
+    /// and tie-breaking. The alleles are hashed one at a time, so that
+    /// integer alleles go through the hasher's integer methods, rather
+    /// than being hashed as a slice of native-endian bytes.
+    fn stable_hash(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        hasher.write_usize(self.len());
+        for allele in self.chromosome() {
+            allele.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
     fn random<H: Hash>(config: &Config, salt: H) -> Self
     where
         Self: Sized;
 
+    /// Build a genome from a chromosome that was produced elsewhere, such as
+    /// a population exported by an earlier run. Returns an error if the
+    /// chromosome isn't compatible with the current configuration.
+    fn from_chromosome(
+        _name: Option<String>,
+        _chromosome: Vec<Self::Allele>,
+        _config: &Config,
+    ) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        Err(Error::Misc(
+            "This genome doesn't support being imported".to_string(),
+        ))
+    }
+
     fn crossover(parents: &[&Self], config: &Config) -> Self
     where
         Self: Sized;
@@ -313,18 +765,58 @@ pub trait Genome: Hash {
 
     fn mutate(&mut self, config: &Config);
 
+    /// Check the genome against the constraints of the search space, such
+    /// as a required length or alphabet. Offspring are validated after
+    /// crossover and mutation, before they're scored. Every genome is valid
+    /// by default.
+    fn validate(&self, _config: &Config) -> Result<(), RepairNeeded> {
+        Ok(())
+    }
+
+    /// Try to bring a genome that failed validation back within the
+    /// constraints, returning false if it can't be done. By default, no
+    /// genome can be repaired.
+    fn repair(&mut self, _problem: &RepairNeeded, _config: &Config) -> bool {
+        false
+    }
+
+    /// Produce an offspring by crossover and mutation. Offspring that fail
+    /// validation are repaired if possible, and otherwise rejected and bred
+    /// anew, with the parents rotated and mutation forced, up to
+    /// `MAX_MATING_ATTEMPTS` times.
     fn mate(parents: &[&Self], config: &Config) -> Self
     where
         Self: Sized,
     {
-        let mut child = Self::crossover(parents, config);
-        // the mutate method should check the mutation rate or exponent and
-        // make the mutation decisions internally
-        let mut rng = hash_seed_rng(&parents);
-        if rng.gen_range(0.0, 1.0) < config.mutation_rate {
-            child.mutate(&config);
+        let mut parents = parents.to_vec();
+        let mut attempt = 0;
+        loop {
+            let mut child = Self::crossover(&parents, config);
+            // the mutate method should check the mutation rate or exponent and
+            // make the mutation decisions internally
+            let mut rng = hash_seed_rng(&parents);
+            if attempt > 0 || rng.gen_range(0.0, 1.0) < config.mutation_rate {
+                child.mutate(&config);
+            }
+            let problem = match child.validate(config) {
+                Ok(()) => return child,
+                Err(problem) => problem,
+            };
+            if child.repair(&problem, config) && child.validate(config).is_ok() {
+                return child;
+            }
+            attempt += 1;
+            if attempt >= MAX_MATING_ATTEMPTS {
+                log::warn!(
+                    target: EVOLUTION,
+                    "Failed to breed a valid offspring in {} attempts: {}",
+                    attempt,
+                    problem.0
+                );
+                return child;
+            }
+            parents.rotate_left(1);
         }
-        child
     }
 
     fn digrams(&self) -> Box<dyn Iterator<Item = (Self::Allele, Self::Allele)> + '_> {
@@ -340,29 +832,23 @@ pub trait Genome: Hash {
         )
     }
 
+    /// A hash of the genetic material alone, so that genomes with the same
+    /// chromosome hash alike, whatever their names, tags or ancestry.
+    fn genome_hash(&self) -> u64 {
+        self.stable_hash()
+    }
+
+    /// Count the genome in the sketch. This should be done once per
+    /// evaluation, before `query_genetic_frequency`.
     fn record_genetic_frequency<S: Sketch>(&self, sketch: &mut S) {
-        for digram in self.digrams() {
-            sketch.insert(digram)
-        }
+        sketch.insert(self.genome_hash())
     }
 
+    /// The fraction of the genomes recorded in the sketch that were the same
+    /// as this one, between 0 and 1. The lower the score, the rarer the
+    /// genome. This is what the fitness functions report as `genetic_freq`.
     fn query_genetic_frequency<S: Sketch>(&self, sketch: &S) -> f64 {
-        // The lower the score, the rarer the digrams composing the genome.
-        // We divide by the length to avoid penalizing longer genomes.
-        // let mut sum = 0_f64;
-        // for digram in self.digrams() {
-        //     sum += (sketch.query(digram, timestamp)?);
-        // };
-        // Ok(sum)
-        let d = self.len();
-        let d = if d <= 1 { 1.0 } else { d as f64 };
-        self.digrams()
-            .map(|digram| sketch.query(digram))
-            .collect::<Vec<_>>()
-            .into_iter()
-            //.fold(std::f64::MAX, |a, b| a.min(b))
-            .sum::<f64>()
-            / d
+        sketch.query(self.genome_hash())
     }
 
     fn incr_num_offspring(&mut self, _n: usize);
@@ -370,6 +856,17 @@ pub trait Genome: Hash {
     fn generation(&self) -> usize;
 
     fn num_offspring(&self) -> usize;
+
+    /// The number of generations this specimen has survived. Offspring
+    /// are born at age 0.
+    fn age(&self) -> usize;
+
+    fn incr_age(&mut self);
+
+    /// The names of the genome's parents, if it keeps track of them.
+    fn parent_names(&self) -> Vec<String> {
+        vec![]
+    }
 }
 
 pub trait Phenome: Clone + Debug + Send + Serialize + Hash {
@@ -413,6 +910,42 @@ pub trait Phenome: Clone + Debug + Send + Serialize + Hash {
 
     fn is_goal_reached(&self, config: &Config) -> bool;
 
+    /// A coarse estimate, in bytes, of the heap memory held by the
+    /// phenotype's behavioural data, used to enforce
+    /// `Config::memory_budget`.
+    fn approx_heap_size(&self) -> usize {
+        0
+    }
+
+    /// Discard the oldest behavioural data, until what remains takes up
+    /// roughly `max_bytes` or less.
+    fn prune_behavior(&mut self, _max_bytes: usize) {}
+
+    /// A compact summary of the phenotype's execution profile, if it has
+    /// one, for export.
+    #[cfg(feature = "emulator")]
+    fn profile_summary(&self) -> Option<ProfileSummary> {
+        None
+    }
+
+    /// The phenotype's behaviour descriptor, if it has one, for export.
+    fn behavior_descriptor(&self) -> Option<Vec<f64>> {
+        None
+    }
+
+    /// The given features of the phenotype's behaviour descriptor, if it
+    /// has them, which place it in the MAP-Elites grid.
+    fn describe_behavior(&self, _features: &[BehaviorFeature]) -> Option<Vec<f64>> {
+        None
+    }
+
+    /// How unalike two phenotypes are, between 0 and 1, if there's a
+    /// measure for it. This is what fitness sharing uses to find the
+    /// phenotypes that share a niche.
+    fn distance(&self, _other: &Self) -> Option<f64> {
+        None
+    }
+
     fn fails(&self, _problem: &Self::Problem) -> bool {
         unimplemented!("implement as needed (for lexicase, e.g.)");
     }
@@ -421,3 +954,497 @@ pub trait Phenome: Clone + Debug + Send + Serialize + Hash {
         unimplemented!("implement as needed")
     }
 }
+
+/// Orders phenotypes by fitness, breaking ties by a hash of their
+/// chromosomes, and then by their tags, so that equally fit phenotypes are
+/// ordered the same way whatever order they arrive in.
+pub fn compare_fitness<P: Phenome + Genome>(a: &P, b: &P) -> Ordering {
+    total_cmp(a.fitness(), b.fitness())
+        .then_with(|| a.stable_hash().cmp(&b.stable_hash()))
+        .then_with(|| a.tag().cmp(&b.tag()))
+}
+
+/// Trim the population back to `config.pop_size`, discarding creatures in
+/// the order given by `config.culling`. Returns the number culled.
+pub fn cull<P: Phenome + Genome>(population: &mut Vec<P>, config: &Config) -> usize {
+    let excess = population.len().saturating_sub(config.pop_size);
+    if excess == 0 {
+        return 0;
+    }
+    match config.culling {
+        CullingPolicy::WorstFitness => population.sort_by(compare_fitness),
+        CullingPolicy::OldestAge => {
+            population.sort_by(|a, b| a.age().cmp(&b.age()).then_with(|| compare_fitness(a, b)))
+        }
+        CullingPolicy::LeastNovel => population.sort_by(|a, b| {
+            let novelty = |p: &P| {
+                p.scalar_fitness("novelty")
+                    .unwrap_or(std::f64::NEG_INFINITY)
+            };
+            total_cmp_f64(novelty(b), novelty(a)).then_with(|| compare_fitness(a, b))
+        }),
+    }
+    population.truncate(config.pop_size);
+    log::debug!(
+        target: EVOLUTION,
+        "Culled {} creatures from island {} by {:?}",
+        excess,
+        config.island_id,
+        config.culling
+    );
+    excess
+}
+
+#[cfg(test)]
+mod test {
+    use hashbrown::HashSet;
+
+    use crate::configure::MutationOperatorSet;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+    struct Flip;
+
+    impl Mutation for Flip {
+        type Allele = u8;
+
+        fn mutate_point(allele: &mut u8, _config: &Config) -> Self {
+            *allele = !*allele;
+            Flip
+        }
+    }
+
+    fn chromosome(allele: u8, len: usize) -> LinearChromosome<u8, Flip> {
+        LinearChromosome {
+            chromosome: vec![allele; len],
+            mutations: vec![None; len],
+            parentage: vec![],
+            parent_names: vec![],
+            name: format!("parent-{}", allele),
+            generation: allele as usize,
+            strategy_params: None,
+        }
+    }
+
+    #[test]
+    fn test_boundary_crossover_keeps_frames_intact() {
+        let mut config = Config::default();
+        config.max_length = 100;
+        config.crossover_rate = 1.0;
+        // frames begin with a 0, followed by as many copies of the frame's
+        // length as it takes to fill it
+        let frames = |lengths: &[u8]| {
+            let mut c = chromosome(0, 0);
+            for &k in lengths {
+                c.chromosome.push(0);
+                c.chromosome
+                    .extend(std::iter::repeat(k).take(k as usize - 1));
+            }
+            c
+        };
+        let mother = frames(&[2, 3, 1, 4, 2]);
+        let father = frames(&[4, 1, 1, 3, 2, 5]);
+        for _ in 0..100 {
+            let child =
+                LinearChromosome::boundary_crossover(&[&mother, &father], &config, |a| *a == 0);
+            let mut words = child.chromosome.iter().peekable();
+            while let Some(&head) = words.next() {
+                assert_eq!(head, 0, "frame doesn't start on a boundary");
+                let mut len = 1;
+                let mut body = None;
+                while let Some(&&w) = words.peek() {
+                    if w == 0 {
+                        break;
+                    }
+                    assert!(body.map(|b| b == w).unwrap_or(true));
+                    body = Some(w);
+                    len += 1;
+                    words.next();
+                }
+                assert_eq!(len, body.unwrap_or(1), "frame was cut");
+            }
+        }
+    }
+
+    #[test]
+    fn test_uniform_crossover_with_many_parents() {
+        let mut config = Config::default();
+        config.max_length = 100;
+        config.crossover_rate = 1.0;
+        config.crossover_algorithm = "one_point".to_string();
+        let parents = (0..4_u8)
+            .map(|i| chromosome(i, 10 + i as usize))
+            .collect::<Vec<_>>();
+        let parents = parents.iter().collect::<Vec<_>>();
+        for _ in 0..100 {
+            let child = LinearChromosome::crossover(&parents, &config);
+            assert!(child.len() >= 10 && child.len() <= 13);
+            assert_eq!(child.parentage.len(), child.len());
+            assert_eq!(child.parent_names.len(), 4);
+            assert_eq!(child.generation, 4);
+            for (allele, src) in child.chromosome.iter().zip(child.parentage.iter()) {
+                // each parent's alleles are all equal to its index
+                assert_eq!(*allele as usize, *src);
+            }
+        }
+        // a max_length of 0 sets no limit
+        config.max_length = 0;
+        let child = LinearChromosome::crossover(&parents, &config);
+        assert!(child.len() >= 10 && child.len() <= 13);
+    }
+
+    /// A word of at most five letters.
+    #[derive(Hash)]
+    struct Word(Vec<u8>);
+
+    impl Genome for Word {
+        type Allele = u8;
+
+        fn chromosome(&self) -> &[u8] {
+            &self.0
+        }
+
+        fn chromosome_mut(&mut self) -> &mut [u8] {
+            &mut self.0
+        }
+
+        fn native_island(&self) -> usize {
+            0
+        }
+
+        fn random<H: Hash>(_config: &Config, _salt: H) -> Self {
+            Word(b"abc".to_vec())
+        }
+
+        fn crossover(parents: &[&Self], _config: &Config) -> Self {
+            Word(parents.iter().flat_map(|p| p.0.iter().cloned()).collect())
+        }
+
+        fn mutate(&mut self, _config: &Config) {}
+
+        fn validate(&self, _config: &Config) -> Result<(), RepairNeeded> {
+            if self.0.len() > 5 {
+                Err(RepairNeeded(format!(
+                    "{} letters is too many",
+                    self.0.len()
+                )))
+            } else {
+                Ok(())
+            }
+        }
+
+        fn repair(&mut self, _problem: &RepairNeeded, _config: &Config) -> bool {
+            self.0.truncate(5);
+            true
+        }
+
+        fn incr_num_offspring(&mut self, _n: usize) {}
+
+        fn generation(&self) -> usize {
+            0
+        }
+
+        fn num_offspring(&self) -> usize {
+            0
+        }
+
+        fn age(&self) -> usize {
+            0
+        }
+
+        fn incr_age(&mut self) {}
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        let word = Word(b"abc".to_vec());
+        assert_eq!(word.stable_hash(), 0xc11a_b6d2_519b_c2b2);
+        assert_eq!(word.stable_hash(), Word(b"abc".to_vec()).stable_hash());
+        assert_ne!(word.stable_hash(), Word(b"abd".to_vec()).stable_hash());
+    }
+
+    #[test]
+    fn test_genetic_frequency() {
+        use crate::util::count_min_sketch::CountMinSketch;
+
+        let mut sketch = CountMinSketch::with_dimensions(8, 1024);
+        let unique = Word(b"abc".to_vec());
+        let common = Word(b"xyz".to_vec());
+        assert_eq!(unique.query_genetic_frequency(&sketch), 0.0);
+        unique.record_genetic_frequency(&mut sketch);
+        for _ in 0..3 {
+            // distinct creatures, with the same genes
+            Word(b"xyz".to_vec()).record_genetic_frequency(&mut sketch);
+        }
+        let rare = unique.query_genetic_frequency(&sketch);
+        let frequent = common.query_genetic_frequency(&sketch);
+        assert!(rare < frequent);
+        assert_eq!(rare, 0.25);
+        assert_eq!(frequent, 0.75);
+    }
+
+    #[test]
+    fn test_mate_repairs_invalid_offspring() {
+        let config = Config::default();
+        let a = Word(b"abc".to_vec());
+        let b = Word(b"xy".to_vec());
+        assert_eq!(Genome::mate(&[&a, &b], &config).0, b"abcxy".to_vec());
+        let c = Word(b"defg".to_vec());
+        let child = Genome::mate(&[&a, &c], &config);
+        assert!(child.validate(&config).is_ok());
+        assert_eq!(child.0, b"abcde".to_vec());
+    }
+
+    #[test]
+    fn test_mutation_operators() {
+        let mut config = Config::default();
+        config.max_length = 12;
+        config.mutation_exponent = 2.0;
+        let with_operator = |op: MutationOperator| {
+            let mut config = config.clone();
+            config.mutation_operators.weights.insert(op, 1.0);
+            config
+        };
+        let mut c = chromosome(0, 10);
+        c.chromosome[3] = 1;
+        c.parentage = vec![0; 10];
+
+        c.mutate(&with_operator(MutationOperator::Insert));
+        assert_eq!(c.len(), 11);
+        assert_eq!(c.parentage.len(), 11);
+        assert_eq!(c.mutations.len(), 11);
+
+        let ones = c.chromosome.iter().filter(|a| **a == 1).count();
+        c.mutate(&with_operator(MutationOperator::Rotate));
+        assert_eq!(c.len(), 11);
+        assert_eq!(c.chromosome.iter().filter(|a| **a == 1).count(), ones);
+
+        c.mutate(&with_operator(MutationOperator::Delete));
+        c.mutate(&with_operator(MutationOperator::Delete));
+        assert_eq!(c.len(), 9);
+        assert_eq!(c.parentage.len(), 9);
+        assert_eq!(c.mutations.len(), 9);
+
+        // insertions stop at the maximum length
+        for _ in 0..10 {
+            c.mutate(&with_operator(MutationOperator::Insert));
+        }
+        assert_eq!(c.len(), 12);
+
+        // operators with no weight are never drawn
+        let mut config = with_operator(MutationOperator::Insert);
+        config
+            .mutation_operators
+            .weights
+            .insert(MutationOperator::Delete, 0.0);
+        let mut rng = hash_seed_rng(&"mutation operators");
+        for _ in 0..100 {
+            assert_eq!(
+                config.mutation_operators.choose(&mut rng),
+                Some(MutationOperator::Insert)
+            );
+        }
+        assert_eq!(Config::default().mutation_operators.choose(&mut rng), None);
+
+        // operators that don't apply to the alleles fall back to pointwise
+        // mutation
+        c.mutate(&with_operator(MutationOperator::LevyStep));
+        c.mutate(&with_operator(MutationOperator::GadgetSnap));
+        assert_eq!(c.len(), 12);
+        assert_eq!(c.mutations.len(), 12);
+
+        // operators are named in snake case in the config
+        let set: MutationOperatorSet = toml::from_str("point = 0.8\nlevy_step = 0.2").unwrap();
+        assert_eq!(set.weights.get(&MutationOperator::LevyStep), Some(&0.2));
+        assert!(toml::from_str::<MutationOperatorSet>("teleport = 1.0").is_err());
+    }
+
+    #[test]
+    fn test_self_adaptive_mutation_rate() {
+        let mut config = Config::default();
+        config.max_length = 100;
+        config.crossover_rate = 1.0;
+        config.crossover_algorithm = "uniform".to_string();
+        config.self_adaptation = Some(SelfAdaptationConfig {
+            initial_rate: 0.2,
+            learning_rate: 1.0,
+            min_rate: 0.1,
+            max_rate: 0.4,
+        });
+        assert_eq!(
+            StrategyParams::initial(&config),
+            Some(StrategyParams { mutation_rate: 0.2 })
+        );
+
+        // offspring inherit the mean of their parents' rates
+        let mut mother = chromosome(0, 20);
+        mother.strategy_params = Some(StrategyParams { mutation_rate: 0.1 });
+        let mut father = chromosome(0, 20);
+        father.strategy_params = Some(StrategyParams { mutation_rate: 0.3 });
+        let child = LinearChromosome::crossover(&[&mother, &father], &config);
+        assert!((child.strategy_params.unwrap().mutation_rate - 0.2).abs() < 1e-9);
+        let orphan = LinearChromosome::crossover(&[&chromosome(0, 5), &chromosome(1, 5)], &config);
+        assert!(orphan.strategy_params.is_none());
+
+        // the rate drifts, but stays in bounds
+        let mut c = child;
+        let mut rates = HashSet::new();
+        for _ in 0..100 {
+            c.mutate(&config);
+            let rate = c.strategy_params.unwrap().mutation_rate;
+            assert!(rate >= 0.1 && rate <= 0.4);
+            rates.insert(rate.to_bits());
+        }
+        assert!(rates.len() > 1);
+
+        let mut alleles = vec![0_u8; 20];
+        assert!(Flip::mutate_at_rate(&mut alleles, 0.0, &config)
+            .iter()
+            .all(Option::is_none));
+        assert!(Flip::mutate_at_rate(&mut alleles, 1.0, &config)
+            .iter()
+            .all(Option::is_some));
+        assert!(alleles.iter().all(|a| *a == 0xff));
+    }
+
+    #[test]
+    fn test_locked_prefix_survives_breeding() {
+        let mut config = Config::default();
+        config.max_length = 20;
+        config.mutation_exponent = 1.0;
+        config.crossover_rate = 1.0;
+        config.locked_prefix = 3;
+        for op in &[
+            MutationOperator::Insert,
+            MutationOperator::Delete,
+            MutationOperator::Rotate,
+            MutationOperator::Point,
+        ] {
+            config.mutation_operators.weights.insert(*op, 1.0);
+        }
+        let mut population = (0..8_u8)
+            .map(|i| {
+                let mut c = chromosome(i, 12);
+                c.chromosome[..3].copy_from_slice(&[0xde, 0xad, 0xbe]);
+                c.parentage = vec![0; 12];
+                c
+            })
+            .collect::<Vec<_>>();
+        let mut rng = hash_seed_rng(&"locked prefix");
+        for algorithm in &["one_point", "uniform", "alternating"] {
+            config.crossover_algorithm = algorithm.to_string();
+            for _ in 0..100 {
+                let mother = &population[rng.gen_range(0, population.len())];
+                let father = &population[rng.gen_range(0, population.len())];
+                let mut child = LinearChromosome::crossover(&[mother, father], &config);
+                child.mutate(&config);
+                assert_eq!(child.chromosome[..3], [0xde, 0xad, 0xbe]);
+                assert!(child.len() <= 20);
+                assert_eq!(child.parentage.len(), child.len());
+                assert_eq!(child.mutations.len(), child.len());
+                let i = rng.gen_range(0, population.len());
+                population[i] = child;
+            }
+        }
+        let child =
+            LinearChromosome::boundary_crossover(&[&population[0], &population[1]], &config, |a| {
+                *a == 0xde
+            });
+        assert_eq!(child.chromosome[..3], [0xde, 0xad, 0xbe]);
+    }
+
+    #[test]
+    fn test_crossover_rate() {
+        use crate::examples::hello_world::Genotype;
+
+        let mut config = Config::default();
+        config.max_init_len = 20;
+        config.mutation_rate = 0.0;
+        config.crossover_rate = 0.0;
+        let parents = (0..3)
+            .map(|i| Genotype::random(&config, i))
+            .collect::<Vec<Genotype>>();
+        let parents = parents.iter().collect::<Vec<&Genotype>>();
+        for _ in 0..10 {
+            let child = Genome::mate(&parents, &config);
+            assert!(parents.iter().any(|p| p.genes == child.genes));
+            assert!(child.fitness().is_none());
+        }
+    }
+
+    #[test]
+    fn test_mate_with_one_parent() {
+        use crate::examples::hello_world::Genotype;
+
+        let mut config = Config::default();
+        config.max_init_len = 20;
+        config.mutation_rate = 0.0;
+        config.tournament.num_parents = 1;
+        let parent = Genotype::random(&config, 0);
+        let child = Genome::mate(&[&parent], &config);
+        assert_eq!(child.genes, parent.genes);
+        assert_eq!(child.generation(), parent.generation() + 1);
+    }
+
+    #[test]
+    fn test_cull() {
+        use crate::examples::hello_world::Genotype;
+
+        let mut config = Config::default();
+        config.max_init_len = 20;
+        config.pop_size = 4;
+        let population = (0..6)
+            .map(|i| {
+                let mut g = Genotype::random(&config, i);
+                g.set_fitness(vec![i as f64]);
+                for _ in 0..(i % 3) {
+                    g.incr_age();
+                }
+                g
+            })
+            .collect::<Vec<Genotype>>();
+        let fitnesses = |gs: &[Genotype]| {
+            gs.iter()
+                .map(|g| g.fitness().unwrap()[0])
+                .collect::<Vec<f64>>()
+        };
+
+        let mut culled = population.clone();
+        assert_eq!(cull(&mut culled, &config), 2);
+        assert_eq!(fitnesses(&culled), vec![0.0, 1.0, 2.0, 3.0]);
+
+        config.culling = CullingPolicy::OldestAge;
+        let mut culled = population.clone();
+        assert_eq!(cull(&mut culled, &config), 2);
+        assert_eq!(fitnesses(&culled), vec![0.0, 3.0, 1.0, 4.0]);
+
+        config.pop_size = 10;
+        let mut culled = population.clone();
+        assert_eq!(cull(&mut culled, &config), 0);
+        assert_eq!(culled.len(), 6);
+    }
+
+    #[test]
+    fn test_compare_fitness_breaks_ties_consistently() {
+        use crate::examples::hello_world::Genotype;
+
+        let mut config = Config::default();
+        config.max_init_len = 20;
+        let mut genotypes = (0..10)
+            .map(|i| {
+                let mut g = Genotype::random(&config, i);
+                g.set_fitness(vec![(i % 2) as f64]);
+                g
+            })
+            .collect::<Vec<Genotype>>();
+        let mut reversed = genotypes.iter().rev().cloned().collect::<Vec<Genotype>>();
+        genotypes.sort_by(compare_fitness);
+        reversed.sort_by(compare_fitness);
+        let tags = |gs: &[Genotype]| gs.iter().map(|g| g.tag()).collect::<Vec<u64>>();
+        assert_eq!(tags(&genotypes), tags(&reversed));
+        assert!(genotypes[..5]
+            .iter()
+            .all(|g| g.fitness() == Some(&vec![0.0])));
+    }
+}
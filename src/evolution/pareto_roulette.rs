@@ -7,7 +7,7 @@ use rand_distr::Distribution;
 use non_dominated_sort::{non_dominated_sort, DominanceOrd};
 
 use crate::configure::Config;
-use crate::evolution::{Genome, Phenome};
+use crate::evolution::{cull, Genome, Phenome};
 use crate::increment_epoch_counter;
 use crate::observer::Observer;
 use crate::ontogenesis::Develop;
@@ -103,7 +103,9 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Roulette<
         // new population as-is.
         for idxs in &elite_fronts {
             for idx in idxs.iter() {
-                new_population.push(cloned_population[*idx].clone());
+                let mut elite = cloned_population[*idx].clone();
+                elite.incr_age();
+                new_population.push(elite);
             }
         }
 
@@ -128,6 +130,9 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static, D: DominanceOrd<P>> Roulette<
             new_population.push(child)
         }
 
+        // the elites alone may have outnumbered the population
+        cull(&mut new_population, &config);
+
         increment_epoch_counter();
 
         Self {
@@ -2,6 +2,8 @@ use std::sync::atomic::{self, AtomicUsize};
 
 use crossbeam::queue::SegQueue;
 
+use crate::logger::EVOLUTION;
+
 pub struct Pier<P> {
     capacity: usize,
     count: AtomicUsize,
@@ -31,19 +33,27 @@ impl<P> Pier<P> {
 
     pub fn embark(&self, emigrant: P) -> Result<(), P> {
         if self.len() >= self.capacity {
-            log::debug!("Pier at capacity, returning emigrant");
+            log::debug!(target: EVOLUTION, "Pier at capacity, returning emigrant");
             return Err(emigrant);
         }
         self.q.push(emigrant);
         let len = self.incr_count();
-        log::debug!("Emigrant embarked onto pier. Holding {}", len + 1);
+        log::debug!(
+            target: EVOLUTION,
+            "Emigrant embarked onto pier. Holding {}",
+            len + 1
+        );
         Ok(())
     }
 
     pub fn disembark(&self) -> Option<P> {
         if let Ok(p) = self.q.pop() {
             let len = self.decr_count();
-            log::debug!("Immigrant disembarked from pier. Holding {}", len - 1);
+            log::debug!(
+                target: EVOLUTION,
+                "Immigrant disembarked from pier. Holding {}",
+                len - 1
+            );
             Some(p)
         } else {
             None
@@ -5,7 +5,10 @@ use rand::prelude::SliceRandom;
 use rand::Rng;
 use rayon::prelude::{FromParallelIterator, IntoParallelIterator, ParallelIterator};
 
+use crate::configure::Sampling;
 use crate::error::Error;
+use crate::fitness::total_cmp_f64;
+use crate::logger::EVOLUTION;
 use crate::util::random::hash_seed;
 
 /// For a description and justification of the "trivial geography" algorithm,
@@ -35,7 +38,10 @@ impl<P: Hash> TrivialGeography<P> {
             panic!("Generate the population before setting the radius");
         }
         if radius == 0 {
-            log::info!("Passing a radius of 0 tells the geography to default to a maximum radius.");
+            log::info!(
+                target: EVOLUTION,
+                "Passing a radius of 0 tells the geography to default to a maximum radius."
+            );
             return;
         }
         self.radius = radius.min(self.len())
@@ -55,7 +61,7 @@ impl<P: Hash> TrivialGeography<P> {
             self.vacancies.push(i);
             res
         } else {
-            log::debug!("cell was empty, sliding along...");
+            log::debug!(target: EVOLUTION, "cell was empty, sliding along...");
             self.extract(index + 1)
         }
     }
@@ -68,13 +74,55 @@ impl<P: Hash> TrivialGeography<P> {
         } else {
             // make room. we need the geography to be a bit more elastic
             // if migration is going to work.
-            log::debug!("expanding deme to accommodate newcomer");
+            log::debug!(target: EVOLUTION, "expanding deme to accommodate newcomer");
             self.deme.push(Some(creature));
             //Err(Error::NoVacancy)
             Ok(())
         }
     }
 
+    /// Replace the occupant of the cell at `index`, returning the previous
+    /// occupant. Vacant cells are left untouched.
+    pub fn replace(&mut self, index: usize, creature: P) -> Option<P> {
+        match self.deme.get_mut(index) {
+            Some(cell) if cell.is_some() => cell.replace(creature),
+            _ => None,
+        }
+    }
+
+    /// Returns the indices of the occupied cells for which `key` yields a
+    /// score, sorted from lowest (best) to highest (worst) score.
+    pub fn ranked_indices<F: Fn(&P) -> Option<f64>>(&self, key: F) -> Vec<usize> {
+        let mut ranked = self
+            .deme
+            .iter()
+            .enumerate()
+            .filter_map(|(i, cell)| cell.as_ref().and_then(|p| key(p)).map(|k| (i, k)))
+            .collect::<Vec<(usize, f64)>>();
+        ranked.sort_by(|a, b| total_cmp_f64(a.1, b.1));
+        ranked.into_iter().map(|(i, _)| i).collect()
+    }
+
+    /// Iterates over the occupants of the geography.
+    pub fn iter(&self) -> impl Iterator<Item = &P> {
+        self.deme.iter().filter_map(Option::as_ref)
+    }
+
+    /// Iterates mutably over the occupants of the geography.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut P> {
+        self.deme.iter_mut().filter_map(Option::as_mut)
+    }
+
+    /// Returns the indices of all occupied cells.
+    pub fn occupied_indices(&self) -> Vec<usize> {
+        self.deme
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.is_some())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
     pub fn get_range<R: Rng>(&self, rng: &mut R) -> Vec<usize> {
         let len = self.len();
         let base = rng.gen_range(0, len);
@@ -94,7 +142,7 @@ impl<P: Hash> TrivialGeography<P> {
         range
             .choose_multiple(rng, n)
             .filter_map(|i| {
-                log::debug!("choosing combatant from index {}", i);
+                log::debug!(target: EVOLUTION, "choosing combatant from index {}", i);
                 let c = self.extract(*i);
                 debug_assert!(c.is_some());
                 c
@@ -102,14 +150,101 @@ impl<P: Hash> TrivialGeography<P> {
             .collect()
     }
 
-    pub fn choose_combatants<R: Rng>(&mut self, n: usize, rng: &mut R) -> Vec<P> {
+    /// Draw `n` indices from the range with replacement. Each cell drawn is
+    /// extracted only once; repeated draws yield clones of its occupant.
+    fn choose_with_range_replacing<R: Rng>(
+        &mut self,
+        range: &[usize],
+        n: usize,
+        rng: &mut R,
+    ) -> Vec<P>
+    where
+        P: Clone,
+    {
+        let mut chosen: Vec<(usize, P)> = Vec::with_capacity(n);
+        for _ in 0..n {
+            let i = *range.choose(rng).expect("empty range");
+            log::debug!(target: EVOLUTION, "choosing combatant from index {}", i);
+            let c = if let Some((_, p)) = chosen.iter().find(|(j, _)| *j == i) {
+                Some(p.clone())
+            } else {
+                self.extract(i)
+            };
+            debug_assert!(c.is_some());
+            if let Some(c) = c {
+                chosen.push((i, c))
+            }
+        }
+        chosen.into_iter().map(|(_, p)| p).collect()
+    }
+
+    /// Like `choose_with_range`, but the occupant with the lowest score by
+    /// `key` in the whole geography is always among the `n` chosen. Until
+    /// some occupant has a score, there is no champion to include.
+    fn choose_with_champion<R, F>(
+        &mut self,
+        range: &[usize],
+        n: usize,
+        key: F,
+        rng: &mut R,
+    ) -> Vec<P>
+    where
+        R: Rng,
+        F: Fn(&P) -> Option<f64>,
+    {
+        let champion = self
+            .deme
+            .iter()
+            .enumerate()
+            .filter_map(|(i, cell)| cell.as_ref().and_then(|p| key(p)).map(|k| (i, k)))
+            .min_by(|a, b| total_cmp_f64(a.1, b.1))
+            .map(|(i, _)| i);
+        let champion = match champion {
+            Some(i) if n > 0 => i,
+            _ => return self.choose_with_range(range, n, rng),
+        };
+        let others = range
+            .iter()
+            .copied()
+            .filter(|i| *i != champion)
+            .collect::<Vec<usize>>();
+        let mut chosen = self.choose_with_range(&others, n - 1, rng);
+        log::debug!(
+            target: EVOLUTION,
+            "choosing champion from index {}",
+            champion
+        );
+        chosen.extend(self.extract(champion));
+        chosen
+    }
+
+    /// Removes the chosen combatants from the geography. The caller is
+    /// responsible for returning as many creatures as were removed, which
+    /// may be fewer than `n` when sampling with replacement. The `key` is
+    /// only used to find the champion, when sampling `WithChampion`.
+    pub fn choose_combatants<R, F>(
+        &mut self,
+        n: usize,
+        sampling: Sampling,
+        key: F,
+        rng: &mut R,
+    ) -> Vec<P>
+    where
+        P: Clone,
+        R: Rng,
+        F: Fn(&P) -> Option<f64>,
+    {
         debug_assert!(
-            n < self.radius,
+            sampling == Sampling::WithReplacement || n < self.radius,
             "don't try to take more creatures than the radius allows"
         );
 
         let range = self.get_range(rng);
-        self.choose_with_range(&range, n, rng)
+        match sampling {
+            Sampling::WithoutReplacement => self.choose_with_range(&range, n, rng),
+            Sampling::WithReplacement => self.choose_with_range_replacing(&range, n, rng),
+            Sampling::WithChampion => self.choose_with_champion(&range, n, key, rng),
+        }
     }
 
     #[allow(dead_code)]
@@ -225,6 +360,71 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_ranked_indices_and_replace() {
+        let mut geo = TrivialGeography {
+            radius: 5,
+            deme: vec![Some(3), Some(1), None, Some(4), Some(0)],
+            vacancies: vec![2],
+        };
+        let ranked = geo.ranked_indices(|&x| if x == 0 { None } else { Some(x as f64) });
+        assert_eq!(ranked, vec![1, 0, 3]);
+        assert_eq!(geo.replace(3, 9), Some(4));
+        assert_eq!(geo.replace(2, 9), None);
+        assert_eq!(geo.len(), 4);
+        assert_eq!(geo.occupied_indices(), vec![0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn test_choose_combatants_with_replacement() {
+        let size = 16;
+        let mut geo = (0..size).collect::<TrivialGeography<usize>>();
+        let mut rng = hash_seed_rng(&0xcafe);
+        let combatants = geo.choose_combatants(size, Sampling::WithReplacement, |_| None, &mut rng);
+        assert_eq!(combatants.len(), size);
+        let mut distinct = combatants.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert!(distinct.len() < size, "expected some repeated draws");
+        assert_eq!(geo.len(), size - distinct.len());
+        for c in distinct.into_iter() {
+            geo.insert(c).unwrap();
+        }
+        assert_eq!(geo.len(), size);
+    }
+
+    #[test]
+    fn test_choose_combatants_with_champion() {
+        let size = 64;
+        let n = 4;
+        let mut geo = (0..size).collect::<TrivialGeography<usize>>();
+        geo.set_radius(8);
+        // lower scores are better, and 0 is unscored
+        let key = |x: &usize| if *x == 0 { None } else { Some(*x as f64) };
+        for i in 0..1000 {
+            let mut rng = hash_seed_rng(&i);
+            let mut combatants = geo.choose_combatants(n, Sampling::WithChampion, key, &mut rng);
+            assert_eq!(combatants.len(), n);
+            assert!(
+                combatants.contains(&1),
+                "champion missing from {:?}",
+                combatants
+            );
+            combatants.sort();
+            combatants.dedup();
+            assert_eq!(combatants.len(), n, "champion drawn twice");
+            assert_eq!(geo.len(), size - n);
+            for c in combatants.into_iter() {
+                geo.insert(c).unwrap();
+            }
+        }
+
+        // with nobody scored, there's no champion to include
+        let mut rng = hash_seed_rng(&0);
+        let combatants = geo.choose_combatants(n, Sampling::WithChampion, |_| None, &mut rng);
+        assert_eq!(combatants.len(), n);
+    }
+
     #[test]
     fn test_get_range() {
         let geo = TrivialGeography {
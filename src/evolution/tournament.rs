@@ -1,14 +1,20 @@
-use std::cmp::{Ordering, PartialOrd};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt::Debug;
 use std::iter;
+use std::path::Path;
 use std::sync::Arc;
 
 use rand::Rng;
 use rayon::prelude::*;
 
 use crate::configure::Config;
+use crate::error::Error;
 use crate::evolution::population::pier::Pier;
 use crate::evolution::population::trivial_geography::TrivialGeography;
-use crate::evolution::{Genome, Phenome};
+use crate::evolution::{compare_fitness, export, Genome, Phenome};
+use crate::fitness::total_cmp_f64;
+use crate::logger::EVOLUTION;
 use crate::observer::Observer;
 use crate::ontogenesis::Develop;
 use crate::util::random::hash_seed_rng;
@@ -20,6 +26,8 @@ pub struct Tournament<E: Develop<P>, P: Phenome + 'static> {
     pub observer: Observer<P>,
     pub evaluator: E,
     pub pier: Arc<Pier<P>>,
+    pub best_scalar_fitness: Option<f64>,
+    pub last_improvement: usize,
 }
 
 impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
@@ -28,16 +36,20 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
         Self: Sized,
     {
         let config = config.clone();
-        log::debug!("Initializing population");
-        let mut population: TrivialGeography<P> = (0..config.pop_size)
-            .into_par_iter()
-            .map(|i| {
-                log::debug!("creating phenome {}/{}", i, config.pop_size);
-                P::random(&config, i)
-            })
-            .collect();
+        log::debug!(target: EVOLUTION, "Initializing population");
+        let seeds = export::initial_population::<P>(&config);
+        let randoms = (seeds.len()..config.pop_size).into_par_iter().map(|i| {
+            log::debug!(
+                target: EVOLUTION,
+                "creating phenome {}/{}",
+                i,
+                config.pop_size
+            );
+            P::random(&config, i)
+        });
+        let mut population: TrivialGeography<P> = seeds.into_par_iter().chain(randoms).collect();
         population.set_radius(config.tournament.geographic_radius);
-        log::debug!("population initialized");
+        log::debug!(target: EVOLUTION, "population initialized");
 
         Self {
             population,
@@ -46,7 +58,164 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
             observer,
             evaluator,
             pier,
+            best_scalar_fitness: None,
+            last_improvement: 0,
+        }
+    }
+
+    /// Preserve the `keep` best evaluated specimens, and replace everyone
+    /// else with a randomly generated newcomer.
+    fn restart(population: &mut TrivialGeography<P>, config: &Config, keep: usize, salt: usize) {
+        let elites = population
+            .ranked_indices(|p| p.scalar_fitness(&config.fitness.weighting))
+            .into_iter()
+            .take(keep)
+            .collect::<Vec<usize>>();
+        log::info!(
+            target: EVOLUTION,
+            "Restarting island {}, preserving {} elites",
+            config.island_id,
+            elites.len()
+        );
+        for i in population.occupied_indices() {
+            if !elites.contains(&i) {
+                population.replace(i, P::random(config, (salt, i)));
+            }
+        }
+    }
+
+    /// Replace the `n` worst evaluated specimens with randomly generated
+    /// newcomers.
+    fn admit_immigrants(
+        population: &mut TrivialGeography<P>,
+        config: &Config,
+        n: usize,
+        salt: usize,
+    ) {
+        let worst = population
+            .ranked_indices(|p| p.scalar_fitness(&config.fitness.weighting))
+            .into_iter()
+            .rev()
+            .take(n)
+            .collect::<Vec<usize>>();
+        log::debug!(
+            target: EVOLUTION,
+            "Admitting {} random immigrants to island {}",
+            worst.len(),
+            config.island_id
+        );
+        for i in worst {
+            population.replace(i, P::random(config, (salt, i)));
+        }
+    }
+
+    /// If the population's behavioural data takes up more than `budget`
+    /// bytes, prune each creature's share down to an equal part of it.
+    fn enforce_memory_budget(population: &mut TrivialGeography<P>, config: &Config, budget: usize) {
+        let retained = population
+            .iter_mut()
+            .map(|p| p.approx_heap_size())
+            .sum::<usize>();
+        if retained <= budget {
+            return;
+        }
+        log::info!(
+            target: EVOLUTION,
+            "Island {} retains ~{} bytes of behavioural data, over its budget of {}. Pruning.",
+            config.island_id,
+            retained,
+            budget
+        );
+        let share = budget / population.len().max(1);
+        population.iter_mut().for_each(|p| p.prune_behavior(share));
+    }
+
+    /// Evaluate the offspring, and let each compete with one of the losers
+    /// for its place in the population. Offspring that are no worse than
+    /// their rival always win. Worse offspring still win with probability
+    /// `exp(-delta / temperature)`.
+    fn anneal<R: Rng>(
+        evaluator: &mut E,
+        offspring: Vec<P>,
+        losers: Vec<P>,
+        temperature: f64,
+        config: &Config,
+        rng: &mut R,
+    ) -> Vec<P> {
+        let offspring = evaluator
+            .development_pipeline(offspring.into_iter())
+            .into_iter()
+            .map(|p| evaluator.apply_fitness_function(p))
+            .collect::<Vec<P>>();
+        let mut losers = losers.into_iter();
+        offspring
+            .into_iter()
+            .map(|child| {
+                let rival = match losers.next() {
+                    Some(rival) => rival,
+                    None => return child,
+                };
+                let fitness = |p: &P| p.scalar_fitness(&config.fitness.weighting);
+                let delta = match (fitness(&child), fitness(&rival)) {
+                    (Some(c), Some(r)) => c - r,
+                    (Some(_), None) => 0.0,
+                    (None, _) => std::f64::INFINITY,
+                };
+                if metropolis_accepts(delta, temperature, rng) {
+                    child
+                } else {
+                    log::debug!(
+                        target: EVOLUTION,
+                        "offspring rejected at temperature {}",
+                        temperature
+                    );
+                    rival
+                }
+            })
+            .collect()
+    }
+
+    /// Export the whole population to a single JSON file at `path`, for
+    /// analysis outside of berbalang.
+    pub fn export_population<Q: AsRef<Path> + Debug>(&self, path: Q) -> Result<(), Error> {
+        export::export_population(self.population.iter(), &self.config, path)
+    }
+
+    /// With probability `migration_rate`, send the best of the survivors
+    /// (the tournament's winner) to the pier, and return an immigrant to
+    /// take its place. Otherwise, return any immigrant waiting on the pier.
+    /// An emigrant that can't board a full pier stays home.
+    fn migrate<R: Rng>(
+        survivors: &mut Vec<P>,
+        pier: &Pier<P>,
+        config: &Config,
+        rng: &mut R,
+    ) -> Option<P> {
+        if rng.gen_range(0.0, 1.0) < config.tournament.migration_rate {
+            log::debug!(target: EVOLUTION, "Attempting migration...");
+            let emigrant = survivors.remove(0);
+            let tag = emigrant.tag();
+            match pier.embark(emigrant) {
+                Err(emigrant) => {
+                    log::debug!(target: EVOLUTION, "Pier full, emigrant stays home");
+                    survivors.insert(0, emigrant);
+                    return None;
+                }
+                // when sampling with replacement, the emigrant may have
+                // survived more than once
+                Ok(()) => survivors.retain(|s| s.tag() != tag),
+            }
         }
+        let immigrant = pier.disembark();
+        if let Some(ref immigrant) = immigrant {
+            log::debug!(
+                target: EVOLUTION,
+                "{} has arrived from the pier of island {}",
+                immigrant.name(),
+                config.island_id
+            );
+        }
+        immigrant
     }
 
     pub fn evolve(self) -> Self {
@@ -58,8 +227,11 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
             config,
             iteration,
             pier,
+            mut best_scalar_fitness,
+            mut last_improvement,
         } = self;
         log::debug!(
+            target: EVOLUTION,
             "population size in island {}: {}",
             config.island_id,
             population.len()
@@ -67,8 +239,16 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
 
         let mut rng = hash_seed_rng(&population);
 
-        let combatants: Vec<P> =
-            population.choose_combatants(config.tournament.tournament_size, &mut rng);
+        let size_before = population.len();
+        let combatants: Vec<P> = population.choose_combatants(
+            config.tournament.tournament_size,
+            config.tournament.sampling,
+            |p| p.scalar_fitness(&config.fitness.weighting),
+            &mut rng,
+        );
+        // the number of cells left vacant by the combatants, which may be
+        // fewer than the tournament size when sampling with replacement
+        let vacated = size_before - population.len();
 
         let mut combatants = evaluator
             .development_pipeline(combatants.into_iter())
@@ -80,45 +260,33 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
             })
             .collect::<Vec<P>>();
 
-        combatants.sort_by(|a, b| {
-            a.fitness()
-                .partial_cmp(&b.fitness())
-                .unwrap_or(Ordering::Equal)
-        });
-
-        // kill one off for every offspring to be produced
-        for _ in 0..config.tournament.num_offspring {
-            let _ = combatants.pop();
+        combatants.sort_by(compare_fitness);
+        if let Some(radius) = config.tournament.niche_radius {
+            sort_by_shared_fitness(&mut combatants, radius, &config.fitness.weighting);
         }
 
-        let mut survivors = combatants;
-
-        // NOTE: migration relies on tournaments being at least 1 larger than
-        // the number of parents plus the number of children
-        if survivors.len() > config.tournament.num_parents {
-            let mut migrated = false;
-            if rng.gen_range(0.0, 1.0) < config.tournament.migration_rate {
-                log::debug!("Attempting migration...");
-                let emigrant = survivors.pop().unwrap();
-                if let Err(emigrant) = pier.embark(emigrant) {
-                    log::debug!("Pier full, returning emigrant to population");
-                    survivors.push(emigrant);
-                } else {
-                    migrated = true;
-                }
-            }
-            if !migrated {
-                if let Some(immigrant) = pier.disembark() {
-                    log::debug!(
-                        "{} has arrived from the pier of island {}",
-                        immigrant.name(),
-                        config.island_id
-                    );
-                    survivors.push(immigrant);
-                }
+        for f in combatants
+            .iter()
+            .filter_map(|c| c.scalar_fitness(&config.fitness.weighting))
+        {
+            if best_scalar_fitness.map(|best| f < best).unwrap_or(true) {
+                best_scalar_fitness = Some(f);
+                last_improvement = iteration;
             }
         }
 
+        // kill one off for every offspring to be produced
+        let losers = (0..config.tournament.num_offspring)
+            .filter_map(|_| combatants.pop())
+            .collect::<Vec<P>>();
+
+        // should the pier be emptied by a neighbour between the departure
+        // of an emigrant and the arrival of an immigrant, the best loser is
+        // kept back to take the emigrant's place
+        let reserve = losers.last().cloned();
+
+        let mut survivors = combatants;
+
         debug_assert!(survivors.len() >= config.tournament.num_parents);
 
         let parents = survivors
@@ -135,12 +303,89 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
             .map(|()| Genome::mate(&parents, &config))
             .collect::<Vec<_>>();
 
-        // return everyone to the population
-        for other_guy in survivors.into_iter() {
-            population.insert(other_guy).unwrap()
+        // NOTE: migration relies on tournaments being at least 1 larger than
+        // the number of parents plus the number of children.
+        // The winner of the tournament emigrates once it has bred, and an
+        // immigrant from the pier takes its place. An immigrant who arrives
+        // when no one has left takes the place of the worst survivor.
+        let immigrant = if survivors.len() > config.tournament.num_parents {
+            Self::migrate(&mut survivors, &pier, &config, &mut rng)
+        } else {
+            None
+        };
+
+        let newcomers = if let Some(ref annealing) = config.annealing {
+            let temperature = annealing.temperature(iteration / config.epoch_length());
+            Self::anneal(
+                &mut evaluator,
+                offspring,
+                losers,
+                temperature,
+                &config,
+                &mut rng,
+            )
+        } else {
+            offspring
+        };
+
+        // return everyone to the population. when sampling with replacement,
+        // a specimen may have survived the tournament more than once, but it
+        // only goes back once, and the worst survivors make way for the
+        // offspring and any immigrant if there isn't room for everyone.
+        let mut returned = HashSet::new();
+        for creature in newcomers
+            .into_iter()
+            .chain(immigrant)
+            .chain(survivors)
+            .chain(reserve)
+            .filter(|p| returned.insert(p.tag()))
+            .take(vacated)
+        {
+            population.insert(creature).unwrap()
+        }
+
+        let generation_complete = (iteration + 1) % config.epoch_length() == 0;
+
+        if generation_complete {
+            // everyone who made it through the generation grows older
+            population.iter_mut().for_each(|p| p.incr_age());
+        }
+
+        if let (Some(budget), true) = (config.memory_budget, generation_complete) {
+            Self::enforce_memory_budget(&mut population, &config, budget);
         }
-        for child in offspring.into_iter() {
-            population.insert(child).unwrap()
+
+        if config.random_immigrants > 0 && generation_complete {
+            Self::admit_immigrants(
+                &mut population,
+                &config,
+                config.random_immigrants,
+                iteration,
+            );
+        }
+
+        if let Some(ref restart) = config.restart {
+            let stagnation_limit = restart.stagnation_generations * config.epoch_length();
+            if iteration - last_improvement >= stagnation_limit {
+                Self::restart(&mut population, &config, restart.keep, iteration);
+                last_improvement = iteration;
+            }
+        }
+
+        debug_assert_eq!(
+            population.len(),
+            size_before,
+            "population size drifted on island {}",
+            config.island_id
+        );
+        if population.len() != size_before {
+            log::error!(
+                target: EVOLUTION,
+                "Population size on island {} drifted from {} to {}",
+                config.island_id,
+                size_before,
+                population.len()
+            );
         }
 
         Self {
@@ -150,6 +395,166 @@ impl<E: Develop<P>, P: Phenome + Genome + 'static> Tournament<E, P> {
             observer,
             evaluator,
             pier,
+            best_scalar_fitness,
+            last_improvement,
         }
     }
 }
+
+/// Sort the combatants by their scalar fitnesses, each multiplied by the
+/// combatant's niche count: the sum, over all the combatants, of
+/// `max(0, 1 - d / radius)`, where `d` is the distance between the two. The
+/// count is at least 1, and pairs with no distance between them don't share
+/// a niche. Unscored combatants come last, and ties keep their order.
+fn sort_by_shared_fitness<P: Phenome>(combatants: &mut Vec<P>, radius: f64, weighting: &str) {
+    let shared = combatants
+        .iter()
+        .map(|p| {
+            let niche_count = combatants
+                .iter()
+                .filter_map(|q| p.distance(q))
+                .map(|d| (1.0 - d / radius).max(0.0))
+                .sum::<f64>();
+            p.scalar_fitness(weighting)
+                .map(|f| f * niche_count.max(1.0))
+        })
+        .collect::<Vec<Option<f64>>>();
+    let mut ranked = combatants.drain(..).zip(shared).collect::<Vec<_>>();
+    ranked.sort_by(|(_, a), (_, b)| match (a, b) {
+        (Some(a), Some(b)) => total_cmp_f64(*a, *b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+    combatants.extend(ranked.into_iter().map(|(p, _)| p));
+}
+
+/// The Metropolis criterion, for a minimized fitness: improvements are
+/// always accepted, and deteriorations with probability `exp(-delta / t)`.
+fn metropolis_accepts<R: Rng>(delta: f64, temperature: f64, rng: &mut R) -> bool {
+    if delta <= 0.0 {
+        return true;
+    }
+    if temperature <= 0.0 || delta.is_nan() {
+        return false;
+    }
+    rng.gen_range(0.0, 1.0) < (-delta / temperature).exp()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::{Sampling, TournamentConfig};
+    use crate::examples::hello_world::Genotype;
+    use crate::ontogenesis::fixtures::{length_evaluator, quiet_observer};
+
+    use super::*;
+
+    fn assert_population_size_invariant(sampling: Sampling, migration_rate: f64) {
+        let mut config = Config::default();
+        config.pop_size = 32;
+        config.max_init_len = 16;
+        config.tournament = TournamentConfig {
+            tournament_size: 6,
+            num_offspring: 2,
+            num_parents: 2,
+            migration_rate,
+            sampling,
+            ..Default::default()
+        };
+        let observer = quiet_observer(
+            &mut config,
+            &format!("tournament_{:?}_{}", sampling, migration_rate),
+        );
+        // a pier shared with some imaginary neighbouring island, which has
+        // already sent a few emigrants our way
+        let pier = Arc::new(Pier::new(4));
+        for i in 0..2 {
+            pier.embark(Genotype::random(&config, ("elsewhere", i)))
+                .unwrap();
+        }
+        let mut tournament = Tournament::new(&config, observer, length_evaluator(), pier);
+        for _ in 0..(4 * config.epoch_length()) {
+            tournament = tournament.evolve();
+            assert_eq!(tournament.population.len(), config.pop_size);
+        }
+        // a generation has just been completed, so everyone has aged
+        assert!(tournament.population.iter_mut().all(|p| p.age() > 0));
+    }
+
+    #[test]
+    fn test_population_size_invariant_without_replacement() {
+        assert_population_size_invariant(Sampling::WithoutReplacement, 0.0)
+    }
+
+    #[test]
+    fn test_population_size_invariant_with_replacement() {
+        assert_population_size_invariant(Sampling::WithReplacement, 0.0)
+    }
+
+    #[test]
+    fn test_population_size_invariant_with_champion() {
+        assert_population_size_invariant(Sampling::WithChampion, 0.0)
+    }
+
+    #[test]
+    fn test_sort_by_shared_fitness() {
+        let mut config = Config::default();
+        config.max_init_len = 16;
+        let weighting = config.fitness.weighting.clone();
+        let scored = |genes: &str, fitness: f64| {
+            let mut g = Genotype::random(&config, (genes, fitness.to_bits()));
+            g.genes = genes.to_string();
+            g.set_fitness(vec![fitness]);
+            g
+        };
+        let mut unscored = Genotype::random(&config, "unscored");
+        unscored.genes = "mmmm".to_string();
+        let mut combatants = vec![
+            scored("aaaa", 1.0),
+            scored("aaab", 1.0),
+            scored("zzzz", 1.5),
+            unscored,
+        ];
+        let fitnesses = |c: &[Genotype]| {
+            c.iter()
+                .map(|g| g.scalar_fitness(&weighting))
+                .collect::<Vec<_>>()
+        };
+        // the near twins share a niche, each counting the other for 3/4,
+        // while the loner has one to itself
+        sort_by_shared_fitness(&mut combatants, 1.0, &weighting);
+        assert_eq!(
+            fitnesses(&combatants),
+            vec![Some(1.5), Some(1.0), Some(1.0), None]
+        );
+        // with a radius too small for a niche to hold both, the fittest
+        // come first
+        sort_by_shared_fitness(&mut combatants, 0.1, &weighting);
+        assert_eq!(
+            fitnesses(&combatants),
+            vec![Some(1.0), Some(1.0), Some(1.5), None]
+        );
+    }
+
+    #[test]
+    fn test_metropolis_accepts() {
+        let mut rng = hash_seed_rng(&0x5eed);
+        assert!(metropolis_accepts(-1.0, 0.0, &mut rng));
+        assert!(metropolis_accepts(0.0, 1.0, &mut rng));
+        assert!(!metropolis_accepts(1.0, 0.0, &mut rng));
+        let hot = (0..1000)
+            .filter(|_| metropolis_accepts(1.0, 100.0, &mut rng))
+            .count();
+        let cold = (0..1000)
+            .filter(|_| metropolis_accepts(1.0, 0.1, &mut rng))
+            .count();
+        assert!(hot > 900, "hot acceptances: {}", hot);
+        assert!(cold < 10, "cold acceptances: {}", cold);
+    }
+
+    #[test]
+    fn test_population_size_invariant_under_migration() {
+        assert_population_size_invariant(Sampling::WithoutReplacement, 0.5);
+        assert_population_size_invariant(Sampling::WithReplacement, 0.5);
+    }
+}
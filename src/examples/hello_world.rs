@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::cmp::Ordering;
 use std::hash::{Hash, Hasher};
 use std::iter;
@@ -9,13 +10,15 @@ use rand::distributions::Alphanumeric;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::configure::Config;
+use crate::configure::{BehaviorFeature, Config};
 use crate::evolution::population::pier::Pier;
-use crate::evolution::{Genome, Phenome};
+use crate::evolution::{compare_fitness, Genome, Phenome};
+use crate::logger::EXAMPLES;
 use crate::observer::Window;
 use crate::util::count_min_sketch::CountMinSketch;
+use crate::util::distance::levenshtein;
 use crate::util::levy_flight::levy_decision;
-use crate::util::random::hash_seed_rng;
+use crate::util::random::{hash_seed_rng, Prng, StableHasher};
 use crate::{evolution::tournament::*, observer::Observer, ontogenesis::Develop};
 
 pub type Fitness = Vec<f64>;
@@ -28,6 +31,8 @@ pub struct Genotype {
     // used for sorting in heap
     generation: usize,
     num_offspring: usize,
+    #[serde(default)]
+    age: usize,
     native_island: usize,
 }
 
@@ -57,6 +62,50 @@ impl Ord for Genotype {
     }
 }
 
+thread_local! {
+    /// The number of matings so far on this thread.
+    static MATINGS: Cell<u64> = Cell::new(0);
+}
+
+/// A generator for a single mating, seeded by the mates, the random seed,
+/// and the number of matings before it on this thread, so that mating the
+/// same mates twice needn't give the same child, while a run can still be
+/// reproduced from its seed.
+fn mating_rng(mates: &[&Genotype], config: &Config) -> Prng {
+    let mating = MATINGS.with(|m| {
+        let n = m.get();
+        m.set(n + 1);
+        n
+    });
+    hash_seed_rng(&(mates, config.random_seed, mating))
+}
+
+impl Genotype {
+    /// Recombine any number of mates, taking each character from a randomly
+    /// chosen mate long enough to have one at that position.
+    fn uniform_crossover<R: Rng>(mates: &[&Self], config: &Config, rng: &mut R) -> Self {
+        let len = mates[rng.gen_range(0, mates.len())].len();
+        let genes = (0..len)
+            .map(|i| {
+                let donors = mates
+                    .iter()
+                    .filter(|m| m.len() > i)
+                    .collect::<Vec<&&Self>>();
+                donors[rng.gen_range(0, donors.len())].genes.as_bytes()[i] as char
+            })
+            .collect::<String>();
+        Genotype {
+            genes,
+            fitness: None,
+            tag: rng.gen::<u64>(),
+            generation: mates.iter().map(|m| m.generation).max().unwrap_or(0) + 1,
+            num_offspring: 0,
+            age: 0,
+            native_island: config.island_id,
+        }
+    }
+}
+
 // because this is a GA we identify genome and phenome
 impl Phenome for Genotype {
     type Fitness = Fitness;
@@ -91,17 +140,34 @@ impl Phenome for Genotype {
     }
 
     fn is_goal_reached(&self, config: &Config) -> bool {
-        (self
-            .scalar_fitness(&config.fitness.weighting)
-            .unwrap_or(std::f64::MAX)
-            - config.fitness.target)
-            <= std::f64::EPSILON
+        self.scalar_fitness(&config.fitness.weighting)
+            .map(|f| config.fitness.is_target_reached(f))
+            .unwrap_or(false)
     }
-}
 
-impl Genotype {
-    pub fn len(&self) -> usize {
-        self.genes.len()
+    /// The string's length stands in for the chain length, squashed into
+    /// `[0, 1)` as in `roper::behavior`. There are no other features.
+    fn describe_behavior(&self, features: &[BehaviorFeature]) -> Option<Vec<f64>> {
+        features
+            .iter()
+            .map(|f| match f {
+                BehaviorFeature::ChainLength => {
+                    let n = self.genes.len() as f64;
+                    Some(n / (1.0 + n))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The edit distance between the strings, as a fraction of the longer.
+    fn distance(&self, other: &Self) -> Option<f64> {
+        let longer = self.genes.len().max(other.genes.len());
+        if longer == 0 {
+            return Some(0.0);
+        }
+        let edits = levenshtein(self.genes.as_bytes(), other.genes.as_bytes());
+        Some(edits as f64 / longer as f64)
     }
 }
 
@@ -120,6 +186,20 @@ impl Genome for Genotype {
         self.native_island
     }
 
+    fn len(&self) -> usize {
+        self.genes.len()
+    }
+
+    fn size(&self) -> usize {
+        self.genes.len()
+    }
+
+    fn stable_hash(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        self.genes.hash(&mut hasher);
+        hasher.finish()
+    }
+
     fn chromosome(&self) -> &[Self::Allele] {
         unimplemented!("rust makes treating strings as &[char] tricky")
     }
@@ -146,11 +226,28 @@ impl Genome for Genotype {
             generation: 0,
             native_island: config.island_id,
             num_offspring: 0,
+            age: 0,
         }
     }
 
     fn crossover(mates: &[&Self], config: &Config) -> Self {
-        let mut rng = hash_seed_rng(&mates[0]);
+        let mut rng = mating_rng(mates, config);
+        // a lone parent can only be cloned
+        if mates.len() < 2 || !rng.gen_bool(config.crossover_rate) {
+            let parent = mates[rng.gen_range(0, mates.len())];
+            return Genotype {
+                genes: parent.genes.clone(),
+                fitness: None,
+                tag: rng.gen::<u64>(),
+                generation: parent.generation + 1,
+                num_offspring: 0,
+                age: 0,
+                native_island: config.island_id,
+            };
+        }
+        if mates.len() > 2 {
+            return Self::uniform_crossover(mates, config, &mut rng);
+        }
         let father = &mates[0];
         let mother = &mates[1];
         let split_m: usize = rng.gen::<usize>() % mother.len();
@@ -164,6 +261,7 @@ impl Genome for Genotype {
             tag: rng.gen::<u64>(),
             generation,
             num_offspring: 0,
+            age: 0,
             native_island: config.island_id,
         }
     }
@@ -207,6 +305,14 @@ impl Genome for Genotype {
     fn incr_num_offspring(&mut self, n: usize) {
         self.num_offspring += n
     }
+
+    fn age(&self) -> usize {
+        self.age
+    }
+
+    fn incr_age(&mut self) {
+        self.age += 1
+    }
 }
 
 fn report(window: &Window<Genotype>, counter: usize, _config: &Config) {
@@ -225,12 +331,17 @@ fn report(window: &Window<Genotype>, counter: usize, _config: &Config) {
         .map(|v| v / len as f64)
         .collect::<Vec<f64>>();
     let avg_gen = frame.iter().map(|g| g.generation).sum::<usize>() as f64 / frame.len() as f64;
+    let avg_size = frame.iter().map(|g| g.size()).sum::<usize>() as f64 / frame.len() as f64;
+    let avg_age = frame.iter().map(|g| g.age()).sum::<usize>() as f64 / frame.len() as f64;
 
     log::info!(
-        "[{}] AVERAGE FITNESS: {:?}; AVG GEN: {}",
+        target: EXAMPLES,
+        "[{}] AVERAGE FITNESS: {:?}; AVG GEN: {}; AVG SIZE: {}; AVG AGE: {}",
         counter,
         avg_fit,
-        avg_gen
+        avg_gen,
+        avg_size,
+        avg_age
     );
 }
 
@@ -254,36 +365,72 @@ cached_key! {
     }
 }
 
+/// The genome's distance from the target, which depends on nothing but the
+/// genome and the config, and so can be computed on any thread.
+fn score(phenome: &Genotype, config: &Config) -> Fitness {
+    let mut fitness = ff_helper(&phenome.genes, &config.hello.target);
+    if config.fitness.parsimony > 0.0 {
+        // penalize the distance from the target length, rather than the
+        // raw length, so that a perfect match still scores zero.
+        let drift = (phenome.size() as f64 - config.hello.target.len() as f64).abs();
+        fitness[0] += config.fitness.parsimony * drift;
+    }
+    fitness
+}
+
+/// Count the genome in the sketch, and append its frequency to its fitness.
+fn record_frequency(phenome: &mut Genotype, sketch: &mut CountMinSketch) {
+    sketch.insert(&phenome.genes);
+    let freq = sketch.query(&phenome.genes);
+    phenome.fitness.as_mut().map(|f| f.push(freq));
+}
+
 fn fitness_function(
     mut phenome: Genotype,
     sketch: &mut CountMinSketch,
     config: Arc<Config>,
 ) -> Genotype {
     if phenome.fitness.is_none() {
-        phenome.set_fitness(ff_helper(&phenome.genes, &config.hello.target));
-        sketch.insert(&phenome.genes);
-        let freq = sketch.query(&phenome.genes);
-        phenome.fitness.as_mut().map(|f| f.push(freq));
+        phenome.set_fitness(score(&phenome, &config));
+        record_frequency(&mut phenome, sketch);
     };
     phenome
 }
 
-pub fn run(config: Config) {
+/// Evolve strings until one reaches the fitness target, and return it, or
+/// until evolution is halted for some other reason, and return `None`.
+pub fn run(config: Config) -> Option<Genotype> {
+    if config.hello.parallel {
+        let evaluator = evaluation::ParallelEvaluator::new(&config);
+        evolve(config, evaluator)
+    } else {
+        let fitness_fn = Box::new(fitness_function);
+        let evaluator = evaluation::Evaluator::spawn(&config, fitness_fn);
+        evolve(config, evaluator)
+    }
+}
+
+fn evolve<E: Develop<Genotype>>(config: Config, evaluator: E) -> Option<Genotype> {
     let report_fn = Box::new(report);
-    let fitness_fn = Box::new(fitness_function);
     let observer = Observer::spawn(&config, report_fn);
-    let evaluator = evaluation::Evaluator::spawn(&config, fitness_fn);
     let pier = Pier::new(4); // FIXME: don't hardcode, make this the number of islands, say
-    let mut world = Tournament::<evaluation::Evaluator, Genotype>::new(
-        &config,
-        observer,
-        evaluator,
-        Arc::new(pier),
-    );
-
+    let mut world = Tournament::<E, Genotype>::new(&config, observer, evaluator, Arc::new(pier));
+
+    let winner = |world: &Tournament<E, Genotype>| {
+        world
+            .population
+            .iter()
+            .filter(|g| g.is_goal_reached(&config))
+            .min_by(|a, b| compare_fitness(*a, *b))
+            .cloned()
+    };
     while crate::keep_going() {
         world = world.evolve();
+        if let Some(champion) = winner(&world) {
+            return Some(champion);
+        }
     }
+    winner(&world)
 }
 
 mod evaluation {
@@ -291,6 +438,8 @@ mod evaluation {
     use std::sync::{Arc, Mutex};
     use std::thread::{spawn, JoinHandle};
 
+    use rayon::prelude::*;
+
     use crate::ontogenesis::FitnessFn;
     use crate::util::count_min_sketch::CountMinSketch;
 
@@ -347,6 +496,47 @@ mod evaluation {
             creature
         }
     }
+
+    /// Scores each batch of genomes in parallel on rayon's thread pool. Only
+    /// the update of the genetic frequency sketch is serialized, so the
+    /// expensive comparison with the target scales with the number of
+    /// cores.
+    pub struct ParallelEvaluator {
+        config: Arc<Config>,
+        sketch: Mutex<CountMinSketch>,
+    }
+
+    impl ParallelEvaluator {
+        pub fn new(config: &Config) -> Self {
+            Self {
+                config: Arc::new(config.clone()),
+                sketch: Mutex::new(CountMinSketch::new(config)),
+            }
+        }
+    }
+
+    impl Develop<Genotype> for ParallelEvaluator {
+        fn develop(&self, mut phenome: Genotype) -> Genotype {
+            if phenome.fitness.is_none() {
+                phenome.set_fitness(score(&phenome, &self.config));
+                let mut sketch = self.sketch.lock().unwrap();
+                record_frequency(&mut phenome, &mut sketch);
+            }
+            phenome
+        }
+
+        fn development_pipeline<I: Iterator<Item = Genotype>>(&self, inbound: I) -> Vec<Genotype> {
+            inbound
+                .collect::<Vec<Genotype>>()
+                .into_par_iter()
+                .map(|p| self.develop(p))
+                .collect::<Vec<Genotype>>()
+        }
+
+        fn apply_fitness_function(&mut self, creature: Genotype) -> Genotype {
+            creature
+        }
+    }
 }
 
 //build_observation_mod!(observation, Genotype, Config);
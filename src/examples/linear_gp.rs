@@ -8,11 +8,14 @@ use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::configure::{ClassificationProblem, Config, Selection};
+use crate::error::Error;
+use crate::evolution::alps::Alps;
 use crate::evolution::metropolis::Metropolis;
 use crate::evolution::pareto_roulette::Roulette;
 use crate::evolution::population::pier::Pier;
 use crate::evolution::{tournament::Tournament, Genome, Phenome};
 use crate::fitness::Weighted;
+use crate::logger::EXAMPLES;
 use crate::observer::{Observer, ReportFn, Window};
 use crate::ontogenesis::FitnessFn;
 use crate::util;
@@ -207,7 +210,10 @@ pub mod machine {
 
         fn load_input(&mut self, inputs: &[MachineWord]) {
             if inputs.len() > self.registers.len() - 1 {
-                log::error!("Too many inputs to load into input registers. Wrapping.");
+                log::error!(
+                    target: EXAMPLES,
+                    "Too many inputs to load into input registers. Wrapping."
+                );
             }
             (self.return_registers..(self.registers.len()))
                 .zip(inputs.iter())
@@ -231,6 +237,7 @@ pub mod machine {
                 self.eval(inst);
                 self.pc %= code.len();
                 log::trace!(
+                    target: EXAMPLES,
                     "[{}]\t{}\t{:X?}{}",
                     old_pc,
                     inst,
@@ -287,6 +294,8 @@ pub struct Creature {
     generation: usize,
     native_island: usize,
     num_offspring: usize,
+    #[serde(default)]
+    age: usize,
 }
 
 impl Hash for Creature {
@@ -360,10 +369,9 @@ impl Phenome for Creature {
     }
 
     fn is_goal_reached<'a>(&'a self, config: &'a Config) -> bool {
-        if let Some(fitness) = self.scalar_fitness(&config.fitness.priority()) {
-            return fitness <= config.fitness.target;
-        }
-        false
+        self.scalar_fitness(&config.fitness.priority())
+            .map(|f| config.fitness.is_target_reached(f))
+            .unwrap_or(false)
     }
 }
 
@@ -409,10 +417,22 @@ impl Genome for Creature {
         self.num_offspring += n
     }
 
+    fn age(&self) -> usize {
+        self.age
+    }
+
+    fn incr_age(&mut self) {
+        self.age += 1
+    }
+
     fn native_island(&self) -> usize {
         self.native_island
     }
 
+    fn parent_names(&self) -> Vec<String> {
+        self.parents.clone()
+    }
+
     fn chromosome(&self) -> &[Self::Allele] {
         &self.chromosome
     }
@@ -439,6 +459,37 @@ impl Genome for Creature {
         }
     }
 
+    /// Imported instructions may only refer to registers that exist in
+    /// the configured machine.
+    fn from_chromosome(
+        name: Option<String>,
+        chromosome: Vec<machine::Inst>,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let num_registers = config.linear_gp.num_registers.unwrap();
+        if let Some(inst) = chromosome
+            .iter()
+            .find(|i| i.a >= num_registers || i.b >= num_registers)
+        {
+            return Err(Error::Misc(format!(
+                "{} refers to a register beyond R{}",
+                inst,
+                num_registers - 1
+            )));
+        }
+        let length = chromosome.len();
+        let mut rng = hash_seed_rng(&chromosome);
+        let name = name.unwrap_or_else(|| crate::util::name::random(4, &chromosome));
+        Ok(Self {
+            chromosome,
+            tag: rng.gen::<u64>(),
+            name,
+            chromosome_mutation: vec![None; length],
+            native_island: config.island_id,
+            ..Default::default()
+        })
+    }
+
     fn crossover(mates: &[&Self], config: &Config) -> Self {
         let distribution = rand_distr::Exp::new(config.crossover_period)
             .expect("Failed to create random distribution");
@@ -451,11 +502,11 @@ impl Genome for Creature {
                 let (c, p) = Self::crossover_by_distribution(&distribution, &parental_chromosomes);
                 (c, p, names)
             } else {
-                let parent = parental_chromosomes[rng.gen_range(0, 2)];
-                let chromosome = parent.to_vec();
+                let i = rng.gen_range(0, mates.len());
+                let chromosome = parental_chromosomes[i].to_vec();
                 let parentage =
                     chromosome.iter().map(|_| 0).collect::<Vec<usize>>();
-                (chromosome, parentage, vec![mates[0].name.clone()])
+                (chromosome, parentage, vec![mates[i].name.clone()])
             };
         let generation = mates.iter().map(|p| p.generation).max().unwrap() + 1;
         let name = util::name::random(4, &chromosome);
@@ -473,6 +524,7 @@ impl Genome for Creature {
             generation,
             native_island: config.island_id,
             num_offspring: 0,
+            age: 0,
         }
     }
 
@@ -511,6 +563,7 @@ fn report(window: &Window<Creature>, counter: usize, config: &Config) {
         .sum::<f64>()
         / frame.len() as f64;
     log::info!(
+        target: EXAMPLES,
         "[{}] Average length: {}, average genetic frequency: {}; average fitness: {}",
         counter,
         avg_len,
@@ -518,10 +571,10 @@ fn report(window: &Window<Creature>, counter: usize, config: &Config) {
         avg_fit,
     );
     let soup = window.soup();
-    log::info!("soup size: {}", soup.len());
+    log::info!(target: EXAMPLES, "soup size: {}", soup.len());
     // TODO export tsv stats here too. generalize a bit.
     if let Some(ref best) = window.best {
-        log::info!("Best: {:?}", best);
+        log::info!(target: EXAMPLES, "Best: {:?}", best);
     }
 }
 
@@ -699,7 +752,7 @@ mod evaluation {
                 .as_mut()
                 .map(|fit| fit.insert("genetic_freq", genetic_frequency));
 
-            log::debug!("fitness: {:?}", phenome.fitness());
+            log::debug!(target: EXAMPLES, "fitness: {:?}", phenome.fitness());
             phenome
         }
     }
@@ -733,7 +786,7 @@ fn prepare(mut config: Config) -> (Config, Observer<Creature>, evaluation::Evalu
     // things when it comes to regression data sets. Not a priority to fix right now.
     config.linear_gp.return_registers = Some(return_registers);
     config.linear_gp.num_registers = Some(num_registers);
-    log::info!("Config: {:#?}", config);
+    log::info!(target: EXAMPLES, "Config: {:#?}", config);
     let report_fn: ReportFn<_> = Box::new(report);
     let fitness_fn: FitnessFn<Creature, _, _> = Box::new(evaluation::fitness_function);
     let observer = Observer::spawn(&config, report_fn);
@@ -779,6 +832,13 @@ pub fn run(config: Config) {
                 world = world.evolve();
             }
         }
+        Selection::Alps => {
+            let mut world =
+                Alps::<evaluation::Evaluator, Creature>::new(&config, observer, evaluator);
+            while crate::keep_going() {
+                world = world.evolve();
+            }
+        }
         sel => unimplemented!("{:?} not implemented for {:?}", sel, config.job),
     }
 }
@@ -1,15 +1,19 @@
 use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
 use std::fmt::Debug;
 use std::hash::{Hash, Hasher};
 use std::ops::Index;
 use std::sync::Mutex;
 
+use hashbrown::HashMap;
 use itertools::Itertools;
 use serde::export::Formatter;
 use serde::{Deserialize, Serialize};
 
+use crate::configure::{Bounds, Standardization, StandardizationConfig};
+use crate::evolution::Genome;
+
 pub type FitnessMap<'a> = BTreeMap<&'a str, f64>;
 
 pub trait HasScalar {
@@ -29,6 +33,51 @@ pub trait FitnessScore:
 
 impl FitnessScore for Vec<f64> {}
 
+/// A total order on `f64` scores, which are minimized, in which NaN is the
+/// worst possible score (and equal to itself).
+pub fn total_cmp_f64(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).expect("non-NaN floats are comparable"),
+    }
+}
+
+/// A total order on optional fitness scores, for use wherever creatures are
+/// sorted or selected. Scores that `partial_cmp` can't order, because a NaN
+/// is involved, are ordered by their scalar values, with NaN as the worst.
+/// A missing score is worse than any other.
+pub fn total_cmp<F: FitnessScore>(a: Option<&F>, b: Option<&F>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a
+            .partial_cmp(b)
+            .unwrap_or_else(|| total_cmp_f64(a.scalar(), b.scalar())),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// The dominance relation on optional fitness scores, for non-dominated
+/// sorting. Unlike `total_cmp`, this leaves scores that `partial_cmp` can't
+/// order, like mutually non-dominating Pareto scores, `Equal`, unless just
+/// one of them has a NaN scalar, which makes it the dominated one. A
+/// missing score is dominated by any other.
+pub fn dominance_cmp<F: FitnessScore>(a: Option<&F>, b: Option<&F>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            a.partial_cmp(b)
+                .unwrap_or_else(|| match (a.scalar().is_nan(), b.scalar().is_nan()) {
+                    (true, false) => Ordering::Greater,
+                    (false, true) => Ordering::Less,
+                    _ => Ordering::Equal,
+                })
+        }
+        _ => total_cmp(a, b),
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Pareto<'a>(#[serde(borrow)] BTreeMap<&'a str, f64>);
 
@@ -285,6 +334,14 @@ pub struct Weighted<'a> {
     weighting: String,
     #[serde(borrow)]
     pub scores: BTreeMap<&'a str, f64>,
+    /// Standardized counterparts of some or all of the scores, which take
+    /// their place in the weighting expression.
+    #[serde(skip)]
+    standardized: Option<BTreeMap<&'a str, f64>>,
+    /// Set by `declare_failure`. A failed score's scalar value is always
+    /// the worst possible, whatever components are inserted afterwards.
+    #[serde(default)]
+    failed: bool,
     cached_scalar: Mutex<Option<f64>>,
 }
 
@@ -313,6 +370,8 @@ impl std::ops::Add for Weighted<'static> {
 
 fn add_weighted(a: &Weighted<'static>, b: &Weighted<'static>) -> Weighted<'static> {
     let mut res = a.clone();
+    res.standardized = None;
+    res.failed = a.failed || b.failed;
     let mut keys = a.scores.keys().collect::<Vec<_>>();
     keys.extend(b.scores.keys());
     keys.sort();
@@ -329,6 +388,8 @@ impl Clone for Weighted<'_> {
             cached_scalar: Mutex::new(None),
             weighting: self.weighting.clone(),
             scores: self.scores.clone(),
+            standardized: self.standardized.clone(),
+            failed: self.failed,
         }
     }
 }
@@ -338,6 +399,8 @@ impl Weighted<'static> {
         Self {
             weighting: weighting.to_string(),
             scores: FitnessMap::new(),
+            standardized: None,
+            failed: false,
             cached_scalar: Mutex::new(None),
         }
     }
@@ -355,24 +418,47 @@ impl Weighted<'static> {
     }
 
     pub fn scale_by(&mut self, factor: f64) {
+        self.invalidate_cache();
         for (_, v) in self.scores.iter_mut() {
             *v = *v / factor
         }
     }
 
     pub fn insert(&mut self, key: &'static str, val: f64) {
+        self.invalidate_cache();
         self.scores.insert(key, val);
     }
 
+    /// Clamps each of the components listed in `bounds` into its interval.
+    pub fn clamp(&mut self, bounds: &HashMap<String, Bounds>) {
+        if bounds.is_empty() {
+            return;
+        }
+        for (key, v) in self.scores.iter_mut() {
+            if let Some(b) = bounds.get(*key) {
+                *v = b.clamp(*v)
+            }
+        }
+        self.invalidate_cache();
+    }
+
+    fn invalidate_cache(&mut self) {
+        *self.cached_scalar.get_mut().expect("poisoned") = None
+    }
+
     pub fn get(&self, key: &'static str) -> Option<&f64> {
         self.scores.get(key)
     }
 
     pub fn insert_or_add(&mut self, key: &'static str, val: f64) {
+        self.invalidate_cache();
         *self.scores.entry(key).or_insert(0.0) += val
     }
 
     pub fn scalar(&self) -> f64 {
+        if self.failed {
+            return f64::MAX;
+        }
         let mut cache = self.cached_scalar.lock().expect("poisoned");
         if let Some(res) = *cache {
             return res;
@@ -383,11 +469,21 @@ impl Weighted<'static> {
         }
     }
 
+    pub fn set_standardized(&mut self, standardized: FitnessMap<'static>) {
+        self.invalidate_cache();
+        self.standardized = Some(standardized);
+    }
+
     pub fn scalar_with_expression(&self, expr: &str) -> f64 {
         if self.scores.is_empty() {
             return f64::MAX;
         }
         let mut ns = self.scores.clone();
+        if expr == self.weighting {
+            if let Some(ref standardized) = self.standardized {
+                ns.extend(standardized.iter());
+            }
+        }
         match fasteval::ez_eval(expr, &mut ns) {
             Err(e) => panic!(
                 "Failed to evaluate expression {:?} with scores {:?}: {:?}",
@@ -397,9 +493,184 @@ impl Weighted<'static> {
         }
     }
 
+    /// Compares the scalar values of the two scores, treating NaN as the
+    /// worst.
+    pub fn total_cmp(&self, other: &Self) -> Ordering {
+        total_cmp_f64(self.scalar(), other.scalar())
+    }
+
     pub fn declare_failure(&mut self) {
-        *self.cached_scalar.get_mut().unwrap() = Some(f64::MAX)
+        self.failed = true
+    }
+
+    pub fn is_failure(&self) -> bool {
+        self.failed
+    }
+}
+
+/// The most recent values observed for a fitness component.
+#[derive(Debug, Clone, Default)]
+struct RollingStats {
+    values: VecDeque<f64>,
+    sum: f64,
+    sum_of_squares: f64,
+}
+
+impl RollingStats {
+    fn push(&mut self, value: f64, window: usize) {
+        self.values.push_back(value);
+        self.sum += value;
+        self.sum_of_squares += value * value;
+        while self.values.len() > window.max(1) {
+            if let Some(old) = self.values.pop_front() {
+                self.sum -= old;
+                self.sum_of_squares -= old * old;
+            }
+        }
     }
+
+    fn mean(&self) -> f64 {
+        self.sum / self.values.len() as f64
+    }
+
+    fn std_dev(&self) -> f64 {
+        let mean = self.mean();
+        (self.sum_of_squares / self.values.len() as f64 - mean * mean)
+            .max(0.0)
+            .sqrt()
+    }
+
+    fn min_max(&self) -> (f64, f64) {
+        self.values
+            .iter()
+            .fold((std::f64::MAX, std::f64::MIN), |(lo, hi), v| {
+                (lo.min(*v), hi.max(*v))
+            })
+    }
+}
+
+/// A summary of the values recently observed for a fitness component.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ComponentSummary {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub std_dev: f64,
+}
+
+impl fmt::Display for ComponentSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "min {}, max {}, mean {}, std dev {} (of {})",
+            self.min, self.max, self.mean, self.std_dev, self.count
+        )
+    }
+}
+
+/// Running statistics of each fitness component, over a rolling window of
+/// the most recently observed values. Non-finite values are ignored.
+#[derive(Debug, Clone)]
+pub struct FitnessStatistics {
+    window: usize,
+    stats: BTreeMap<&'static str, RollingStats>,
+}
+
+impl FitnessStatistics {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            stats: BTreeMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, fitness: &Weighted<'static>) {
+        for (key, value) in fitness.scores.iter() {
+            if value.is_finite() {
+                self.stats
+                    .entry(*key)
+                    .or_default()
+                    .push(*value, self.window);
+            }
+        }
+    }
+
+    /// The statistics of each component observed so far.
+    pub fn summary(&self) -> BTreeMap<&'static str, ComponentSummary> {
+        self.stats
+            .iter()
+            .map(|(key, stats)| {
+                let (min, max) = stats.min_max();
+                let summary = ComponentSummary {
+                    count: stats.values.len(),
+                    min,
+                    max,
+                    mean: stats.mean(),
+                    std_dev: stats.std_dev(),
+                };
+                (*key, summary)
+            })
+            .collect()
+    }
+}
+
+/// Standardizes fitness components online, against the statistics of the
+/// values recently observed for each. Since the statistics drift as the
+/// population evolves, scores standardized at different times are only
+/// roughly comparable, so the window shouldn't be too small.
+#[derive(Debug, Clone)]
+pub struct Standardizer {
+    method: Standardization,
+}
+
+impl Standardizer {
+    pub fn new(config: &StandardizationConfig) -> Self {
+        Self {
+            method: config.method,
+        }
+    }
+
+    /// Give the fitness the standardized values of its components. They
+    /// should already have been observed in `statistics`.
+    pub fn standardize(&self, statistics: &FitnessStatistics, fitness: &mut Weighted<'static>) {
+        if fitness.scores.is_empty() {
+            return;
+        }
+        let mut standardized = FitnessMap::new();
+        for (key, value) in fitness.scores.iter() {
+            let stats = match statistics.stats.get(key) {
+                Some(stats) if value.is_finite() => stats,
+                _ => continue,
+            };
+            let score = match self.method {
+                Standardization::ZScore => {
+                    let std_dev = stats.std_dev();
+                    if std_dev < std::f64::EPSILON {
+                        0.0
+                    } else {
+                        (value - stats.mean()) / std_dev
+                    }
+                }
+                Standardization::MinMax => {
+                    let (min, max) = stats.min_max();
+                    if max - min < std::f64::EPSILON {
+                        0.0
+                    } else {
+                        (value - min) / (max - min)
+                    }
+                }
+            };
+            standardized.insert(*key, score);
+        }
+        fitness.set_standardized(standardized);
+    }
+}
+
+/// Parsimony pressure: a penalty proportional to the size of a genome,
+/// which counteracts bloat.
+pub fn parsimony_penalty<G: Genome>(genome: &G, weight: f64) -> f64 {
+    weight * genome.size() as f64
 }
 
 pub fn average_weighted(ws: &[Weighted<'static>]) -> Weighted<'static> {
@@ -496,6 +767,172 @@ mod test {
         assert_eq!(ps[0], &p2);
     }
 
+    #[test]
+    fn test_clamp() {
+        let mut bounds = HashMap::new();
+        bounds.insert(
+            "register_error".to_string(),
+            Bounds {
+                min: None,
+                max: Some(100.0),
+            },
+        );
+        bounds.insert(
+            "gadgets_executed".to_string(),
+            Bounds {
+                min: Some(1.0),
+                max: Some(10.0),
+            },
+        );
+        let mut fitness = Weighted::new("register_error + gadgets_executed + ret_count");
+        fitness.insert("register_error", 1e12);
+        fitness.insert("gadgets_executed", 0.0);
+        fitness.insert("ret_count", 1e6);
+        assert_eq!(fitness.scalar(), 1e12 + 1e6);
+        fitness.clamp(&bounds);
+        assert_eq!(fitness.get("register_error"), Some(&100.0));
+        assert_eq!(fitness.get("gadgets_executed"), Some(&1.0));
+        // unlisted components are untouched
+        assert_eq!(fitness.get("ret_count"), Some(&1e6));
+        assert_eq!(fitness.scalar(), 100.0 + 1.0 + 1e6);
+
+        fitness.insert("register_error", std::f64::NAN);
+        fitness.clamp(&bounds);
+        assert!(fitness.get("register_error").unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_failure_survives_insertion() {
+        let mut fitness = Weighted::new("gadgets");
+        fitness.declare_failure();
+        assert_eq!(fitness.scalar(), f64::MAX);
+        fitness.insert("gadgets", 1.0);
+        fitness.insert_or_add("gadgets", 1.0);
+        fitness.clamp(&HashMap::new());
+        fitness.set_standardized(FitnessMap::new());
+        assert_eq!(fitness.scalar(), f64::MAX);
+        assert!(fitness.clone().is_failure());
+        assert_eq!(
+            (fitness.clone() + Weighted::new("gadgets")).scalar(),
+            f64::MAX
+        );
+        // other expressions are still evaluated over the components
+        assert_eq!(fitness.scalar_with_expression("gadgets"), 2.0);
+    }
+
+    #[test]
+    fn test_standardization() {
+        let weighted = |coverage: f64, gadgets: f64| {
+            let mut w = Weighted::new("coverage + gadgets");
+            w.insert("coverage", coverage);
+            w.insert("gadgets", gadgets);
+            w
+        };
+        let standardizer = Standardizer::new(&StandardizationConfig {
+            method: Standardization::MinMax,
+            window: 3,
+        });
+        let mut statistics = FitnessStatistics::new(3);
+        let mut ws = vec![
+            weighted(0.0, 300.0),
+            weighted(1.0, 100.0),
+            weighted(0.5, 200.0),
+        ];
+        for w in ws.iter_mut() {
+            statistics.observe(w);
+            standardizer.standardize(&statistics, w);
+        }
+        assert_eq!(ws[2].scalar(), 1.0);
+        // raw scores are left alone, as are other expressions
+        assert_eq!(ws[2]["gadgets"], 200.0);
+        assert_eq!(ws[2].scalar_with_expression("gadgets"), 200.0);
+
+        // the oldest values fall out of the window
+        let mut w = weighted(0.0, 150.0);
+        statistics.observe(&w);
+        standardizer.standardize(&statistics, &mut w);
+        assert_eq!(w.scalar(), 0.5);
+
+        let standardizer = Standardizer::new(&StandardizationConfig {
+            method: Standardization::ZScore,
+            window: 100,
+        });
+        let mut statistics = FitnessStatistics::new(100);
+        for i in 0..10 {
+            statistics.observe(&weighted(i as f64 / 10.0, i as f64 * 100.0));
+        }
+        let mut w = weighted(0.45, 450.0);
+        statistics.observe(&w);
+        standardizer.standardize(&statistics, &mut w);
+        // both components sit at their means, and so count for the same
+        assert!(w.scalar().abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_fitness_statistics() {
+        let mut statistics = FitnessStatistics::new(4);
+        for (i, error) in [60000.0, 0.0, 30000.0, 10.0, 20.0].iter().enumerate() {
+            let mut w = Weighted::new("register_error");
+            w.insert("register_error", *error);
+            w.insert("code_coverage", if i == 0 { std::f64::NAN } else { 0.9 });
+            statistics.observe(&w);
+        }
+        let summary = statistics.summary();
+        let errors = summary["register_error"];
+        assert_eq!(errors.count, 4);
+        assert_eq!((errors.min, errors.max), (0.0, 30000.0));
+        assert_eq!(errors.mean, 7507.5);
+        let coverage = summary["code_coverage"];
+        assert_eq!(coverage.count, 4);
+        assert!((coverage.mean - 0.9).abs() < 1e-9);
+        assert!(coverage.std_dev < 1e-6);
+    }
+
+    #[test]
+    fn test_nan_fitness_sorts_last() {
+        let weighted = |x: f64| {
+            let mut w = Weighted::new("foo + bar");
+            w.insert("foo", x);
+            w.insert("bar", 1.0);
+            w
+        };
+        let mut ws = vec![
+            weighted(std::f64::NAN),
+            weighted(3.0),
+            weighted(std::f64::NAN),
+            weighted(1.0),
+            weighted(2.0),
+        ];
+        ws.sort_by(|a, b| total_cmp(Some(a), Some(b)));
+        assert_eq!(ws[0].scalar(), 2.0);
+        assert_eq!(ws[1].scalar(), 3.0);
+        assert_eq!(ws[2].scalar(), 4.0);
+        assert!(ws[3].scalar().is_nan());
+        assert!(ws[4].scalar().is_nan());
+
+        assert_eq!(ws[0].total_cmp(&ws[3]), Ordering::Less);
+        assert_eq!(ws[3].total_cmp(&ws[4]), Ordering::Equal);
+        assert_eq!(total_cmp(None, Some(&ws[3])), Ordering::Greater);
+        assert_eq!(
+            total_cmp_f64(std::f64::NAN, std::f64::INFINITY),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn test_dominance_cmp() {
+        let p1: Pareto<'static> = pareto! {"a" => 1.0, "b" => 3.0, };
+        let p2: Pareto<'static> = pareto! {"a" => 2.0, "b" => 1.0, };
+        let p3: Pareto<'static> = pareto! {"a" => 0.5, "b" => 0.5, };
+        let p4: Pareto<'static> = pareto! {"a" => std::f64::NAN, "b" => 0.0, };
+        // neither dominates the other, though their sums differ
+        assert_eq!(dominance_cmp(Some(&p1), Some(&p2)), Ordering::Equal);
+        assert_ne!(total_cmp(Some(&p1), Some(&p2)), Ordering::Equal);
+        assert_eq!(dominance_cmp(Some(&p3), Some(&p2)), Ordering::Less);
+        assert_eq!(dominance_cmp(Some(&p4), Some(&p1)), Ordering::Greater);
+        assert_eq!(dominance_cmp(None, Some(&p1)), Ordering::Greater);
+    }
+
     #[test]
     fn test_add_weighted() {
         let mut w1 = Weighted::new("foo + 2 * bar");
@@ -6,10 +6,15 @@ use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use configure::Config;
+use logger::RUN;
 
 pub mod configure;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+#[cfg(feature = "emulator")]
 #[allow(dead_code)] // FIXME
 mod disassembler;
+#[cfg(feature = "emulator")]
 #[allow(dead_code)] // FIXME
 pub mod emulator;
 pub mod error;
@@ -21,6 +26,7 @@ pub mod logger;
 pub mod macros;
 pub mod observer;
 pub mod ontogenesis;
+#[cfg(feature = "emulator")]
 pub mod roper;
 #[allow(dead_code)] // FIXME
 pub mod util;
@@ -66,10 +72,10 @@ pub fn timeout_expired() -> bool {
     }
     let timeout = Duration::from_secs(timeout);
     let up_for = uptime();
-    log::debug!("Uptime: {:?}", up_for);
+    log::debug!(target: RUN, "Uptime: {:?}", up_for);
     let expired = up_for > timeout;
     if expired {
-        log::error!("Berbalang has timed out, at {:?}", up_for);
+        log::error!(target: RUN, "Berbalang has timed out, at {:?}", up_for);
     }
     expired
 }
@@ -112,6 +118,9 @@ pub fn limit_threads(threads: usize, config: &mut Config) {
         .build_global()
         .unwrap();
     config.num_islands = threads;
-    config.roper.num_emulators = threads;
-    config.roper.num_workers = threads;
+    #[cfg(feature = "emulator")]
+    {
+        config.roper.num_emulators = threads;
+        config.roper.num_workers = threads;
+    }
 }
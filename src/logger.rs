@@ -5,6 +5,29 @@ use chrono::prelude::*;
 
 use crate::EPOCH_COUNTER;
 
+// The targets of Berbalang's log messages, one for each subsystem, so that
+// each can be given its own level in `BERBALANG_LOG`, as in
+// `BERBALANG_LOG=info,berbalang::evolution=debug,berbalang::emulator=warn`.
+// A directive applies to every target that begins with it, so `berbalang`
+// covers them all.
+
+/// Selection, breeding, and the population structures.
+pub const EVOLUTION: &str = "berbalang::evolution";
+/// Loading binaries, and running and profiling the emulators.
+pub const EMULATOR: &str = "berbalang::emulator";
+/// Reports, champions, and the data dumped during a run.
+pub const OBSERVER: &str = "berbalang::observer";
+/// ROPER's creatures, fitness functions, and analyses.
+pub const ROPER: &str = "berbalang::roper";
+/// The example problems, like hello_world and linear_gp.
+pub const EXAMPLES: &str = "berbalang::examples";
+/// Parsing and checking the configuration.
+pub const CONFIG: &str = "berbalang::config";
+/// Shared helpers, like the sketches, statistics, and dumping data to disk.
+pub const UTIL: &str = "berbalang::util";
+/// Starting and stopping a run.
+pub const RUN: &str = "berbalang::run";
+
 /// This function initializes the Berbalang logger.
 ///
 pub fn init(population_name: &str) {
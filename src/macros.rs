@@ -6,9 +6,7 @@ macro_rules! impl_dominance_ord_for_phenome {
 
         impl ::non_dominated_sort::DominanceOrd<$phenome> for $ord {
             fn dominance_ord(&self, a: &$phenome, b: &$phenome) -> std::cmp::Ordering {
-                a.fitness()
-                    .partial_cmp(&b.fitness())
-                    .unwrap_or(std::cmp::Ordering::Equal)
+                $crate::fitness::dominance_cmp(a.fitness(), b.fitness())
             }
         }
 
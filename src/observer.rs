@@ -16,8 +16,10 @@ use hashbrown::HashMap;
 
 use crate::configure::Config;
 use crate::evolution::{Genome, Phenome};
+use crate::fitness::total_cmp_f64;
+use crate::logger::OBSERVER;
 use crate::util::count_min_sketch::CountMinSketch;
-use crate::util::dump::dump;
+use crate::util::dump::dump_as;
 
 // TODO: fix the stat writer so that it uses the header() and row() functions.
 
@@ -54,7 +56,7 @@ pub type ReportFn<T> = Box<dyn Fn(&Window<T>, usize, &Config) -> () + Sync + Sen
 #[allow(dead_code)]
 pub fn default_report_fn<P: Phenome + Genome>(window: &Window<P>, counter: usize, config: &Config) {
     let frame = &window.frame;
-    log::info!("default report function");
+    log::info!(target: OBSERVER, "default report function");
     let avg_len = frame.iter().map(|c| c.len()).sum::<usize>() as f64 / frame.len() as f64;
     let mut sketch = CountMinSketch::new(config);
     for g in frame {
@@ -71,13 +73,19 @@ pub fn default_report_fn<P: Phenome + Genome>(window: &Window<P>, counter: usize
         .sum::<f64>()
         / frame.len() as f64;
     log::info!(
+        target: OBSERVER,
         "[{}] Average length: {}, average genetic frequency: {}, avg scalar fit: {}",
         counter,
         avg_len,
         avg_freq,
         avg_fit,
     );
-    log::info!("[{}] Reigning champion: {:#?}", counter, window.best);
+    log::info!(
+        target: OBSERVER,
+        "[{}] Reigning champion: {:#?}",
+        counter,
+        window.best
+    );
 }
 
 fn epoch_length(config: &Config) -> usize {
@@ -128,7 +136,7 @@ impl<O: Genome + Phenome + 'static> Window<O> {
         let epoch_limit_reached =
             self.config.num_epochs != 0 && self.config.num_epochs <= crate::get_epoch_counter();
         if epoch_limit_reached {
-            log::debug!("epoch limit reached");
+            log::debug!(target: OBSERVER, "epoch limit reached");
             self.report();
             self.dump_soup();
             self.dump_population();
@@ -137,9 +145,14 @@ impl<O: Genome + Phenome + 'static> Window<O> {
 
         if let Some(ref champion) = self.champion {
             if champion.is_goal_reached(&self.config) {
-                let path = format!("{}/winning_champion.json.gz", self.config.data_directory());
-                log::info!("dumping winning champion to {}", path);
-                dump(champion, &path).expect("failed to dump champion");
+                let path = format!(
+                    "{}/winning_champion.{}",
+                    self.config.data_directory(),
+                    self.config.observer.dump_format.extension()
+                );
+                log::info!(target: OBSERVER, "dumping winning champion to {}", path);
+                dump_as(champion, &path, self.config.observer.dump_format)
+                    .expect("failed to dump champion");
                 self.report();
                 crate::stop_everything(self.config.island_id, true);
             }
@@ -158,6 +171,7 @@ impl<O: Genome + Phenome + 'static> Window<O> {
         if self.counter > 0 && self.counter % self.window_size == 0 {
             let epoch = self.local_epoch.fetch_add(1, atomic::Ordering::Relaxed) + 1;
             log::info!(
+                target: OBSERVER,
                 "Epoch {} on island {} ({} specimens seen)",
                 epoch,
                 self.config.island_id,
@@ -166,7 +180,7 @@ impl<O: Genome + Phenome + 'static> Window<O> {
 
             if self.config.island_id == 0 {
                 let global_epoch = crate::increment_epoch_counter();
-                log::info!("New global epoch: {}", global_epoch);
+                log::info!(target: OBSERVER, "New global epoch: {}", global_epoch);
             }
             true
         } else {
@@ -194,6 +208,7 @@ impl<O: Genome + Phenome + 'static> Window<O> {
         if epoch_has_incremented {
             self.dump_soup();
             self.dump_population();
+            self.dump_fitness_distribution();
             self.report();
         }
 
@@ -223,6 +238,7 @@ impl<O: Genome + Phenome + 'static> Window<O> {
 
         if updated {
             log::info!(
+                target: OBSERVER,
                 "Island {}: new best:\n{:#?}",
                 self.config.island_id,
                 self.best.as_ref().expect("Updated, but no best?")
@@ -255,21 +271,25 @@ impl<O: Genome + Phenome + 'static> Window<O> {
             if let Some(ref mut champion) = self.champion {
                 champion.generate_description();
                 log::info!(
+                    target: OBSERVER,
                     "Island {}: new champion:\n{:#?}",
                     self.config.island_id,
                     champion
                 );
                 // dump the champion
                 let path = format!(
-                    "{}/champions/champion_{}.json.gz",
+                    "{}/champions/champion_{}.{}",
                     self.config.data_directory(),
                     self.counter,
+                    self.config.observer.dump_format.extension(),
                 );
-                log::info!("Dumping new champion to {}", path);
-                dump(champion, &path).expect("Failed to dump champion");
+                log::info!(target: OBSERVER, "Dumping new champion to {}", path);
+                dump_as(champion, &path, self.config.observer.dump_format)
+                    .expect("Failed to dump champion");
                 let latest = format!(
-                    "{}/champions/latest_champion.json.gz",
-                    self.config.data_directory()
+                    "{}/champions/latest_champion.{}",
+                    self.config.data_directory(),
+                    self.config.observer.dump_format.extension(),
                 );
                 let latest = Path::new(&latest);
                 if latest.exists() {
@@ -286,6 +306,7 @@ impl<O: Genome + Phenome + 'static> Window<O> {
 
     pub fn log_record<S: LogRecord + Debug>(&self, record: S, name: &str) {
         log::debug!(
+            target: OBSERVER,
             "Island {}, logging to {}: {:#?}",
             self.config.island_id,
             name,
@@ -294,7 +315,7 @@ impl<O: Genome + Phenome + 'static> Window<O> {
         let filename = get_log_filename(name, &self.config);
         // check to see if file exists yet
         let msg = if !Path::exists((&filename).as_ref()) {
-            log::debug!("Creating header for {}", filename);
+            log::debug!(target: OBSERVER, "Creating header for {}", filename);
             format!("{}\n{}\n", record.header(), record.row())
         } else {
             format!("{}\n", record.row())
@@ -322,15 +343,53 @@ impl<O: Genome + Phenome + 'static> Window<O> {
 
     pub fn dump_population(&self) {
         if !self.config.observer.dump_population {
-            log::debug!("Not dumping population");
+            log::debug!(target: OBSERVER, "Not dumping population");
             return;
         }
         let path = format!(
-            "{}/population/population_{}.json.gz",
+            "{}/population/population_{}.{}",
             self.config.data_directory(),
             self.get_local_epoch(),
+            self.config.observer.dump_format.extension(),
         );
-        dump(&self.frame, &path).expect("Failed to dump population");
+        dump_as(&self.frame, &path, self.config.observer.dump_format)
+            .expect("Failed to dump population");
+    }
+
+    /// Returns the scalar fitnesses of the specimens in the observation
+    /// window, sorted from best to worst.
+    pub fn fitness_distribution(&self) -> Vec<f64> {
+        let mut fitnesses = self
+            .frame
+            .iter()
+            .filter_map(|g| g.scalar_fitness(&self.config.fitness.weighting))
+            .collect::<Vec<f64>>();
+        fitnesses.sort_by(|a, b| total_cmp_f64(*a, *b));
+        fitnesses
+    }
+
+    pub fn dump_fitness_distribution(&self) {
+        let period = self.config.observer.fitness_distribution_period;
+        let epoch = self.get_local_epoch();
+        if period == 0 || epoch % period != 0 {
+            return;
+        }
+        let path = format!(
+            "{}/fitness_distribution/fitness_distribution_at_epoch_{}.json",
+            self.config.data_directory(),
+            epoch,
+        );
+        if let Ok(mut file) = fs::File::create(&path).map_err(|e| {
+            log::error!(
+                target: OBSERVER,
+                "Failed to create fitness distribution file: {:?}",
+                e
+            )
+        }) {
+            serde_json::to_writer(&mut file, &self.fitness_distribution())
+                .expect("Failed to dump fitness distribution!");
+            log::debug!(target: OBSERVER, "Fitness distribution dumped to {}", path);
+        }
     }
 
     pub fn soup(&self) -> HashMap<<O as Genome>::Allele, usize> {
@@ -348,11 +407,12 @@ impl<O: Genome + Phenome + 'static> Window<O> {
 
     pub fn dump_soup(&self) {
         if !self.config.observer.dump_soup {
-            log::debug!("Not dumping soup");
+            log::debug!(target: OBSERVER, "Not dumping soup");
             return;
         }
         let mut soup = self.soup();
         log::debug!(
+            target: OBSERVER,
             "Island {} soup size: {} alleles",
             self.config.island_id,
             soup.len()
@@ -362,12 +422,12 @@ impl<O: Genome + Phenome + 'static> Window<O> {
             self.config.data_directory(),
             self.get_local_epoch(),
         );
-        if let Ok(mut soup_file) =
-            fs::File::create(&path).map_err(|e| log::error!("Failed to create soup file: {:?}", e))
+        if let Ok(mut soup_file) = fs::File::create(&path)
+            .map_err(|e| log::error!(target: OBSERVER, "Failed to create soup file: {:?}", e))
         {
             let soup_vec = soup.drain().collect::<Vec<_>>();
             serde_json::to_writer(&mut soup_file, &soup_vec).expect("Failed to dump soup!");
-            log::debug!("Soup dumped to {}", path);
+            log::debug!(target: OBSERVER, "Soup dumped to {}", path);
         }
     }
 }
@@ -382,6 +442,22 @@ impl<O: 'static + Phenome + Genome> Observer<O> {
     pub fn spawn(config: &Config, report_fn: ReportFn<O>) -> Observer<O> {
         let (tx, rx): (Sender<O>, Receiver<O>) = channel();
 
+        #[cfg(feature = "dashboard")]
+        let report_fn = if config.observer.dashboard {
+            crate::dashboard::wrap(report_fn)
+        } else {
+            report_fn
+        };
+        #[cfg(not(feature = "dashboard"))]
+        {
+            if config.observer.dashboard {
+                log::warn!(
+                    target: OBSERVER,
+                    "observer.dashboard is set, but berbalang was built without the dashboard feature"
+                );
+            }
+        }
+
         let config = Arc::new(config.clone());
         let handle: JoinHandle<()> = spawn(move || {
             let mut window: Window<O> = Window::new(report_fn, config.clone());
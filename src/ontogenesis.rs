@@ -1,5 +1,13 @@
-use std::sync::Arc;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll, Waker};
 
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::error::Error;
 use crate::evolution::Phenome;
 
 pub type FitnessFn<Pheno, State, Conf> =
@@ -24,3 +32,265 @@ pub trait Develop<P: Phenome> {
 
     fn development_pipeline<I: 'static + Iterator<Item = P> + Send>(&self, inbound: I) -> Vec<P>;
 }
+
+/// Adapts a `Develop` implementation for use from async code, such as a
+/// service running on tokio. Each evaluation is handed to a pool of worker
+/// threads, which develops the phenome and applies the fitness function,
+/// and is returned as a future that resolves once that's done. The thread
+/// that awaits the future is never blocked by the emulation.
+///
+/// Phenomes are developed concurrently, but `apply_fitness_function`
+/// needs exclusive access to the evaluator, so fitness assessments are
+/// made one at a time.
+pub struct AsyncEvaluator<E, P> {
+    evaluator: Arc<RwLock<E>>,
+    pool: ThreadPool,
+    _phenome: PhantomData<fn() -> P>,
+}
+
+impl<E, P> AsyncEvaluator<E, P>
+where
+    E: Develop<P> + Send + Sync + 'static,
+    P: Phenome + 'static,
+{
+    pub fn new(evaluator: E, num_workers: usize) -> Result<Self, Error> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(num_workers.max(1))
+            .thread_name(|i| format!("async-evaluator-{}", i))
+            .build()
+            .map_err(|e| {
+                Error::Misc(format!(
+                    "Failed to build the async evaluator's thread pool: {}",
+                    e
+                ))
+            })?;
+        Ok(Self {
+            evaluator: Arc::new(RwLock::new(evaluator)),
+            pool,
+            _phenome: PhantomData,
+        })
+    }
+
+    pub fn evaluate(&self, phenome: P) -> Evaluation<P> {
+        let state = Arc::new(Mutex::new(EvaluationState {
+            result: None,
+            waker: None,
+        }));
+        let shared = state.clone();
+        let evaluator = self.evaluator.clone();
+        self.pool.spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let phenome = evaluator
+                    .read()
+                    .expect("Failed to lock evaluator")
+                    .develop(phenome);
+                evaluator
+                    .write()
+                    .expect("Failed to lock evaluator")
+                    .apply_fitness_function(phenome)
+            }))
+            .map_err(|_| Error::Misc("Evaluation panicked".to_string()));
+            let mut state = shared.lock().expect("Failed to lock evaluation state");
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        Evaluation { state }
+    }
+
+    /// Dispatch each of the phenomes for evaluation, returning their
+    /// futures in the same order.
+    pub fn evaluate_all<I: IntoIterator<Item = P>>(&self, phenomes: I) -> Vec<Evaluation<P>> {
+        phenomes.into_iter().map(|p| self.evaluate(p)).collect()
+    }
+}
+
+/// A one-shot channel, from the worker that evaluates a phenome to the
+/// `Evaluation` that awaits it.
+struct EvaluationState<P> {
+    result: Option<Result<P, Error>>,
+    waker: Option<Waker>,
+}
+
+/// A pending evaluation, which resolves to the evaluated phenome, or to an
+/// error if the evaluator panicked.
+pub struct Evaluation<P> {
+    state: Arc<Mutex<EvaluationState<P>>>,
+}
+
+impl<P> Future for Evaluation<P> {
+    type Output = Result<P, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().expect("Failed to lock evaluation state");
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// An evaluator that does no real work, for measuring the cost of the
+/// evolutionary machinery -- selection, breeding and bookkeeping -- apart
+/// from the cost of emulation. Each phenome is instantly given a constant
+/// fitness, or one computed by some cheap function of the phenome.
+pub struct IdentityEvaluator<P: Phenome> {
+    fitness: Box<dyn Fn(&P) -> P::Fitness + Send + Sync>,
+}
+
+impl<P: Phenome> IdentityEvaluator<P> {
+    pub fn new<F>(fitness: F) -> Self
+    where
+        F: Fn(&P) -> P::Fitness + Send + Sync + 'static,
+    {
+        Self {
+            fitness: Box::new(fitness),
+        }
+    }
+
+    pub fn constant(fitness: P::Fitness) -> Self
+    where
+        P::Fitness: Sync + 'static,
+    {
+        Self::new(move |_| fitness.clone())
+    }
+}
+
+impl<P: Phenome> Develop<P> for IdentityEvaluator<P> {
+    fn develop(&self, mut phenome: P) -> P {
+        let fitness = (self.fitness)(&phenome);
+        phenome.set_fitness(fitness);
+        phenome
+    }
+
+    fn apply_fitness_function(&mut self, phenome: P) -> P {
+        phenome
+    }
+
+    fn development_pipeline<I: 'static + Iterator<Item = P> + Send>(&self, inbound: I) -> Vec<P> {
+        inbound.map(|p| self.develop(p)).collect()
+    }
+}
+
+/// Fixtures shared by the tests of the evaluators and selection drivers,
+/// which evolve hello-world genotypes.
+#[cfg(test)]
+pub mod fixtures {
+    use crate::configure::Config;
+    use crate::examples::hello_world::Genotype;
+    use crate::observer::{Observer, Window};
+
+    use super::IdentityEvaluator;
+
+    /// Scores each genotype by its length, so that shorter is fitter.
+    pub fn length_evaluator() -> IdentityEvaluator<Genotype> {
+        IdentityEvaluator::new(|g: &Genotype| vec![g.genes.len() as f64])
+    }
+
+    /// An observer that reports nothing, logging to a directory of the
+    /// test's own under the temporary directory, which is set in the config.
+    pub fn quiet_observer(config: &mut Config, test_name: &str) -> Observer<Genotype> {
+        let dir = std::env::temp_dir().join(format!("berbalang_test_{}", test_name));
+        config.observer.full_data_directory = dir.to_string_lossy().to_string();
+        std::fs::create_dir_all(dir.join("champions")).unwrap();
+        Observer::spawn(
+            config,
+            Box::new(|_: &Window<Genotype>, _: usize, _: &Config| ()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{self, AtomicUsize};
+    use std::task::Wake;
+    use std::thread::{self, Thread};
+
+    use crate::configure::Config;
+    use crate::evolution::Genome;
+    use crate::examples::hello_world::Genotype;
+
+    use super::*;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Arc::new(ThreadWaker(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_identity_evaluator() {
+        let mut config = Config::default();
+        config.min_init_len = 1;
+        config.max_init_len = 16;
+        let creatures = (0..8)
+            .map(|i| Genotype::random(&config, i))
+            .collect::<Vec<Genotype>>();
+
+        let mut evaluator = IdentityEvaluator::constant(vec![1.0]);
+        let evaluated = evaluator.development_pipeline(creatures.clone().into_iter());
+        assert_eq!(evaluated.len(), 8);
+        for creature in evaluated {
+            let creature = evaluator.apply_fitness_function(creature);
+            assert_eq!(creature.fitness(), Some(&vec![1.0]));
+        }
+
+        let evaluator = fixtures::length_evaluator();
+        for creature in creatures {
+            let len = creature.genes.len() as f64;
+            assert_eq!(evaluator.develop(creature).fitness(), Some(&vec![len]));
+        }
+    }
+
+    #[test]
+    fn test_async_evaluation() {
+        let mut config = Config::default();
+        config.min_init_len = 1;
+        config.max_init_len = 16;
+        let assessed = Arc::new(AtomicUsize::new(0));
+        let counter = assessed.clone();
+        let length_evaluator = IdentityEvaluator::new(move |g: &Genotype| {
+            if g.genes.is_empty() {
+                panic!("nothing to develop");
+            }
+            counter.fetch_add(1, atomic::Ordering::Relaxed);
+            vec![g.genes.len() as f64]
+        });
+        let evaluator = AsyncEvaluator::new(length_evaluator, 4).unwrap();
+        let creatures = (0..8)
+            .map(|i| Genotype::random(&config, i))
+            .collect::<Vec<Genotype>>();
+        let lengths = creatures
+            .iter()
+            .map(|c| c.genes.len())
+            .collect::<Vec<usize>>();
+        let evaluations = evaluator.evaluate_all(creatures);
+        for (evaluation, len) in evaluations.into_iter().zip(lengths) {
+            let creature = block_on(evaluation).expect("evaluation failed");
+            assert_eq!(creature.fitness(), Some(&vec![len as f64]));
+        }
+        assert_eq!(assessed.load(atomic::Ordering::Relaxed), 8);
+
+        let mut empty = Genotype::random(&config, 8);
+        empty.genes.clear();
+        assert!(block_on(evaluator.evaluate(empty)).is_err());
+    }
+}
@@ -1,25 +1,34 @@
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
 use hashbrown::HashSet;
 use itertools::Itertools;
 use serde::Serialize;
 
 use crate::configure::Config;
 use crate::emulator::loader::get_static_memory_image;
+use crate::emulator::pack::Pack;
 use crate::emulator::profiler::{HasProfile, Profile};
 use crate::evolution::{Genome, Phenome};
-use crate::fitness::{average_weighted, stdev_weighted, Weighted};
+use crate::fitness::{average_weighted, stdev_weighted, FitnessStatistics, Weighted};
+use crate::logger::ROPER;
 use crate::observer::{LogRecord, Window};
+use crate::roper::Payload;
 
 #[derive(Serialize, Clone, Debug)]
 pub struct StatRecord {
     pub counter: usize,
     pub epoch: usize,
     pub generation: f64,
+    pub age: f64,
     pub indigeneity: f64,
     pub num_offspring: f64,
     pub ratio_visited: f64,
     pub soup_len: usize,
     pub length: f64,
     pub emulation_time: f64,
+    pub profile_bytes: f64,
     pub fitness: Weighted<'static>,
     pub stdev_fitness: Option<Weighted<'static>>,
 }
@@ -27,7 +36,7 @@ pub struct StatRecord {
 impl LogRecord for StatRecord {
     fn header(&self) -> String {
         let mut s = format!(
-            "epoch,generation,indigeneity,offspring,length,emulation_time,ratio_visited,soup_len,fitness"
+            "epoch,generation,age,indigeneity,offspring,length,emulation_time,profile_bytes,ratio_visited,soup_len,fitness"
         );
         if self.stdev_fitness.is_some() {
             s.push_str(",stdev_fitness");
@@ -44,13 +53,15 @@ impl LogRecord for StatRecord {
 
     fn row(&self) -> String {
         let mut s = format!(
-            "{epoch},{generation},{indigeneity},{offspring},{length},{emulation_time},{ratio_visited},{soup_len},{fitness}",
+            "{epoch},{generation},{age},{indigeneity},{offspring},{length},{emulation_time},{profile_bytes},{ratio_visited},{soup_len},{fitness}",
             epoch = self.epoch,
             generation = self.generation,
+            age = self.age,
             indigeneity = self.indigeneity,
             offspring = self.num_offspring,
             length = self.length,
             emulation_time = self.emulation_time,
+            profile_bytes = self.profile_bytes,
             ratio_visited = self.ratio_visited,
             soup_len = self.soup_len,
             fitness = self.fitness.scalar(),
@@ -80,12 +91,16 @@ impl StatRecord {
     where
         C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
     {
-        let specimen_len = specimen.chromosome().len() as f64;
+        let specimen_len = specimen.size() as f64;
         let specimen_emulation_time = specimen
             .profile()
             .as_ref()
             .map(|p| p.avg_emulation_micros())
             .unwrap_or(0.0);
+        let profile_bytes = specimen
+            .profile()
+            .map(Profile::approx_heap_size)
+            .unwrap_or(0) as f64;
 
         let addresses_visited = specimen
             .profile()
@@ -104,11 +119,13 @@ impl StatRecord {
             counter,
             epoch,
             generation: specimen.generation() as f64,
+            age: specimen.age() as f64,
             indigeneity,
             num_offspring: specimen.num_offspring() as f64,
             soup_len: 0,
             length: specimen_len,
             emulation_time: specimen_emulation_time,
+            profile_bytes,
             ratio_visited,
             fitness: specimen
                 .fitness()
@@ -157,7 +174,7 @@ impl StatRecord {
         // let ratio_eligible = frame.len() as f64 / window.frame.len() as f64;
         // log::info!("Ratio eligible = {}", ratio_eligible);
 
-        let length = frame.iter().map(|c| c.chromosome().len()).sum::<usize>() as f64 / frame_len;
+        let length = frame.iter().map(|c| c.size()).sum::<usize>() as f64 / frame_len;
 
         let fitnesses = frame
             .iter()
@@ -173,8 +190,16 @@ impl StatRecord {
             .sum::<f64>()
             / frame.len() as f64;
 
+        let profile_bytes = frame
+            .iter()
+            .filter_map(|g| g.profile().map(Profile::approx_heap_size))
+            .sum::<usize>() as f64
+            / frame_len;
+
         let generation = frame.iter().map(|g| g.generation()).sum::<usize>() as f64 / frame_len;
 
+        let age = frame.iter().map(|g| g.age()).sum::<usize>() as f64 / frame_len;
+
         let num_offspring =
             frame.iter().map(|g| g.num_offspring()).sum::<usize>() as f64 / frame_len;
 
@@ -196,23 +221,92 @@ impl StatRecord {
             ratio_visited,
             soup_len,
             generation,
+            age,
             num_offspring,
             indigeneity,
             length,
             emulation_time,
+            profile_bytes,
             fitness: mean_fitness,
             stdev_fitness: Some(stdev_fitness),
         }
     }
 }
 
+/// The path to which the island's champion's payload is written: the
+/// island's id is inserted into `roper.output_payload_path`, before the
+/// extension, if any, so that islands don't overwrite each other's.
+fn payload_path(path: &str, island_id: usize) -> String {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_island_{}.{}", stem, island_id, ext.to_string_lossy()),
+        None => format!("{}_island_{}", stem, island_id),
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+/// Write the champion's packed payload to `roper.output_payload_path` (see
+/// `payload_path`), as raw bytes, and a report describing it alongside.
+/// Nothing is written if the file already holds the same payload, since
+/// the champion is reported again and again until it's dethroned.
+fn write_payload<C>(champion: &C, config: &Config) -> std::io::Result<()>
+where
+    C: Payload + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
+{
+    let path = match config.roper.output_payload_path {
+        Some(ref path) => payload_path(path, config.island_id),
+        None => return Ok(()),
+    };
+    let memory = get_static_memory_image();
+    let payload = champion.payload(&config.roper);
+    let packed = payload.pack(
+        memory.word_size,
+        memory.endian,
+        config.roper.byte_filter().as_ref(),
+    );
+    if fs::read(&path)
+        .map(|written| written == packed)
+        .unwrap_or(false)
+    {
+        return Ok(());
+    }
+    fs::write(&path, &packed)?;
+
+    let mut report = format!(
+        "Champion {name} of island {island}\n\
+         {words} words ({bytes} bytes), {word_size}-byte {endian:?} words\n\n",
+        name = champion.name(),
+        island = config.island_id,
+        words = payload.len(),
+        bytes = packed.len(),
+        word_size = memory.word_size,
+        endian = memory.endian,
+    );
+    for word in payload.iter() {
+        report.push_str(&format!(
+            "0x{:0width$x}\n",
+            word,
+            width = memory.word_size * 2
+        ));
+    }
+    report.push_str(&format!("\n{:#?}\n", champion.fitness()));
+    fs::write(format!("{}.txt", path), report)?;
+    log::info!(target: ROPER, "Wrote the champion's payload to {}", path);
+    Ok(())
+}
+
 pub fn report_fn<C>(window: &Window<C>, counter: usize, config: &Config)
 where
-    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
+    C: HasProfile + Payload + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
 {
     let epoch = window.get_local_epoch();
     let record = StatRecord::mean_from_window(window, counter);
     log::debug!(
+        target: ROPER,
         "Island #{island} {record:#?}",
         island = config.island_id,
         record = record,
@@ -223,6 +317,15 @@ where
         let champion_record =
             StatRecord::for_specimen(champion, counter, epoch, window.config.island_id);
         window.log_record(champion_record, "champion");
+        if champion.is_goal_reached(config) {
+            if let Err(e) = write_payload(champion, config) {
+                log::error!(
+                    target: ROPER,
+                    "Failed to write the champion's payload: {:?}",
+                    e
+                );
+            }
+        }
     }
 
     if let Some(ref best) = window.best {
@@ -231,6 +334,7 @@ where
     }
 
     log::debug!(
+        target: ROPER,
         "Island #{island} Champion: {champion:#?}\nIsland #{island} Best: {best:#?}\nIsland #{island}, Epoch {epoch}",
         island = config.island_id,
         best = window.best,
@@ -239,7 +343,32 @@ where
     );
 
     if let Ok(stat) = procinfo::pid::statm_self() {
-        log::debug!("Memory status: {:#x?}", stat);
+        log::debug!(target: ROPER, "Memory status: {:#x?}", stat);
+    }
+}
+
+/// Log the running statistics of each fitness component, as gathered by the
+/// evaluator.
+pub fn report_fitness_statistics(statistics: &Arc<Mutex<FitnessStatistics>>, config: &Config) {
+    let summary = match statistics.lock() {
+        Ok(statistics) => statistics.summary(),
+        Err(e) => {
+            log::error!(
+                target: ROPER,
+                "Failed to read the fitness statistics: {:?}",
+                e
+            );
+            return;
+        }
+    };
+    for (component, stats) in summary.iter() {
+        log::info!(
+            target: ROPER,
+            "Island #{island} {component}: {stats}",
+            island = config.island_id,
+            component = component,
+            stats = stats,
+        );
     }
 }
 //
@@ -255,3 +384,17 @@ where
 //         log::info!("soup size: {}", soup.len());
 //     }
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_payload_path() {
+        assert_eq!(
+            payload_path("./out/payload.bin", 3),
+            "./out/payload_island_3.bin"
+        );
+        assert_eq!(payload_path("payload", 0), "payload_island_0");
+    }
+}
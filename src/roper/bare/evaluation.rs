@@ -1,11 +1,14 @@
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 
 use unicorn::Cpu;
 
-use crate::configure::ClassificationProblem;
+use crate::configure::{ClassificationProblem, SampleAggregation};
+use crate::emulator::pack::count_words_with_bytes;
 use crate::emulator::register_pattern::Register;
+use crate::fitness::{average_weighted, parsimony_penalty, FitnessStatistics, Weighted};
 use crate::ontogenesis::FitnessFn;
-use crate::roper::Sketches;
+use crate::roper::{penalize_non_executable, Payload, Sketches};
 use crate::{configure::Config, emulator::hatchery::Hatchery, ontogenesis::Develop, util};
 
 use super::*;
@@ -13,6 +16,9 @@ use super::*;
 pub struct Evaluator<C: 'static + Cpu<'static>> {
     config: Arc<Config>,
     hatchery: Hatchery<C>,
+    /// The initial register states of each sample, if they're drawn
+    /// afresh for each, rather than taken from the hatchery.
+    sample_registers: Vec<HashMap<Register<C>, u64>>,
     sketches: Sketches,
     fitness_fn: Box<FitnessFn<Creature, Sketches, Config>>,
 }
@@ -34,24 +40,107 @@ impl<C: 'static + Cpu<'static>> Evaluator<C> {
             util::architecture::random_register_state::<u64, C>(
                 &output_registers,
                 config.random_seed,
+                &config.roper.register_distribution,
             )
         } else {
             util::architecture::constant_register_state::<C>(&output_registers, 0_u64)
         };
+        let sample_registers = if config.roper.resample_registers {
+            (0..config.roper.num_samples())
+                .map(|sample| {
+                    util::architecture::random_register_state::<_, C>(
+                        &output_registers,
+                        (config.random_seed, sample),
+                        &config.roper.register_distribution,
+                    )
+                })
+                .collect()
+        } else {
+            vec![]
+        };
         let hatchery: Hatchery<C> = Hatchery::new(
             hatch_config,
             Arc::new(initial_register_state),
             Arc::new(output_registers),
-        );
+        )
+        .unwrap_or_else(|e| panic!("Failed to start the hatchery: {:?}", e));
 
         let sketches = Sketches::new(&config);
         Self {
             config: Arc::new(config),
             hatchery,
+            sample_registers,
             sketches,
             fitness_fn: Box::new(fitness_fn),
         }
     }
+
+    fn payload(&self, creature: &Creature) -> Vec<u64> {
+        creature.payload(&self.config.roper)
+    }
+
+    /// Score each sample's runs separately, and aggregate the fitnesses
+    /// according to `roper.resample_aggregation`. The creature keeps the
+    /// profile of every run. Its genome is recorded in the genetic sketch
+    /// only once, however many samples there are.
+    fn score_samples(&mut self, mut creature: Creature) -> Creature {
+        let profile = match creature.profile.take() {
+            Some(profile) => profile,
+            None => return (self.fitness_fn)(creature, &mut self.sketches, self.config.clone()),
+        };
+        let mut fitnesses = Vec::new();
+        // every sample is scored against the genetic sketch as it was
+        // before the evaluation, and only the first sample's record is kept
+        let before = self.sketches.genetic.clone();
+        let mut after = None;
+        for sample in profile.split_runs(self.config.roper.num_samples()) {
+            if after.is_some() {
+                self.sketches.genetic = before.clone();
+            }
+            let mut specimen = creature.clone();
+            specimen.profile = Some(sample);
+            let specimen = (self.fitness_fn)(specimen, &mut self.sketches, self.config.clone());
+            fitnesses.extend(specimen.fitness);
+            if after.is_none() {
+                after = Some(self.sketches.genetic.clone());
+            }
+        }
+        if let Some(sketch) = after {
+            self.sketches.genetic = sketch;
+        }
+        creature.profile = Some(profile);
+        creature.fitness = match self.config.roper.resample_aggregation {
+            _ if fitnesses.is_empty() => None,
+            SampleAggregation::Mean => Some(average_weighted(&fitnesses)),
+            SampleAggregation::Worst => fitnesses.into_iter().max_by(|a, b| a.total_cmp(b)),
+        };
+        creature
+    }
+
+    /// Run the creature once, from the given initial register state rather
+    /// than the configured ones, and score that run alone. The creature is
+    /// left untouched, and the scored specimen is returned. This is how the
+    /// co-evolution driver pits chains against its population of inputs.
+    pub fn evaluate_against(
+        &mut self,
+        creature: &Creature,
+        registers: HashMap<Register<C>, u64>,
+    ) -> Creature {
+        let mut specimen = creature.clone();
+        specimen.profile = None;
+        specimen.fitness = None;
+        let profile = self
+            .hatchery
+            .execute(self.payload(&specimen), Some(registers))
+            .expect("Failed to evaluate creature");
+        specimen.add_profile(profile);
+        self.apply_fitness_function(specimen)
+    }
+
+    /// The running statistics of each fitness component.
+    pub fn fitness_statistics(&self) -> Arc<Mutex<FitnessStatistics>> {
+        self.sketches.fitness_statistics.clone()
+    }
 }
 
 // TODO: refactor classification problems substantially.
@@ -75,19 +164,12 @@ impl<'a, C: 'static + Cpu<'static>> Develop<Creature> for Evaluator<C> {
         if creature.profile.is_some() {
             return creature;
         }
-        // TODO: implement classification task here.
-        if let Some(ref problems) = self.config.problems {
-            for problem in problems {
-                let reg_map: HashMap<Register<C>, u64> = classification_problem_to_register_map::<C>(
-                    problem,
-                    &self.config.roper.input_registers,
-                );
-                let profile = self
-                    .hatchery
-                    .execute(creature.chromosome().to_vec(), Some(reg_map))
-                    .expect("Failed to evaluate creature");
-                creature.add_profile(profile);
-            }
+        if self.config.roper.prefilter
+            && gadget::count_executable_words(get_static_memory_image(), &self.payload(&creature))
+                == 0
+        {
+            // this will mark the profile as non-executable
+            creature.profile = Some(Profile::default());
             return creature;
         }
         // We could detach the chromosome here (Vec<u64>) and send it
@@ -98,16 +180,93 @@ impl<'a, C: 'static + Cpu<'static>> Develop<Creature> for Evaluator<C> {
         // is probably no less expensive, all things considered.
         // However, if we start appending arguments to the payload, then
         // we might want to do this differently.
-        let profile = self
-            .hatchery
-            .execute(creature.chromosome().to_vec(), None)
-            .expect("Failed to evaluate creature");
-        creature.add_profile(profile);
+        let payload = self.payload(&creature);
+        let runs = (0..self.config.roper.num_samples()).flat_map(|sample| {
+            // TODO: implement classification task here.
+            let args = match self.config.problems {
+                Some(ref problems) => problems
+                    .iter()
+                    .map(|problem| {
+                        Some(classification_problem_to_register_map::<C>(
+                            problem,
+                            &self.config.roper.input_registers,
+                        ))
+                    })
+                    .collect::<Vec<_>>(),
+                None => vec![self.sample_registers.get(sample).cloned()],
+            };
+            let payload = payload.clone();
+            args.into_iter().map(move |args| (payload.clone(), args))
+        });
+        // The runs are emulated concurrently. Each profile is absorbed as
+        // soon as those of the runs before it have been, so that the runs
+        // stay in order, to be split into samples again when they're scored.
+        let mut arrived = BTreeMap::new();
+        let mut next = 0;
+        for result in self.hatchery.eval_pipeline(runs) {
+            let (run, profile) = result.expect("Failed to evaluate creature");
+            arrived.insert(run, profile);
+            while let Some(profile) = arrived.remove(&next) {
+                creature.add_profile(profile);
+                next += 1;
+            }
+        }
         creature
     }
 
-    fn apply_fitness_function(&mut self, creature: Creature) -> Creature {
-        (self.fitness_fn)(creature, &mut self.sketches, self.config.clone())
+    fn apply_fitness_function(&mut self, mut creature: Creature) -> Creature {
+        if creature.profile.as_ref().map_or(false, |p| !p.executable) {
+            let mut fitness = Weighted::new(&self.config.fitness.weighting);
+            fitness.declare_failure();
+            creature.set_fitness(fitness);
+            return creature;
+        }
+        let mut creature = if self.config.roper.num_samples() > 1 {
+            self.score_samples(creature)
+        } else {
+            (self.fitness_fn)(creature, &mut self.sketches, self.config.clone())
+        };
+        if !self.config.roper.forbidden_bytes.is_empty() {
+            let memory = get_static_memory_image();
+            let packed = self.payload(&creature).pack(
+                memory.word_size,
+                memory.endian,
+                self.config.roper.byte_filter().as_ref(),
+            );
+            let bad_words = count_words_with_bytes(
+                &packed,
+                memory.word_size,
+                &self.config.roper.forbidden_bytes,
+            );
+            if let Some(ref mut fitness) = creature.fitness {
+                fitness.insert("bad_bytes", bad_words as f64);
+            }
+        }
+        if let Some(ref mut archive) = self.sketches.novelty {
+            if let Some(novelty) = creature.profile.as_ref().map(|p| archive.assess(p)) {
+                if let Some(ref mut fitness) = creature.fitness {
+                    fitness.insert("novelty", novelty);
+                }
+            }
+        }
+        if self.config.fitness.parsimony > 0.0 {
+            let penalty = parsimony_penalty(&creature, self.config.fitness.parsimony);
+            if let Some(ref mut fitness) = creature.fitness {
+                fitness.insert("parsimony", penalty);
+            }
+        }
+        if let Some(penalty) = self.config.roper.chain_length_penalty {
+            let chain_length = self.payload(&creature).len() as f64;
+            if let Some(ref mut fitness) = creature.fitness {
+                fitness.insert("chain_length", chain_length * penalty);
+            }
+        }
+        if let Some(ref mut fitness) = creature.fitness {
+            penalize_non_executable(creature.profile.as_ref(), fitness, &self.config);
+            fitness.clamp(&self.config.fitness.clamp);
+            self.sketches.observe_fitness(fitness);
+        }
+        creature
     }
 
     fn development_pipeline<I: 'static + Iterator<Item = Creature> + Send>(
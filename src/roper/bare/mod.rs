@@ -2,20 +2,25 @@ use std::fmt;
 use std::hash::{Hash, Hasher};
 
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use rand::seq::IteratorRandom;
 use rand::{thread_rng, Rng};
 use rand_distr::{Distribution, Standard};
 use serde::{Deserialize, Serialize};
 
-use crate::configure::Config;
+use crate::configure::{BehaviorFeature, Config, RoperConfig};
 use crate::emulator::loader;
 use crate::emulator::loader::get_static_memory_image;
 use crate::emulator::pack::Pack;
-use crate::emulator::profiler::{HasProfile, Profile};
-use crate::evolution::{Genome, LinearChromosome, Mutation, Phenome};
-use crate::roper::Fitness;
+use crate::emulator::profiler::{HasProfile, Profile, ProfileSummary};
+use crate::emulator::register_pattern::register_diff_table;
+use crate::error::Error;
+use crate::evolution::{Genome, LinearChromosome, Mutation, Phenome, StrategyParams};
+use crate::logger::ROPER;
+use crate::roper::behavior::{BehaviorDescriptor, ALL_FEATURES};
+use crate::roper::{gadget, Fitness, Payload};
 use crate::util::architecture::{read_integer, write_integer, Perms};
+use crate::util::distance::jaccard_distance;
 use crate::util::random::hash_seed_rng;
 use crate::util::{self, architecture::Endian};
 
@@ -36,6 +41,7 @@ pub struct Creature {
     pub fitness: Option<Fitness<'static>>,
     pub front: Option<usize>,
     pub num_offspring: usize,
+    pub age: usize,
     pub native_island: usize,
     pub description: Option<String>,
 }
@@ -64,6 +70,22 @@ impl Hash for Creature {
     }
 }
 
+impl Payload for Creature {
+    /// The portion of the creature's chromosome that will actually be
+    /// packed and executed, laid out according to the gadgets' stack deltas
+    /// if they're being modelled, and respecting `max_chain_length`.
+    fn payload(&self, config: &RoperConfig) -> Vec<u64> {
+        let mut payload = match config.stack_deltas {
+            Some(ref deltas) => gadget::lay_out(self.chromosome(), deltas, 0),
+            None => self.chromosome().to_vec(),
+        };
+        if let Some(max) = config.max_chain_length {
+            payload.truncate(max);
+        }
+        payload
+    }
+}
+
 impl Pack for Creature {
     fn pack(
         &self,
@@ -118,6 +140,34 @@ impl Pack for Creature {
     }
 }
 
+impl Creature {
+    /// A bare creature with the given chromosome, for tests that don't
+    /// need a loaded binary or a soup to draw from.
+    #[cfg(test)]
+    pub(crate) fn with_chromosome(chromosome: Vec<u64>) -> Self {
+        let len = chromosome.len();
+        Self {
+            chromosome: LinearChromosome {
+                chromosome,
+                mutations: vec![None; len],
+                parentage: vec![],
+                parent_names: vec![],
+                name: "test".to_string(),
+                generation: 0,
+                strategy_params: None,
+            },
+            tag: 0,
+            profile: None,
+            fitness: None,
+            front: None,
+            num_offspring: 0,
+            age: 0,
+            native_island: 0,
+            description: None,
+        }
+    }
+}
+
 impl fmt::Debug for Creature {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(
@@ -186,8 +236,16 @@ impl fmt::Debug for Creature {
                 writeln!(f, "{}", path)?;
             }
             //writeln!(f, "Register state: {:#x?}", profile.registers)?;
+            let gadgets = self.chromosome().iter().cloned().collect::<HashSet<u64>>();
             for state in &profile.registers {
-                writeln!(f, "\nSpidered register state:\n{:?}", state)?;
+                writeln!(
+                    f,
+                    "\nSpidered register state:\n{}",
+                    state.annotated(&gadgets)
+                )?;
+            }
+            if let Some(table) = register_diff_table(&profile.registers) {
+                writeln!(f, "\nRegisters that varied across runs:\n{}", table)?;
             }
             for mem in profile.memory_writes.iter() {
                 writeln!(f, "Memory writes: {:#x?}", mem)?;
@@ -222,7 +280,10 @@ fn try_to_ensure_exec(mut chromosome: Vec<u64>) -> Vec<u64> {
         }
     }
     if chromosome.is_empty() {
-        log::warn!("Failed to ensure executability for chromosome");
+        log::warn!(
+            target: ROPER,
+            "Failed to ensure executability for chromosome"
+        );
         tail
     } else {
         chromosome.reverse();
@@ -242,6 +303,14 @@ impl Genome for Creature {
         self.num_offspring
     }
 
+    fn age(&self) -> usize {
+        self.age
+    }
+
+    fn incr_age(&mut self) {
+        self.age += 1
+    }
+
     fn chromosome(&self) -> &[Self::Allele] {
         &self.chromosome.chromosome
     }
@@ -254,6 +323,10 @@ impl Genome for Creature {
         self.native_island
     }
 
+    fn parent_names(&self) -> Vec<String> {
+        self.chromosome.parent_names.clone()
+    }
+
     fn random<H: Hash>(config: &Config, salt: H) -> Self {
         let mut hasher = fnv::FnvHasher::default();
         salt.hash(&mut hasher);
@@ -285,23 +358,94 @@ impl Genome for Creature {
                 parent_names: vec![],
                 name,
                 generation: 0,
+                strategy_params: StrategyParams::initial(config),
             },
             tag,
             profile: None,
             fitness: None,
             front: None,
             num_offspring: 0,
+            age: 0,
             native_island: config.island_id,
             description: None,
         }
     }
 
+    /// Imported chromosomes must consist of words that fit the target
+    /// architecture, and begin with an executable address.
+    fn from_chromosome(
+        name: Option<String>,
+        chromosome: Vec<u64>,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let memory = loader::try_to_get_static_memory_image().ok_or_else(|| {
+            Error::Misc("The memory image must be loaded before importing creatures".to_string())
+        })?;
+        if memory.word_size < 8 {
+            if let Some(w) = chromosome
+                .iter()
+                .find(|w| **w >> (memory.word_size * 8) != 0)
+            {
+                return Err(Error::Misc(format!(
+                    "0x{:x} doesn't fit in a {}-byte word",
+                    w, memory.word_size
+                )));
+            }
+        }
+        let head = chromosome
+            .first()
+            .ok_or_else(|| Error::Misc("Empty chromosome".to_string()))?;
+        if !memory
+            .perm_of_addr(*head)
+            .map(|p| p.intersects(Perms::EXEC))
+            .unwrap_or(false)
+        {
+            return Err(Error::Misc(format!(
+                "Chromosome begins with a non-executable address, 0x{:x}",
+                head
+            )));
+        }
+        let len = chromosome.len();
+        let mut rng = hash_seed_rng(&chromosome);
+        let name = name.unwrap_or_else(|| util::name::random(4, &chromosome));
+        Ok(Self {
+            chromosome: LinearChromosome {
+                chromosome,
+                mutations: vec![None; len],
+                parentage: vec![],
+                parent_names: vec![],
+                name,
+                generation: 0,
+                strategy_params: StrategyParams::initial(config),
+            },
+            tag: rng.gen::<u64>(),
+            profile: None,
+            fitness: None,
+            front: None,
+            num_offspring: 0,
+            age: 0,
+            native_island: config.island_id,
+            description: None,
+        })
+    }
+
     fn crossover(mates: &[&Self], config: &Config) -> Self {
         let parents = mates
             .iter()
             .map(|x| &x.chromosome)
             .collect::<Vec<&LinearChromosome<_, _>>>();
-        let chromosome = LinearChromosome::crossover(&parents, config);
+        let chromosome = if config.crossover_algorithm == "gadget_boundary" {
+            // treat each executable word as the start of a gadget's frame
+            let memory = loader::try_to_get_static_memory_image();
+            LinearChromosome::boundary_crossover(&parents, config, |w| {
+                memory
+                    .and_then(|m| m.perm_of_addr(*w))
+                    .map(|p| p.intersects(Perms::EXEC))
+                    .unwrap_or(false)
+            })
+        } else {
+            LinearChromosome::crossover(&parents, config)
+        };
         Self {
             chromosome,
             tag: thread_rng().gen::<u64>(),
@@ -309,6 +453,7 @@ impl Genome for Creature {
             fitness: None,
             front: None,
             num_offspring: 0,
+            age: 0,
             native_island: config.island_id,
             description: None,
         }
@@ -330,6 +475,8 @@ pub enum WordMutation {
     AddressAdd,
     AddressSub,
     BitFlip,
+    LevyStep,
+    GadgetSnap,
 }
 
 impl Mutation for WordMutation {
@@ -372,9 +519,29 @@ impl Mutation for WordMutation {
                 let word = *allele ^ (1 << rng.gen_range(0, word_size as u64 * 8));
                 *allele = word;
             }
+            // only ever made by their own mutation operators
+            WordMutation::LevyStep | WordMutation::GadgetSnap => {}
         }
         mutation
     }
+
+    fn levy_step(allele: &mut Self::Allele, config: &Config) -> Option<Self> {
+        let step = util::levy_flight::levy_step(&mut thread_rng(), config.mutation_exponent);
+        *allele = allele.wrapping_add(step as u64);
+        Some(WordMutation::LevyStep)
+    }
+
+    fn snap_to_gadget(allele: &mut Self::Allele, config: &Config) -> Option<Self> {
+        let word = *allele;
+        let nearest = config
+            .roper
+            .soup
+            .as_ref()?
+            .iter()
+            .min_by_key(|gadget| (**gadget as i128 - word as i128).abs())?;
+        *allele = *nearest;
+        Some(WordMutation::GadgetSnap)
+    }
 }
 
 impl Distribution<WordMutation> for Standard {
@@ -443,7 +610,7 @@ impl Phenome for Creature {
 
     fn is_goal_reached(&self, config: &Config) -> bool {
         self.scalar_fitness(&config.fitness.priority())
-            .map(|p| p - config.fitness.target <= std::f64::EPSILON)
+            .map(|p| config.fitness.is_target_reached(p))
             .unwrap_or(false)
     }
 
@@ -455,4 +622,96 @@ impl Phenome for Creature {
     fn mature(&self) -> bool {
         self.profile.is_some()
     }
+
+    fn approx_heap_size(&self) -> usize {
+        self.profile
+            .as_ref()
+            .map(Profile::approx_heap_size)
+            .unwrap_or(0)
+    }
+
+    fn prune_behavior(&mut self, max_bytes: usize) {
+        if let Some(ref mut profile) = self.profile {
+            profile.prune_to_size(max_bytes)
+        }
+    }
+
+    fn profile_summary(&self) -> Option<ProfileSummary> {
+        self.profile.as_ref().map(Profile::summary)
+    }
+
+    fn behavior_descriptor(&self) -> Option<Vec<f64>> {
+        self.profile.as_ref().map(|p| p.describe(&ALL_FEATURES))
+    }
+
+    fn describe_behavior(&self, features: &[BehaviorFeature]) -> Option<Vec<f64>> {
+        self.profile.as_ref().map(|p| p.describe(features))
+    }
+
+    /// The Jaccard distance between the sets of gadgets that the two
+    /// creatures executed, over all of their runs, or, unless both have
+    /// executed some gadgets, between the sets of addresses in their
+    /// chromosomes.
+    /// Unlike a positional measure, like the Hamming distance, this isn't
+    /// thrown off by gadgets that merely appear in a different order.
+    /// Gadgets are only logged with `roper.record_basic_blocks` set.
+    fn distance(&self, other: &Self) -> Option<f64> {
+        fn executed(profile: &Profile) -> HashSet<u64> {
+            profile
+                .gadgets_executed
+                .iter()
+                .flat_map(|g| g.keys())
+                .copied()
+                .collect()
+        }
+        fn genomic(creature: &Creature) -> HashSet<u64> {
+            creature.chromosome().iter().copied().collect()
+        }
+        let a = self.profile.as_ref().map(executed).unwrap_or_default();
+        let b = other.profile.as_ref().map(executed).unwrap_or_default();
+        // two creatures that executed nothing aren't alike for it
+        if a.is_empty() || b.is_empty() {
+            Some(jaccard_distance(&genomic(self), &genomic(other)))
+        } else {
+            Some(jaccard_distance(&a, &b))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snap_to_gadget() {
+        let mut config = Config::default();
+        let mut word = 0x1900_u64;
+        assert!(WordMutation::snap_to_gadget(&mut word, &config).is_none());
+        assert_eq!(word, 0x1900);
+        config.roper.soup = Some(vec![0x1000, 0x2000, 0x3000]);
+        assert!(WordMutation::snap_to_gadget(&mut word, &config).is_some());
+        assert_eq!(word, 0x2000);
+    }
+
+    #[test]
+    fn test_distance() {
+        let profiled = |chromosome: Vec<u64>, gadgets: &[u64]| {
+            let mut creature = Creature::with_chromosome(chromosome);
+            creature.profile = Some(Profile {
+                gadgets_executed: vec![gadgets.iter().map(|g| (*g, 1)).collect()],
+                executable: true,
+                ..Default::default()
+            });
+            creature
+        };
+        let a = profiled(vec![1, 2, 3, 4], &[1, 2]);
+        let b = profiled(vec![1, 2, 5, 6], &[2, 1]);
+        // judged by the gadgets executed, where both executed some
+        assert_eq!(a.distance(&b), Some(0.0));
+        // and otherwise by their genomes
+        let c = profiled(vec![1, 2, 5, 6], &[]);
+        assert!((a.distance(&c).unwrap() - 4.0 / 6.0).abs() < 1e-9);
+        let d = Creature::with_chromosome(vec![1, 2, 3, 4]);
+        assert_eq!(a.distance(&d), Some(0.0));
+    }
 }
@@ -0,0 +1,161 @@
+use std::hash::Hasher;
+
+use itertools::Itertools;
+
+use crate::configure::BehaviorFeature;
+use crate::emulator::profiler::Profile;
+
+/// The number of buckets into which the visited blocks are hashed.
+pub const BLOCK_BUCKETS: usize = 16;
+
+/// Every feature, in the order in which exported descriptors list them.
+pub const ALL_FEATURES: [BehaviorFeature; 4] = [
+    BehaviorFeature::Blocks,
+    BehaviorFeature::Registers,
+    BehaviorFeature::WriteSpan,
+    BehaviorFeature::ChainLength,
+];
+
+/// Something whose behaviour can be summarized as a low-dimensional
+/// feature vector, for comparison by the quality-diversity algorithms.
+pub trait BehaviorDescriptor {
+    /// Concatenates the requested features into a single descriptor. Each
+    /// element of the descriptor lies between 0 and 1.
+    fn describe(&self, features: &[BehaviorFeature]) -> Vec<f64> {
+        features
+            .iter()
+            .flat_map(|f| self.feature(*f).into_iter())
+            .collect()
+    }
+
+    fn feature(&self, feature: BehaviorFeature) -> Vec<f64>;
+}
+
+/// Squashes a count into the interval `[0, 1)`, preserving its order.
+fn squash(n: f64) -> f64 {
+    n / (1.0 + n)
+}
+
+impl BehaviorDescriptor for Profile {
+    fn feature(&self, feature: BehaviorFeature) -> Vec<f64> {
+        match feature {
+            // The fraction of the distinct blocks visited that fall into
+            // each bucket.
+            BehaviorFeature::Blocks => {
+                let blocks = self
+                    .paths
+                    .iter()
+                    .flatten()
+                    .map(|block| block.entry)
+                    .unique()
+                    .collect::<Vec<u64>>();
+                let mut buckets = vec![0.0; BLOCK_BUCKETS];
+                for entry in blocks.iter() {
+                    let mut hasher = fnv::FnvHasher::default();
+                    hasher.write_u64(*entry);
+                    buckets[hasher.finish() as usize % BLOCK_BUCKETS] += 1.0;
+                }
+                if !blocks.is_empty() {
+                    buckets.iter_mut().for_each(|b| *b /= blocks.len() as f64);
+                }
+                buckets
+            }
+            // Each output register's value at the end of the last run,
+            // ordered by register name.
+            BehaviorFeature::Registers => self
+                .registers
+                .last()
+                .map(|state| {
+                    state
+                        .0
+                        .iter()
+                        .sorted_by_key(|(reg, _)| *reg)
+                        .filter_map(|(_, vals)| vals.first())
+                        .map(|v| *v as f64 / std::u64::MAX as f64)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            // The logarithm of the widest span of addresses written to in
+            // any run, scaled by the width of the address space.
+            BehaviorFeature::WriteSpan => {
+                let span = self
+                    .memory_writes
+                    .iter()
+                    .filter_map(|writes| writes.span())
+                    .map(|(lo, hi)| hi - lo)
+                    .max()
+                    .unwrap_or(0);
+                vec![(span.max(1) as f64).log2() / 64.0]
+            }
+            // The mean number of gadgets returned through per run.
+            BehaviorFeature::ChainLength => {
+                let runs = self.ret_counts.len().max(1) as f64;
+                vec![squash(self.ret_counts.iter().sum::<usize>() as f64 / runs)]
+            }
+        }
+    }
+}
+
+/// The distance between two descriptors, between 0 and 1: the Euclidean
+/// distance, scaled by the greatest possible distance. Elements missing from
+/// the shorter descriptor count as entirely different.
+pub fn distance(a: &[f64], b: &[f64]) -> f64 {
+    let len = a.len().max(b.len());
+    if len == 0 {
+        return 0.0;
+    }
+    let missing = len - a.len().min(b.len());
+    let sum_of_squares = a
+        .iter()
+        .zip(b.iter())
+        .map(|(x, y)| (x - y).powi(2))
+        .sum::<f64>();
+    ((sum_of_squares + missing as f64) / len as f64).sqrt()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emulator::profiler::{Block, SparseDataHelper};
+
+    use super::*;
+
+    #[test]
+    fn test_profile_features() {
+        let mut writes = SparseDataHelper::new();
+        writes.insert_u8(0x1000, 1);
+        writes.insert_u8(0x10ff, 2);
+        let profile = Profile {
+            paths: vec![
+                vec![Block { entry: 1, size: 1 }, Block { entry: 2, size: 1 }],
+                vec![Block { entry: 2, size: 1 }],
+            ],
+            memory_writes: vec![writes.into()],
+            ret_counts: vec![3, 5],
+            ..Default::default()
+        };
+
+        let blocks = profile.feature(BehaviorFeature::Blocks);
+        assert_eq!(blocks.len(), BLOCK_BUCKETS);
+        assert!((blocks.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        assert_eq!(
+            profile.feature(BehaviorFeature::WriteSpan),
+            vec![8.0 / 64.0]
+        );
+        assert_eq!(profile.feature(BehaviorFeature::ChainLength), vec![0.8]);
+
+        let descriptor =
+            profile.describe(&[BehaviorFeature::WriteSpan, BehaviorFeature::ChainLength]);
+        assert_eq!(descriptor, vec![8.0 / 64.0, 0.8]);
+        assert!(descriptor.iter().all(|x| *x >= 0.0 && *x <= 1.0));
+    }
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance(&[0.5, 0.5], &[0.5, 0.5]), 0.0);
+        assert_eq!(distance(&[0.0, 0.0], &[1.0, 1.0]), 1.0);
+        assert_eq!(distance(&[0.0, 0.0], &[1.0, 0.0]), 0.5_f64.sqrt());
+        assert_eq!(distance(&[0.0, 0.0], &[0.0]), 0.5_f64.sqrt());
+        assert_eq!(distance(&[], &[]), 0.0);
+    }
+}
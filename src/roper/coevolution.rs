@@ -0,0 +1,334 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::iter;
+use std::path::Path;
+
+use hashbrown::HashMap;
+use rand::Rng;
+use unicorn::Cpu;
+
+use crate::configure::{Config, SampleAggregation};
+use crate::emulator::profiler::HasProfile;
+use crate::emulator::register_pattern::Register;
+use crate::error::Error;
+use crate::evolution::engine::Epoch;
+use crate::evolution::{compare_fitness, export, Genome};
+use crate::fitness::{average_weighted, total_cmp_f64};
+use crate::increment_epoch_counter;
+use crate::logger::ROPER;
+use crate::observer::Observer;
+use crate::roper::bare::evaluation::Evaluator;
+use crate::roper::bare::Creature;
+use crate::util::architecture::random_register_state;
+use crate::util::random::hash_seed_rng;
+
+/// An initial register state, evolving to break the chains it meets.
+pub struct Parasite<C: 'static + Cpu<'static>> {
+    pub registers: HashMap<Register<C>, u64>,
+    /// The mean scalar fitness of the chains that met this register state
+    /// in the last generation in which any did. Since fitness is
+    /// minimized, the higher the score, the fitter the parasite.
+    pub score: Option<f64>,
+}
+
+/// Competitive co-evolution of a population of chains (the hosts) with a
+/// population of the initial register states they're run from (the
+/// parasites), after W. Daniel Hillis, "Co-evolving parasites improve
+/// simulated evolution as an optimization procedure", 1990.
+///
+/// In each generation, the hosts and their offspring are each run against
+/// `coevolution.encounters` parasites, chosen at random, and their
+/// fitnesses aggregated according to `roper.resample_aggregation`. The
+/// fittest `pop_size` hosts survive. Each parasite is scored by the
+/// fitness of the hosts that met it, and the worse half of the parasites
+/// is replaced by the offspring of the better half.
+///
+/// The parasites vary the registers named in `roper.input_registers`, or,
+/// if there are none, every register that's read at the end of a run.
+pub struct Coevolution<C: 'static + Cpu<'static>> {
+    pub hosts: Vec<Creature>,
+    pub parasites: Vec<Parasite<C>>,
+    pub config: Config,
+    pub iteration: usize,
+    pub observer: Observer<Creature>,
+    pub evaluator: Evaluator<C>,
+    registers: Vec<Register<C>>,
+}
+
+impl<C: 'static + Cpu<'static>> Coevolution<C> {
+    pub fn new(config: &Config, observer: Observer<Creature>, evaluator: Evaluator<C>) -> Self {
+        let names = if config.roper.input_registers.is_empty() {
+            config.roper.registers_to_check()
+        } else {
+            config.roper.input_registers.clone()
+        };
+        let registers = names
+            .iter()
+            // running error through ok() because it can't be formatted with Debug
+            .map(|r| r.parse().ok().expect("Failed to parse register name"))
+            .collect::<Vec<Register<C>>>();
+        let parasites = (0..config.coevolution.num_parasites.max(1))
+            .map(|i| Parasite {
+                registers: random_register_state::<_, C>(
+                    &registers,
+                    (config.random_seed, config.island_id, "parasite", i),
+                    &config.roper.register_distribution,
+                ),
+                score: None,
+            })
+            .collect::<Vec<Parasite<C>>>();
+        let mut hosts = export::initial_population::<Creature>(config);
+        hosts.extend((hosts.len()..config.pop_size).map(|i| Creature::random(config, i)));
+
+        let mut world = Self {
+            hosts: vec![],
+            parasites,
+            config: config.clone(),
+            iteration: 0,
+            observer,
+            evaluator,
+            registers,
+        };
+        let mut rng = hash_seed_rng(&(config.random_seed, config.island_id, "encounters"));
+        let mut tally = vec![vec![]; world.parasites.len()];
+        world.hosts = hosts
+            .into_iter()
+            .map(|host| world.encounter(host, &mut rng, &mut tally))
+            .collect();
+        score_parasites(&mut world.parasites, tally);
+        world
+    }
+
+    /// Run the host against a sample of the parasites, and score it by the
+    /// aggregate of its fitnesses. Each encounter's scalar fitness is added
+    /// to the parasite's tally.
+    fn encounter<R: Rng>(
+        &mut self,
+        mut host: Creature,
+        rng: &mut R,
+        tally: &mut [Vec<f64>],
+    ) -> Creature {
+        host.profile = None;
+        host.fitness = None;
+        let mut fitnesses = Vec::new();
+        for _ in 0..self.config.coevolution.encounters.max(1) {
+            let i = rng.gen_range(0, self.parasites.len());
+            let specimen = self
+                .evaluator
+                .evaluate_against(&host, self.parasites[i].registers.clone());
+            if let Some(profile) = specimen.profile {
+                host.add_profile(profile);
+            }
+            if let Some(fitness) = specimen.fitness {
+                tally[i].push(fitness.scalar());
+                fitnesses.push(fitness);
+            }
+        }
+        host.fitness = match self.config.roper.resample_aggregation {
+            _ if fitnesses.is_empty() => None,
+            SampleAggregation::Mean => Some(average_weighted(&fitnesses)),
+            SampleAggregation::Worst => fitnesses.into_iter().max_by(|a, b| a.total_cmp(b)),
+        };
+        host
+    }
+
+    fn select<'a, R: Rng>(&'a self, rng: &mut R) -> &'a Creature {
+        iter::repeat(())
+            .take(self.config.tournament.tournament_size.max(1))
+            .map(|()| &self.hosts[rng.gen_range(0, self.hosts.len())])
+            .min_by(|a, b| compare_fitness(*a, *b))
+            .expect("empty tournament")
+    }
+
+    /// Export the hosts to a single JSON file at `path`.
+    pub fn export_population<Q: AsRef<Path> + Debug>(&self, path: Q) -> Result<(), Error> {
+        export::export_population(self.hosts.iter(), &self.config, path)
+    }
+
+    pub fn evolve(mut self) -> Self {
+        let mut rng = hash_seed_rng(&(
+            self.config.random_seed,
+            self.config.island_id,
+            self.iteration,
+        ));
+        let offspring = (0..self.config.pop_size)
+            .map(|_| {
+                let parents = iter::repeat(())
+                    .take(self.config.tournament.num_parents)
+                    .map(|()| self.select(&mut rng))
+                    .collect::<Vec<&Creature>>();
+                Genome::mate(&parents, &self.config)
+            })
+            .collect::<Vec<Creature>>();
+
+        // The parents are run again alongside their offspring, since the
+        // parasites they were scored against have since changed.
+        let num_parents = self.hosts.len();
+        let mut hosts = std::mem::take(&mut self.hosts);
+        hosts.extend(offspring);
+        let mut tally = vec![vec![]; self.parasites.len()];
+        let mut hosts = hosts
+            .into_iter()
+            .map(|host| self.encounter(host, &mut rng, &mut tally))
+            .collect::<Vec<Creature>>();
+        for child in hosts.iter().skip(num_parents) {
+            self.observer.observe(child.clone());
+        }
+        hosts.sort_by(compare_fitness);
+        hosts.truncate(self.config.pop_size);
+        for host in hosts.iter_mut() {
+            host.incr_age();
+        }
+        self.hosts = hosts;
+
+        score_parasites(&mut self.parasites, tally);
+        breed_parasites(&mut self.parasites, &self.registers, &self.config, &mut rng);
+        log::debug!(
+            target: ROPER,
+            "Island {} iteration {}: strongest parasite scored {:?}",
+            self.config.island_id,
+            self.iteration,
+            self.parasites.first().and_then(|p| p.score),
+        );
+
+        increment_epoch_counter();
+        self.iteration += 1;
+        self
+    }
+}
+
+impl<C: 'static + Cpu<'static>> Epoch<Creature> for Coevolution<C> {
+    fn evolve(self) -> Self {
+        Coevolution::evolve(self)
+    }
+
+    fn iteration(&self) -> usize {
+        self.iteration
+    }
+
+    fn population(&self) -> Vec<&Creature> {
+        self.hosts.iter().collect()
+    }
+}
+
+/// Score each parasite that met any hosts by their mean fitness. Those that
+/// met none keep their old scores.
+fn score_parasites<C: 'static + Cpu<'static>>(parasites: &mut [Parasite<C>], tally: Vec<Vec<f64>>) {
+    for (parasite, fitnesses) in parasites.iter_mut().zip(tally.into_iter()) {
+        if !fitnesses.is_empty() {
+            parasite.score = Some(fitnesses.iter().sum::<f64>() / fitnesses.len() as f64);
+        }
+    }
+}
+
+/// Replace the worse half of the parasites with the offspring of the better
+/// half. Parasites that have never been scored count as the worst.
+fn breed_parasites<C: 'static + Cpu<'static>, R: Rng>(
+    parasites: &mut Vec<Parasite<C>>,
+    registers: &[Register<C>],
+    config: &Config,
+    rng: &mut R,
+) {
+    let score = |p: &Parasite<C>| p.score.unwrap_or(std::f64::NEG_INFINITY);
+    parasites.sort_by(|a, b| total_cmp_f64(score(b), score(a)));
+    let survivors = (parasites.len() + 1) / 2;
+    let offspring = (survivors..parasites.len())
+        .map(|_| {
+            let mother = &parasites[rng.gen_range(0, survivors)].registers;
+            let father = &parasites[rng.gen_range(0, survivors)].registers;
+            let mut child = cross_registers(mother, father, rng);
+            for reg in registers.iter() {
+                if rng.gen_bool(config.coevolution.mutation_rate) {
+                    let fresh = random_register_state::<_, C>(
+                        &[*reg],
+                        rng.gen::<u64>(),
+                        &config.roper.register_distribution,
+                    );
+                    child.extend(fresh);
+                }
+            }
+            Parasite {
+                registers: child,
+                score: None,
+            }
+        })
+        .collect::<Vec<Parasite<C>>>();
+    parasites.truncate(survivors);
+    parasites.extend(offspring);
+}
+
+/// A uniform crossover of two register states. Each register takes its
+/// value from one parent or the other, at random.
+pub fn cross_registers<K: Hash + Eq + Copy, R: Rng>(
+    mother: &HashMap<K, u64>,
+    father: &HashMap<K, u64>,
+    rng: &mut R,
+) -> HashMap<K, u64> {
+    mother
+        .iter()
+        .map(|(reg, val)| match father.get(reg) {
+            Some(other) if rng.gen::<bool>() => (*reg, *other),
+            _ => (*reg, *val),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use unicorn::{CpuX86, RegisterX86};
+
+    use super::*;
+
+    fn parasite(rax: u64, score: Option<f64>) -> Parasite<CpuX86<'static>> {
+        Parasite {
+            registers: iter::once((RegisterX86::RAX, rax)).collect(),
+            score,
+        }
+    }
+
+    #[test]
+    fn test_score_parasites() {
+        let mut parasites = vec![parasite(0, Some(5.0)), parasite(1, None)];
+        score_parasites(&mut parasites, vec![vec![], vec![1.0, 2.0]]);
+        // a parasite that met no hosts keeps its old score
+        assert_eq!(parasites[0].score, Some(5.0));
+        assert_eq!(parasites[1].score, Some(1.5));
+    }
+
+    #[test]
+    fn test_breed_parasites() {
+        let mut config = Config::default();
+        config.coevolution.mutation_rate = 0.0;
+        let mut parasites = vec![
+            parasite(0, Some(1.0)),
+            parasite(1, None),
+            parasite(2, Some(3.0)),
+            parasite(3, Some(2.0)),
+        ];
+        let mut rng = hash_seed_rng(&"breed_parasites");
+        breed_parasites(&mut parasites, &[RegisterX86::RAX], &config, &mut rng);
+        assert_eq!(parasites.len(), 4);
+        // the better half survives, fittest first
+        assert_eq!(parasites[0].registers[&RegisterX86::RAX], 2);
+        assert_eq!(parasites[0].score, Some(3.0));
+        assert_eq!(parasites[1].registers[&RegisterX86::RAX], 3);
+        assert_eq!(parasites[1].score, Some(2.0));
+        // and the worse half, with the unscored parasite sorted last, is
+        // replaced by their unscored offspring
+        for child in &parasites[2..] {
+            assert!(child.score.is_none());
+            assert!([2, 3].contains(&child.registers[&RegisterX86::RAX]));
+        }
+    }
+
+    #[test]
+    fn test_cross_registers() {
+        let mother = (0..16_u8).map(|r| (r, 0_u64)).collect::<HashMap<u8, u64>>();
+        let father = (0..16_u8).map(|r| (r, 1_u64)).collect::<HashMap<u8, u64>>();
+        let mut rng = hash_seed_rng(&"cross_registers");
+        let child = cross_registers(&mother, &father, &mut rng);
+        assert_eq!(child.len(), 16);
+        assert!(child.values().any(|v| *v == 0));
+        assert!(child.values().any(|v| *v == 1));
+    }
+}
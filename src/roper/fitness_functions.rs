@@ -1,13 +1,15 @@
-use std::sync::Arc;
-
-use hashbrown::HashSet;
+use std::any::{Any, TypeId};
+use std::sync::{Arc, Mutex};
 
 use crate::configure::Config;
 use crate::emulator::loader::get_static_memory_image;
 use crate::emulator::profiler::HasProfile;
+use crate::error::Error;
 use crate::evolution::{Genome, Phenome};
 use crate::fitness::Weighted;
+use crate::logger::ROPER;
 use crate::ontogenesis::FitnessFn;
+use crate::roper::gadget::GadgetBitSet;
 use crate::roper::Sketches;
 use crate::util::entropy::Entropy;
 
@@ -19,8 +21,8 @@ where
         let mut scores = vec![];
         for reg_state in &profile.registers {
             for (reg, vals) in reg_state.0.iter() {
-                sketch.register_error.insert((reg, vals));
-                scores.push(sketch.register_error.query((reg, vals)));
+                sketch.register_error.insert_hashable(&(reg, vals));
+                scores.push(sketch.register_error.query_hashable(&(reg, vals)));
             }
         }
         let register_freq = stats::mean(scores.into_iter());
@@ -52,6 +54,7 @@ where
         // failure, and exit the function.
         if number_of_cases != config.roper.register_patterns().len() {
             log::error!(
+                target: ROPER,
                 "Creature has only {} register states! Expecting {}!",
                 number_of_cases,
                 config.roper.register_patterns().len()
@@ -60,18 +63,20 @@ where
             return creature;
         }
         for (idx, pattern) in config.roper.register_patterns().iter().enumerate() {
-            let register_error = pattern.distance_from_register_state(&profile.registers[idx]);
+            let tolerance = &config.roper.register_tolerance;
+            let register_error =
+                pattern.distance_from_register_state(&profile.registers[idx], tolerance);
             let mut weighted_fitness = Weighted::new(&config.fitness.weighting);
             weighted_fitness.insert_or_add("register_error", register_error);
 
             // Calculate the novelty of register state errors
             let register_freq = stats::mean(
                 pattern
-                    .incorrect_register_states(&profile.registers[idx])
+                    .incorrect_register_states(&profile.registers[idx], tolerance)
                     .iter()
                     .map(|goof| {
-                        sketch.register_error.insert(goof);
-                        sketch.register_error.query(goof)
+                        sketch.register_error.insert_hashable(goof);
+                        sketch.register_error.query_hashable(goof)
                     }),
             );
 
@@ -84,13 +89,14 @@ where
             let ret_count = profile.ret_counts[idx];
             weighted_fitness.insert_or_add("ret_count", ret_count as f64);
 
-            creature.record_genetic_frequency(&mut sketch.genetic);
-            let gen_freq = creature.query_genetic_frequency(&sketch.genetic);
-            weighted_fitness.scores.insert("genetic_freq", gen_freq);
-
             fitness = weighted_fitness + fitness;
         }
         fitness.scale_by(number_of_cases as f64);
+
+        // the genome is counted once per evaluation, not once per case
+        creature.record_genetic_frequency(&mut sketch.genetic);
+        let gen_freq = creature.query_genetic_frequency(&sketch.genetic);
+        fitness.insert("genetic_freq", gen_freq);
         // Now add a constancy penalty if appropriate
         // let mut regs = profile.registers.clone();
         // regs.dedup();
@@ -98,7 +104,7 @@ where
         //     "constancy_penalty",
         //     (profile.registers.len() - regs.len()) as f64,
         // );
-        log::debug!("Setting creature fitness to {:#?}", fitness);
+        log::debug!(target: ROPER, "Setting creature fitness to {:#?}", fitness);
         creature.set_fitness(fitness);
     }
     creature
@@ -114,10 +120,15 @@ where
             let entropy = just_regs.entropy();
             let mut weighted_fitness = Weighted::new(&config.fitness.weighting);
             weighted_fitness.insert("register_entropy", entropy);
-            log::debug!("registers = {:x?}\n1/entropy = {}", just_regs, entropy);
+            log::debug!(
+                target: ROPER,
+                "registers = {:x?}\n1/entropy = {}",
+                just_regs,
+                entropy
+            );
 
-            sketch.register_error.insert(&just_regs);
-            let reg_freq = sketch.register_error.query(&just_regs);
+            sketch.register_error.insert_hashable(&just_regs);
+            let reg_freq = sketch.register_error.query_hashable(&just_regs);
             weighted_fitness.insert("register_freq", reg_freq);
 
             weighted_fitness.insert("gadgets_executed", profile.gadgets_executed.len() as f64);
@@ -152,8 +163,8 @@ where
             weighted_fitness.insert("zeroes", score);
             weighted_fitness.insert("gadgets_executed", profile.gadgets_executed.len() as f64);
 
-            sketch.register_error.insert(registers);
-            let reg_freq = sketch.register_error.query(registers);
+            sketch.register_error.insert_hashable(registers);
+            let reg_freq = sketch.register_error.query_hashable(registers);
             weighted_fitness.insert("register_freq", reg_freq);
 
             creature.set_fitness(weighted_fitness);
@@ -193,8 +204,8 @@ where
             fitness.insert_or_add(SUBPATTERN_LABELS[i - 1], occurrences);
         }
 
-        sketch.memory_writes.insert(&profile.memory_writes);
-        let memory_freq = sketch.memory_writes.query(&profile.memory_writes);
+        sketch.memory_writes.insert_hashable(&profile.memory_writes);
+        let memory_freq = sketch.memory_writes.query_hashable(&profile.memory_writes);
 
         fitness.insert_or_add("memory_freq", memory_freq);
 
@@ -227,15 +238,7 @@ where
     // }
 
     if let Some(ref profile) = creature.profile() {
-        let mut addresses_visited = HashSet::new();
-        // TODO: optimize this, maybe parallelize
-        profile.execution_trace_iter().for_each(|path| {
-            for block in path {
-                for addr in block.entry..(block.entry + block.size as u64) {
-                    addresses_visited.insert(addr);
-                }
-            }
-        });
+        let addresses_visited = profile.addresses_visited();
         let mut freq_score = 0.0;
         for addr in addresses_visited.iter() {
             sketch.addresses_visited.insert(*addr);
@@ -258,23 +261,343 @@ where
         let gadgets_executed = profile.ret_counts.iter().sum::<usize>();
         fitness.insert("ret_count", gadgets_executed as f64);
 
+        // The fraction of the distinct gadgets executed that no creature
+        // had executed before (0 if none were executed).
+        let mut executed = GadgetBitSet::default();
+        for addr in profile.gadgets_executed.iter().flat_map(|g| g.keys()) {
+            executed.insert(sketch.gadget_index.insert(*addr));
+        }
+        let new_gadgets = if executed.is_empty() {
+            0.0
+        } else {
+            executed.difference_len(&sketch.gadgets_seen) as f64 / executed.len() as f64
+        };
+        sketch.gadgets_seen.union_with(&executed);
+        fitness.insert("new_gadgets", new_gadgets);
+
         creature.set_fitness(fitness);
     }
 
     creature
 }
 
-pub fn get_fitness_function<C>(name: &str) -> FitnessFn<C, Sketches, Config>
+/// Rewards traversing rare control flow edges, which distinguish the ways
+/// in which blocks are chained together, rather than just which blocks are
+/// reached. `edge_freq` is the mean frequency, among the creatures seen so
+/// far, of the edges the creature traversed (1 if it traversed none), and
+/// `edge_count` the number of distinct edges it traversed.
+pub fn edge_novelty_ff<C>(mut creature: C, sketch: &mut Sketches, config: Arc<Config>) -> C
+where
+    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
+{
+    if let Some(ref profile) = creature.profile() {
+        let edges = profile.edge_coverage();
+        let mut freq_score = 0.0;
+        for edge in edges.iter() {
+            sketch.edges.insert(edge);
+            freq_score += sketch.edges.query(edge);
+        }
+        let edge_count = edges.len() as f64;
+        let avg_freq = if edges.is_empty() {
+            1.0
+        } else {
+            freq_score / edge_count
+        };
+
+        let mut fitness = Weighted::new(&config.fitness.weighting);
+        fitness.insert("edge_freq", avg_freq);
+        fitness.insert("edge_count", edge_count);
+
+        creature.set_fitness(fitness);
+    }
+
+    creature
+}
+
+/// The fraction of the bits that differ between two words.
+fn bit_distance(a: u64, b: u64) -> f64 {
+    (a ^ b).count_ones() as f64 / 64.0
+}
+
+/// Rewards reaching a system call. `syscall_missed` is the fraction of runs
+/// that never reached one. If `roper.syscall_number` is set,
+/// `syscall_number_error` measures how far the accumulator was from it at
+/// the syscall, as a fraction of differing bits, counting runs that missed
+/// the syscall as entirely wrong.
+pub fn syscall_ff<C>(mut creature: C, _sketch: &mut Sketches, config: Arc<Config>) -> C
+where
+    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
+{
+    if let Some(ref profile) = creature.profile() {
+        let runs = profile.syscalls.len().max(1) as f64;
+        let missed = profile.syscalls.iter().filter(|s| s.is_none()).count() as f64;
+
+        let mut fitness = Weighted::new(&config.fitness.weighting);
+        fitness.insert("syscall_missed", missed / runs);
+        if let Some(number) = config.roper.syscall_number {
+            let error = profile
+                .syscalls
+                .iter()
+                .map(|s| s.as_ref().map_or(1.0, |s| bit_distance(s.number, number)))
+                .sum::<f64>();
+            fitness.insert("syscall_number_error", error / runs);
+        }
+        let ret_count = profile.ret_counts.iter().sum::<usize>();
+        fitness.insert("ret_count", ret_count as f64);
+
+        creature.set_fitness(fitness);
+    }
+
+    creature
+}
+
+/// Rewards chains that hand control back cleanly. `unclean_exit` is the
+/// fraction of runs that didn't end by returning to `roper.return_address`,
+/// and `cpu_error_rate` the fraction that ended in a CPU error. Both are
+/// meant to be combined with a task-specific objective, e.g. through the
+/// `composite` fitness function.
+pub fn clean_exit_ff<C>(mut creature: C, _sketch: &mut Sketches, config: Arc<Config>) -> C
+where
+    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
+{
+    if let Some(ref profile) = creature.profile() {
+        let runs = profile.cpu_errors.len().max(1) as f64;
+        let errors = profile.cpu_errors.iter().filter(|e| e.is_some()).count() as f64;
+        let unclean = profile.cpu_errors.len() - profile.clean_exits();
+
+        let mut fitness = Weighted::new(&config.fitness.weighting);
+        fitness.insert("unclean_exit", unclean as f64 / runs);
+        fitness.insert("cpu_error_rate", errors / runs);
+
+        creature.set_fitness(fitness);
+    }
+
+    creature
+}
+
+/// Rewards reaching `roper.target_address` while keeping out of
+/// `roper.bad_regions`. `target_missed` is the fraction of runs that never
+/// executed the target, and is only scored when a target is set, and
+/// `bad_region_hits` the mean number of instructions executed per run
+/// within the bad regions.
+pub fn reach_target_ff<C>(mut creature: C, _sketch: &mut Sketches, config: Arc<Config>) -> C
+where
+    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized,
+{
+    if let Some(ref profile) = creature.profile() {
+        let runs = profile.cpu_errors.len().max(1) as f64;
+        let reached = profile.targets_reached.iter().filter(|r| **r).count() as f64;
+        let hits = profile.bad_region_hits.iter().sum::<usize>() as f64;
+
+        let mut fitness = Weighted::new(&config.fitness.weighting);
+        if config.roper.target_address.is_some() {
+            fitness.insert("target_missed", 1.0 - reached / runs);
+        }
+        fitness.insert("bad_region_hits", hits / runs);
+
+        creature.set_fitness(fitness);
+    }
+
+    creature
+}
+
+type SharedFitnessFn<C> = Arc<dyn Fn(C, &mut Sketches, Arc<Config>) -> C + Sync + Send>;
+
+/// Fitness functions registered at runtime, keyed by the type of creature
+/// they assess and by name. Each is stored as a `SharedFitnessFn<C>`.
+#[allow(clippy::type_complexity)]
+static CUSTOM_FITNESS_FUNCTIONS: Mutex<Vec<(TypeId, String, Box<dyn Any + Send>)>> =
+    Mutex::new(Vec::new());
+
+/// Register a fitness function for creatures of type `C`, which can then be
+/// selected by setting `fitness.function` to `name`. A registered function
+/// takes precedence over a built-in function of the same name, and over any
+/// function previously registered under that name for the same type.
+pub fn register_fitness_function<C, F>(name: &str, fitness_fn: F)
+where
+    C: 'static,
+    F: Fn(C, &mut Sketches, Arc<Config>) -> C + Sync + Send + 'static,
+{
+    let fitness_fn: SharedFitnessFn<C> = Arc::new(fitness_fn);
+    let type_id = TypeId::of::<C>();
+    let mut registry = CUSTOM_FITNESS_FUNCTIONS
+        .lock()
+        .expect("Failed to lock the fitness function registry");
+    registry.retain(|(t, n, _)| *t != type_id || n != name);
+    registry.push((type_id, name.to_string(), Box::new(fitness_fn)));
+}
+
+fn custom_fitness_function<C: 'static>(name: &str) -> Option<SharedFitnessFn<C>> {
+    let type_id = TypeId::of::<C>();
+    CUSTOM_FITNESS_FUNCTIONS
+        .lock()
+        .expect("Failed to lock the fitness function registry")
+        .iter()
+        .find(|(t, n, _)| *t == type_id && n == name)
+        .and_then(|(_, _, f)| f.downcast_ref::<SharedFitnessFn<C>>())
+        .cloned()
+}
+
+/// Runs several fitness functions on each creature, and merges the scores
+/// that each of them records into a single `Weighted` fitness. Each
+/// function's scores are multiplied by its weight in `fitness.weights`, and
+/// scores recorded under the same name by more than one function are summed.
+pub struct CompositeFitness<C> {
+    components: Vec<(String, FitnessFn<C, Sketches, Config>)>,
+}
+
+impl<C> CompositeFitness<C>
 where
     C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized + 'static,
 {
-    match name {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let components = config
+            .fitness
+            .components
+            .iter()
+            .map(|name| {
+                if name == "composite" {
+                    return Err(Error::Misc(
+                        "The composite fitness function can't be one of its own components"
+                            .to_string(),
+                    ));
+                }
+                Ok((name.clone(), get_fitness_function(name, config)?))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self { components })
+    }
+
+    pub fn assess(&self, mut creature: C, sketch: &mut Sketches, config: Arc<Config>) -> C {
+        let mut fitness = Weighted::new(&config.fitness.weighting);
+        let mut assessed = false;
+        for (name, fitness_fn) in self.components.iter() {
+            // Each component records into a fresh fitness, on a copy of the
+            // creature, so that it's credited only with the scores it
+            // records itself, and not with the creature's previous fitness,
+            // or the previous component's.
+            let mut trial = creature.clone();
+            trial.set_fitness(Weighted::new(&config.fitness.weighting));
+            let trial = fitness_fn(trial, sketch, config.clone());
+            let component = match trial.fitness() {
+                Some(component) if !component.scores.is_empty() => component,
+                _ => continue,
+            };
+            let weight = config.fitness.weights.get(name).cloned().unwrap_or(1.0);
+            for (key, score) in component.scores.iter() {
+                fitness.insert_or_add(*key, score * weight);
+            }
+            assessed = true;
+        }
+        if assessed {
+            creature.set_fitness(fitness);
+        }
+        creature
+    }
+}
+
+pub fn get_fitness_function<C>(
+    name: &str,
+    config: &Config,
+) -> Result<FitnessFn<C, Sketches, Config>, Error>
+where
+    C: HasProfile + Genome + Phenome<Fitness = Weighted<'static>> + Sized + 'static,
+{
+    if let Some(fitness_fn) = custom_fitness_function::<C>(name) {
+        return Ok(Box::new(move |creature, sketch, config| {
+            fitness_fn(creature, sketch, config)
+        }));
+    }
+    let fitness_fn: FitnessFn<C, Sketches, Config> = match name {
+        "composite" => {
+            let composite = CompositeFitness::new(config)?;
+            Box::new(move |creature, sketch, config| composite.assess(creature, sketch, config))
+        }
         "register_pattern" => Box::new(register_pattern_ff),
         "register_conjunction" => Box::new(register_conjunction_ff),
         "register_entropy" => Box::new(register_entropy_ff),
         "code_coverage" => Box::new(code_coverage_ff),
+        "edge_novelty" => Box::new(edge_novelty_ff),
         "memory_pattern" => Box::new(memory_pattern_ff),
         "just_novelty" => Box::new(just_novelty_ff),
-        s => unimplemented!("No such fitness function as {}", s),
+        "syscall" => Box::new(syscall_ff),
+        "clean_exit" => Box::new(clean_exit_ff),
+        "reach_target" => Box::new(reach_target_ff),
+        s => return Err(Error::Misc(format!("No such fitness function as {}", s))),
+    };
+    Ok(fitness_fn)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::Address;
+    use crate::emulator::profiler::Profile;
+    use crate::roper::{bare, push};
+
+    use super::*;
+
+    #[test]
+    fn test_register_fitness_function() {
+        assert!(custom_fitness_function::<bare::Creature>("do_nothing").is_none());
+        register_fitness_function("do_nothing", |creature: bare::Creature, _, _| creature);
+        assert!(custom_fitness_function::<bare::Creature>("do_nothing").is_some());
+        // functions are registered for one type of creature only
+        assert!(custom_fitness_function::<push::Creature>("do_nothing").is_none());
+        assert!(get_fitness_function::<bare::Creature>("do_nothing", &Config::default()).is_ok());
+        assert!(
+            get_fitness_function::<bare::Creature>("no_such_thing", &Config::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_composite_fitness() {
+        let mut config = Config::default();
+        config.fitness.weighting = "a + b".to_string();
+        config.fitness.components = vec!["composite".to_string()];
+        assert!(CompositeFitness::<bare::Creature>::new(&config).is_err());
+
+        register_fitness_function(
+            "score_a",
+            |mut creature: bare::Creature, _, config: Arc<Config>| {
+                let mut fitness = Weighted::new(&config.fitness.weighting);
+                fitness.insert("a", 1.0);
+                creature.set_fitness(fitness);
+                creature
+            },
+        );
+        // records nothing, like a built-in function given no profile
+        register_fitness_function("score_nothing", |creature: bare::Creature, _, _| creature);
+        config.fitness.components = vec!["score_a".to_string(), "score_nothing".to_string()];
+        let composite = CompositeFitness::<bare::Creature>::new(&config).unwrap();
+
+        let mut creature = bare::Creature::with_chromosome(vec![0x1000]);
+        let mut stale = Weighted::new(&config.fitness.weighting);
+        stale.insert("b", 100.0);
+        creature.set_fitness(stale);
+        let mut sketches = Sketches::new(&config);
+        let creature = composite.assess(creature, &mut sketches, Arc::new(config.clone()));
+        let fitness = creature.fitness().unwrap();
+        // neither the stale score nor the first component's is counted twice
+        assert_eq!(fitness.get("a"), Some(&1.0));
+        assert_eq!(fitness.get("b"), None);
+    }
+
+    #[test]
+    fn test_reach_target() {
+        let mut config = Config::default();
+        config.fitness.weighting = "target_missed + bad_region_hits".to_string();
+        config.roper.target_address = Some(Address::Literal(0x1000));
+        let mut creature = bare::Creature::with_chromosome(vec![0x1000]);
+        creature.profile = Some(Profile {
+            cpu_errors: vec![None, None, None, None],
+            targets_reached: vec![true, false, true, true],
+            bad_region_hits: vec![0, 6, 0, 2],
+            ..Default::default()
+        });
+        let mut sketches = Sketches::new(&config);
+        let creature = reach_target_ff(creature, &mut sketches, Arc::new(config));
+        let fitness = creature.fitness().unwrap();
+        assert_eq!(fitness.get("target_missed"), Some(&0.25));
+        assert_eq!(fitness.get("bad_region_hits"), Some(&2.0));
     }
 }
@@ -0,0 +1,897 @@
+use std::fmt::Debug;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use hashbrown::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::configure::GadgetFilter;
+use crate::emulator::loader::MemoryImage;
+use crate::emulator::profiler::Profile;
+use crate::error::Error;
+use crate::logger::ROPER;
+use crate::util::architecture::Perms;
+
+/// The most instructions we're willing to read while looking for the
+/// `ret` that ends a gadget.
+const MAX_GADGET_INSTS: usize = 16;
+/// Enough bytes to hold `MAX_GADGET_INSTS` x86 instructions of ordinary length.
+const MAX_GADGET_BYTES: usize = 64;
+
+/// Returns the number of data words that the gadget at `addr` pops from
+/// the stack before returning, not counting the return address itself.
+/// Returns `None` if the gadget can't be disassembled, doesn't end in a
+/// plain `ret`, or manipulates the stack pointer in a way we don't model.
+pub fn stack_delta(memory: &MemoryImage, addr: u64) -> Option<usize> {
+    let bytes = memory.read(addr, MAX_GADGET_BYTES, None)?;
+    let insts = memory
+        .disasm
+        .as_ref()?
+        .disas(bytes, addr, Some(MAX_GADGET_INSTS))
+        .ok()?;
+    let insts = insts
+        .iter()
+        .map(|i| {
+            (
+                i.mnemonic().unwrap_or("").to_string(),
+                i.op_str().unwrap_or("").to_string(),
+            )
+        })
+        .collect::<Vec<(String, String)>>();
+    stack_delta_of_insts(&insts, memory.word_size)
+}
+
+/// Rewrites an x86 instruction, as a mnemonic and operand string, from AT&T
+/// syntax into the Intel syntax that `stack_delta_of_insts` parses: the
+/// operands are put destination first and stripped of their sigils, and
+/// the size suffix is dropped from the mnemonics it looks for. AT&T syntax
+/// is recognized by those sigils, so Intel syntax passes through as it is.
+fn intel_syntax(mnemonic: &str, op_str: &str) -> (String, String) {
+    if !op_str.contains('%') && !op_str.contains('$') {
+        return (mnemonic.to_string(), op_str.to_string());
+    }
+    let mnemonic = match mnemonic {
+        "addq" | "addl" | "addw" => "add",
+        "popq" | "popl" | "popw" => "pop",
+        "retq" | "retl" | "retw" => "ret",
+        m => m,
+    };
+    let op_str = op_str
+        .split(',')
+        .rev()
+        .map(|op| op.trim().trim_start_matches('$').replace('%', ""))
+        .collect::<Vec<String>>()
+        .join(", ");
+    (mnemonic.to_string(), op_str)
+}
+
+/// The stack delta of a sequence of x86 instructions, given as pairs of
+/// mnemonics and operand strings, as capstone formats them in either the
+/// Intel or the AT&T syntax.
+pub fn stack_delta_of_insts(insts: &[(String, String)], word_size: usize) -> Option<usize> {
+    let is_stack_pointer = |op: &str| op == "rsp" || op == "esp" || op == "sp";
+    let mut delta = 0;
+    for (mnemonic, op_str) in insts {
+        let (mnemonic, op_str) = intel_syntax(mnemonic, op_str);
+        let mut operands = op_str.split(',').map(str::trim);
+        let dest = operands.next().unwrap_or("");
+        match mnemonic.as_str() {
+            "ret" | "retq" | "retd" if op_str.is_empty() => return Some(delta),
+            "pop" | "popq" | "popl" if !is_stack_pointer(dest) => delta += 1,
+            "add" if is_stack_pointer(dest) => {
+                let imm = operands.next()?;
+                let imm: u64 = if imm.starts_with("0x") {
+                    u64::from_str_radix(imm.trim_start_matches("0x"), 16).ok()?
+                } else {
+                    imm.parse().ok()?
+                };
+                let imm = imm as usize;
+                if imm % word_size != 0 {
+                    return None;
+                }
+                delta += imm / word_size;
+            }
+            // anything else that touches the stack pointer, or transfers
+            // control somewhere other than the next frame, is beyond us
+            m if m.starts_with('j')
+                || m.starts_with("call")
+                || m.starts_with("ret")
+                || m.starts_with("push")
+                || m.starts_with("pop")
+                || m == "leave"
+                || m == "enter"
+                || op_str.contains("sp") =>
+            {
+                return None
+            }
+            _ => {}
+        }
+    }
+    // ran out of instructions before reaching a ret
+    None
+}
+
+/// Whether the filter admits the gadget at `addr`. Gadgets that can't be
+/// disassembled are never admitted.
+pub fn is_admissible(memory: &MemoryImage, addr: u64, filter: &GadgetFilter) -> bool {
+    let max_bytes = filter.max_bytes.unwrap_or(MAX_GADGET_BYTES);
+    let max_insts = filter.max_insts.unwrap_or(MAX_GADGET_INSTS);
+    let insts = memory
+        .read(addr, max_bytes, None)
+        .and_then(|bytes| {
+            memory
+                .disasm
+                .as_ref()?
+                .disas(bytes, addr, Some(max_insts))
+                .ok()
+        })
+        .map(|insts| {
+            insts
+                .iter()
+                .map(|i| (i.mnemonic().unwrap_or("").to_string(), i.bytes().len()))
+                .collect::<Vec<(String, usize)>>()
+        });
+    match insts {
+        Some(insts) => admits_insts(&insts, filter),
+        None => false,
+    }
+}
+
+/// Whether the filter admits a gadget consisting of the given x86
+/// instructions, as pairs of mnemonics and lengths in bytes, in order.
+/// Only the instructions up to the first that transfers control are
+/// considered.
+pub fn admits_insts(insts: &[(String, usize)], filter: &GadgetFilter) -> bool {
+    let max_bytes = filter.max_bytes.unwrap_or(MAX_GADGET_BYTES);
+    let max_insts = filter.max_insts.unwrap_or(MAX_GADGET_INSTS);
+    let mut num_bytes = 0;
+    for (count, (mnemonic, size)) in insts.iter().enumerate() {
+        num_bytes += size;
+        if count + 1 > max_insts || num_bytes > max_bytes {
+            return false;
+        }
+        if filter
+            .forbidden_mnemonics
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(mnemonic))
+        {
+            return false;
+        }
+        if mnemonic.starts_with("ret") {
+            return true;
+        }
+        if mnemonic.starts_with("call") {
+            return filter.allow_calls;
+        }
+        if mnemonic.starts_with('j')
+            || mnemonic.starts_with("int")
+            || mnemonic.starts_with("loop")
+            || mnemonic.starts_with("sys")
+        {
+            return false;
+        }
+    }
+    // ran out of instructions before the gadget ended
+    false
+}
+
+/// The longest of the x86 instructions that can end a discovered gadget.
+const MAX_TERMINATOR_BYTES: usize = 3;
+
+/// Whether the bytes begin with an x86 instruction that can end a gadget:
+/// a `ret`, with or without an immediate, or a `jmp` or `call` through a
+/// register.
+pub fn is_x86_terminator(bytes: &[u8]) -> bool {
+    match bytes {
+        [0xc3, ..] | [0xc2, _, _, ..] => true,
+        [0xff, modrm, ..] => {
+            let reg = (modrm >> 3) & 7;
+            modrm >> 6 == 3 && (reg == 2 || reg == 4)
+        }
+        _ => false,
+    }
+}
+
+fn transfers_control(mnemonic: &str) -> bool {
+    mnemonic.starts_with("ret")
+        || mnemonic.starts_with("call")
+        || mnemonic.starts_with('j')
+        || mnemonic.starts_with("int")
+        || mnemonic.starts_with("loop")
+        || mnemonic.starts_with("sys")
+}
+
+/// A gadget found by `GadgetCatalog::discover`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Gadget {
+    pub address: u64,
+    pub bytes: Vec<u8>,
+    /// The gadget's instructions, separated by semicolons.
+    pub disassembly: String,
+    /// The number of data words the gadget pops, as by `stack_delta`, if
+    /// it can be modelled.
+    pub stack_delta: Option<usize>,
+}
+
+/// The formats in which a `GadgetCatalog` can be exported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CatalogFormat {
+    /// A JSON list of every gadget's address, bytes, disassembly and stack
+    /// delta.
+    Json,
+    /// A line for each gadget, as ROPgadget prints them, like
+    /// `0x0000000000401234 : pop rdi ; ret`.
+    Text,
+}
+
+impl CatalogFormat {
+    /// JSON if the path ends in `.json`, and text otherwise.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension() {
+            Some(ext) if ext == "json" => Self::Json,
+            _ => Self::Text,
+        }
+    }
+}
+
+/// The distinct gadgets found in the executable segments of a binary.
+#[derive(Clone, Debug, Default)]
+pub struct GadgetCatalog {
+    pub gadgets: Vec<Gadget>,
+    /// The word size of the binary, in bytes.
+    pub word_size: usize,
+}
+
+impl GadgetCatalog {
+    /// Search the executable segments for gadgets, the way ROPgadget and
+    /// its kin do. Each return, indirect jump or indirect call is taken
+    /// as the end of a gadget, and the bytes before it are decoded from
+    /// every offset in the preceding `window` bytes, so that gadgets
+    /// starting partway into an instruction are found, too. An offset
+    /// yields a gadget if its instructions run, without any other transfer
+    /// of control, into the terminator, and if the filter, when given,
+    /// admits them. Gadgets that decode to the same instructions are kept
+    /// only once, at the lowest address. Only x86 is supported.
+    pub fn discover(
+        memory: &MemoryImage,
+        window: usize,
+        filter: Option<&GadgetFilter>,
+    ) -> Result<Self, Error> {
+        if memory.arch != unicorn::Arch::X86 {
+            return Err(Error::Misc(format!(
+                "Gadget discovery isn't supported on {:?}",
+                memory.arch
+            )));
+        }
+        let disasm = memory
+            .disasm
+            .as_ref()
+            .ok_or_else(|| Error::Misc("No disassembler for the memory image".to_string()))?;
+        let mut seen = HashSet::new();
+        let mut gadgets = Vec::new();
+        for seg in memory.segments().iter().filter(|s| s.is_executable()) {
+            let data = &seg.data;
+            let base = seg.aligned_start();
+            for end in (0..data.len()).filter(|i| is_x86_terminator(&data[*i..])) {
+                let stop = (end + MAX_TERMINATOR_BYTES).min(data.len());
+                for start in end.saturating_sub(window)..=end {
+                    let insts = match disasm.disas(&data[start..stop], base + start as u64, None) {
+                        Ok(insts) => insts,
+                        Err(_) => continue,
+                    };
+                    let mut listing = Vec::new();
+                    let mut length = 0;
+                    let mut reached = false;
+                    for inst in insts.iter() {
+                        let mnemonic = inst.mnemonic().unwrap_or("").to_string();
+                        let op_str = inst.op_str().unwrap_or("").to_string();
+                        let size = inst.bytes().len();
+                        let at_end = inst.address() == base + end as u64;
+                        let ends_early = !at_end && transfers_control(&mnemonic);
+                        length += size;
+                        listing.push((mnemonic, op_str, size));
+                        if at_end || ends_early || inst.address() > base + end as u64 {
+                            reached = at_end;
+                            break;
+                        }
+                    }
+                    if !reached {
+                        continue;
+                    }
+                    if let Some(filter) = filter {
+                        let sizes = listing
+                            .iter()
+                            .map(|(m, _, size)| (m.clone(), *size))
+                            .collect::<Vec<(String, usize)>>();
+                        if !admits_insts(&sizes, filter) {
+                            continue;
+                        }
+                    }
+                    let disassembly = listing
+                        .iter()
+                        .map(|(m, o, _)| {
+                            if o.is_empty() {
+                                m.clone()
+                            } else {
+                                format!("{} {}", m, o)
+                            }
+                        })
+                        .collect::<Vec<String>>()
+                        .join(" ; ");
+                    if seen.insert(disassembly.clone()) {
+                        let insts =
+                            listing
+                                .into_iter()
+                                .map(|(m, o, _)| (m, o))
+                                .collect::<Vec<(String, String)>>();
+                        gadgets.push(Gadget {
+                            address: base + start as u64,
+                            bytes: data[start..start + length].to_vec(),
+                            disassembly,
+                            stack_delta: stack_delta_of_insts(&insts, memory.word_size),
+                        });
+                    }
+                }
+            }
+        }
+        gadgets.sort_by_key(|g| g.address);
+        Ok(Self {
+            gadgets,
+            word_size: memory.word_size,
+        })
+    }
+
+    /// Write the catalog to `path`, in the given format.
+    pub fn export<P: AsRef<Path> + Debug>(
+        &self,
+        path: P,
+        format: CatalogFormat,
+    ) -> Result<(), Error> {
+        let mut writer = BufWriter::new(fs::File::create(&path)?);
+        match format {
+            CatalogFormat::Json => serde_json::to_writer(&mut writer, &self.gadgets)?,
+            CatalogFormat::Text => {
+                let width = self.word_size * 2;
+                for gadget in self.gadgets.iter() {
+                    writeln!(
+                        writer,
+                        "0x{:0width$x} : {}",
+                        gadget.address,
+                        gadget.disassembly,
+                        width = width
+                    )?;
+                }
+            }
+        }
+        writer.flush()?;
+        log::info!(
+            target: ROPER,
+            "Exported {} gadgets to {:?}",
+            self.gadgets.len(),
+            path
+        );
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.gadgets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gadgets.is_empty()
+    }
+
+    pub fn addresses(&self) -> Vec<u64> {
+        self.gadgets.iter().map(|g| g.address).collect()
+    }
+}
+
+/// Calculate the stack deltas of every gadget in the soup that we're able
+/// to model.
+pub fn stack_deltas(memory: &MemoryImage, soup: &[u64]) -> HashMap<u64, usize> {
+    soup.iter()
+        .filter_map(|addr| stack_delta(memory, *addr).map(|d| (*addr, d)))
+        .collect()
+}
+
+/// The number of words in the payload that point into executable memory,
+/// and so might be the entry point of a gadget. A chain with none at all
+/// will crash on its first return.
+pub fn count_executable_words(memory: &MemoryImage, payload: &[u64]) -> usize {
+    payload
+        .iter()
+        .filter(|w| {
+            memory
+                .perm_of_addr(**w)
+                .map(|p| p.intersects(Perms::EXEC))
+                .unwrap_or(false)
+        })
+        .count()
+}
+
+/// Lays out a chain so that each gadget with a known stack delta is
+/// followed by exactly as many data words as it pops. Words following a
+/// gadget are used as its data, unless they're themselves known gadgets,
+/// in which case `filler` is used instead. Words that aren't known gadgets
+/// and aren't needed as data are left where they are.
+pub fn lay_out(chain: &[u64], deltas: &HashMap<u64, usize>, filler: u64) -> Vec<u64> {
+    let mut payload = Vec::with_capacity(chain.len());
+    let mut words = chain.iter().peekable();
+    while let Some(word) = words.next() {
+        payload.push(*word);
+        if let Some(delta) = deltas.get(word) {
+            for _ in 0..*delta {
+                match words.peek() {
+                    Some(w) if !deltas.contains_key(*w) => payload.push(*words.next().unwrap()),
+                    _ => payload.push(filler),
+                }
+            }
+        }
+    }
+    payload
+}
+
+/// Assigns each gadget in a finite catalog (the soup, typically) a dense
+/// index, so that sets of gadgets can be stored as `GadgetBitSet`s.
+#[derive(Clone, Debug, Default)]
+pub struct GadgetIndex {
+    addresses: Vec<u64>,
+    indices: HashMap<u64, usize>,
+}
+
+impl GadgetIndex {
+    pub fn new(catalog: &[u64]) -> Self {
+        let mut index = Self::default();
+        for addr in catalog {
+            if !index.indices.contains_key(addr) {
+                index.indices.insert(*addr, index.addresses.len());
+                index.addresses.push(*addr);
+            }
+        }
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    pub fn index_of(&self, addr: u64) -> Option<usize> {
+        self.indices.get(&addr).cloned()
+    }
+
+    /// The index of the gadget, which is added to the catalog if it isn't
+    /// already there.
+    pub fn insert(&mut self, addr: u64) -> usize {
+        if let Some(i) = self.index_of(addr) {
+            return i;
+        }
+        let i = self.addresses.len();
+        self.indices.insert(addr, i);
+        self.addresses.push(addr);
+        i
+    }
+
+    /// Converts a set of gadget addresses to a bitset. Addresses that
+    /// aren't in the catalog are left out.
+    pub fn to_bitset<'a, I: IntoIterator<Item = &'a u64>>(&self, addresses: I) -> GadgetBitSet {
+        let mut bits = GadgetBitSet::with_capacity(self.len());
+        for i in addresses.into_iter().filter_map(|a| self.index_of(*a)) {
+            bits.insert(i);
+        }
+        bits
+    }
+
+    pub fn to_addresses(&self, bits: &GadgetBitSet) -> HashSet<u64> {
+        bits.iter().map(|i| self.addresses[i]).collect()
+    }
+
+    /// The gadgets executed in each run of the profile, as bitsets.
+    pub fn gadgets_executed(&self, profile: &Profile) -> Vec<GadgetBitSet> {
+        profile
+            .gadgets_executed
+            .iter()
+            .map(|executed| self.to_bitset(executed.keys()))
+            .collect()
+    }
+}
+
+/// A set of gadgets, represented by their indices in a `GadgetIndex`.
+/// Much more compact than a set of addresses, when the gadgets executed by
+/// a population overlap, and cheap to union and intersect.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GadgetBitSet(Vec<u64>);
+
+// Sets are compared and hashed by their blocks up to the last non-empty
+// one, since, e.g., intersecting can leave empty blocks at the end.
+impl PartialEq for GadgetBitSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.blocks() == other.blocks()
+    }
+}
+
+impl Eq for GadgetBitSet {}
+
+impl Hash for GadgetBitSet {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.blocks().hash(state)
+    }
+}
+
+impl GadgetBitSet {
+    fn blocks(&self) -> &[u64] {
+        let len = self.0.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+        &self.0[..len]
+    }
+
+    pub fn with_capacity(n: usize) -> Self {
+        Self(vec![0; (n + 63) / 64])
+    }
+
+    pub fn insert(&mut self, i: usize) {
+        let block = i / 64;
+        if block >= self.0.len() {
+            self.0.resize(block + 1, 0);
+        }
+        self.0[block] |= 1_u64 << (i % 64);
+    }
+
+    pub fn contains(&self, i: usize) -> bool {
+        self.0
+            .get(i / 64)
+            .map(|block| block & (1_u64 << (i % 64)) != 0)
+            .unwrap_or(false)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.iter().map(|block| block.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|block| *block == 0)
+    }
+
+    pub fn union_with(&mut self, other: &Self) {
+        if other.0.len() > self.0.len() {
+            self.0.resize(other.0.len(), 0);
+        }
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a |= *b;
+        }
+    }
+
+    pub fn intersect_with(&mut self, other: &Self) {
+        for (i, a) in self.0.iter_mut().enumerate() {
+            *a &= other.0.get(i).cloned().unwrap_or(0);
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut bits = self.clone();
+        bits.union_with(other);
+        bits
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut bits = self.clone();
+        bits.intersect_with(other);
+        bits
+    }
+
+    /// The number of gadgets in this set that aren't in the other.
+    pub fn difference_len(&self, other: &Self) -> usize {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(i, a)| (a & !other.0.get(i).cloned().unwrap_or(0)).count_ones() as usize)
+            .sum()
+    }
+
+    /// The indices of the gadgets in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().enumerate().flat_map(|(b, block)| {
+            (0..64_usize)
+                .filter(move |i| block & (1_u64 << i) != 0)
+                .map(move |i| b * 64 + i)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::DisassemblySyntax;
+    use crate::disassembler::Disassembler;
+
+    use super::*;
+
+    fn insts(listing: &[(&str, &str)]) -> Vec<(String, String)> {
+        listing
+            .iter()
+            .map(|(m, o)| (m.to_string(), o.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_stack_delta_of_insts() {
+        let pop_ret = insts(&[("pop", "rdi"), ("ret", "")]);
+        assert_eq!(stack_delta_of_insts(&pop_ret, 8), Some(1));
+
+        let add_rsp = insts(&[
+            ("pop", "rbx"),
+            ("add", "rsp, 0x18"),
+            ("mov", "eax, ebx"),
+            ("ret", ""),
+        ]);
+        assert_eq!(stack_delta_of_insts(&add_rsp, 8), Some(4));
+
+        let bare_ret = insts(&[("ret", "")]);
+        assert_eq!(stack_delta_of_insts(&bare_ret, 8), Some(0));
+
+        let ret_imm = insts(&[("pop", "rdi"), ("ret", "8")]);
+        assert_eq!(stack_delta_of_insts(&ret_imm, 8), None);
+
+        let jump = insts(&[("pop", "rdi"), ("jmp", "rax")]);
+        assert_eq!(stack_delta_of_insts(&jump, 8), None);
+
+        let pivot = insts(&[("xchg", "rax, rsp"), ("ret", "")]);
+        assert_eq!(stack_delta_of_insts(&pivot, 8), None);
+
+        let no_ret = insts(&[("pop", "rdi"), ("nop", "")]);
+        assert_eq!(stack_delta_of_insts(&no_ret, 8), None);
+    }
+
+    #[test]
+    fn test_stack_delta_of_att_insts() {
+        let add_rsp = insts(&[
+            ("popq", "%rbx"),
+            ("addq", "$0x18, %rsp"),
+            ("movl", "%ebx, %eax"),
+            ("retq", ""),
+        ]);
+        assert_eq!(stack_delta_of_insts(&add_rsp, 8), Some(4));
+
+        let ret_imm = insts(&[("popq", "%rdi"), ("retq", "$8")]);
+        assert_eq!(stack_delta_of_insts(&ret_imm, 8), None);
+
+        let pivot = insts(&[("xchgq", "%rax, %rsp"), ("retq", "")]);
+        assert_eq!(stack_delta_of_insts(&pivot, 8), None);
+
+        let load = insts(&[("movq", "0x8(%rsp), %rax"), ("retq", "")]);
+        assert_eq!(stack_delta_of_insts(&load, 8), None);
+    }
+
+    #[test]
+    fn test_stack_delta_in_either_syntax() {
+        // pop rdi; add rsp, 0x18; ret
+        let code = [0x5f, 0x48, 0x83, 0xc4, 0x18, 0xc3];
+        for syntax in [DisassemblySyntax::Intel, DisassemblySyntax::Att].iter() {
+            let disassembler =
+                Disassembler::new(unicorn::Arch::X86, unicorn::Mode::MODE_64, *syntax).unwrap();
+            let listing = disassembler
+                .disas(&code, 0x1000, None)
+                .unwrap()
+                .iter()
+                .map(|i| {
+                    (
+                        i.mnemonic().unwrap_or("").to_string(),
+                        i.op_str().unwrap_or("").to_string(),
+                    )
+                })
+                .collect::<Vec<(String, String)>>();
+            assert_eq!(stack_delta_of_insts(&listing, 8), Some(4), "{:?}", syntax);
+        }
+    }
+
+    #[test]
+    fn test_admits_insts() {
+        let insts = |listing: &[(&str, usize)]| {
+            listing
+                .iter()
+                .map(|(m, n)| (m.to_string(), *n))
+                .collect::<Vec<(String, usize)>>()
+        };
+        let filter = GadgetFilter {
+            max_insts: Some(3),
+            max_bytes: Some(8),
+            allow_calls: false,
+            forbidden_mnemonics: vec!["hlt".to_string()],
+        };
+        let pop_ret = insts(&[("pop", 1), ("ret", 1)]);
+        assert!(admits_insts(&pop_ret, &filter));
+
+        let too_long = insts(&[("pop", 1), ("pop", 1), ("pop", 1), ("ret", 1)]);
+        assert!(!admits_insts(&too_long, &filter));
+        let too_wide = insts(&[("movabs", 10), ("ret", 1)]);
+        assert!(!admits_insts(&too_wide, &filter));
+
+        let call = insts(&[("pop", 1), ("call", 2)]);
+        assert!(!admits_insts(&call, &filter));
+        let calls_allowed = GadgetFilter {
+            allow_calls: true,
+            ..filter.clone()
+        };
+        assert!(admits_insts(&call, &calls_allowed));
+
+        let halt = insts(&[("hlt", 1), ("ret", 1)]);
+        assert!(!admits_insts(&halt, &filter));
+        let shouting = GadgetFilter {
+            forbidden_mnemonics: vec!["HLT".to_string()],
+            ..filter.clone()
+        };
+        assert!(!admits_insts(&halt, &shouting));
+        // the gadget ends at the jump, so the ret is never reached
+        let jump = insts(&[("jmp", 2), ("ret", 1)]);
+        assert!(!admits_insts(&jump, &filter));
+        let no_end = insts(&[("pop", 1), ("nop", 1)]);
+        assert!(!admits_insts(&no_end, &filter));
+    }
+
+    #[test]
+    fn test_is_x86_terminator() {
+        assert!(is_x86_terminator(&[0xc3]));
+        assert!(is_x86_terminator(&[0xc2, 0x08, 0x00]));
+        assert!(!is_x86_terminator(&[0xc2, 0x08]));
+        // jmp rax, call rbx
+        assert!(is_x86_terminator(&[0xff, 0xe0]));
+        assert!(is_x86_terminator(&[0xff, 0xd3]));
+        // jmp [rax], inc eax
+        assert!(!is_x86_terminator(&[0xff, 0x20]));
+        assert!(!is_x86_terminator(&[0xff, 0xc0]));
+        assert!(!is_x86_terminator(&[]));
+    }
+
+    #[test]
+    fn test_discover_gadgets() {
+        use crate::configure::RoperConfig;
+        use crate::emulator::loader;
+
+        let config = RoperConfig {
+            arch: unicorn::Arch::X86,
+            mode: unicorn::Mode::MODE_64,
+            binary_path: "/bin/sh".to_string(),
+            ..Default::default()
+        };
+        let _ = loader::load_from_path(&config, true);
+        let memory = loader::get_static_memory_image();
+        let catalog = GadgetCatalog::discover(memory, 8, None).expect("Failed to discover gadgets");
+        assert!(!catalog.is_empty());
+        let mut seen = HashSet::new();
+        for gadget in catalog.gadgets.iter() {
+            assert!(seen.insert(gadget.disassembly.clone()));
+            assert_eq!(
+                memory.read(gadget.address, gadget.bytes.len(), None),
+                Some(&gadget.bytes[..])
+            );
+            assert!(gadget.bytes.len() <= 8 + MAX_TERMINATOR_BYTES);
+        }
+    }
+
+    #[test]
+    fn test_export_catalog() {
+        let catalog = GadgetCatalog {
+            gadgets: vec![
+                Gadget {
+                    address: 0x401234,
+                    bytes: vec![0x5f, 0xc3],
+                    disassembly: "pop rdi ; ret".to_string(),
+                    stack_delta: Some(1),
+                },
+                Gadget {
+                    address: 0x401300,
+                    bytes: vec![0xff, 0xe0],
+                    disassembly: "jmp rax".to_string(),
+                    stack_delta: None,
+                },
+            ],
+            word_size: 8,
+        };
+        // unique to this process, so that concurrent test runs don't
+        // clobber each other's files
+        let dir = std::env::temp_dir().join(format!(
+            "berbalang_test_export_catalog_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+
+        let text = dir.join("berbalang_test_catalog.txt");
+        catalog
+            .export(&text, CatalogFormat::from_path(&text))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&text).unwrap(),
+            "0x0000000000401234 : pop rdi ; ret\n0x0000000000401300 : jmp rax\n"
+        );
+
+        let json = dir.join("berbalang_test_catalog.json");
+        catalog
+            .export(&json, CatalogFormat::from_path(&json))
+            .unwrap();
+        let gadgets: Vec<Gadget> =
+            serde_json::from_str(&fs::read_to_string(&json).unwrap()).unwrap();
+        assert_eq!(gadgets, catalog.gadgets);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lay_out() {
+        let mut deltas = HashMap::new();
+        deltas.insert(0x1000, 2);
+        deltas.insert(0x2000, 0);
+        deltas.insert(0x3000, 1);
+
+        let chain = vec![0x1000, 7, 0x2000, 0x3000, 8, 9, 0x1000];
+        let payload = lay_out(&chain, &deltas, 0xf111);
+        assert_eq!(
+            payload,
+            vec![0x1000, 7, 0xf111, 0x2000, 0x3000, 8, 9, 0x1000, 0xf111, 0xf111]
+        );
+    }
+
+    #[test]
+    fn test_gadget_bitset_round_trip() {
+        let catalog = (0..200).map(|i| 0x1000 + i * 0x10).collect::<Vec<u64>>();
+        let index = GadgetIndex::new(&catalog);
+        assert_eq!(index.len(), 200);
+
+        let a = [0x1000, 0x1010, 0x1000 + 130 * 0x10]
+            .iter()
+            .cloned()
+            .collect::<HashSet<u64>>();
+        let b = [0x1010, 0x1000 + 199 * 0x10, 0xdead_beef]
+            .iter()
+            .cloned()
+            .collect::<HashSet<u64>>();
+        let bits_a = index.to_bitset(&a);
+        let bits_b = index.to_bitset(&b);
+        assert_eq!(index.to_addresses(&bits_a), a);
+        // addresses outside the catalog are dropped
+        assert_eq!(bits_b.len(), 2);
+
+        let union = index.to_addresses(&bits_a.union(&bits_b));
+        let expected = a.union(&b).cloned().filter(|x| *x != 0xdead_beef);
+        assert_eq!(union, expected.collect::<HashSet<u64>>());
+        let intersection = index.to_addresses(&bits_a.intersection(&bits_b));
+        assert_eq!(
+            intersection,
+            [0x1010].iter().cloned().collect::<HashSet<u64>>()
+        );
+        assert!(bits_a.contains(130));
+        assert!(!bits_a.contains(131));
+        assert!(!bits_a.contains(10_000));
+        assert_eq!(bits_a.difference_len(&bits_b), 2);
+        assert_eq!(bits_b.difference_len(&bits_a), 1);
+    }
+
+    #[test]
+    fn test_gadget_bitset_ignores_empty_blocks() {
+        let mut short = GadgetBitSet::default();
+        short.insert(3);
+        let mut long = GadgetBitSet::with_capacity(256);
+        long.insert(3);
+        long.insert(200);
+        long.intersect_with(&short);
+        assert_eq!(long, short);
+
+        let hash = |bits: &GadgetBitSet| {
+            let mut hasher = fnv::FnvHasher::default();
+            bits.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&long), hash(&short));
+        assert_eq!(GadgetBitSet::with_capacity(64), GadgetBitSet::default());
+    }
+
+    #[test]
+    fn test_gadget_index_insert() {
+        let mut index = GadgetIndex::new(&[0x1000, 0x2000]);
+        assert_eq!(index.insert(0x2000), 1);
+        assert_eq!(index.insert(0x3000), 2);
+        assert_eq!(index.index_of(0x3000), Some(2));
+        assert_eq!(index.len(), 3);
+    }
+}
@@ -2,27 +2,35 @@ use std::fs::File;
 use std::hash::Hash;
 use std::hash::Hasher;
 use std::io::{BufRead, BufReader};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::spawn;
 
 use non_dominated_sort::DominanceOrd;
 use rand::Rng;
 use unicorn::Cpu;
 
-use crate::configure::{Config, Selection};
+use crate::configure::{Config, RoperConfig, Selection};
 use crate::error::Error;
+use crate::evolution::alps::Alps;
+use crate::evolution::engine::Epoch;
+use crate::evolution::export;
+use crate::evolution::map_elites::MapElites;
 use crate::evolution::metropolis::Metropolis;
 use crate::evolution::pareto_roulette::Roulette;
 use crate::evolution::population::pier::Pier;
-use crate::fitness::Weighted;
-use crate::observer::Observer;
+use crate::fitness::{dominance_cmp, FitnessStatistics, Standardizer, Weighted};
+use crate::logger::ROPER;
+use crate::observer::{Observer, Window};
 use crate::ontogenesis::FitnessFn;
+use crate::roper::coevolution::Coevolution;
+use crate::roper::gadget::{GadgetBitSet, GadgetIndex};
+use crate::roper::novelty::NoveltyArchive;
 use crate::util::architecture::Perms;
 use crate::util::count_min_sketch::CountMinSketch;
 use crate::util::random::hash_seed_rng;
 use crate::{
-    emulator::loader,
-    evolution::{tournament::Tournament, Phenome},
+    emulator::{loader, profiler::Profile},
+    evolution::{tournament::Tournament, Genome, Phenome},
 };
 
 /// The `analysis` module contains the reporting function passed to the observation
@@ -30,8 +38,25 @@ use crate::{
 mod analysis;
 
 /// Generic fitness functions, which can be used for either push or bare
-/// mode ROPER.
-mod fitness_functions;
+/// mode ROPER. Functions of your own can be added with
+/// `fitness_functions::register_fitness_function`.
+pub mod fitness_functions;
+
+/// The `gadget` module models the stack effects of the gadgets in the soup,
+/// so that chains can be laid out with the data each gadget expects.
+pub mod gadget;
+
+/// The `behavior` module describes what a creature did, as recorded in
+/// its profile, by a feature vector.
+pub mod behavior;
+
+/// The `coevolution` module pits chains against an evolving population of
+/// the register states they're run from.
+pub mod coevolution;
+
+/// The `novelty` module scores creatures by how different their behaviour
+/// is from what's been seen before.
+pub mod novelty;
 
 /// The `creature` module contains the implementation of the `Genome` and `Phenome`
 /// traits associated with `roper` mode.
@@ -40,6 +65,10 @@ pub mod bare;
 /// A ROPER-specific implementation of Spector's PUSH VM.
 pub mod push;
 
+/// How many addresses may be drawn for each gadget in the soup, when
+/// they're sampled from executable memory and filtered.
+const MAX_DRAWS_PER_GADGET: usize = 100;
+
 /// load binary before calling this function
 pub fn init_soup(config: &mut Config) -> Result<(), Error> {
     let mut soup = Vec::new();
@@ -50,41 +79,114 @@ pub fn init_soup(config: &mut Config) -> Result<(), Error> {
             .values()
             .for_each(|w| w.vals.iter().for_each(|word| soup.push(*word)))
     }
+    let filter = config.roper.gadget_filter.as_ref();
     if let Some(gadget_file) = config.roper.gadget_file.as_ref() {
         // parse the gadget file
         let reader = File::open(gadget_file).map(BufReader::new)?;
 
+        let mut gadgets = Vec::new();
         if gadget_file.ends_with(".json") {
-            log::info!("Deserializing soup from {}", gadget_file);
-            soup = serde_json::from_reader(reader)?;
+            log::info!(target: ROPER, "Deserializing soup from {}", gadget_file);
+            soup.clear();
+            gadgets = serde_json::from_reader(reader)?;
         } else {
-            log::info!("Parsing soup from {}", gadget_file);
+            log::info!(target: ROPER, "Parsing soup from {}", gadget_file);
             for line in reader.lines() {
                 let word = line?.parse::<u64>()?;
-                soup.push(word)
+                gadgets.push(word)
             }
         }
+        if let Some(filter) = filter {
+            let memory = loader::get_static_memory_image();
+            let num_gadgets = gadgets.len();
+            gadgets.retain(|addr| gadget::is_admissible(memory, *addr, filter));
+            log::info!(
+                target: ROPER,
+                "The gadget filter admitted {} of {} gadgets from {}",
+                gadgets.len(),
+                num_gadgets,
+                gadget_file
+            );
+        }
+        soup.extend(gadgets);
+    } else if config.roper.discover_gadgets {
+        let memory = loader::get_static_memory_image();
+        let catalog =
+            gadget::GadgetCatalog::discover(memory, config.roper.gadget_search_window, filter)?;
+        log::info!(
+            target: ROPER,
+            "Discovered {} distinct gadgets for the soup",
+            catalog.len()
+        );
+        if let Some(path) = config.roper.gadget_catalog_path.as_ref() {
+            catalog.export(path, gadget::CatalogFormat::from_path(path))?;
+        }
+        soup.extend(catalog.addresses());
     } else if let Some(soup_size) = config.roper.soup_size.as_ref() {
         let memory = loader::get_static_memory_image();
-        for addr in (0..(*soup_size)).map(|i| {
-            let mut hasher = fnv::FnvHasher::default();
-            i.hash(&mut hasher);
-            config.random_seed.hash(&mut hasher);
-            let seed = hasher.finish();
-            memory.random_address(Some(Perms::EXEC), seed)
-        }) {
-            soup.push(addr)
+        // with a filter, keep drawing addresses until there are enough
+        // admissible ones, or we've drawn far too many
+        let max_draws = if filter.is_some() {
+            soup_size * MAX_DRAWS_PER_GADGET
+        } else {
+            *soup_size
+        };
+        let gadgets = (0..max_draws)
+            .map(|i| {
+                let mut hasher = fnv::FnvHasher::default();
+                i.hash(&mut hasher);
+                config.random_seed.hash(&mut hasher);
+                let seed = hasher.finish();
+                memory.random_address(Some(Perms::EXEC), seed)
+            })
+            .filter(|addr| filter.map_or(true, |f| gadget::is_admissible(memory, *addr, f)))
+            .take(*soup_size)
+            .collect::<Vec<u64>>();
+        if gadgets.len() < *soup_size {
+            log::warn!(
+                target: ROPER,
+                "The gadget filter admitted only {} of {} addresses drawn for the soup",
+                gadgets.len(),
+                max_draws
+            );
         }
+        soup.extend(gadgets);
+    }
+    if config.roper.model_stack_deltas {
+        let memory = loader::get_static_memory_image();
+        let deltas = gadget::stack_deltas(memory, &soup);
+        log::info!(
+            target: ROPER,
+            "Modelled the stack deltas of {} of {} gadgets in the soup",
+            deltas.len(),
+            soup.len()
+        );
+        config.roper.stack_deltas = Some(deltas);
     }
     config.roper.soup = Some(soup);
     Ok(())
 }
 
+/// The chain of words that a creature actually has executed, before it's
+/// packed and written onto the stack.
+pub trait Payload {
+    fn payload(&self, config: &RoperConfig) -> Vec<u64>;
+}
+
 pub struct Sketches {
     pub register_error: CountMinSketch,
     pub memory_writes: CountMinSketch,
     pub genetic: CountMinSketch,
     pub addresses_visited: CountMinSketch,
+    pub edges: CountMinSketch,
+    /// Indexes every gadget executed so far, as it's first seen.
+    pub gadget_index: GadgetIndex,
+    /// The union of the gadgets executed by every creature so far.
+    pub gadgets_seen: GadgetBitSet,
+    pub novelty: Option<NoveltyArchive>,
+    pub standardizer: Option<Standardizer>,
+    /// Shared with the observer, which logs it with each report.
+    pub fitness_statistics: Arc<Mutex<FitnessStatistics>>,
 }
 
 impl Sketches {
@@ -93,49 +195,99 @@ impl Sketches {
             register_error: CountMinSketch::new(config),
             memory_writes: CountMinSketch::new(config),
             addresses_visited: CountMinSketch::new(config),
+            edges: CountMinSketch::new(config),
+            gadget_index: GadgetIndex::new(config.roper.soup.as_deref().unwrap_or(&[])),
+            gadgets_seen: GadgetBitSet::default(),
             genetic: CountMinSketch::new(config),
+            novelty: config.fitness.novelty.as_ref().map(NoveltyArchive::new),
+            standardizer: config
+                .fitness
+                .standardization
+                .as_ref()
+                .map(Standardizer::new),
+            fitness_statistics: Arc::new(Mutex::new(FitnessStatistics::new(
+                config.fitness.statistics_window(),
+            ))),
+        }
+    }
+
+    /// Record the fitness's components in the running statistics, and
+    /// standardize them, if standardization is enabled. This should be
+    /// done once the fitness is complete.
+    pub fn observe_fitness(&self, fitness: &mut Weighted<'static>) {
+        let mut statistics = self
+            .fitness_statistics
+            .lock()
+            .expect("poisoned fitness statistics");
+        statistics.observe(fitness);
+        if let Some(ref standardizer) = self.standardizer {
+            standardizer.standardize(&statistics, fitness);
         }
     }
 }
 
+/// Penalize a chain that didn't execute a single gadget, in the
+/// `non_executable` fitness component. The component is always scored, so
+/// that a weighting that names it can be evaluated, and is 0 when the
+/// penalty is disabled.
+pub fn penalize_non_executable(
+    profile: Option<&Profile>,
+    fitness: &mut Weighted<'static>,
+    config: &Config,
+) {
+    let penalty = config.fitness.non_executable_penalty;
+    let executed = match profile {
+        // without the block log, we can't tell which gadgets ran
+        Some(profile) if !config.roper.record_basic_blocks => profile.executable,
+        Some(profile) => profile.executed_any_gadgets(),
+        None => false,
+    };
+    fitness.insert("non_executable", if executed { 0.0 } else { penalty });
+}
+
 type Fitness<'a> = Weighted<'a>; //Pareto<'static>;
 
 fn prepare_bare<C: 'static + Cpu<'static>>(
     config: &Config,
-) -> (Observer<bare::Creature>, bare::evaluation::Evaluator<C>) {
+) -> Result<(Observer<bare::Creature>, bare::evaluation::Evaluator<C>), Error> {
     let fitness_function: FitnessFn<bare::Creature, Sketches, Config> =
-        fitness_functions::get_fitness_function(&config.fitness.function);
-    let observer = Observer::spawn(&config, Box::new(analysis::report_fn));
+        fitness_functions::get_fitness_function(&config.fitness.function, config)?;
     let evaluator = bare::evaluation::Evaluator::spawn(&config, fitness_function);
-    (observer, evaluator)
+    let statistics = evaluator.fitness_statistics();
+    let report_fn = move |window: &Window<bare::Creature>, counter: usize, config: &Config| {
+        analysis::report_fn(window, counter, config);
+        analysis::report_fitness_statistics(&statistics, config);
+    };
+    let observer = Observer::spawn(&config, Box::new(report_fn));
+    Ok((observer, evaluator))
 }
 
 fn prepare_push<C: 'static + Cpu<'static>>(
     config: &Config,
-) -> (Observer<push::Creature>, push::evaluation::Evaluator<C>) {
+) -> Result<(Observer<push::Creature>, push::evaluation::Evaluator<C>), Error> {
     let fitness_function: FitnessFn<push::Creature, Sketches, Config> =
-        fitness_functions::get_fitness_function(&config.fitness.function);
-    let observer: Observer<push::Creature> =
-        Observer::spawn(&config, Box::new(analysis::report_fn));
+        fitness_functions::get_fitness_function(&config.fitness.function, config)?;
     let evaluator = push::evaluation::Evaluator::spawn(&config, fitness_function);
-    (observer, evaluator)
+    let statistics = evaluator.fitness_statistics();
+    let report_fn = move |window: &Window<push::Creature>, counter: usize, config: &Config| {
+        analysis::report_fn(window, counter, config);
+        analysis::report_fitness_statistics(&statistics, config);
+    };
+    let observer: Observer<push::Creature> = Observer::spawn(&config, Box::new(report_fn));
+    Ok((observer, evaluator))
 }
 
 pub struct CreatureDominanceOrd;
 
 impl DominanceOrd<bare::Creature> for CreatureDominanceOrd {
     fn dominance_ord(&self, a: &bare::Creature, b: &bare::Creature) -> std::cmp::Ordering {
-        a.fitness()
-            .partial_cmp(&b.fitness())
-            .unwrap_or(std::cmp::Ordering::Equal)
+        dominance_cmp(a.fitness(), b.fitness())
     }
 }
 
 impl DominanceOrd<push::Creature> for CreatureDominanceOrd {
     fn dominance_ord(&self, a: &push::Creature, b: &push::Creature) -> std::cmp::Ordering {
-        a.fitness()
-            .partial_cmp(&b.fitness())
-            .unwrap_or(std::cmp::Ordering::Equal)
+        dominance_cmp(a.fitness(), b.fitness())
     }
 }
 
@@ -161,7 +313,51 @@ pub fn run(mut config: Config) {
     }
 }
 
+/// Where the population is exported when a run comes to an end.
+fn final_population_path(config: &Config) -> String {
+    format!("{}/final_population.json", config.data_directory())
+}
+
+/// Evolve the world until the run comes to an end, and then export its
+/// population to `final_population_path`.
+fn evolve_to_completion<W, P>(mut world: W, config: &Config) -> W
+where
+    W: Epoch<P>,
+    P: Phenome + Genome,
+{
+    while crate::keep_going() {
+        world = world.evolve();
+    }
+    let population = world.population();
+    if let Err(e) = export::export_population(
+        population.into_iter(),
+        config,
+        final_population_path(config),
+    ) {
+        log::error!(
+            target: ROPER,
+            "Failed to export the final population: {:?}",
+            e
+        );
+    }
+    world
+}
+
 pub fn launch<C: 'static + Cpu<'static>>(config: Config) {
+    if let Err(e) = crate::emulator::hatchery::check_cpu::<C>(&config.roper) {
+        log::error!(
+            target: ROPER,
+            "Check roper.arch and roper.mode in the config: {:?}",
+            e
+        );
+        return;
+    }
+    if let Err(e) = evolve::<C>(config) {
+        log::error!(target: ROPER, "Failed to set up the evolution: {:?}", e);
+    }
+}
+
+fn evolve<C: 'static + Cpu<'static>>(config: Config) -> Result<(), Error> {
     match config.selection {
         Selection::Tournament => {
             // TODO: Refactor this!!
@@ -175,16 +371,14 @@ pub fn launch<C: 'static + Cpu<'static>>(config: Config) {
                     config.island_id = i;
                     config.set_data_directory();
                     config.random_seed = rng.gen::<u64>();
-                    let (observer, evaluator) = prepare_push(&config);
+                    let (observer, evaluator) = prepare_push(&config)?;
                     let pier = pier.clone();
                     let h = spawn(move || {
-                        let mut world =
+                        let world =
                             Tournament::<push::evaluation::Evaluator<C>, push::Creature>::new(
                                 &config, observer, evaluator, pier,
                             );
-                        while crate::keep_going() {
-                            world = world.evolve();
-                        }
+                        evolve_to_completion(world, &config);
                     });
                     handles.push(h);
                 }
@@ -200,16 +394,14 @@ pub fn launch<C: 'static + Cpu<'static>>(config: Config) {
                     config.island_id = i;
                     config.set_data_directory();
                     config.random_seed = rng.gen::<u64>();
-                    let (observer, evaluator) = prepare_bare(&config);
+                    let (observer, evaluator) = prepare_bare(&config)?;
                     let pier = pier.clone();
                     let h = spawn(move || {
-                        let mut world =
+                        let world =
                             Tournament::<bare::evaluation::Evaluator<C>, bare::Creature>::new(
                                 &config, observer, evaluator, pier,
                             );
-                        while crate::keep_going() {
-                            world = world.evolve();
-                        }
+                        evolve_to_completion(world, &config);
                     });
                     handles.push(h);
                 }
@@ -219,24 +411,47 @@ pub fn launch<C: 'static + Cpu<'static>>(config: Config) {
             }
         }
         Selection::Roulette => {
-            let (observer, evaluator) = prepare_bare(&config);
-            let mut world = Roulette::<
+            let (observer, evaluator) = prepare_bare(&config)?;
+            let world = Roulette::<
                 bare::evaluation::Evaluator<C>,
                 bare::Creature,
                 CreatureDominanceOrd,
             >::new(&config, observer, evaluator, CreatureDominanceOrd);
-            while crate::keep_going() {
-                world = world.evolve();
-            }
+            evolve_to_completion(world, &config);
         }
         Selection::Metropolis => {
-            let (observer, evaluator) = prepare_bare(&config);
-            let mut world = Metropolis::<bare::evaluation::Evaluator<C>, bare::Creature>::new(
+            let (observer, evaluator) = prepare_bare(&config)?;
+            let world = Metropolis::<bare::evaluation::Evaluator<C>, bare::Creature>::new(
+                &config, observer, evaluator,
+            );
+            evolve_to_completion(world, &config);
+        }
+        Selection::Alps => {
+            let (observer, evaluator) = prepare_bare(&config)?;
+            let world = Alps::<bare::evaluation::Evaluator<C>, bare::Creature>::new(
                 &config, observer, evaluator,
             );
-            while crate::keep_going() {
-                world = world.evolve();
+            evolve_to_completion(world, &config);
+        }
+        Selection::MapElites => {
+            let (observer, evaluator) = prepare_bare(&config)?;
+            let world = MapElites::<bare::evaluation::Evaluator<C>, bare::Creature>::new(
+                &config, observer, evaluator,
+            );
+            evolve_to_completion(world, &config).dump_grid();
+        }
+        Selection::Coevolution => {
+            let mut config = config.clone();
+            if config.roper.resample > 1 {
+                log::warn!(
+                    target: ROPER,
+                    "roper.resample is ignored under co-evolution, where the parasites supply the samples"
+                );
+                config.roper.resample = 0;
             }
+            let (observer, evaluator) = prepare_bare(&config)?;
+            let world = Coevolution::<C>::new(&config, observer, evaluator);
+            evolve_to_completion(world, &config);
         }
         Selection::Lexicase => unimplemented!("Probably needs an overhaul"),
         // Selection::Lexicase => {
@@ -279,4 +494,34 @@ pub fn launch<C: 'static + Cpu<'static>>(config: Config) {
         //     }
         // }
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_penalize_non_executable() {
+        let mut config = Config::default();
+        config.fitness.weighting = "non_executable".to_string();
+        let unexecuted = Profile::default();
+
+        let mut fitness = Weighted::new(&config.fitness.weighting);
+        penalize_non_executable(Some(&unexecuted), &mut fitness, &config);
+        assert_eq!(fitness.get("non_executable"), Some(&0.0));
+
+        config.fitness.non_executable_penalty = 10.0;
+        let mut fitness = Weighted::new(&config.fitness.weighting);
+        penalize_non_executable(Some(&unexecuted), &mut fitness, &config);
+        assert_eq!(fitness.get("non_executable"), Some(&10.0));
+
+        let executed = Profile {
+            executable: true,
+            ..Default::default()
+        };
+        let mut fitness = Weighted::new(&config.fitness.weighting);
+        penalize_non_executable(Some(&executed), &mut fitness, &config);
+        assert_eq!(fitness.get("non_executable"), Some(&0.0));
+    }
 }
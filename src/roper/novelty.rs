@@ -0,0 +1,115 @@
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+use crate::configure::NoveltyConfig;
+use crate::emulator::profiler::Profile;
+use crate::roper::behavior::{distance, BehaviorDescriptor};
+
+/// An archive of the behaviour descriptors seen so far, against which the
+/// novelty of new behaviours is measured.
+pub struct NoveltyArchive {
+    config: NoveltyConfig,
+    behaviors: VecDeque<Vec<f64>>,
+}
+
+impl NoveltyArchive {
+    pub fn new(config: &NoveltyConfig) -> Self {
+        Self {
+            config: config.clone(),
+            behaviors: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.behaviors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.behaviors.is_empty()
+    }
+
+    /// The mean distance of the behaviour from its `k` nearest neighbours
+    /// in the archive. Anything is maximally novel to an empty archive.
+    pub fn novelty(&self, behavior: &[f64]) -> f64 {
+        if self.behaviors.is_empty() {
+            return 1.0;
+        }
+        let mut distances = self
+            .behaviors
+            .iter()
+            .map(|b| distance(behavior, b))
+            .collect::<Vec<f64>>();
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let k = self.config.k.max(1).min(distances.len());
+        distances[..k].iter().sum::<f64>() / k as f64
+    }
+
+    pub fn insert(&mut self, behavior: Vec<f64>) {
+        self.behaviors.push_back(behavior);
+        while self.behaviors.len() > self.config.archive_size {
+            self.behaviors.pop_front();
+        }
+    }
+
+    /// Measure the novelty of the behaviour recorded in the profile, and
+    /// archive it if it's novel enough.
+    pub fn assess(&mut self, profile: &Profile) -> f64 {
+        let behavior = profile.describe(&self.config.features);
+        let novelty = self.novelty(&behavior);
+        if novelty >= self.config.archive_threshold {
+            self.insert(behavior);
+        }
+        novelty
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::BehaviorFeature;
+
+    use super::*;
+
+    #[test]
+    fn test_novelty_archive() {
+        let config = NoveltyConfig {
+            k: 2,
+            features: vec![BehaviorFeature::ChainLength],
+            archive_size: 3,
+            archive_threshold: 0.1,
+        };
+        let mut archive = NoveltyArchive::new(&config);
+        assert_eq!(archive.novelty(&[0.5]), 1.0);
+
+        archive.insert(vec![0.5]);
+        archive.insert(vec![0.75]);
+        archive.insert(vec![0.0]);
+        // the two nearest neighbours are at distances 0 and 0.25
+        assert_eq!(archive.novelty(&[0.5]), 0.125);
+
+        archive.insert(vec![1.0]);
+        assert_eq!(archive.len(), 3);
+        // the oldest behaviour was dropped
+        assert_eq!(archive.novelty(&[0.5]), 0.375);
+    }
+
+    #[test]
+    fn test_assess_archives_novel_behaviour() {
+        let config = NoveltyConfig {
+            k: 1,
+            features: vec![BehaviorFeature::ChainLength],
+            archive_size: 10,
+            archive_threshold: 0.1,
+        };
+        let mut archive = NoveltyArchive::new(&config);
+        let profile = |rets: usize| Profile {
+            ret_counts: vec![rets],
+            ..Default::default()
+        };
+        assert_eq!(archive.assess(&profile(1)), 1.0);
+        // the same behaviour again isn't novel, and isn't archived
+        assert_eq!(archive.assess(&profile(1)), 0.0);
+        assert_eq!(archive.len(), 1);
+        assert!(archive.assess(&profile(9)) > 0.1);
+        assert_eq!(archive.len(), 2);
+    }
+}
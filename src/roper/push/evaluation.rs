@@ -1,17 +1,19 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use unicorn::Cpu;
 
 use crate::configure::Config;
 use crate::emulator::hatchery::Hatchery;
+use crate::emulator::loader::get_static_memory_image;
 use crate::emulator::profiler::{HasProfile, Profile};
 use crate::emulator::register_pattern::{Register, RegisterPattern};
 use crate::evolution::{Genome, Phenome};
-use crate::fitness::Weighted;
+use crate::fitness::{FitnessStatistics, Weighted};
+use crate::logger::ROPER;
 use crate::ontogenesis::{Develop, FitnessFn};
-use crate::roper::push;
 use crate::roper::push::{register_pattern_to_push_args, Creature, MachineState};
-use crate::roper::Sketches;
+use crate::roper::{gadget, push};
+use crate::roper::{penalize_non_executable, Sketches};
 use crate::util;
 
 pub struct Evaluator<C: Cpu<'static> + 'static> {
@@ -35,6 +37,7 @@ impl<C: 'static + Cpu<'static>> Evaluator<C> {
             util::architecture::random_register_state::<u64, C>(
                 &output_registers,
                 config.random_seed,
+                &config.roper.register_distribution,
             )
         } else {
             util::architecture::constant_register_state::<C>(&output_registers, 0_u64)
@@ -43,7 +46,8 @@ impl<C: 'static + Cpu<'static>> Evaluator<C> {
             hatch_config,
             Arc::new(initial_register_state),
             Arc::new(output_registers),
-        );
+        )
+        .unwrap_or_else(|e| panic!("Failed to start the hatchery: {:?}", e));
 
         let sketches = Sketches::new(&config);
         Self {
@@ -53,6 +57,11 @@ impl<C: 'static + Cpu<'static>> Evaluator<C> {
             fitness_fn: Box::new(fitness_fn),
         }
     }
+
+    /// The running statistics of each fitness component.
+    pub fn fitness_statistics(&self) -> Arc<Mutex<FitnessStatistics>> {
+        self.sketches.fitness_statistics.clone()
+    }
 }
 
 pub fn problem_to_payload(
@@ -80,9 +89,15 @@ impl<C: 'static + Cpu<'static>> Develop<push::Creature> for Evaluator<C> {
 
             // TODO refactor bare roper in a similar fashion. just send the payload,
             // not the whole creature.
+            let memory = get_static_memory_image();
             let mut used_payloads = Vec::new();
             for payload in payloads.into_iter() {
-                if !payload.is_empty() {
+                let viable = if self.config.roper.prefilter {
+                    gadget::count_executable_words(memory, &payload) > 0
+                } else {
+                    !payload.is_empty()
+                };
+                if viable {
                     let profile = self
                         .hatchery
                         .execute(payload.clone(), None)
@@ -101,6 +116,7 @@ impl<C: 'static + Cpu<'static>> Develop<push::Creature> for Evaluator<C> {
             }
             creature.payloads = used_payloads;
             log::debug!(
+                target: ROPER,
                 "Finished developing creature. profile: {:#x?}",
                 creature.profile
             );
@@ -118,7 +134,20 @@ impl<C: 'static + Cpu<'static>> Develop<push::Creature> for Evaluator<C> {
             creature.set_fitness(fitness);
             creature
         } else {
-            (self.fitness_fn)(creature, &mut self.sketches, self.config.clone())
+            let mut creature = (self.fitness_fn)(creature, &mut self.sketches, self.config.clone());
+            if let Some(ref mut archive) = self.sketches.novelty {
+                if let Some(novelty) = creature.profile.as_ref().map(|p| archive.assess(p)) {
+                    if let Some(ref mut fitness) = creature.fitness {
+                        fitness.insert("novelty", novelty);
+                    }
+                }
+            }
+            if let Some(ref mut fitness) = creature.fitness {
+                penalize_non_executable(creature.profile.as_ref(), fitness, &self.config);
+                fitness.clamp(&self.config.fitness.clamp);
+                self.sketches.observe_fitness(fitness);
+            }
+            creature
         }
     }
 
@@ -13,6 +13,7 @@ use crate::configure::Config;
 use crate::emulator::loader;
 use crate::emulator::loader::get_static_memory_image;
 use crate::emulator::register_pattern::RegisterPattern;
+use crate::logger::ROPER;
 use crate::util::architecture::{read_integer, write_integer, Perms};
 
 pub mod evaluation;
@@ -375,7 +376,7 @@ impl Op {
                 if let Exec(a) = mach.pop(&Type::Exec) {
                     if let ExecY = a {
                         // infinite loop detected.
-                        log::trace!("Infinite loop detected. Skipping")
+                        log::trace!(target: ROPER, "Infinite loop detected. Skipping")
                     } else {
                         mach.push(Exec(List(vec![ExecY, a.clone()])));
                         mach.push(Exec(a));
@@ -1229,7 +1230,7 @@ impl MachineState {
         }
 
         while let Some(Val::Exec(op)) = self.pop_opt(&Type::Exec) {
-            log::trace!("[{}] {:x?}", self.counter, op);
+            log::trace!(target: ROPER, "[{}] {:x?}", self.counter, op);
             self.counter += 1;
             if self.counter >= max_steps {
                 break;
@@ -1238,7 +1239,11 @@ impl MachineState {
             op.eval(self)
         }
 
-        log::trace!("Completed execution. Machine state: {:#?}", self);
+        log::trace!(
+            target: ROPER,
+            "Completed execution. Machine state: {:#?}",
+            self
+        );
         // first, take the explicitly-marked gadgets.
         // ensure that this list begins with an executable.
 
@@ -1271,7 +1276,7 @@ impl MachineState {
         }
         payload.reverse();
 
-        log::trace!("Payload: {:#x?}", payload);
+        log::trace!(target: ROPER, "Payload: {:#x?}", payload);
         payload
     }
 }
@@ -1303,9 +1308,13 @@ pub mod creature {
 
     use rand::thread_rng;
 
-    use crate::emulator::profiler::{HasProfile, Profile};
-    use crate::evolution::{Genome, LinearChromosome, Mutation, Phenome};
-    use crate::roper::Fitness;
+    use crate::configure::{BehaviorFeature, RoperConfig};
+    use crate::emulator::profiler::{HasProfile, Profile, ProfileSummary};
+    use crate::emulator::register_pattern::register_diff_table;
+    use crate::error::Error;
+    use crate::evolution::{Genome, LinearChromosome, Mutation, Phenome, StrategyParams};
+    use crate::roper::behavior::{BehaviorDescriptor, ALL_FEATURES};
+    use crate::roper::{Fitness, Payload};
     use crate::util;
     use crate::util::random::hash_seed_rng;
 
@@ -1346,10 +1355,19 @@ pub mod creature {
         pub fitness: Option<Fitness<'static>>,
         pub front: Option<usize>,
         pub num_offspring: usize,
+        pub age: usize,
         pub native_island: usize,
         pub description: Option<String>,
     }
 
+    impl Payload for Creature {
+        /// The payload generated for the first problem, if the creature has
+        /// been developed.
+        fn payload(&self, _config: &RoperConfig) -> Vec<u64> {
+            self.payloads.first().cloned().unwrap_or_default()
+        }
+    }
+
     impl HasProfile for Creature {
         fn profile(&self) -> Option<&Profile> {
             self.profile.as_ref()
@@ -1387,6 +1405,14 @@ pub mod creature {
             self.num_offspring
         }
 
+        fn age(&self) -> usize {
+            self.age
+        }
+
+        fn incr_age(&mut self) {
+            self.age += 1
+        }
+
         fn chromosome(&self) -> &[Self::Allele] {
             &self.chromosome.chromosome
         }
@@ -1399,6 +1425,10 @@ pub mod creature {
             self.native_island
         }
 
+        fn parent_names(&self) -> Vec<String> {
+            self.chromosome.parent_names.clone()
+        }
+
         fn random<H: Hash>(config: &Config, salt: H) -> Self
         where
             Self: Sized,
@@ -1416,6 +1446,7 @@ pub mod creature {
                     parent_names: vec![],
                     name: util::name::random(4, rng.gen::<u64>()),
                     generation: 0,
+                    strategy_params: StrategyParams::initial(config),
                 },
                 tag: rng.gen::<u64>(),
                 payloads: vec![],
@@ -1423,11 +1454,42 @@ pub mod creature {
                 fitness: None,
                 front: None,
                 num_offspring: 0,
+                age: 0,
                 native_island: config.island_id,
                 description: None,
             }
         }
 
+        fn from_chromosome(
+            name: Option<String>,
+            chromosome: Vec<Op>,
+            config: &Config,
+        ) -> Result<Self, Error> {
+            let len = chromosome.len();
+            let mut rng = hash_seed_rng(&chromosome);
+            let name = name.unwrap_or_else(|| util::name::random(4, &chromosome));
+            Ok(Self {
+                chromosome: LinearChromosome {
+                    chromosome,
+                    mutations: vec![None; len],
+                    parentage: vec![],
+                    parent_names: vec![],
+                    name,
+                    generation: 0,
+                    strategy_params: StrategyParams::initial(config),
+                },
+                tag: rng.gen::<u64>(),
+                payloads: vec![],
+                profile: None,
+                fitness: None,
+                front: None,
+                num_offspring: 0,
+                age: 0,
+                native_island: config.island_id,
+                description: None,
+            })
+        }
+
         fn crossover(mates: &[&Self], config: &Config) -> Self
         where
             Self: Sized,
@@ -1445,6 +1507,7 @@ pub mod creature {
                 fitness: None,
                 front: None,
                 num_offspring: 0,
+                age: 0,
                 native_island: 0,
                 description: None,
             }
@@ -1499,9 +1562,34 @@ pub mod creature {
 
         fn is_goal_reached(&self, config: &Config) -> bool {
             self.scalar_fitness(&config.fitness.priority())
-                .map(|p| p - config.fitness.target <= std::f64::EPSILON)
+                .map(|p| config.fitness.is_target_reached(p))
                 .unwrap_or(false)
         }
+
+        fn approx_heap_size(&self) -> usize {
+            self.profile
+                .as_ref()
+                .map(Profile::approx_heap_size)
+                .unwrap_or(0)
+        }
+
+        fn prune_behavior(&mut self, max_bytes: usize) {
+            if let Some(ref mut profile) = self.profile {
+                profile.prune_to_size(max_bytes)
+            }
+        }
+
+        fn profile_summary(&self) -> Option<ProfileSummary> {
+            self.profile.as_ref().map(Profile::summary)
+        }
+
+        fn behavior_descriptor(&self) -> Option<Vec<f64>> {
+            self.profile.as_ref().map(|p| p.describe(&ALL_FEATURES))
+        }
+
+        fn describe_behavior(&self, features: &[BehaviorFeature]) -> Option<Vec<f64>> {
+            self.profile.as_ref().map(|p| p.describe(features))
+        }
     }
 
     impl fmt::Debug for Creature {
@@ -1539,13 +1627,23 @@ pub mod creature {
                     writeln!(f, "Trace for payload {}:", i)?;
                     writeln!(f, "{}", path)?;
                 }
+                let gadgets = self
+                    .payloads
+                    .iter()
+                    .flatten()
+                    .cloned()
+                    .collect::<hashbrown::HashSet<u64>>();
                 for (i, state) in profile.registers.iter().enumerate() {
                     writeln!(
                         f,
-                        "\nSpidered register state for payload {}:\n{:?}",
-                        i, state
+                        "\nSpidered register state for payload {}:\n{}",
+                        i,
+                        state.annotated(&gadgets)
                     )?;
                 }
+                if let Some(table) = register_diff_table(&profile.registers) {
+                    writeln!(f, "\nRegisters that varied across payloads:\n{}", table)?;
+                }
                 writeln!(f, "CPU Error code(s): {:?}", profile.cpu_errors)?;
             }
             writeln!(f, "Fitness: {:#?}", self.fitness())?;
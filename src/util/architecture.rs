@@ -1,15 +1,29 @@
 use std::fmt;
+#[cfg(feature = "emulator")]
 use std::hash::Hash;
+use std::str::FromStr;
 
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
+#[cfg(feature = "emulator")]
 use hashbrown::HashMap;
+#[cfg(feature = "emulator")]
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "emulator")]
 use unicorn::{Arch, Cpu, Mode};
 
 use bitflags::bitflags;
 
+#[cfg(feature = "emulator")]
+use crate::configure::RegisterDistribution;
+#[cfg(feature = "emulator")]
+use crate::emulator::loader::get_static_memory_image;
+#[cfg(feature = "emulator")]
 use crate::emulator::register_pattern::Register;
+use crate::error::Error;
+#[cfg(feature = "emulator")]
+use crate::logger::UTIL;
+#[cfg(feature = "emulator")]
 use crate::util::random::hash_seed_rng;
 
 // TODO: Define berbalang-specific Arch and Mode, and translate
@@ -60,30 +74,54 @@ impl fmt::Display for Perms {
     }
 }
 
+/// The inverse of `Display`: any of the letters R, W and X, in any order
+/// or case. The empty string is `Perms::NONE`.
+impl FromStr for Perms {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.chars()
+            .try_fold(Perms::NONE, |perms, c| match c.to_ascii_uppercase() {
+                'R' => Ok(perms | Perms::READ),
+                'W' => Ok(perms | Perms::WRITE),
+                'X' => Ok(perms | Perms::EXEC),
+                _ => Err(Error::Parsing(format!(
+                    "Invalid permission {:?} in {:?}",
+                    c, s
+                ))),
+            })
+    }
+}
+
+#[cfg(feature = "emulator")]
 impl From<unicorn::Protection> for Perms {
     fn from(p: unicorn::Protection) -> Self {
         Perms::from_bits_truncate(p.bits() as u8)
     }
 }
 
+#[cfg(feature = "emulator")]
 impl Into<unicorn::Protection> for Perms {
     fn into(self) -> unicorn::Protection {
         unicorn::Protection::from_bits_truncate(self.bits() as u32)
     }
 }
 
+#[cfg(feature = "emulator")]
 impl From<falcon::memory::MemoryPermissions> for Perms {
     fn from(p: falcon::memory::MemoryPermissions) -> Self {
         Perms::from_bits_truncate(p.bits() as u8)
     }
 }
 
+#[cfg(feature = "emulator")]
 impl Into<falcon::memory::MemoryPermissions> for Perms {
     fn into(self) -> falcon::memory::MemoryPermissions {
         falcon::memory::MemoryPermissions::from_bits_truncate(self.bits() as u32)
     }
 }
 
+#[cfg(feature = "emulator")]
 impl From<&goblin::elf::ProgramHeader> for Perms {
     fn from(phdr: &goblin::elf::ProgramHeader) -> Self {
         let mut perm = Perms::NONE;
@@ -135,18 +173,63 @@ pub fn write_integer(endian: Endian, word_size: usize, word: u64, bytes: &mut [u
     }
 }
 
+#[cfg(feature = "emulator")]
 pub fn random_register_state<H: Hash, C: 'static + Cpu<'static>>(
     registers: &[Register<C>],
     seed: H,
+    distribution: &RegisterDistribution,
 ) -> HashMap<Register<C>, u64> {
     let mut map = HashMap::new();
-    let mut rng = hash_seed_rng(&seed);
     for reg in registers.iter() {
-        map.insert(*reg, rng.gen::<u64>());
+        let name = format!("{:?}", reg);
+        // Each register is drawn from its own generator, so that the state
+        // depends only on the seed, and not on the order of the registers.
+        let mut rng = hash_seed_rng(&(&seed, &name));
+        map.insert(*reg, sample_register(&mut rng, &name, distribution));
     }
     map
 }
 
+#[cfg(feature = "emulator")]
+fn sample_register<R: Rng>(rng: &mut R, reg: &str, distribution: &RegisterDistribution) -> u64 {
+    match distribution {
+        RegisterDistribution::FullRange => rng.gen::<u64>(),
+        RegisterDistribution::SmallIntegers { max } => {
+            if *max == std::u64::MAX {
+                rng.gen::<u64>()
+            } else {
+                rng.gen_range(0, max + 1)
+            }
+        }
+        RegisterDistribution::MappedAddresses => {
+            let segs = &get_static_memory_image().segs;
+            if segs.is_empty() {
+                log::warn!(
+                    target: UTIL,
+                    "No segments are loaded, so drawing {} from the full range",
+                    reg
+                );
+                return rng.gen::<u64>();
+            }
+            // weight the segments by size, so that every address is equally likely
+            let total = segs.iter().map(|s| s.memsz as u64).sum::<u64>().max(1);
+            let mut offset = rng.gen_range(0, total);
+            for seg in segs.iter() {
+                if offset < seg.memsz as u64 {
+                    return seg.addr + offset;
+                }
+                offset -= seg.memsz as u64;
+            }
+            segs[0].addr
+        }
+        RegisterDistribution::PerRegister(distributions) => match distributions.get(reg) {
+            Some(d) => sample_register(rng, reg, d),
+            None => rng.gen::<u64>(),
+        },
+    }
+}
+
+#[cfg(feature = "emulator")]
 pub fn constant_register_state<C: 'static + Cpu<'static>>(
     registers: &[Register<C>],
     constant: u64,
@@ -158,6 +241,7 @@ pub fn constant_register_state<C: 'static + Cpu<'static>>(
     map
 }
 
+#[cfg(feature = "emulator")]
 pub fn endian(arch: Arch, mode: Mode) -> Endian {
     use Arch::*;
     use Endian::*;
@@ -173,6 +257,27 @@ pub fn endian(arch: Arch, mode: Mode) -> Endian {
     }
 }
 
+/// The names of the general-purpose registers of the architecture, including
+/// the stack and instruction pointers and the flags. Empty for architectures
+/// that haven't been listed yet.
+#[cfg(feature = "emulator")]
+pub fn general_purpose_registers(arch: Arch, mode: Mode) -> Vec<&'static str> {
+    use Arch::*;
+    use Mode::*;
+
+    match (arch, mode) {
+        (X86, MODE_64) => vec![
+            "RAX", "RBX", "RCX", "RDX", "RSI", "RDI", "RBP", "RSP", "R8", "R9", "R10", "R11",
+            "R12", "R13", "R14", "R15", "RIP", "EFLAGS",
+        ],
+        (X86, MODE_32) => vec![
+            "EAX", "EBX", "ECX", "EDX", "ESI", "EDI", "EBP", "ESP", "EIP", "EFLAGS",
+        ],
+        (_, _) => vec![],
+    }
+}
+
+#[cfg(feature = "emulator")]
 pub fn word_size_in_bytes(arch: Arch, mode: Mode) -> usize {
     use Arch::*;
     use Mode::*;
@@ -193,3 +298,60 @@ pub fn word_size_in_bytes(arch: Arch, mode: Mode) -> usize {
         (_, _) => unimplemented!("invalid arch/mode combination"),
     }
 }
+
+#[cfg(all(test, feature = "emulator"))]
+mod test {
+    use unicorn::{CpuX86, RegisterX86};
+
+    use super::*;
+
+    #[test]
+    fn test_general_purpose_registers() {
+        for mode in [Mode::MODE_32, Mode::MODE_64].iter() {
+            let names = general_purpose_registers(Arch::X86, *mode);
+            assert!(!names.is_empty());
+            for name in names {
+                assert!(name.parse::<RegisterX86>().is_ok(), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_register_distributions() {
+        let registers = [RegisterX86::RAX, RegisterX86::RDI, RegisterX86::RSI];
+        let small = RegisterDistribution::SmallIntegers { max: 0x10 };
+        for seed in 0..20_u64 {
+            let state = random_register_state::<u64, CpuX86<'_>>(&registers, seed, &small);
+            assert!(state.values().all(|v| *v <= 0x10));
+        }
+
+        let mut per_register = HashMap::new();
+        per_register.insert(
+            "RDI".to_string(),
+            RegisterDistribution::SmallIntegers { max: 0 },
+        );
+        let per_register = RegisterDistribution::PerRegister(per_register);
+        let state = random_register_state::<u64, CpuX86<'_>>(&registers, 1_u64, &per_register);
+        assert_eq!(state[&RegisterX86::RDI], 0);
+    }
+
+    #[test]
+    fn test_register_state_is_reproducible() {
+        let registers = [RegisterX86::RAX, RegisterX86::RDI, RegisterX86::RSI];
+        let reordered = [RegisterX86::RSI, RegisterX86::RAX, RegisterX86::RDI];
+        let full = RegisterDistribution::FullRange;
+        let state = random_register_state::<u64, CpuX86<'_>>(&registers, 42_u64, &full);
+        assert_eq!(
+            state,
+            random_register_state::<u64, CpuX86<'_>>(&registers, 42_u64, &full)
+        );
+        assert_eq!(
+            state,
+            random_register_state::<u64, CpuX86<'_>>(&reordered, 42_u64, &full)
+        );
+        assert_ne!(
+            state,
+            random_register_state::<u64, CpuX86<'_>>(&registers, 43_u64, &full)
+        );
+    }
+}
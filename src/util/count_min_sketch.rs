@@ -4,6 +4,7 @@ use std::hash::{Hash, Hasher};
 //use std::collections::hash_map::DefaultHasher;
 use crate::configure::Config;
 use crate::get_epoch_counter;
+use crate::logger::UTIL;
 
 pub trait Sketch: Send + Sync + Clone {
     fn insert<T: Hash>(&mut self, thing: T);
@@ -197,7 +198,7 @@ impl DecayingSketch {
         // and we divide the score by the counter because we're interested in
         // *relative* frequency
         let f = d * freq as f64 / self.counter as f64;
-        log::debug!("f = {}", f);
+        log::debug!(target: UTIL, "f = {}", f);
         f
     }
 
@@ -265,6 +266,9 @@ impl CountMinSketch {
     }
 
     pub fn query<T: Hash>(&self, thing: T) -> f64 {
+        if self.counter == 0 {
+            return 0.0;
+        }
         (0..self.depth)
             .map(|i| {
                 let loc = hash(&thing, i) % self.width;
@@ -273,6 +277,26 @@ impl CountMinSketch {
             .fold(std::usize::MAX, std::cmp::min) as f64
             / self.counter as f64
     }
+
+    /// Like `insert`, but hashes the thing only once, rather than once for
+    /// each row of the table. Prefer this for anything larger than a word,
+    /// such as a whole genome or a memory write log, and `insert` for
+    /// addresses. A thing counted with `insert_hashable` must be queried
+    /// with `query_hashable`.
+    pub fn insert_hashable<H: Hash>(&mut self, thing: &H) {
+        self.insert(fingerprint(thing))
+    }
+
+    /// The relative frequency of a thing counted with `insert_hashable`.
+    pub fn query_hashable<H: Hash>(&self, thing: &H) -> f64 {
+        self.query(fingerprint(thing))
+    }
+}
+
+fn fingerprint<H: Hash>(thing: &H) -> u64 {
+    let mut hasher = fnv::FnvHasher::default();
+    thing.hash(&mut hasher);
+    hasher.finish()
 }
 
 pub fn suggest_width(expected_count: usize) -> usize {
@@ -292,6 +316,22 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn test_hashable_count_min_sketch() {
+        let mut sketch = CountMinSketch::with_dimensions(8, 1024);
+        let genomes = vec![vec![1_u64, 2, 3], vec![4, 5], vec![1, 2, 3], vec![1, 2, 3]];
+        for genome in genomes.iter() {
+            sketch.insert_hashable(genome);
+        }
+        assert_eq!(sketch.query_hashable(&vec![1_u64, 2, 3]), 0.75);
+        assert_eq!(sketch.query_hashable(&vec![4_u64, 5]), 0.25);
+        assert_eq!(sketch.query_hashable(&vec![6_u64]), 0.0);
+
+        // addresses still take the fast path
+        sketch.insert(0x400000_u64);
+        assert_eq!(sketch.query(0x400000_u64), 0.2);
+    }
+
     #[test]
     fn test_decaying_count_min_sketch() {
         let count = 100;
@@ -1,5 +1,9 @@
-use seahash::hash_seeded;
+use std::hash::Hash;
 use std::iter::Iterator;
+use std::mem;
+
+use hashbrown::HashSet;
+use seahash::hash_seeded;
 
 /// See https://en.wikipedia.org/wiki/MinHash for discussion of algorithm
 pub fn jaccard(one: &[u8], two: &[u8], grain: usize, num_hashes: u64) -> f64 {
@@ -43,3 +47,132 @@ fn byte_positions(bytes: &[u8], grain: usize) -> Vec<[u8; 4]> {
         })
         .collect::<Vec<[u8; 4]>>()
 }
+
+/// One minus the Jaccard similarity of two sets: the fraction of the
+/// elements in either set that aren't in both. Two empty sets are
+/// identical, at distance 0.
+pub fn jaccard_distance<T: Eq + Hash>(one: &HashSet<T>, two: &HashSet<T>) -> f64 {
+    let union = one.union(two).count();
+    if union == 0 {
+        return 0.0;
+    }
+    let intersection = one.intersection(two).count();
+    1.0 - intersection as f64 / union as f64
+}
+
+/// The Levenshtein edit distance between two sequences: the fewest
+/// insertions, deletions and substitutions that turn one into the other.
+pub fn levenshtein<T: PartialEq>(one: &[T], two: &[T]) -> usize {
+    let mut prev = (0..=two.len()).collect::<Vec<usize>>();
+    let mut cur = vec![0; two.len() + 1];
+    for (i, x) in one.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, y) in two.iter().enumerate() {
+            let cost = if x == y { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        mem::swap(&mut prev, &mut cur);
+    }
+    prev[two.len()]
+}
+
+/// The Levenshtein distance between two sequences, if it's no more than
+/// `max`, or `None` if it is. Only the cells of the dynamic programming
+/// table within `max` of the diagonal are filled in, and the search stops
+/// as soon as a whole row exceeds `max`, so this is much cheaper than
+/// `levenshtein` when the bound is tight -- when all that matters is
+/// whether a candidate is any closer than the best found so far, say.
+pub fn levenshtein_bounded<T: PartialEq>(one: &[T], two: &[T], max: usize) -> Option<usize> {
+    let (short, long) = if one.len() <= two.len() {
+        (one, two)
+    } else {
+        (two, one)
+    };
+    if long.len() - short.len() > max {
+        return None;
+    }
+    // anything over the bound is as good as infinite
+    let over = max.saturating_add(1);
+    let n = long.len();
+    let mut prev = (0..=n)
+        .map(|j| if j <= max { j } else { over })
+        .collect::<Vec<usize>>();
+    let mut cur = vec![over; n + 1];
+    for (i, x) in short.iter().enumerate().map(|(i, x)| (i + 1, x)) {
+        let lo = i.saturating_sub(max).max(1);
+        let hi = n.min(i.saturating_add(max));
+        // the cells bordering the band may hold values from an earlier row
+        cur[lo - 1] = if lo == 1 && i <= max { i } else { over };
+        if hi < n {
+            cur[hi + 1] = over;
+        }
+        let mut row_min = cur[lo - 1];
+        for j in lo..=hi {
+            let cost = if *x == long[j - 1] { 0 } else { 1 };
+            let d = (prev[j - 1] + cost)
+                .min(prev[j] + 1)
+                .min(cur[j - 1] + 1)
+                .min(over);
+            cur[j] = d;
+            row_min = row_min.min(d);
+        }
+        if row_min > max {
+            return None;
+        }
+        mem::swap(&mut prev, &mut cur);
+    }
+    Some(prev[n]).filter(|d| *d <= max)
+}
+
+#[cfg(test)]
+mod test {
+    use rand::Rng;
+
+    use crate::util::random::hash_seed_rng;
+
+    use super::*;
+
+    #[test]
+    fn test_jaccard_distance() {
+        let set = |xs: &[u64]| xs.iter().copied().collect::<HashSet<u64>>();
+        assert_eq!(jaccard_distance(&set(&[]), &set(&[])), 0.0);
+        assert_eq!(jaccard_distance(&set(&[1, 2]), &set(&[2, 1])), 0.0);
+        assert_eq!(jaccard_distance(&set(&[1, 2]), &set(&[3])), 1.0);
+        assert_eq!(jaccard_distance(&set(&[]), &set(&[3])), 1.0);
+        assert_eq!(jaccard_distance(&set(&[1, 2, 3]), &set(&[2, 3, 4])), 0.5);
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein(b"kitten", b"sitting"), 3);
+        assert_eq!(levenshtein(b"", b"abc"), 3);
+        assert_eq!(levenshtein(b"abc", b"abc"), 0);
+        assert_eq!(levenshtein(b"flaw", b"lawn"), 2);
+    }
+
+    #[test]
+    fn test_levenshtein_bounded() {
+        assert_eq!(levenshtein_bounded(b"kitten", b"sitting", 3), Some(3));
+        assert_eq!(levenshtein_bounded(b"kitten", b"sitting", 2), None);
+        assert_eq!(levenshtein_bounded(b"", b"abc", 3), Some(3));
+        assert_eq!(levenshtein_bounded(b"abc", b"", 2), None);
+        assert_eq!(levenshtein_bounded(b"abc", b"abc", 0), Some(0));
+
+        // the bounded distance agrees with the exact one wherever it's defined
+        let mut rng = hash_seed_rng(&"levenshtein_bounded");
+        for _ in 0..200 {
+            let mut word = || {
+                let len = rng.gen_range(0, 12);
+                (0..len)
+                    .map(|_| rng.gen_range(b'a', b'd'))
+                    .collect::<Vec<u8>>()
+            };
+            let (a, b) = (word(), word());
+            let exact = levenshtein(&a, &b);
+            for max in 0..12 {
+                let expected = if exact <= max { Some(exact) } else { None };
+                assert_eq!(levenshtein_bounded(&a, &b, max), expected);
+            }
+        }
+    }
+}
@@ -7,7 +7,9 @@ use deflate::write::GzEncoder;
 use deflate::Compression;
 use serde::{de::DeserializeOwned, Serialize};
 
+use crate::configure::DumpFormat;
 use crate::error::Error;
+use crate::logger::UTIL;
 
 pub fn zip(bytes: &[u8]) -> Result<Vec<u8>, Error> {
     let mut gz = GzEncoder::new(Vec::new(), Compression::Default);
@@ -25,7 +27,7 @@ pub fn dump<T: Serialize, P: AsRef<Path> + Debug>(thing: T, path: P) -> Result<(
     };
     if let Err(e) = dumper() {
         fs::remove_file(&path)?;
-        log::warn!("Failed to dump to {:?}, removed file", path);
+        log::warn!(target: UTIL, "Failed to dump to {:?}, removed file", path);
         Err(e)
     } else {
         Ok(())
@@ -36,7 +38,7 @@ pub fn undump<T: DeserializeOwned, P: AsRef<Path> + Debug>(path: P) -> Result<T,
     let mut file = fs::File::open(&path)?;
     let mut bytes = Vec::new();
     let num_bytes = file.read_to_end(&mut bytes)?;
-    log::debug!("{} bytes read from {:?}", num_bytes, path);
+    log::debug!(target: UTIL, "{} bytes read from {:?}", num_bytes, path);
     let decompressed: Vec<u8> = deflate::deflate_bytes_gzip(&bytes);
     let thing = serde_json::de::from_reader::<&[u8], T>(&decompressed)?;
     Ok(thing)
@@ -53,7 +55,7 @@ pub fn ron_dump<T: Serialize, P: AsRef<Path> + Debug>(thing: T, path: P) -> Resu
     };
     if let Err(e) = dumper() {
         fs::remove_file(&path)?;
-        log::warn!("Failed to dump to {:?}, removed file", path);
+        log::warn!(target: UTIL, "Failed to dump to {:?}, removed file", path);
         Err(e)
     } else {
         Ok(())
@@ -64,8 +66,106 @@ pub fn ron_undump<T: DeserializeOwned, P: AsRef<Path> + Debug>(path: P) -> Resul
     let mut file = fs::File::open(&path)?;
     let mut bytes = Vec::new();
     let num_bytes = file.read_to_end(&mut bytes)?;
-    log::debug!("{} bytes read from {:?}", num_bytes, path);
+    log::debug!(target: UTIL, "{} bytes read from {:?}", num_bytes, path);
     let decompressed: Vec<u8> = deflate::deflate_bytes_gzip(&bytes);
     let thing = ron::de::from_reader::<&[u8], T>(&decompressed)?;
     Ok(thing)
 }
+
+/// Dump `thing` to `path` in the given format. The path should end in the
+/// format's `extension`.
+pub fn dump_as<T: Serialize, P: AsRef<Path> + Debug>(
+    thing: T,
+    path: P,
+    format: DumpFormat,
+) -> Result<(), Error> {
+    match format {
+        DumpFormat::Json => dump(thing, path),
+        DumpFormat::Bincode => bincode_dump(thing, path),
+    }
+}
+
+#[cfg(feature = "binary_dump")]
+pub fn bincode_dump<T: Serialize, P: AsRef<Path> + Debug>(thing: T, path: P) -> Result<(), Error> {
+    let file = fs::File::create(&path)?;
+    let mut dumper = || -> Result<(), Error> {
+        let mut writer = std::io::BufWriter::new(file);
+        bincode::serialize_into(&mut writer, &thing)?;
+        writer.flush().map_err(Error::from)
+    };
+    if let Err(e) = dumper() {
+        fs::remove_file(&path)?;
+        log::warn!(target: UTIL, "Failed to dump to {:?}, removed file", path);
+        Err(e)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "binary_dump"))]
+pub fn bincode_dump<T: Serialize, P: AsRef<Path> + Debug>(_thing: T, path: P) -> Result<(), Error> {
+    Err(Error::Misc(format!(
+        "Can't dump to {:?}: berbalang was built without the binary_dump feature",
+        path
+    )))
+}
+
+#[cfg(feature = "binary_dump")]
+pub fn bincode_undump<T: DeserializeOwned, P: AsRef<Path> + Debug>(path: P) -> Result<T, Error> {
+    let file = fs::File::open(&path)?;
+    let thing = bincode::deserialize_from(std::io::BufReader::new(file))?;
+    Ok(thing)
+}
+
+#[cfg(all(test, feature = "binary_dump", feature = "emulator"))]
+mod test {
+    use std::time::Duration;
+
+    use crate::emulator::profiler::{Block, Profile};
+
+    use super::*;
+
+    #[test]
+    fn test_bincode_round_trip_profile() {
+        let mut gadgets = hashbrown::HashMap::new();
+        gadgets.insert(0x4000_u64, 3_usize);
+        let profile = Profile {
+            paths: vec![vec![
+                Block {
+                    entry: 0x4000,
+                    size: 5,
+                },
+                Block {
+                    entry: 0x4010,
+                    size: 2,
+                },
+            ]],
+            code_executed: vec![vec![0x90, 0xc3]],
+            cpu_errors: vec![None],
+            emulation_times: vec![Duration::from_micros(1234)],
+            gadgets_executed: vec![gadgets],
+            executable: true,
+            ret_counts: vec![2],
+            ..Default::default()
+        };
+        // unique to this process, so that concurrent test runs don't
+        // clobber each other's files
+        let dir = std::env::temp_dir().join(format!(
+            "berbalang_test_bincode_round_trip_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("profile.bin");
+        dump_as(&profile, &path, DumpFormat::Bincode).unwrap();
+        let restored: Profile = bincode_undump(&path).unwrap();
+
+        assert_eq!(restored.paths, profile.paths);
+        assert_eq!(restored.code_executed, profile.code_executed);
+        assert_eq!(restored.emulation_times, profile.emulation_times);
+        assert_eq!(restored.gadgets_executed, profile.gadgets_executed);
+        assert_eq!(restored.ret_counts, profile.ret_counts);
+        assert!(restored.executable);
+        assert!(restored.cpu_errors[0].is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -21,12 +21,37 @@ pub fn levy_decision<R: Rng>(rng: &mut R, length: usize, exponent: f64) -> bool
         >= thresh
 }
 
+/// A step of random sign, whose length follows a power law with the given
+/// exponent, so that most steps are short, but a few are very long.
+pub fn levy_step<R: Rng>(rng: &mut R, exponent: f64) -> i64 {
+    let length = rand_distr::Pareto::new(1.0, exponent)
+        .expect("Bad exponent for Pareto distribution")
+        .sample(rng)
+        .min(std::i64::MAX as f64) as i64;
+    if rng.gen() {
+        length
+    } else {
+        -length
+    }
+}
+
 #[cfg(test)]
 mod test {
     use rand::thread_rng;
 
     use super::*;
 
+    #[test]
+    fn test_levy_step() {
+        let mut rng = thread_rng();
+        let steps = (0..1000)
+            .map(|_| levy_step(&mut rng, 1.5))
+            .collect::<Vec<i64>>();
+        assert!(steps.iter().all(|s| s.abs() >= 1));
+        assert!(steps.iter().any(|s| *s < 0));
+        assert!(steps.iter().any(|s| *s > 0));
+    }
+
     #[test]
     fn test_levy_flight_rate() {
         let len = 100;
@@ -22,3 +22,56 @@ pub fn hash_seed<H: Hash>(thing: &H) -> [u8; 8] {
     }
     seed
 }
+
+/// A hasher whose output depends only on the values hashed, and not on the
+/// run or the platform. It's FNV-1a with the standard offset basis, but
+/// integers are fed to it in little-endian order, with `usize` and `isize`
+/// widened to 64 bits.
+#[derive(Default)]
+pub struct StableHasher(fnv::FnvHasher);
+
+macro_rules! write_le {
+    ($($method:ident: $t:ty as $u:ty),* $(,)?) => {
+        $(
+            fn $method(&mut self, i: $t) {
+                self.0.write(&(i as $u).to_le_bytes())
+            }
+        )*
+    };
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.write(bytes)
+    }
+
+    write_le! {
+        write_u16: u16 as u16,
+        write_u32: u32 as u32,
+        write_u64: u64 as u64,
+        write_u128: u128 as u128,
+        write_usize: usize as u64,
+        write_i16: i16 as i16,
+        write_i32: i32 as i32,
+        write_i64: i64 as i64,
+        write_i128: i128 as i128,
+        write_isize: isize as i64,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stable_hasher() {
+        let mut h = StableHasher::default();
+        0x0102_0304_0506_0708_u64.hash(&mut h);
+        7_usize.hash(&mut h);
+        assert_eq!(h.finish(), 0xebb6_0ddf_e988_4812);
+    }
+}